@@ -0,0 +1,92 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput},
+    prost::Message,
+    solana_accountsdb_plugin_kafka::{Config, Filter, KeyHashSet, UpdateAccountEvent},
+    solana_program::{hash::hash, pubkey::Pubkey},
+    std::collections::HashSet,
+};
+
+/// Pseudo-random pubkey, unlike `Pubkey::new_unique` which only fills the first bytes.
+fn random_pubkey(i: usize) -> Pubkey {
+    Pubkey::new_from_array(hash(&i.to_le_bytes()).to_bytes())
+}
+
+fn bench_filter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("filter_wants_program");
+    for size in [0usize, 100, 10_000, 100_000] {
+        let programs: Vec<Pubkey> = (0..size).map(random_pubkey).collect();
+        let config = Config {
+            program_allowlist: programs.iter().map(|p| p.to_string()).collect(),
+            ..Config::default()
+        };
+        let filter = Filter::new(&config);
+        let hit = programs
+            .first()
+            .copied()
+            .unwrap_or_else(|| random_pubkey(0));
+        let miss = random_pubkey(usize::MAX);
+
+        group.bench_with_input(BenchmarkId::new("hit", size), &hit, |b, key| {
+            b.iter(|| filter.wants_program(black_box(key.as_ref())))
+        });
+        group.bench_with_input(BenchmarkId::new("miss", size), &miss, |b, key| {
+            b.iter(|| filter.wants_program(black_box(key.as_ref())))
+        });
+    }
+    group.finish();
+}
+
+fn bench_key_hashing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("key_set_contains");
+    let keys: Vec<[u8; 32]> = (0..100_000).map(|i| random_pubkey(i).to_bytes()).collect();
+    let sip: HashSet<[u8; 32]> = keys.iter().copied().collect();
+    let fast: KeyHashSet = keys.iter().copied().collect();
+    let probe = keys[keys.len() / 2];
+
+    group.bench_function("siphash", |b| b.iter(|| sip.contains(black_box(&probe))));
+    group.bench_function("key_hasher", |b| {
+        b.iter(|| fast.contains(black_box(&probe)))
+    });
+    group.finish();
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode_update_account");
+    for size in [0usize, 165, 3_000, 1024 * 1024] {
+        let event = UpdateAccountEvent {
+            slot: 1,
+            pubkey: random_pubkey(1).to_bytes().to_vec().into(),
+            lamports: 1_000_000,
+            owner: random_pubkey(2).to_bytes().to_vec().into(),
+            executable: false,
+            rent_epoch: 300,
+            data: vec![7u8; size].into(),
+            write_version: 42,
+            sequence: 0,
+            discarded_slots: Vec::new(),
+            decoded: None,
+        };
+        group.throughput(Throughput::Bytes(event.encoded_len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &event, |b, event| {
+            b.iter(|| black_box(event).encode_to_vec())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_filter, bench_key_hashing, bench_encode);
+criterion_main!(benches);
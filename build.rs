@@ -1,6 +1,109 @@
-use std::io::Result;
+use std::{env, io::Result, path::PathBuf};
+
+/// Binary fields and how the JSON output renders them, like Solana RPC does.
+const JSON_FIELDS: &[(&str, &str)] = &[
+    ("UpdateAccountEvent.pubkey", "base58"),
+    ("UpdateAccountEvent.owner", "base58"),
+    ("UpdateAccountEvent.data", "base64"),
+    ("TokenAccount.mint", "base58"),
+    ("TokenAccount.owner", "base58"),
+    ("TokenAccount.delegate", "base58"),
+    ("TokenAccount.close_authority", "base58"),
+    ("TokenMint.mint_authority", "base58"),
+    ("TokenMint.freeze_authority", "base58"),
+    ("TransferFeeConfig.transfer_fee_config_authority", "base58"),
+    ("TransferFeeConfig.withdraw_withheld_authority", "base58"),
+    ("InterestBearingConfig.rate_authority", "base58"),
+    ("ConfidentialTransferMint.authority", "base58"),
+    ("ConfidentialTransferMint.auditor_elgamal_pubkey", "base64"),
+    ("StakeAccount.staker", "base58"),
+    ("StakeAccount.withdrawer", "base58"),
+    ("StakeAccount.lockup_custodian", "base58"),
+    ("StakeDelegation.voter", "base58"),
+    ("NonceAccount.authority", "base58"),
+    ("NonceAccount.blockhash", "base58"),
+    ("VoteAccount.node_pubkey", "base58"),
+    ("VoteAccount.authorized_voter", "base58"),
+    ("VoteAccount.authorized_withdrawer", "base58"),
+    ("AddressLookupTable.authority", "base58"),
+    ("AddressLookupTable.addresses", "base58_list"),
+    ("OpenBookMarket.own_address", "base58"),
+    ("OpenBookMarket.base_mint", "base58"),
+    ("OpenBookMarket.quote_mint", "base58"),
+    ("OpenBookMarket.base_vault", "base58"),
+    ("OpenBookMarket.quote_vault", "base58"),
+    ("OpenBookMarket.request_queue", "base58"),
+    ("OpenBookMarket.event_queue", "base58"),
+    ("OpenBookMarket.bids", "base58"),
+    ("OpenBookMarket.asks", "base58"),
+    ("MetadataPointer.authority", "base58"),
+    ("MetadataPointer.metadata_address", "base58"),
+    ("SlotStatusEvent.status", "slot_status"),
+    ("TopicSwitch.phase", "topic_switch_phase"),
+    ("CompiledInstruction.data", "base64"),
+    ("LoadedAddresses.writable", "base58_list"),
+    ("LoadedAddresses.readonly", "base58_list"),
+    ("MessageAddressTableLookup.account_key", "base58"),
+    ("V0Message.account_keys", "base58_list"),
+    ("V0Message.recent_block_hash", "base58"),
+    ("LegacyMessage.account_keys", "base58_list"),
+    ("LegacyMessage.recent_block_hash", "base58"),
+    ("SanitizedTransaction.message_hash", "base58"),
+    ("SanitizedTransaction.signatures", "base58_list"),
+    ("SanitizedTransaction.account_keys", "base58_list"),
+    ("TransactionEvent.signature", "base58"),
+    ("TransactionStatusEvent.signature", "base58"),
+    ("TokenBalanceChange.account", "base58"),
+    ("TokenBalanceChange.owner", "base58"),
+    ("TokenBalanceChange.mint", "base58"),
+    ("OwnerChanged.account", "base58"),
+    ("OwnerChanged.previous_owner", "base58"),
+    ("OwnerChanged.owner", "base58"),
+    ("ProgramUpgraded.program_id", "base58"),
+    ("ProgramUpgraded.programdata", "base58"),
+    ("ProgramUpgraded.upgrade_authority", "base58"),
+    ("ProgramUpgraded.data_hash", "hex"),
+    ("CompressedNftEvent.signature", "base58"),
+    ("CompressedNftEvent.tree", "base58"),
+    ("CompressedNftEvent.asset_id", "base58"),
+    ("CompressedNftEvent.owner", "base58"),
+    ("CompressedNftEvent.delegate", "base58"),
+    ("CompressedNftEvent.data_hash", "base58"),
+    ("CompressedNftEvent.creator_hash", "base58"),
+];
 
 fn main() -> Result<()> {
-    prost_build::compile_protos(&["proto/event.proto"], &["proto/"])?;
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let mut config = prost_build::Config::new();
+    config
+        // Exported by the plugin so consumers can fetch the exact schema of a build.
+        .file_descriptor_set_path(out_dir.join("event_descriptor_set.bin"))
+        // JSON output of the non-Kafka sinks.
+        .type_attribute(
+            ".blockdaemon.solana.accountsdb_plugin_kafka.types",
+            "#[derive(serde::Serialize)]",
+        )
+        // Account payloads are large; share them as `Bytes` instead of copying `Vec<u8>`s around.
+        .bytes([
+            ".blockdaemon.solana.accountsdb_plugin_kafka.types.UpdateAccountEvent",
+            ".geyser.SubscribeUpdateAccountInfo",
+        ]);
+    for (field, with) in JSON_FIELDS {
+        config.field_attribute(
+            format!(
+                ".blockdaemon.solana.accountsdb_plugin_kafka.types.{}",
+                field
+            ),
+            format!("#[serde(serialize_with = \"crate::json::{}\")]", with),
+        );
+    }
+    config.compile_protos(
+        &[
+            "proto/event.proto",
+            "proto/yellowstone/geyser.proto",
+            "proto/yellowstone/solana-storage.proto",
+        ],
+        &["proto/"],
+    )?;
     Ok(())
 }
@@ -0,0 +1,427 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender},
+    log::{error, info},
+    std::{
+        io::{self, BufRead, BufReader, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+        sync::Arc,
+        thread::{self, JoinHandle},
+        time::Duration,
+    },
+};
+#[cfg(feature = "profiling")]
+use {
+    lazy_static::lazy_static,
+    std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+#[cfg(feature = "profiling")]
+lazy_static! {
+    /// Thread taking the last CPU profile, joined before the endpoint stops.
+    static ref PROFILE_THREAD: Mutex<Option<JoinHandle<()>>> = Mutex::default();
+}
+
+/// How often the admin endpoint checks for connections, and how long it waits on a client.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(100);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What the admin endpoint reports on.
+pub struct AdminSources {
+    pub queues: QueueGauges,
+    pub publisher: Arc<Publisher>,
+    /// Publishers of the tenant pipelines, by tenant name.
+    pub tenants: Vec<(String, Arc<Publisher>)>,
+    /// Context of the Kafka producers, if publishing to Kafka.
+    pub context: Option<DeliveryContext>,
+    /// Alerts firing, if alert thresholds are configured.
+    pub health: Option<Arc<Health>>,
+}
+
+impl AdminSources {
+    /// Current occupancy of the publisher queues and the producers, as served on `/queues`.
+    pub fn queue_report(&self) -> serde_json::Value {
+        let producers: Vec<serde_json::Value> = self
+            .publisher
+            .producer_queue_lengths()
+            .into_iter()
+            .map(|(shard, len)| serde_json::json!({ "shard": shard, "out_queue": len }))
+            .collect();
+        let mut report = serde_json::json!({
+            "queues": self.queues.lengths(),
+            "producers": producers,
+        });
+        if let Some(context) = &self.context {
+            report["buffered_bytes"] = serde_json::json!(context.buffered_bytes().by_topic());
+            if let Some(budget) = context.in_flight_budget() {
+                let (messages, bytes) = budget.in_flight();
+                report["in_flight"] = serde_json::json!({ "messages": messages, "bytes": bytes });
+            }
+        }
+        report
+    }
+
+    /// Switches a topic of the root pipeline, or of the `tenant` one, over to another, as served on
+    /// `POST /topics/switch?from=..&to=..&tenant=..`.
+    pub fn switch_topic(&self, path: &str) -> (&'static str, String) {
+        let error = |status, msg: String| (status, serde_json::json!({ "error": msg }).to_string());
+        let (from, to) = match (query_param(path, "from"), query_param(path, "to")) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return error("400 Bad Request", "from and to are required".to_owned()),
+        };
+        let publisher = match query_param(path, "tenant") {
+            None => &self.publisher,
+            Some(name) => match self.tenants.iter().find(|(tenant, _)| tenant == name) {
+                Some((_, publisher)) => publisher,
+                None => return error("404 Not Found", format!("no tenant {}", name)),
+            },
+        };
+        match publisher.switch_topic(from, to) {
+            Ok(()) => (
+                "200 OK",
+                serde_json::json!({ "from": from, "to": to }).to_string(),
+            ),
+            Err(msg) => error("409 Conflict", msg),
+        }
+    }
+}
+
+/// The value of the `name` query parameter of `path`, if any.
+fn query_param<'a>(path: &'a str, name: &str) -> Option<&'a str> {
+    path.split_once('?')
+        .into_iter()
+        .flat_map(|(_, query)| query.split('&'))
+        .find_map(|param| {
+            let (key, value) = param.split_once('=')?;
+            Some(value).filter(|_| key == name)
+        })
+}
+
+/// Serves JSON reports for operators over plain HTTP, one request at a time, CPU profiles aside.
+pub struct AdminServer {
+    address: SocketAddr,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AdminServer {
+    pub fn spawn(address: &str, sources: AdminSources) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        info!("Serving admin endpoint on {}", address);
+        let (stop, stopped) = bounded(0);
+        let thread = thread::Builder::new()
+            .name("kafka-admin".to_owned())
+            .spawn(move || Self::run(listener, sources, stopped))
+            .expect("failed to spawn admin thread");
+        Ok(Self {
+            address,
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    /// Address the endpoint listens on, with the port picked if `0` was configured.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    fn run(listener: TcpListener, sources: AdminSources, stopped: Receiver<()>) {
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(ACCEPT_INTERVAL) {
+            loop {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        if let Err(e) = Self::serve(stream, &sources) {
+                            error!("Failed to serve admin request: {}", e);
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        error!("Failed to accept admin connection: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+        #[cfg(feature = "profiling")]
+        if let Some(thread) = PROFILE_THREAD.lock().unwrap().take() {
+            if thread.join().is_err() {
+                error!("Profile thread panicked");
+            }
+        }
+    }
+
+    fn serve(stream: TcpStream, sources: &AdminSources) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+        let mut reader = BufReader::new(&stream);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // Skip the request headers.
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        let mut parts = request.split_whitespace();
+        const JSON: &str = "application/json";
+        let (status, content_type, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/queues")) => ("200 OK", JSON, sources.queue_report().to_string()),
+            (Some("GET"), Some("/health")) => match &sources.health {
+                Some(health) if !health.is_healthy() => {
+                    ("503 Service Unavailable", JSON, health.report().to_string())
+                }
+                Some(health) => ("200 OK", JSON, health.report().to_string()),
+                None => ("200 OK", JSON, Health::default().report().to_string()),
+            },
+            (Some("GET"), Some("/memory")) => ("200 OK", JSON, memory().report().to_string()),
+            (Some("GET"), Some("/metrics")) => {
+                let metrics: serde_json::Map<String, serde_json::Value> = metrics()
+                    .snapshot()
+                    .into_iter()
+                    .map(|(name, value)| (name, value.into()))
+                    .collect();
+                (
+                    "200 OK",
+                    JSON,
+                    serde_json::Value::Object(metrics).to_string(),
+                )
+            }
+            #[cfg(feature = "profiling")]
+            (Some("GET"), Some("/debug/pprof/heap")) => {
+                ("200 OK", JSON, heap_profile().to_string())
+            }
+            #[cfg(feature = "profiling")]
+            (Some("GET"), Some(path)) if path.split('?').next() == Some("/debug/pprof/profile") => {
+                return Self::serve_profile(stream, Self::profile_duration(path));
+            }
+            (Some("POST"), Some(path)) if path.split('?').next() == Some("/topics/switch") => {
+                let (status, body) = sources.switch_topic(path);
+                (status, JSON, body)
+            }
+            (Some("GET"), Some(_)) => (
+                "404 Not Found",
+                JSON,
+                "{\"error\":\"not found\"}".to_owned(),
+            ),
+            _ => (
+                "405 Method Not Allowed",
+                JSON,
+                "{\"error\":\"method not allowed\"}".to_owned(),
+            ),
+        };
+        Self::respond(&stream, status, content_type, &body)
+    }
+
+    fn respond(
+        mut stream: &TcpStream,
+        status: &str,
+        content_type: &str,
+        body: &str,
+    ) -> io::Result<()> {
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        )
+    }
+
+    /// Takes a CPU profile and responds with it on a thread of its own, so the other requests
+    /// keep being served meanwhile. One profile is taken at a time.
+    #[cfg(feature = "profiling")]
+    fn serve_profile(stream: TcpStream, duration: Duration) -> io::Result<()> {
+        static PROFILING: AtomicBool = AtomicBool::new(false);
+        if PROFILING.swap(true, Ordering::AcqRel) {
+            return Self::respond(
+                &stream,
+                "409 Conflict",
+                "application/json",
+                "{\"error\":\"a profile is already being taken\"}",
+            );
+        }
+        let spawned = thread::Builder::new()
+            .name("kafka-admin-profile".to_owned())
+            .spawn(move || {
+                let profile = profile_cpu(duration);
+                PROFILING.store(false, Ordering::Release);
+                if let Err(e) = Self::respond(&stream, "200 OK", "text/plain", &profile) {
+                    error!("Failed to serve CPU profile: {}", e);
+                }
+            });
+        match spawned {
+            Ok(thread) => {
+                // The previous profile was taken already, at most its response is being written.
+                let previous = PROFILE_THREAD.lock().unwrap().replace(thread);
+                if let Some(previous) = previous {
+                    let _ = previous.join();
+                }
+                Ok(())
+            }
+            Err(e) => {
+                PROFILING.store(false, Ordering::Release);
+                Err(e)
+            }
+        }
+    }
+
+    /// The `seconds` query parameter of a profile request, 10 by default.
+    #[cfg(feature = "profiling")]
+    fn profile_duration(path: &str) -> Duration {
+        let seconds = query_param(path, "seconds")
+            .and_then(|seconds| seconds.parse().ok())
+            .unwrap_or(10);
+        Duration::from_secs(seconds).min(MAX_PROFILE_DURATION)
+    }
+}
+
+impl Drop for AdminServer {
+    fn drop(&mut self) {
+        self.stop = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Admin thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_report() {
+        let config = Config::default();
+        let publisher = Publisher::new_json(Box::new(io::sink()), None, &config);
+        let dispatcher = Dispatcher::new(publisher, &config);
+        let health = Arc::new(Health::default());
+        let sources = AdminSources {
+            queues: dispatcher.queue_gauges(),
+            publisher: dispatcher.publisher().clone(),
+            tenants: Vec::new(),
+            context: Some(DeliveryContext::default()),
+            health: Some(health.clone()),
+        };
+        sources
+            .context
+            .as_ref()
+            .unwrap()
+            .buffered_bytes()
+            .add("accounts", 42);
+        let server = AdminServer::spawn("127.0.0.1:0", sources).unwrap();
+
+        let url = format!("http://{}", server.address());
+        let response = ureq::get(&format!("{}/queues", url)).call().unwrap();
+        let report: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(report["queues"][0]["name"], "publisher-0");
+        assert_eq!(report["queues"][0]["len"], 0);
+        assert_eq!(
+            report["queues"][0]["capacity"],
+            config.queue_capacity / config.serialization_workers
+        );
+        assert_eq!(report["producers"], serde_json::json!([]));
+        assert_eq!(report["buffered_bytes"]["accounts"], 42);
+
+        let response = ureq::get(&format!("{}/metrics", url)).call().unwrap();
+        let metrics: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert!(metrics["records_spooled"].is_u64());
+
+        let response = ureq::get(&format!("{}/memory", url)).call().unwrap();
+        let report: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert!(report["components"]["queued_events"].is_u64());
+
+        let response = ureq::get(&format!("{}/health", url)).call().unwrap();
+        assert_eq!(response.status(), 200);
+        health.update(vec![FiringAlert {
+            alert: "queue_depth",
+            value: 10.0,
+            threshold: 5.0,
+        }]);
+        match ureq::get(&format!("{}/health", url)).call() {
+            Err(ureq::Error::Status(503, response)) => {
+                let report: serde_json::Value =
+                    serde_json::from_str(&response.into_string().unwrap()).unwrap();
+                assert_eq!(report["healthy"], false);
+                assert_eq!(report["alerts"][0]["alert"], "queue_depth");
+            }
+            other => panic!("unexpected response {:?}", other.map(|r| r.status())),
+        }
+
+        match ureq::get(&format!("{}/missing", url)).call() {
+            Err(ureq::Error::Status(404, _)) => {}
+            other => panic!("unexpected response {:?}", other.map(|r| r.status())),
+        }
+
+        let switch =
+            |query: &str| match ureq::post(&format!("{}/topics/switch{}", url, query)).call() {
+                Ok(response) => response.status(),
+                Err(ureq::Error::Status(status, _)) => status,
+                Err(e) => panic!("switch failed: {}", e),
+            };
+        assert_eq!(switch("?from=accounts"), 400);
+        assert_eq!(switch("?from=missing&to=accounts.v2"), 409);
+        assert_eq!(switch("?from=accounts&to=accounts.v2&tenant=acme"), 404);
+    }
+
+    #[cfg(feature = "profiling")]
+    #[test]
+    fn test_profile_in_background() {
+        let config = Config::default();
+        let publisher = Publisher::new_json(Box::new(io::sink()), None, &config);
+        let dispatcher = Dispatcher::new(publisher, &config);
+        let sources = AdminSources {
+            queues: dispatcher.queue_gauges(),
+            publisher: dispatcher.publisher().clone(),
+            tenants: Vec::new(),
+            context: None,
+            health: None,
+        };
+        let server = AdminServer::spawn("127.0.0.1:0", sources).unwrap();
+        let url = format!("http://{}", server.address());
+
+        let profile = {
+            let url = url.clone();
+            thread::spawn(move || {
+                ureq::get(&format!("{}/debug/pprof/profile?seconds=2", url))
+                    .call()
+                    .map_or(0, |response| response.status())
+            })
+        };
+        thread::sleep(Duration::from_millis(500));
+        // Health checks are answered while the profile is taken, another profile is not.
+        let started = std::time::Instant::now();
+        let response = ureq::get(&format!("{}/health", url)).call().unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        match ureq::get(&format!("{}/debug/pprof/profile?seconds=1", url)).call() {
+            Err(ureq::Error::Status(409, _)) => {}
+            other => panic!("unexpected response {:?}", other.map(|r| r.status())),
+        }
+        assert_eq!(profile.join().unwrap(), 200);
+    }
+}
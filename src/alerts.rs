@@ -0,0 +1,260 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender},
+    log::{error, info},
+    serde::{Deserialize, Serialize},
+    std::{
+        sync::{Arc, RwLock},
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    },
+};
+
+/// Log target of alert events, so they can be routed apart from the other logs.
+pub const ALERT_LOG_TARGET: &str = "kafka_plugin_alert";
+
+/// Limits that, when exceeded, fire an alert and make the plugin unhealthy. Zero disables a limit.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct AlertThresholds {
+    /// Slots the publisher threads may fall behind the newest event dispatched.
+    #[serde(default)]
+    pub max_slot_lag: u64,
+    /// Events any one publisher or dead-letter queue may hold.
+    #[serde(default)]
+    pub max_queue_depth: usize,
+    /// Messages per second that may be dropped for reasons other than a filter.
+    #[serde(default)]
+    pub max_drop_rate: f64,
+    /// How often to check the limits.
+    #[serde(default = "AlertThresholds::default_evaluation_interval_sec")]
+    pub evaluation_interval_sec: u64,
+}
+
+impl AlertThresholds {
+    fn default_evaluation_interval_sec() -> u64 {
+        10
+    }
+
+    /// Limits exceeded by `sample`.
+    pub fn breaches(&self, sample: &AlertSample) -> Vec<FiringAlert> {
+        let mut firing = Vec::new();
+        let mut check = |alert: &'static str, value: f64, threshold: f64| {
+            if threshold > 0.0 && value > threshold {
+                firing.push(FiringAlert {
+                    alert,
+                    value,
+                    threshold,
+                });
+            }
+        };
+        check("slot_lag", sample.slot_lag as f64, self.max_slot_lag as f64);
+        check(
+            "queue_depth",
+            sample.queue_depth as f64,
+            self.max_queue_depth as f64,
+        );
+        check("drop_rate", sample.drop_rate, self.max_drop_rate);
+        firing
+    }
+}
+
+/// Values the thresholds are checked against.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AlertSample {
+    pub slot_lag: u64,
+    /// Length of the fullest queue.
+    pub queue_depth: usize,
+    /// Unfiltered drops per second since the last sample.
+    pub drop_rate: f64,
+}
+
+/// An exceeded threshold.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FiringAlert {
+    pub alert: &'static str,
+    pub value: f64,
+    pub threshold: f64,
+}
+
+/// Alerts currently firing, as served on `/health`.
+#[derive(Default)]
+pub struct Health {
+    firing: RwLock<Vec<FiringAlert>>,
+}
+
+impl Health {
+    pub fn is_healthy(&self) -> bool {
+        self.firing.read().unwrap().is_empty()
+    }
+
+    pub fn firing(&self) -> Vec<FiringAlert> {
+        self.firing.read().unwrap().clone()
+    }
+
+    /// Replaces the firing alerts, logging those that started or stopped firing.
+    pub fn update(&self, firing: Vec<FiringAlert>) {
+        let mut current = self.firing.write().unwrap();
+        for alert in &firing {
+            if !current.iter().any(|old| old.alert == alert.alert) {
+                metrics().alerts_fired.inc();
+                error!(
+                    target: ALERT_LOG_TARGET,
+                    "ALERT {}",
+                    serde_json::json!({
+                        "alert": alert.alert,
+                        "state": "firing",
+                        "value": alert.value,
+                        "threshold": alert.threshold,
+                    })
+                );
+            }
+        }
+        for old in current.iter() {
+            if !firing.iter().any(|alert| alert.alert == old.alert) {
+                info!(
+                    target: ALERT_LOG_TARGET,
+                    "ALERT {}",
+                    serde_json::json!({
+                        "alert": old.alert,
+                        "state": "resolved",
+                        "threshold": old.threshold,
+                    })
+                );
+            }
+        }
+        *current = firing;
+    }
+
+    /// Body of a `/health` response.
+    pub fn report(&self) -> serde_json::Value {
+        let firing = self.firing();
+        serde_json::json!({ "healthy": firing.is_empty(), "alerts": firing })
+    }
+}
+
+/// Checks the alert thresholds in the background.
+pub struct AlertMonitor {
+    health: Arc<Health>,
+    /// Stops the thread when dropped.
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl AlertMonitor {
+    pub fn spawn(thresholds: AlertThresholds, queues: QueueGauges) -> Self {
+        let health = Arc::new(Health::default());
+        let (stop, stopped) = bounded(0);
+        let thread = {
+            let health = health.clone();
+            thread::Builder::new()
+                .name("kafka-alerts".to_owned())
+                .spawn(move || Self::run(thresholds, queues, health, stopped))
+                .expect("failed to spawn alerts thread")
+        };
+        Self {
+            health,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    pub fn health(&self) -> &Arc<Health> {
+        &self.health
+    }
+
+    fn run(
+        thresholds: AlertThresholds,
+        queues: QueueGauges,
+        health: Arc<Health>,
+        stopped: Receiver<()>,
+    ) {
+        let interval = Duration::from_secs(thresholds.evaluation_interval_sec.max(1));
+        let mut dropped = drops().unfiltered_total();
+        let mut sampled = Instant::now();
+        while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+            let now_dropped = drops().unfiltered_total();
+            let elapsed = sampled.elapsed().as_secs_f64();
+            let sample = AlertSample {
+                slot_lag: queues.slot_lag(),
+                queue_depth: queues
+                    .lengths()
+                    .iter()
+                    .map(|queue| queue.len)
+                    .max()
+                    .unwrap_or_default(),
+                drop_rate: (now_dropped - dropped) as f64 / elapsed,
+            };
+            dropped = now_dropped;
+            sampled = Instant::now();
+            health.update(thresholds.breaches(&sample));
+        }
+    }
+}
+
+impl Drop for AlertMonitor {
+    fn drop(&mut self) {
+        self.stop = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Alerts thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alert_thresholds() {
+        let thresholds = AlertThresholds {
+            max_slot_lag: 10,
+            max_queue_depth: 0,
+            max_drop_rate: 5.0,
+            evaluation_interval_sec: 1,
+        };
+        let sample = AlertSample {
+            slot_lag: 11,
+            queue_depth: 1_000_000,
+            drop_rate: 5.0,
+        };
+        let health = Health::default();
+        let firing = thresholds.breaches(&sample);
+        assert_eq!(
+            firing,
+            vec![FiringAlert {
+                alert: "slot_lag",
+                value: 11.0,
+                threshold: 10.0,
+            }]
+        );
+        let fired = metrics().alerts_fired.get();
+        health.update(firing.clone());
+        health.update(firing);
+        assert!(!health.is_healthy());
+        assert_eq!(metrics().alerts_fired.get(), fired + 1);
+        assert_eq!(health.report()["alerts"][0]["alert"], "slot_lag");
+
+        health.update(thresholds.breaches(&AlertSample::default()));
+        assert!(health.is_healthy());
+        assert_eq!(
+            health.report(),
+            serde_json::json!({ "healthy": true, "alerts": [] })
+        );
+    }
+}
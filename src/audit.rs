@@ -0,0 +1,153 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    chrono::{SecondsFormat, Utc},
+    log::error,
+    solana_program::pubkey::Pubkey,
+    std::{
+        fs::{File, OpenOptions},
+        io::{self, Write},
+        sync::{Arc, Mutex},
+    },
+};
+
+/// Appends every effective change of the filter's key sets to a local file, one JSON object per line:
+/// when it happened, what caused it (`source`), which set changed (`filter`) and the `added` and
+/// `removed` keys, plus the `tenant` for the filters of tenants.
+pub struct FilterAuditLog {
+    file: Arc<Mutex<File>>,
+    tenant: Option<String>,
+}
+
+impl FilterAuditLog {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+            tenant: None,
+        })
+    }
+
+    /// Appends to the same file, with the entries tagged with `tenant`.
+    pub fn for_tenant(&self, tenant: &str) -> Self {
+        Self {
+            file: self.file.clone(),
+            tenant: Some(tenant.to_owned()),
+        }
+    }
+
+    /// Records the keys of `filter` the plugin starts with, even if there are none.
+    pub fn record_load(&self, filter: &str, keys: &[[u8; 32]]) {
+        self.append("load", filter, keys, &[]);
+    }
+
+    /// Records a change of `filter` by `source`, unless nothing was added or removed.
+    pub fn record_change(
+        &self,
+        source: &str,
+        filter: &str,
+        added: &[[u8; 32]],
+        removed: &[[u8; 32]],
+    ) {
+        if !added.is_empty() || !removed.is_empty() {
+            self.append(source, filter, added, removed);
+        }
+    }
+
+    fn append(&self, source: &str, filter: &str, added: &[[u8; 32]], removed: &[[u8; 32]]) {
+        let mut entry = Self::entry(source, filter, added, removed);
+        if let Some(tenant) = &self.tenant {
+            entry["tenant"] = tenant.as_str().into();
+        }
+        let mut file = self.file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", entry).and_then(|()| file.sync_data()) {
+            error!("Failed to append to the filter audit log: {}", e);
+        }
+    }
+
+    fn entry(
+        source: &str,
+        filter: &str,
+        added: &[[u8; 32]],
+        removed: &[[u8; 32]],
+    ) -> serde_json::Value {
+        let names = |keys: &[[u8; 32]]| {
+            let mut names: Vec<String> = keys
+                .iter()
+                .map(|key| Pubkey::new_from_array(*key).to_string())
+                .collect();
+            names.sort();
+            names
+        };
+        serde_json::json!({
+            "time": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            "source": source,
+            "filter": filter,
+            "added": names(added),
+            "removed": names(removed),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::fs};
+
+    #[test]
+    fn test_filter_audit_log() {
+        let path = std::env::temp_dir().join(format!("kafka-plugin-audit-{}", std::process::id()));
+        let audit = FilterAuditLog::open(path.to_str().unwrap()).unwrap();
+        audit.record_load("program_allowlist", &[]);
+        audit.record_change("http_refresh", "program_allowlist", &[], &[]);
+        audit.record_change(
+            "http_refresh",
+            "program_allowlist",
+            &[[2; 32], [1; 32]],
+            &[[3; 32]],
+        );
+        audit
+            .for_tenant("acme")
+            .record_load("program_ignores", &[[4; 32]]);
+        drop(audit);
+
+        let lines: Vec<serde_json::Value> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["source"], "load");
+        assert_eq!(lines[0]["added"], serde_json::json!([]));
+        assert_eq!(lines[1]["source"], "http_refresh");
+        assert_eq!(lines[1]["filter"], "program_allowlist");
+        assert_eq!(
+            lines[1]["added"],
+            serde_json::json!([
+                Pubkey::new_from_array([1; 32]).to_string(),
+                Pubkey::new_from_array([2; 32]).to_string(),
+            ])
+        );
+        assert_eq!(
+            lines[1]["removed"],
+            serde_json::json!([Pubkey::new_from_array([3; 32]).to_string()])
+        );
+        assert!(lines[1]["time"].as_str().unwrap().ends_with('Z'));
+        assert_eq!(lines[1].get("tenant"), None);
+        assert_eq!(lines[2]["tenant"], "acme");
+        assert_eq!(lines[2]["filter"], "program_ignores");
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,122 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::*;
+
+/// Remembers the last published amounts of the most recently updated token accounts, to derive
+/// [`TokenBalanceChange`]s from their updates.
+pub struct TokenBalances {
+    amounts: RecentAccounts<u64>,
+}
+
+impl TokenBalances {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            amounts: RecentAccounts::new(capacity, MemoryComponent::TokenBalances),
+        }
+    }
+
+    /// Records the amount of a token account update, returning the change if it has one.
+    ///
+    /// Startup accounts only record their amount. Accounts not cached yet change from an unknown amount.
+    pub fn observe(
+        &mut self,
+        slot: u64,
+        info: &AccountInfo,
+        is_startup: bool,
+    ) -> Option<TokenBalanceChange> {
+        let token_2022 = token_program(info.owner)?;
+        let account = match decode_token(token_2022, info.data)? {
+            decoded_account::Account::TokenAccount(account) => account,
+            _ => return None,
+        };
+        let key: [u8; 32] = info.pubkey.try_into().ok()?;
+        let previous = self.amounts.insert(key, account.amount);
+        if is_startup || previous == Some(account.amount) {
+            return None;
+        }
+        Some(TokenBalanceChange {
+            slot,
+            account: key.to_vec(),
+            owner: account.owner,
+            mint: account.mint,
+            token_2022,
+            previous_amount: previous,
+            amount: account.amount,
+            delta: previous.map(|previous| {
+                (i128::from(account.amount) - i128::from(previous))
+                    .clamp(i64::MIN.into(), i64::MAX.into()) as i64
+            }),
+            write_version: info.write_version,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.amounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.amounts.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, solana_program::pubkey::Pubkey, std::str::FromStr};
+
+    fn token_account(amount: u64) -> Vec<u8> {
+        let mut data = vec![0; 165];
+        data[0..32].copy_from_slice(&[1; 32]);
+        data[32..64].copy_from_slice(&[2; 32]);
+        data[64..72].copy_from_slice(&amount.to_le_bytes());
+        // Initialized.
+        data[108] = 1;
+        data
+    }
+
+    #[test]
+    fn test_token_balances() {
+        let token_program =
+            Pubkey::from_str("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA").unwrap();
+        let mut balances = TokenBalances::new(1);
+        let observe = |balances: &mut TokenBalances, pubkey: &[u8], amount, is_startup| {
+            let data = token_account(amount);
+            let info = AccountInfo {
+                pubkey,
+                lamports: 1,
+                owner: token_program.as_ref(),
+                executable: false,
+                rent_epoch: 0,
+                data: &data,
+                write_version: 0,
+            };
+            balances.observe(3, &info, is_startup)
+        };
+
+        assert_eq!(observe(&mut balances, &[7; 32], 100, true), None);
+        let change = observe(&mut balances, &[7; 32], 40, false).unwrap();
+        assert_eq!(change.owner, vec![2; 32]);
+        assert_eq!(change.mint, vec![1; 32]);
+        assert_eq!(change.previous_amount, Some(100));
+        assert_eq!(change.amount, 40);
+        assert_eq!(change.delta, Some(-60));
+        assert_eq!(observe(&mut balances, &[7; 32], 40, false), None);
+
+        // Evicted accounts change from an unknown amount.
+        observe(&mut balances, &[8; 32], 1, false).unwrap();
+        assert_eq!(balances.len(), 1);
+        let change = observe(&mut balances, &[7; 32], 40, false).unwrap();
+        assert_eq!((change.previous_amount, change.delta), (None, None));
+    }
+}
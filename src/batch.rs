@@ -0,0 +1,198 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    prost::bytes::Bytes,
+    std::collections::{BTreeMap, HashMap},
+};
+
+#[derive(Default)]
+struct SlotBuffer {
+    accounts: Vec<UpdateAccountEvent>,
+    /// Position of each account in `accounts`, only maintained when coalescing.
+    positions: HashMap<Bytes, usize>,
+}
+
+/// Accumulates account updates per slot until the slot status advances.
+///
+/// When coalescing, only the update with the highest `write_version`
+/// is kept for every account within a slot.
+pub struct SlotBatcher {
+    pending: BTreeMap<u64, SlotBuffer>,
+    coalesce: bool,
+    /// Of the updates in `pending`.
+    memory: MemoryUsage,
+}
+
+impl Default for SlotBatcher {
+    fn default() -> Self {
+        Self {
+            pending: BTreeMap::new(),
+            coalesce: false,
+            memory: MemoryUsage::new(MemoryComponent::SlotBuffers),
+        }
+    }
+}
+
+impl SlotBatcher {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn new_coalescing() -> Self {
+        Self {
+            coalesce: true,
+            ..Default::default()
+        }
+    }
+
+    /// Buffers `ev`, returning `false` if it was coalesced with an update of the same account.
+    pub fn push(&mut self, ev: UpdateAccountEvent) -> bool {
+        let buffer = self.pending.entry(ev.slot).or_default();
+        self.memory.add(account_update_bytes(&ev));
+        if !self.coalesce {
+            buffer.accounts.push(ev);
+            return true;
+        }
+        match buffer.positions.get(&ev.pubkey) {
+            Some(&i) => {
+                metrics().coalesced_account_updates.inc();
+                let existing = &mut buffer.accounts[i];
+                if ev.write_version > existing.write_version {
+                    self.memory.sub(account_update_bytes(existing));
+                    *existing = ev;
+                } else {
+                    self.memory.sub(account_update_bytes(&ev));
+                }
+                false
+            }
+            None => {
+                buffer
+                    .positions
+                    .insert(ev.pubkey.clone(), buffer.accounts.len());
+                buffer.accounts.push(ev);
+                true
+            }
+        }
+    }
+
+    /// Takes the batch for a single slot, if any updates were buffered for it.
+    pub fn take(&mut self, slot: u64) -> Option<SlotAccountsBatch> {
+        let buffer = self.pending.remove(&slot)?;
+        self.release(&buffer);
+        Some(SlotAccountsBatch {
+            slot,
+            accounts: buffer.accounts,
+        })
+    }
+
+    /// Takes the batches of all slots up to and including `slot`.
+    ///
+    /// Used when a slot is rooted, so slots on abandoned forks don't pile up.
+    pub fn take_until(&mut self, slot: u64) -> Vec<SlotAccountsBatch> {
+        if slot == u64::MAX {
+            return self.take_all();
+        }
+        let newer = self.pending.split_off(&(slot + 1));
+        let older = std::mem::replace(&mut self.pending, newer);
+        self.take_batches(older)
+    }
+
+    /// Takes every buffered batch.
+    pub fn take_all(&mut self) -> Vec<SlotAccountsBatch> {
+        let pending = std::mem::take(&mut self.pending);
+        self.take_batches(pending)
+    }
+
+    fn take_batches(&mut self, pending: BTreeMap<u64, SlotBuffer>) -> Vec<SlotAccountsBatch> {
+        pending
+            .into_iter()
+            .map(|(slot, buffer)| {
+                self.release(&buffer);
+                SlotAccountsBatch {
+                    slot,
+                    accounts: buffer.accounts,
+                }
+            })
+            .collect()
+    }
+
+    fn release(&mut self, buffer: &SlotBuffer) {
+        for ev in &buffer.accounts {
+            self.memory.sub(account_update_bytes(ev));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slot_batcher() {
+        let mut batcher = SlotBatcher::new();
+        for (slot, write_version) in [(1, 1), (2, 2), (1, 3), (3, 4)] {
+            batcher.push(UpdateAccountEvent {
+                slot,
+                write_version,
+                ..UpdateAccountEvent::default()
+            });
+        }
+
+        let batch = batcher.take(1).unwrap();
+        assert_eq!(batch.slot, 1);
+        assert_eq!(
+            batch
+                .accounts
+                .iter()
+                .map(|ev| ev.write_version)
+                .collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+        assert!(batcher.take(1).is_none());
+
+        let batches = batcher.take_until(2);
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].slot, 2);
+
+        let batches = batcher.take_all();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].slot, 3);
+        assert!(batcher.take_all().is_empty());
+    }
+
+    #[test]
+    fn test_slot_batcher_coalescing() {
+        let mut batcher = SlotBatcher::new_coalescing();
+        for (pubkey, write_version) in [(1u8, 5), (2, 1), (1, 7), (1, 6)] {
+            batcher.push(UpdateAccountEvent {
+                slot: 9,
+                pubkey: vec![pubkey; 32].into(),
+                write_version,
+                ..UpdateAccountEvent::default()
+            });
+        }
+
+        let batch = batcher.take(9).unwrap();
+        assert_eq!(
+            batch
+                .accounts
+                .iter()
+                .map(|ev| (ev.pubkey[0], ev.write_version))
+                .collect::<Vec<_>>(),
+            vec![(1, 7), (2, 1)]
+        );
+    }
+}
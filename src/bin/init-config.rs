@@ -0,0 +1,370 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Asks a few questions and writes a plugin config file.
+
+use {
+    serde_json::{json, Map, Value},
+    solana_accountsdb_plugin_kafka::*,
+    solana_program::pubkey::Pubkey,
+    std::{
+        fs::{self, OpenOptions},
+        io::{self, BufRead, Write},
+        path::Path,
+        process::exit,
+        str::FromStr,
+    },
+};
+
+const USAGE: &str = "Usage: init-config <output path>
+
+Asks for the brokers, authentication, topics and programs to follow,
+then writes a plugin config file to <output path>.";
+
+/// Well-known programs that can be followed by name.
+const PROGRAM_PRESETS: &[(&str, &str)] = &[
+    ("token", "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"),
+    ("token-2022", "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb"),
+    (
+        "associated-token",
+        "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL",
+    ),
+    ("metaplex", "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s"),
+    ("stake", "Stake11111111111111111111111111111111111111"),
+    ("openbook", "srmqPvymJeFKQ4zGQed1GFppgkRHL9kaELCbyksJtPX"),
+];
+
+const AUTH_TYPES: &[&str] = &[
+    "none",
+    "plain",
+    "scram",
+    "confluent-cloud",
+    "kerberos",
+    "mtls",
+];
+
+/// Reads answers from `input`, writing the questions to `output`.
+struct Prompter<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Prompter<R, W> {
+    fn ask(&mut self, question: &str, default: &str) -> io::Result<String> {
+        if default.is_empty() {
+            write!(self.output, "{}: ", question)?;
+        } else {
+            write!(self.output, "{} [{}]: ", question, default)?;
+        }
+        self.output.flush()?;
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "no more answers",
+            ));
+        }
+        let answer = line.trim();
+        Ok(if answer.is_empty() {
+            default.to_owned()
+        } else {
+            answer.to_owned()
+        })
+    }
+
+    /// Asks until the answer is non-empty.
+    fn ask_required(&mut self, question: &str) -> io::Result<String> {
+        loop {
+            let answer = self.ask(question, "")?;
+            if !answer.is_empty() {
+                return Ok(answer);
+            }
+        }
+    }
+
+    /// Asks until the answer is one of `options`.
+    fn choose(&mut self, question: &str, options: &[&str], default: &str) -> io::Result<String> {
+        let question = format!("{} ({})", question, options.join(", "));
+        loop {
+            let answer = self.ask(&question, default)?;
+            if options.contains(&answer.as_str()) {
+                return Ok(answer);
+            }
+        }
+    }
+
+    fn confirm(&mut self, question: &str, default: bool) -> io::Result<bool> {
+        loop {
+            match self
+                .ask(question, if default { "y" } else { "n" })?
+                .as_str()
+            {
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => {}
+            }
+        }
+    }
+
+    /// Asks for program names or ids until all of them are valid.
+    fn programs(&mut self) -> io::Result<Vec<String>> {
+        let names: Vec<&str> = PROGRAM_PRESETS.iter().map(|(name, _)| *name).collect();
+        writeln!(self.output, "Known programs: {}", names.join(", "))?;
+        'ask: loop {
+            let answer = self.ask(
+                "Programs to follow, comma separated names or ids, empty for all",
+                "",
+            )?;
+            let mut programs = Vec::new();
+            for program in answer.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+                let id = match PROGRAM_PRESETS.iter().find(|(name, _)| *name == program) {
+                    Some((_, id)) => id.to_string(),
+                    None if Pubkey::from_str(program).is_ok() => program.to_owned(),
+                    None => {
+                        writeln!(self.output, "Unknown program: {}", program)?;
+                        continue 'ask;
+                    }
+                };
+                if !programs.contains(&id) {
+                    programs.push(id);
+                }
+            }
+            return Ok(programs);
+        }
+    }
+}
+
+/// Builds the config from the answers to the questions.
+fn build(prompt: &mut Prompter<impl BufRead, impl Write>) -> io::Result<Value> {
+    let libpath = prompt.ask("Path of the plugin library", &default_libpath())?;
+    let brokers = prompt.ask("Kafka bootstrap servers", "localhost:9092")?;
+
+    let mut config = Map::new();
+    config.insert("libpath".to_owned(), json!(libpath));
+    let mut kafka = Map::new();
+    kafka.insert("bootstrap.servers".to_owned(), json!(brokers));
+    kafka.insert("request.required.acks".to_owned(), json!("1"));
+    kafka.insert("message.timeout.ms".to_owned(), json!("30000"));
+    kafka.insert("compression.type".to_owned(), json!("lz4"));
+
+    match prompt
+        .choose("Authentication", AUTH_TYPES, "none")?
+        .as_str()
+    {
+        auth @ ("plain" | "scram") => {
+            let mechanism = if auth == "plain" {
+                "PLAIN"
+            } else {
+                "SCRAM-SHA-512"
+            };
+            kafka.insert("security.protocol".to_owned(), json!("SASL_SSL"));
+            kafka.insert("sasl.mechanism".to_owned(), json!(mechanism));
+            kafka.insert(
+                "sasl.username".to_owned(),
+                json!(prompt.ask_required("SASL username")?),
+            );
+            kafka.insert(
+                "sasl.password".to_owned(),
+                json!(prompt.ask_required("SASL password")?),
+            );
+        }
+        "confluent-cloud" => {
+            // The preset picks its own delivery settings.
+            config.insert("preset".to_owned(), json!("confluent-cloud"));
+            kafka.remove("request.required.acks");
+            kafka.remove("message.timeout.ms");
+            kafka.insert(
+                "sasl.username".to_owned(),
+                json!(prompt.ask_required("API key")?),
+            );
+            kafka.insert(
+                "sasl.password".to_owned(),
+                json!(prompt.ask_required("API secret")?),
+            );
+        }
+        "kerberos" => {
+            let principal = prompt.ask_required("Kerberos principal")?;
+            let keytab = prompt.ask_required("Keytab path")?;
+            config.insert(
+                "kerberos".to_owned(),
+                json!({ "principal": principal, "keytab": keytab }),
+            );
+        }
+        "mtls" => {
+            kafka.insert("security.protocol".to_owned(), json!("SSL"));
+            kafka.insert(
+                "ssl.certificate.location".to_owned(),
+                json!(prompt.ask_required("Client certificate path")?),
+            );
+            kafka.insert(
+                "ssl.key.location".to_owned(),
+                json!(prompt.ask_required("Client key path")?),
+            );
+            let ca = prompt.ask("CA certificate path, empty for the system roots", "")?;
+            if !ca.is_empty() {
+                kafka.insert("ssl.ca.location".to_owned(), json!(ca));
+            }
+        }
+        _ => {}
+    }
+    config.insert("kafka".to_owned(), Value::Object(kafka));
+    config.insert("shutdown_timeout_ms".to_owned(), json!(30000));
+
+    let prefix = prompt.ask("Topic prefix", "solana.mainnet")?;
+    let topics = [
+        (
+            "update_account_topic",
+            "account updates",
+            "account_updates",
+            true,
+        ),
+        (
+            "slot_status_topic",
+            "slot status updates",
+            "slot_status",
+            true,
+        ),
+        ("transaction_topic", "transactions", "transactions", false),
+        (
+            "block_metadata_topic",
+            "block metadata",
+            "block_metadata",
+            false,
+        ),
+    ];
+    let mut transactions = false;
+    for (option, description, suffix, default) in topics {
+        if prompt.confirm(&format!("Publish {}?", description), default)? {
+            let topic = format!("{}.{}", prefix, suffix);
+            config.insert(option.to_owned(), json!(topic));
+            transactions |= option == "transaction_topic";
+        }
+    }
+
+    config.insert(
+        "program_ignores".to_owned(),
+        json!([
+            "Sysvar1111111111111111111111111111111111111",
+            "Vote111111111111111111111111111111111111111"
+        ]),
+    );
+    let programs = prompt.programs()?;
+    if !programs.is_empty() {
+        if transactions
+            && prompt.confirm("Only publish transactions invoking these programs?", true)?
+        {
+            config.insert("filter_transactions_by_program".to_owned(), json!(true));
+        }
+        config.insert("program_allowlist".to_owned(), json!(programs));
+    }
+    Ok(Value::Object(config))
+}
+
+/// The plugin library next to this binary, where `cargo build` puts both.
+fn default_libpath() -> String {
+    let name = format!(
+        "{}solana_accountsdb_plugin_kafka{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    );
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(&name)))
+        .map_or(name, |path| path.display().to_string())
+}
+
+/// Writes `config` to a new file at `path`, readable only by its owner since it may hold credentials.
+fn write(path: &Path, config: &Value) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    serde_json::to_writer_pretty(&mut file, config)?;
+    writeln!(file)
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let path = match args {
+        [path] if !path.starts_with('-') => Path::new(path),
+        _ => return Err(USAGE.to_owned()),
+    };
+    if path.exists() {
+        return Err(format!("{} already exists", path.display()));
+    }
+    let stdin = io::stdin();
+    let mut prompt = Prompter {
+        input: stdin.lock(),
+        output: io::stdout(),
+    };
+    let config = build(&mut prompt).map_err(|e| format!("Failed to read answers: {}", e))?;
+    write(path, &config).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    if let Err(e) = Config::read_from(path) {
+        let _ = fs::remove_file(path);
+        return Err(format!("Generated config is invalid: {}", e));
+    }
+    println!("Wrote {}", path.display());
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        exit(2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let answers = "/opt/plugin.so\nbroker:9092\nfoo\nscram\nuser\nsecret\nsolana.devnet\n\nn\ny\n\nbogus\ntoken, metaplex, token\nn\n";
+        let mut output = Vec::new();
+        let mut prompt = Prompter {
+            input: answers.as_bytes(),
+            output: &mut output,
+        };
+        let config = build(&mut prompt).unwrap();
+
+        assert_eq!(config["libpath"], "/opt/plugin.so");
+        assert_eq!(config["kafka"]["bootstrap.servers"], "broker:9092");
+        assert_eq!(config["kafka"]["sasl.mechanism"], "SCRAM-SHA-512");
+        assert_eq!(config["kafka"]["sasl.password"], "secret");
+        assert_eq!(
+            config["update_account_topic"],
+            "solana.devnet.account_updates"
+        );
+        assert!(config.get("slot_status_topic").is_none());
+        assert_eq!(config["transaction_topic"], "solana.devnet.transactions");
+        assert!(config.get("block_metadata_topic").is_none());
+        assert_eq!(
+            config["program_allowlist"],
+            json!([
+                "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+                "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s"
+            ])
+        );
+        assert!(config.get("filter_transactions_by_program").is_none());
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("Unknown program: bogus"));
+
+        for (_, id) in PROGRAM_PRESETS {
+            Pubkey::from_str(id).unwrap();
+        }
+    }
+}
@@ -0,0 +1,473 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Subscribes to the plugin's topics and prints the decoded events.
+//!
+//! Topics and connection settings are taken from the plugin's config file.
+
+use {
+    prost::Message as _,
+    rdkafka::{
+        consumer::{BaseConsumer, Consumer},
+        message::{BorrowedMessage, Headers},
+        Message,
+    },
+    solana_accountsdb_plugin_kafka::*,
+    solana_program::pubkey::Pubkey,
+    std::{collections::HashMap, process::exit, str::FromStr, time::Duration},
+};
+
+const USAGE: &str = "Usage: kafka-consume --config <plugin config> [options]
+
+Options:
+    --type <type>             Only print events of this type, may be repeated:
+                              account, slot_status, transaction, transaction_status,
+                              compressed_nft, program_upgraded, token_balance,
+                              owner_changed, block_metadata, slot_accounts_complete, watermark,
+                              stats_snapshot
+    --pubkey <pubkey>         Only print events touching this account, may be repeated
+    --from-slot <slot>        Only print events of this slot or later
+    --to-slot <slot>          Only print events of this slot or earlier
+    --from-beginning          Start from the earliest retained offsets instead of the latest
+    --group-id <id>           Consumer group, defaults to a throwaway group
+    --decryption-key <base64> Key of `static` payload encryption
+    -X <key>=<value>          Override a librdkafka option";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Account,
+    SlotStatus,
+    Transaction,
+    TransactionStatus,
+    CompressedNft,
+    ProgramUpgraded,
+    TokenBalance,
+    OwnerChanged,
+    BlockMetadata,
+    SlotAccountsComplete,
+    Watermark,
+    StatsSnapshot,
+}
+
+impl FromStr for Kind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "account" => Ok(Self::Account),
+            "slot_status" => Ok(Self::SlotStatus),
+            "transaction" => Ok(Self::Transaction),
+            "transaction_status" => Ok(Self::TransactionStatus),
+            "compressed_nft" => Ok(Self::CompressedNft),
+            "program_upgraded" => Ok(Self::ProgramUpgraded),
+            "token_balance" => Ok(Self::TokenBalance),
+            "owner_changed" => Ok(Self::OwnerChanged),
+            "block_metadata" => Ok(Self::BlockMetadata),
+            "slot_accounts_complete" => Ok(Self::SlotAccountsComplete),
+            "watermark" => Ok(Self::Watermark),
+            "stats_snapshot" => Ok(Self::StatsSnapshot),
+            _ => Err(format!("unknown event type: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Decoded {
+    Account(Box<UpdateAccountEvent>),
+    AccountsBatch(SlotAccountsBatch),
+    SlotStatus(SlotStatusEvent),
+    Transaction(Box<TransactionEvent>),
+    TransactionStatus(TransactionStatusEvent),
+    CompressedNft(CompressedNftEvent),
+    ProgramUpgraded(ProgramUpgraded),
+    TokenBalance(TokenBalanceChange),
+    OwnerChanged(OwnerChanged),
+    BlockMetadata(BlockMetadataEvent),
+    SlotAccountsComplete(SlotAccountsComplete),
+    Watermark(Watermark),
+    StatsSnapshot(StatsSnapshot),
+}
+
+impl Decoded {
+    /// Account updates are keyed by pubkey, slot batches on the same topic are not.
+    fn decode(kind: Kind, keyed: bool, payload: &[u8]) -> Result<Self, prost::DecodeError> {
+        Ok(match kind {
+            Kind::Account if keyed => Self::Account(Box::new(UpdateAccountEvent::decode(payload)?)),
+            Kind::Account => Self::AccountsBatch(SlotAccountsBatch::decode(payload)?),
+            Kind::SlotStatus => Self::SlotStatus(SlotStatusEvent::decode(payload)?),
+            Kind::Transaction => Self::Transaction(Box::new(TransactionEvent::decode(payload)?)),
+            Kind::TransactionStatus => {
+                Self::TransactionStatus(TransactionStatusEvent::decode(payload)?)
+            }
+            Kind::CompressedNft => Self::CompressedNft(CompressedNftEvent::decode(payload)?),
+            Kind::ProgramUpgraded => Self::ProgramUpgraded(ProgramUpgraded::decode(payload)?),
+            Kind::TokenBalance => Self::TokenBalance(TokenBalanceChange::decode(payload)?),
+            Kind::OwnerChanged => Self::OwnerChanged(OwnerChanged::decode(payload)?),
+            Kind::BlockMetadata => Self::BlockMetadata(BlockMetadataEvent::decode(payload)?),
+            Kind::SlotAccountsComplete => {
+                Self::SlotAccountsComplete(SlotAccountsComplete::decode(payload)?)
+            }
+            Kind::Watermark => Self::Watermark(Watermark::decode(payload)?),
+            Kind::StatsSnapshot => Self::StatsSnapshot(StatsSnapshot::decode(payload)?),
+        })
+    }
+
+    /// Slot of the event, if it has one.
+    fn slot(&self) -> Option<u64> {
+        Some(match self {
+            Self::Account(ev) => ev.slot,
+            Self::AccountsBatch(ev) => ev.slot,
+            Self::SlotStatus(ev) => ev.slot,
+            Self::Transaction(ev) => ev.slot,
+            Self::TransactionStatus(ev) => ev.slot,
+            Self::CompressedNft(ev) => ev.slot,
+            Self::ProgramUpgraded(ev) => ev.slot,
+            Self::TokenBalance(ev) => ev.slot,
+            Self::OwnerChanged(ev) => ev.slot,
+            Self::BlockMetadata(ev) => ev.slot,
+            Self::SlotAccountsComplete(ev) => ev.slot,
+            Self::Watermark(ev) => ev.newest_delivered_slot,
+            Self::StatsSnapshot(_) => return None,
+        })
+    }
+
+    /// Applies the pubkey filter, returning `false` if nothing of the event is left.
+    ///
+    /// Batches are narrowed down to the matching accounts.
+    fn retain_pubkeys(&mut self, pubkeys: &[[u8; 32]]) -> bool {
+        if pubkeys.is_empty() {
+            return true;
+        }
+        let account_matches = |ev: &UpdateAccountEvent| {
+            pubkeys
+                .iter()
+                .any(|p| ev.pubkey[..] == p[..] || ev.owner[..] == p[..])
+        };
+        match self {
+            Self::Account(ev) => account_matches(ev),
+            Self::AccountsBatch(ev) => {
+                ev.accounts.retain(account_matches);
+                !ev.accounts.is_empty()
+            }
+            Self::Transaction(ev) => ev.transaction.as_ref().map_or(false, |tx| {
+                tx.account_keys
+                    .iter()
+                    .any(|key| pubkeys.iter().any(|p| key[..] == p[..]))
+            }),
+            Self::CompressedNft(ev) => [&ev.tree, &ev.asset_id, &ev.owner, &ev.delegate]
+                .iter()
+                .any(|key| pubkeys.iter().any(|p| key[..] == p[..])),
+            Self::ProgramUpgraded(ev) => pubkeys
+                .iter()
+                .any(|p| ev.program_id[..] == p[..] || ev.programdata[..] == p[..]),
+            Self::TokenBalance(ev) => [&ev.account, &ev.owner, &ev.mint]
+                .iter()
+                .any(|key| pubkeys.iter().any(|p| key[..] == p[..])),
+            Self::OwnerChanged(ev) => [&ev.account, &ev.previous_owner, &ev.owner]
+                .iter()
+                .any(|key| pubkeys.iter().any(|p| key[..] == p[..])),
+            Self::BlockMetadata(ev) => {
+                let leader = bs58::decode(&ev.leader).into_vec().unwrap_or_default();
+                pubkeys.iter().any(|p| leader[..] == p[..])
+            }
+            Self::SlotStatus(_)
+            | Self::TransactionStatus(_)
+            | Self::SlotAccountsComplete(_)
+            | Self::Watermark(_)
+            | Self::StatsSnapshot(_) => false,
+        }
+    }
+}
+
+struct Args {
+    config: String,
+    kinds: Vec<Kind>,
+    pubkeys: Vec<[u8; 32]>,
+    from_slot: u64,
+    to_slot: u64,
+    from_beginning: bool,
+    group_id: Option<String>,
+    decryption_key: Option<Vec<u8>>,
+    overrides: Vec<(String, String)>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut this = Self {
+            config: String::new(),
+            kinds: Vec::new(),
+            pubkeys: Vec::new(),
+            from_slot: 0,
+            to_slot: u64::MAX,
+            from_beginning: false,
+            group_id: None,
+            decryption_key: None,
+            overrides: Vec::new(),
+        };
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value of {}", arg));
+            match arg.as_str() {
+                "--config" => this.config = value()?,
+                "--type" => this.kinds.push(value()?.parse()?),
+                "--pubkey" => this.pubkeys.push(
+                    Pubkey::from_str(&value()?)
+                        .map_err(|e| format!("invalid pubkey: {}", e))?
+                        .to_bytes(),
+                ),
+                "--from-slot" => this.from_slot = value()?.parse().map_err(|e| format!("{}", e))?,
+                "--to-slot" => this.to_slot = value()?.parse().map_err(|e| format!("{}", e))?,
+                "--from-beginning" => this.from_beginning = true,
+                "--group-id" => this.group_id = Some(value()?),
+                "--decryption-key" => {
+                    this.decryption_key = Some(
+                        base64::decode(value()?)
+                            .map_err(|e| format!("invalid decryption key: {}", e))?,
+                    )
+                }
+                "-X" => {
+                    let option = value()?;
+                    let (k, v) = option
+                        .split_once('=')
+                        .ok_or(format!("expected <key>=<value>: {}", option))?;
+                    this.overrides.push((k.to_owned(), v.to_owned()));
+                }
+                "-h" | "--help" => return Err(USAGE.to_owned()),
+                _ => return Err(format!("unexpected argument: {}", arg)),
+            }
+        }
+        if this.config.is_empty() {
+            return Err(USAGE.to_owned());
+        }
+        Ok(this)
+    }
+}
+
+/// Topics of the plugin and the event type published to each.
+fn topics(config: &Config) -> HashMap<String, Kind> {
+    [
+        (&config.update_account_topic, Kind::Account),
+        (&config.startup_account_topic, Kind::Account),
+        (&config.slot_status_topic, Kind::SlotStatus),
+        (&config.transaction_topic, Kind::Transaction),
+        (&config.vote_transaction_topic, Kind::Transaction),
+        (&config.transaction_status_topic, Kind::TransactionStatus),
+        (&config.compressed_nft_topic, Kind::CompressedNft),
+        (&config.program_upgrade_topic, Kind::ProgramUpgraded),
+        (&config.token_balance_topic, Kind::TokenBalance),
+        (&config.owner_change_topic, Kind::OwnerChanged),
+        (&config.block_metadata_topic, Kind::BlockMetadata),
+        (
+            &config.slot_accounts_complete_topic,
+            Kind::SlotAccountsComplete,
+        ),
+        (&config.watermark_topic, Kind::Watermark),
+        (&config.stats_topic, Kind::StatsSnapshot),
+    ]
+    .into_iter()
+    .filter(|(topic, _)| !topic.is_empty())
+    .map(|(topic, kind)| (topic.clone(), kind))
+    .collect()
+}
+
+fn consumer(config: &Config, args: &Args) -> Result<BaseConsumer<DeliveryContext>, String> {
+    let group_id = match &args.group_id {
+        Some(group_id) => group_id.clone(),
+        None => format!("kafka-consume-{}", std::process::id()),
+    };
+    let mut client_config = config.consumer_config(&group_id);
+    client_config.set(
+        "auto.offset.reset",
+        if args.from_beginning {
+            "earliest"
+        } else {
+            "latest"
+        },
+    );
+    for (k, v) in &args.overrides {
+        client_config.set(k, v);
+    }
+    config
+        .consumer(&client_config)
+        .map_err(|e| format!("Failed to create consumer: {}", e))
+}
+
+/// Returns the decrypted and decompressed payload of `message`, `None` while it is a chunk of a
+/// payload not yet complete.
+fn payload(
+    message: &BorrowedMessage,
+    args: &Args,
+    chunks: &mut ChunkAssembler,
+    compressor: &PayloadCompressor,
+) -> Result<Option<Vec<u8>>, String> {
+    let header = |name: &str| {
+        let headers = message.headers()?;
+        let header = headers.iter().find(|h| h.key == name)?;
+        header.value
+    };
+    let mut payload = message.payload().unwrap_or_default();
+    let assembled;
+    if let Some(info) = ChunkInfo::from_headers(header) {
+        match chunks.push(&info?, payload)? {
+            Some(whole) => assembled = whole,
+            None => return Ok(None),
+        }
+        payload = &assembled;
+    }
+    let opened;
+    if header("encryption").is_some() {
+        opened = match &args.decryption_key {
+            Some(key) => {
+                open_payload(key, payload).map_err(|e| format!("decryption failed: {}", e))?
+            }
+            None => return Err("payload is encrypted, pass --decryption-key".to_owned()),
+        };
+        payload = &opened;
+    }
+    match header(COMPRESSION_HEADER) {
+        None => Ok(Some(payload.to_vec())),
+        Some(codec) => {
+            let dictionary = header(COMPRESSION_DICTIONARY_HEADER).map(String::from_utf8_lossy);
+            compressor
+                .decompress(
+                    &String::from_utf8_lossy(codec),
+                    dictionary.as_deref(),
+                    payload,
+                )
+                .map(Some)
+                .map_err(|e| format!("decompression failed: {}", e))
+        }
+    }
+}
+
+/// Renders the message key, a pubkey or signature, in base58.
+fn key(message: &BorrowedMessage) -> String {
+    match message.key() {
+        Some(key) if key.len() == 32 || key.len() == 64 => bs58::encode(key).into_string(),
+        Some(key) => format!("{:?}", key),
+        None => "-".to_owned(),
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let config = Config::read_from(&args.config).map_err(|e| e.to_string())?;
+    let mut topics = topics(&config);
+    if !args.kinds.is_empty() {
+        topics.retain(|_, kind| args.kinds.contains(kind));
+    }
+    if topics.is_empty() {
+        return Err("no matching topics configured".to_owned());
+    }
+
+    let consumer = consumer(&config, &args)?;
+    let names: Vec<&str> = topics.keys().map(String::as_str).collect();
+    consumer
+        .subscribe(&names)
+        .map_err(|e| format!("Failed to subscribe: {}", e))?;
+    eprintln!("Subscribed to {}", names.join(", "));
+
+    let compression = config.payload_compression.clone().unwrap_or_default();
+    let compressor = PayloadCompressor::new(compression).map_err(|e| e.to_string())?;
+    let mut chunks = ChunkAssembler::new();
+    loop {
+        let message = match consumer.poll(Duration::from_secs(1)) {
+            None => continue,
+            Some(Ok(message)) => message,
+            Some(Err(e)) => {
+                eprintln!("Consumer error: {}", e);
+                continue;
+            }
+        };
+        let kind = topics[message.topic()];
+        let location = format!(
+            "{} [{}] @ {}",
+            message.topic(),
+            message.partition(),
+            message.offset()
+        );
+        let decoded = payload(&message, &args, &mut chunks, &compressor).and_then(|payload| {
+            payload
+                .map(|payload| Decoded::decode(kind, message.key().is_some(), &payload))
+                .transpose()
+                .map_err(|e| e.to_string())
+        });
+        let mut decoded = match decoded {
+            Ok(Some(decoded)) => decoded,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("{}: {}", location, e);
+                continue;
+            }
+        };
+        // Events without a slot pass the slot range.
+        let slot = decoded.slot();
+        let in_range = slot.map_or(true, |slot| slot >= args.from_slot && slot <= args.to_slot);
+        if !in_range || !decoded.retain_pubkeys(&args.pubkeys) {
+            continue;
+        }
+        match (&decoded, slot) {
+            (_, Some(slot)) => println!("{} key {} slot {}", location, key(&message), slot),
+            (Decoded::StatsSnapshot(ev), None) => {
+                println!(
+                    "{} key {} time {}",
+                    location,
+                    key(&message),
+                    ev.unix_time_ms
+                )
+            }
+            (_, None) => println!("{} key {}", location, key(&message)),
+        }
+        println!("{:#?}", decoded);
+    }
+}
+
+fn main() {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(2);
+        }
+    };
+    if let Err(e) = run(args) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retain_pubkeys() {
+        let account = |pubkey: u8, owner: u8| UpdateAccountEvent {
+            pubkey: vec![pubkey; 32].into(),
+            owner: vec![owner; 32].into(),
+            ..UpdateAccountEvent::default()
+        };
+        let mut batch = Decoded::AccountsBatch(SlotAccountsBatch {
+            slot: 1,
+            accounts: vec![account(1, 9), account(2, 9), account(3, 7)],
+        });
+        assert!(batch.retain_pubkeys(&[]));
+        assert!(batch.retain_pubkeys(&[[3; 32], [2; 32]]));
+        match &batch {
+            Decoded::AccountsBatch(ev) => assert_eq!(ev.accounts.len(), 2),
+            _ => unreachable!(),
+        }
+        assert!(Decoded::Account(Box::new(account(1, 9))).retain_pubkeys(&[[9; 32]]));
+        assert!(!Decoded::Account(Box::new(account(1, 9))).retain_pubkeys(&[[7; 32]]));
+        assert!(!Decoded::SlotStatus(SlotStatusEvent::default()).retain_pubkeys(&[[1; 32]]));
+    }
+}
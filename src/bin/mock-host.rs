@@ -0,0 +1,479 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Loads the built plugin like a validator does and feeds it synthetic notifications.
+//!
+//! With `--measure-latency` it doubles as a load generator, consuming the account updates back from Kafka
+//! and reporting throughput and end to end latency.
+
+use {
+    libloading::{Library, Symbol},
+    prost::Message as _,
+    rand::{thread_rng, Rng, RngCore},
+    rdkafka::{consumer::Consumer, Message as _},
+    solana_accountsdb_plugin_kafka::*,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPlugin,
+    solana_program::{hash::Hash, message::Message, pubkey::Pubkey, system_instruction},
+    solana_sdk::{
+        signature::Keypair,
+        signer::Signer,
+        transaction::{Transaction, VersionedTransaction},
+    },
+    std::{
+        process::exit,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::{self, sleep},
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+};
+
+const USAGE: &str = "Usage: mock-host <plugin library> <plugin config> [options]
+
+Options:
+    --slots <n>                 Number of slots to simulate, defaults to running forever
+    --slot-ms <ms>              Slot duration, defaults to 400
+    --accounts-per-slot <n>     Account updates per slot, defaults to 1000
+    --transactions-per-slot <n> Transactions per slot, defaults to 100
+    --account-data-bytes <n>    Size of account data, defaults to 165
+    --account-data-max-bytes <n>
+                                Vary account data sizes uniformly up to this size
+    --hot-keys <n>              Number of frequently updated accounts, defaults to 0
+    --hot-key-percent <p>       Percentage of account updates going to the hot accounts, defaults to 80
+    --measure-latency           Consume the account updates and report throughput and latency";
+
+/// Time to wait for further account updates after the simulation ended.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Slots between a slot being processed and confirmed, and between confirmed and rooted.
+const CONFIRMATION_DEPTH: u64 = 2;
+const ROOT_DEPTH: u64 = 32;
+
+type PluginConstructor = unsafe fn() -> *mut dyn GeyserPlugin;
+
+struct Args {
+    library: String,
+    config: String,
+    slots: u64,
+    slot_duration: Duration,
+    accounts_per_slot: usize,
+    transactions_per_slot: usize,
+    account_data_bytes: usize,
+    account_data_max_bytes: usize,
+    hot_keys: usize,
+    hot_key_percent: u64,
+    measure_latency: bool,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut this = Self {
+            library: String::new(),
+            config: String::new(),
+            slots: u64::MAX,
+            slot_duration: Duration::from_millis(400),
+            accounts_per_slot: 1000,
+            transactions_per_slot: 100,
+            account_data_bytes: 165,
+            account_data_max_bytes: 0,
+            hot_keys: 0,
+            hot_key_percent: 80,
+            measure_latency: false,
+        };
+        let mut paths = Vec::new();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or(format!("missing value of {}", arg))?
+                    .parse::<u64>()
+                    .map_err(|e| format!("invalid value of {}: {}", arg, e))
+            };
+            match arg.as_str() {
+                "--slots" => this.slots = value()?,
+                "--slot-ms" => this.slot_duration = Duration::from_millis(value()?),
+                "--accounts-per-slot" => this.accounts_per_slot = value()? as usize,
+                "--transactions-per-slot" => this.transactions_per_slot = value()? as usize,
+                "--account-data-bytes" => this.account_data_bytes = value()? as usize,
+                "--account-data-max-bytes" => this.account_data_max_bytes = value()? as usize,
+                "--hot-keys" => this.hot_keys = value()? as usize,
+                "--hot-key-percent" => this.hot_key_percent = value()?.min(100),
+                "--measure-latency" => this.measure_latency = true,
+                "-h" | "--help" => return Err(USAGE.to_owned()),
+                _ if arg.starts_with("--") => return Err(format!("unexpected argument: {}", arg)),
+                _ => paths.push(arg),
+            }
+        }
+        if paths.len() != 2 {
+            return Err(USAGE.to_owned());
+        }
+        this.account_data_max_bytes = this.account_data_max_bytes.max(this.account_data_bytes);
+        if this.measure_latency {
+            // The send time is stored in the first bytes of the account data.
+            this.account_data_bytes = this.account_data_bytes.max(8);
+            this.account_data_max_bytes = this.account_data_max_bytes.max(8);
+        }
+        this.config = paths.pop().unwrap();
+        this.library = paths.pop().unwrap();
+        Ok(this)
+    }
+}
+
+/// Generates the notifications of simulated slots.
+struct Generator {
+    owners: Vec<Vec<u8>>,
+    hot_keys: Vec<Vec<u8>>,
+    hot_key_percent: u64,
+    data_bytes: (usize, usize),
+    timestamp: bool,
+    payers: Vec<Keypair>,
+    write_version: u64,
+}
+
+impl Generator {
+    fn new(args: &Args) -> Self {
+        Self {
+            owners: (0..8)
+                .map(|_| Pubkey::new_unique().to_bytes().to_vec())
+                .collect(),
+            hot_keys: (0..args.hot_keys)
+                .map(|_| Pubkey::new_unique().to_bytes().to_vec())
+                .collect(),
+            hot_key_percent: args.hot_key_percent,
+            data_bytes: (args.account_data_bytes, args.account_data_max_bytes),
+            timestamp: args.measure_latency,
+            payers: (0..16).map(|_| Keypair::new()).collect(),
+            write_version: 0,
+        }
+    }
+
+    fn account(&mut self, slot: u64) -> Callback {
+        let mut rng = thread_rng();
+        let mut data = vec![0; rng.gen_range(self.data_bytes.0..=self.data_bytes.1)];
+        rng.fill_bytes(&mut data);
+        if self.timestamp {
+            data[..8].copy_from_slice(&now_micros().to_le_bytes());
+        }
+        let pubkey = if !self.hot_keys.is_empty() && rng.gen_range(0..100) < self.hot_key_percent {
+            self.hot_keys[rng.gen_range(0..self.hot_keys.len())].clone()
+        } else {
+            Pubkey::new_unique().to_bytes().to_vec()
+        };
+        self.write_version += 1;
+        Callback::UpdateAccount {
+            account: RecordedAccount {
+                pubkey,
+                lamports: rng.gen_range(890_880..10_000_000_000),
+                owner: self.owners[rng.gen_range(0..self.owners.len())].clone(),
+                executable: false,
+                rent_epoch: 0,
+                data,
+                write_version: self.write_version,
+            },
+            slot,
+            is_startup: false,
+        }
+    }
+
+    fn transaction(&self, slot: u64) -> Callback {
+        let mut rng = thread_rng();
+        let payer = &self.payers[rng.gen_range(0..self.payers.len())];
+        let ix = system_instruction::transfer(
+            &payer.pubkey(),
+            &Pubkey::new_unique(),
+            rng.gen_range(1..1_000_000),
+        );
+        let tx = Transaction::new(
+            &[payer],
+            Message::new(&[ix], Some(&payer.pubkey())),
+            Hash::new_unique(),
+        );
+        let message_hash = tx.message.hash();
+        Callback::NotifyTransaction {
+            transaction: Box::new(RecordedTransaction {
+                signature: tx.signatures[0],
+                is_vote: false,
+                transaction: VersionedTransaction::from(tx),
+                message_hash,
+                is_simple_vote_transaction: false,
+                loaded_addresses: Default::default(),
+                status: Ok(()),
+                fee: 5000,
+                pre_balances: vec![10_000_000_000, 0, 1],
+                post_balances: vec![9_999_995_000, 0, 1],
+                inner_instructions: Some(vec![]),
+                log_messages: Some(vec![
+                    "Program 11111111111111111111111111111111 invoke [1]".to_owned(),
+                    "Program 11111111111111111111111111111111 success".to_owned(),
+                ]),
+                pre_token_balances: Some(vec![]),
+                post_token_balances: Some(vec![]),
+                rewards: Some(vec![]),
+            }),
+            slot,
+        }
+    }
+
+    fn block(&self, slot: u64) -> Callback {
+        Callback::NotifyBlockMetadata {
+            block: RecordedBlock {
+                slot,
+                blockhash: Hash::new_unique().to_string(),
+                rewards: vec![],
+                block_time: None,
+                block_height: Some(slot),
+            },
+        }
+    }
+
+    fn slot_status(slot: u64, status: RecordedSlotStatus) -> Callback {
+        Callback::UpdateSlotStatus {
+            slot,
+            parent: slot.checked_sub(1),
+            status,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    invoked: u64,
+    failed: u64,
+}
+
+impl Stats {
+    fn invoke(&mut self, plugin: &mut dyn GeyserPlugin, callback: Callback) {
+        self.invoked += 1;
+        if let Err(e) = callback.invoke(plugin) {
+            self.failed += 1;
+            eprintln!("Callback failed: {}", e);
+        }
+    }
+}
+
+fn simulate(plugin: &mut dyn GeyserPlugin, args: &Args) {
+    let mut generator = Generator::new(args);
+    let mut stats = Stats::default();
+    stats.invoke(plugin, Callback::NotifyEndOfStartup);
+
+    let started = Instant::now();
+    for slot in 0..args.slots {
+        if plugin.account_data_notifications_enabled() {
+            for _ in 0..args.accounts_per_slot {
+                let callback = generator.account(slot);
+                stats.invoke(plugin, callback);
+            }
+        }
+        if plugin.transaction_notifications_enabled() {
+            for _ in 0..args.transactions_per_slot {
+                stats.invoke(plugin, generator.transaction(slot));
+            }
+        }
+        stats.invoke(plugin, generator.block(slot));
+        stats.invoke(
+            plugin,
+            Generator::slot_status(slot, RecordedSlotStatus::Processed),
+        );
+        if let Some(confirmed) = slot.checked_sub(CONFIRMATION_DEPTH) {
+            stats.invoke(
+                plugin,
+                Generator::slot_status(confirmed, RecordedSlotStatus::Confirmed),
+            );
+        }
+        if let Some(rooted) = slot.checked_sub(CONFIRMATION_DEPTH + ROOT_DEPTH) {
+            stats.invoke(
+                plugin,
+                Generator::slot_status(rooted, RecordedSlotStatus::Rooted),
+            );
+        }
+
+        let next_slot = args.slot_duration * (slot + 1) as u32;
+        match next_slot.checked_sub(started.elapsed()) {
+            Some(remaining) => sleep(remaining),
+            None => eprintln!("Slot {} took longer than {:?}", slot, args.slot_duration),
+        }
+        if slot % 10 == 9 {
+            eprintln!(
+                "Slot {}: {} callbacks, {} failed, {:.0}/s",
+                slot,
+                stats.invoked,
+                stats.failed,
+                stats.invoked as f64 / started.elapsed().as_secs_f64()
+            );
+        }
+    }
+}
+
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+/// Account updates consumed back from Kafka.
+#[derive(Default)]
+struct Consumed {
+    latencies_us: Vec<u64>,
+    bytes: u64,
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl Consumed {
+    fn record(&mut self, data: &[u8], started_us: u64) {
+        let sent_us = u64::from_le_bytes(data[..8].try_into().unwrap());
+        // Updates left on the topic by earlier runs.
+        if sent_us < started_us {
+            return;
+        }
+        let now = Instant::now();
+        self.first.get_or_insert(now);
+        self.last = Some(now);
+        self.latencies_us.push(now_micros().saturating_sub(sent_us));
+        self.bytes += data.len() as u64;
+    }
+
+    fn report(mut self) {
+        let count = self.latencies_us.len();
+        if count == 0 {
+            eprintln!("No account updates consumed");
+            return;
+        }
+        let elapsed = match (self.first, self.last) {
+            (Some(first), Some(last)) => (last - first).as_secs_f64().max(1e-3),
+            _ => 1e-3,
+        };
+        self.latencies_us.sort_unstable();
+        let percentile = |p: f64| {
+            let i = ((count as f64 * p).ceil() as usize).clamp(1, count) - 1;
+            self.latencies_us[i] as f64 / 1000.0
+        };
+        eprintln!(
+            "Consumed {} account updates, {:.0}/s, {:.1} MiB/s of account data",
+            count,
+            count as f64 / elapsed,
+            self.bytes as f64 / elapsed / (1024.0 * 1024.0)
+        );
+        eprintln!(
+            "Latency ms: p50 {:.1}, p90 {:.1}, p99 {:.1}, p99.9 {:.1}, max {:.1}",
+            percentile(0.5),
+            percentile(0.9),
+            percentile(0.99),
+            percentile(0.999),
+            percentile(1.0)
+        );
+    }
+}
+
+/// Consumes the account updates of `config` until `done` is set and no more arrive.
+fn measure(config: Config, done: Arc<AtomicBool>) -> Result<Consumed, String> {
+    if config.update_account_topic.is_empty() {
+        return Err("--measure-latency needs update_account_topic".to_owned());
+    }
+    let mut client_config = config.consumer_config(&format!("mock-host-{}", std::process::id()));
+    client_config.set("auto.offset.reset", "earliest");
+    let consumer = config
+        .consumer(&client_config)
+        .map_err(|e| format!("Failed to create consumer: {}", e))?;
+    consumer
+        .subscribe(&[&config.update_account_topic])
+        .map_err(|e| format!("Failed to subscribe: {}", e))?;
+
+    let started_us = now_micros();
+    let mut consumed = Consumed::default();
+    let mut idle_since = None;
+    loop {
+        let message = match consumer.poll(Duration::from_millis(100)) {
+            Some(Ok(message)) => message,
+            _ => {
+                if done.load(Ordering::Relaxed) {
+                    let idle = *idle_since.get_or_insert_with(Instant::now);
+                    if idle.elapsed() > DRAIN_TIMEOUT {
+                        return Ok(consumed);
+                    }
+                }
+                continue;
+            }
+        };
+        idle_since = None;
+        let payload = message.payload().unwrap_or_default();
+        // Account updates are keyed by pubkey, slot batches are not.
+        if message.key().is_some() {
+            if let Ok(ev) = UpdateAccountEvent::decode(payload) {
+                consumed.record(&ev.data, started_us);
+            }
+        } else if let Ok(batch) = SlotAccountsBatch::decode(payload) {
+            for ev in batch.accounts {
+                consumed.record(&ev.data, started_us);
+            }
+        }
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let probe = if args.measure_latency {
+        let config = Config::read_from(&args.config).map_err(|e| e.to_string())?;
+        let done = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let done = done.clone();
+            thread::spawn(move || measure(config, done))
+        };
+        Some((done, thread))
+    } else {
+        None
+    };
+
+    // The plugin must be dropped before the library is unloaded.
+    let library = unsafe { Library::new(&args.library) }
+        .map_err(|e| format!("Failed to load {}: {}", args.library, e))?;
+    let mut plugin = unsafe {
+        let constructor: Symbol<PluginConstructor> = library
+            .get(b"_create_plugin")
+            .map_err(|e| format!("{} is not a geyser plugin: {}", args.library, e))?;
+        Box::from_raw(constructor())
+    };
+    plugin.on_load(&args.config).map_err(|e| e.to_string())?;
+    eprintln!("Loaded plugin {}", plugin.name());
+
+    simulate(plugin.as_mut(), &args);
+
+    plugin.on_unload();
+    drop(plugin);
+    drop(library);
+
+    if let Some((done, thread)) = probe {
+        done.store(true, Ordering::Relaxed);
+        thread
+            .join()
+            .map_err(|_| "latency measurement panicked".to_owned())??
+            .report();
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(2);
+        }
+    };
+    if let Err(e) = run(args) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+}
@@ -0,0 +1,95 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Drives the plugin with recorded geyser callbacks instead of a validator.
+
+use {
+    solana_accountsdb_plugin_kafka::*,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPlugin,
+    std::{fs::File, io::BufReader, process::exit},
+};
+
+const USAGE: &str = "Usage: replay [--stop-on-error] <plugin config> <recording>...";
+
+fn run(config: &str, recordings: &[String], stop_on_error: bool) -> Result<(), String> {
+    let mut plugin = KafkaPlugin::new();
+    plugin.on_load(config).map_err(|e| e.to_string())?;
+
+    let mut total = ReplayStats::default();
+    let mut result = Ok(());
+    for path in recordings {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                result = Err(format!("Failed to open {}: {}", path, e));
+                break;
+            }
+        };
+        let on_error = |line, e: &dyn std::error::Error| {
+            eprintln!("{}:{}: {}", path, line, e);
+            !stop_on_error
+        };
+        match replay(&mut plugin, BufReader::new(file), on_error) {
+            Ok(stats) => {
+                eprintln!(
+                    "{}: {} invoked, {} skipped, {} failed",
+                    path, stats.invoked, stats.skipped, stats.failed
+                );
+                total.invoked += stats.invoked;
+                total.skipped += stats.skipped;
+                total.failed += stats.failed;
+            }
+            Err(e) => {
+                result = Err(format!("{}: {}", path, e));
+                break;
+            }
+        }
+        if stop_on_error && total.failed > 0 {
+            break;
+        }
+    }
+
+    // Unloading flushes all outstanding messages and logs the metrics.
+    plugin.on_unload();
+    result?;
+    if total.failed > 0 {
+        return Err(format!("{} callbacks failed", total.failed));
+    }
+    Ok(())
+}
+
+fn main() {
+    let mut stop_on_error = false;
+    let mut paths = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--stop-on-error" => stop_on_error = true,
+            "-h" | "--help" => {
+                eprintln!("{}", USAGE);
+                exit(0);
+            }
+            _ => paths.push(arg),
+        }
+    }
+    if paths.len() < 2 {
+        eprintln!("{}", USAGE);
+        exit(2);
+    }
+
+    solana_logger::setup_with_default("info");
+    if let Err(e) = run(&paths[0], &paths[1..], stop_on_error) {
+        eprintln!("{}", e);
+        exit(1);
+    }
+}
@@ -0,0 +1,55 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Prints the message schemas built into this plugin version.
+
+use {
+    solana_accountsdb_plugin_kafka::*,
+    std::{io::Write, process::exit},
+};
+
+const USAGE: &str = "Usage: schema-export <proto | descriptor-set | topics <plugin config>>
+
+    proto           The .proto source of all messages
+    descriptor-set  The binary google.protobuf.FileDescriptorSet of all messages
+    topics          JSON listing the messages published to each topic of a config";
+
+fn run(args: &[String]) -> Result<(), String> {
+    let mut stdout = std::io::stdout();
+    let written = match args {
+        [command] if command == "proto" => stdout.write_all(EVENT_PROTO.as_bytes()),
+        [command] if command == "descriptor-set" => stdout.write_all(EVENT_FILE_DESCRIPTOR_SET),
+        [command, config] if command == "topics" => {
+            let config = Config::read_from(config).map_err(|e| e.to_string())?;
+            let manifest = serde_json::json!({
+                "plugin_version": env!("CARGO_PKG_VERSION"),
+                "package": PROTO_PACKAGE,
+                "topics": topic_schemas(&config),
+            });
+            serde_json::to_writer_pretty(&mut stdout, &manifest)
+                .map_err(std::io::Error::from)
+                .and_then(|()| writeln!(stdout))
+        }
+        _ => return Err(USAGE.to_owned()),
+    };
+    written.map_err(|e| format!("Failed to write schema: {}", e))
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        exit(2);
+    }
+}
@@ -0,0 +1,356 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Publishes the current state of the allowlisted programs' accounts, fetched over RPC.
+//!
+//! Records are keyed by pubkey like live account updates,
+//! so a compacted topic ends up holding the latest state of every account. The topic must not
+//! get live updates, which compaction could replace with the older state of the snapshot.
+
+use {
+    serde::Deserialize,
+    serde_json::json,
+    solana_accountsdb_plugin_kafka::*,
+    solana_program::pubkey::Pubkey,
+    std::{process::exit, str::FromStr, sync::Arc},
+};
+
+const USAGE: &str =
+    "Usage: snapshot --config <plugin config> --rpc-url <url> --topic <topic> [options]
+
+Options:
+    --topic <topic>           Topic to publish to, not one the config publishes account updates to
+    --program <id>            Program whose accounts to publish, may be repeated,
+                              defaults to the config's program allowlist
+    --commitment <level>      Commitment of the fetched state, defaults to finalized";
+
+struct Args {
+    config: String,
+    rpc_url: String,
+    topic: String,
+    programs: Vec<String>,
+    commitment: String,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut this = Self {
+            config: String::new(),
+            rpc_url: String::new(),
+            topic: String::new(),
+            programs: Vec::new(),
+            commitment: "finalized".to_owned(),
+        };
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("missing value of {}", arg));
+            match arg.as_str() {
+                "--config" => this.config = value()?,
+                "--rpc-url" => this.rpc_url = value()?,
+                "--topic" => this.topic = value()?,
+                "--program" => {
+                    let program = value()?;
+                    Pubkey::from_str(&program).map_err(|e| format!("invalid program: {}", e))?;
+                    this.programs.push(program);
+                }
+                "--commitment" => this.commitment = value()?,
+                _ => return Err(USAGE.to_owned()),
+            }
+        }
+        if this.config.is_empty() || this.rpc_url.is_empty() || this.topic.is_empty() {
+            return Err(USAGE.to_owned());
+        }
+        Ok(this)
+    }
+}
+
+#[derive(Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct ProgramAccounts {
+    context: RpcContext,
+    value: Vec<KeyedAccount>,
+}
+
+#[derive(Deserialize)]
+struct RpcContext {
+    slot: u64,
+}
+
+#[derive(Deserialize)]
+struct KeyedAccount {
+    pubkey: String,
+    account: RpcAccount,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RpcAccount {
+    /// Base64 data and its encoding.
+    data: (String, String),
+    executable: bool,
+    lamports: u64,
+    owner: String,
+    rent_epoch: u64,
+}
+
+fn decode_pubkey(pubkey: &str) -> Result<Vec<u8>, String> {
+    Pubkey::from_str(pubkey)
+        .map(|pubkey| pubkey.to_bytes().to_vec())
+        .map_err(|e| format!("invalid pubkey {}: {}", pubkey, e))
+}
+
+/// Turns a `getProgramAccounts` response into account updates of the slot it was taken at.
+fn account_updates(
+    response: RpcResponse<ProgramAccounts>,
+) -> Result<Vec<UpdateAccountEvent>, String> {
+    let accounts = match (response.result, response.error) {
+        (Some(accounts), _) => accounts,
+        (None, Some(error)) => return Err(format!("RPC error: {}", error)),
+        (None, None) => return Err("RPC response has no result".to_owned()),
+    };
+    let slot = accounts.context.slot;
+    accounts
+        .value
+        .into_iter()
+        .map(|keyed| {
+            let account = keyed.account;
+            if account.data.1 != "base64" {
+                return Err(format!("unexpected data encoding {}", account.data.1));
+            }
+            Ok(UpdateAccountEvent {
+                slot,
+                pubkey: decode_pubkey(&keyed.pubkey)?.into(),
+                lamports: account.lamports,
+                owner: decode_pubkey(&account.owner)?.into(),
+                executable: account.executable,
+                rent_epoch: account.rent_epoch,
+                data: base64::decode(&account.data.0)
+                    .map_err(|e| format!("invalid data of {}: {}", keyed.pubkey, e))?
+                    .into(),
+                // Snapshots are not part of the validator's write order.
+                write_version: 0,
+                sequence: 0,
+                discarded_slots: Vec::new(),
+                decoded: None,
+            })
+        })
+        .collect()
+}
+
+fn fetch(args: &Args, program: &str) -> Result<Vec<UpdateAccountEvent>, String> {
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "getProgramAccounts",
+        "params": [program, {
+            "encoding": "base64",
+            "commitment": args.commitment,
+            "withContext": true,
+        }],
+    });
+    let response = ureq::post(&args.rpc_url)
+        .set("Content-Type", "application/json")
+        .send_string(&request.to_string())
+        .map_err(|e| format!("getProgramAccounts of {} failed: {}", program, e))?;
+    let response = serde_json::from_reader(response.into_reader())
+        .map_err(|e| format!("invalid getProgramAccounts response: {}", e))?;
+    account_updates(response)
+}
+
+fn publisher(config: &Config, tracker: Arc<SlotTracker>) -> Result<Publisher, String> {
+    let producers = config
+        .producers(DeliveryContext::tracking(tracker))
+        .map_err(|e| format!("Failed to create producer: {}", e))?;
+    let encryptor = match config.payload_encryption.clone() {
+        Some(encryption) => Some(
+            PayloadEncryptor::new(encryption)
+                .map_err(|e| format!("Failed to set up payload encryption: {}", e))?,
+        ),
+        None => None,
+    };
+    Ok(Publisher::new(producers, encryptor, config))
+}
+
+/// Topics the config publishes account updates to, not counting routes of remote allowlists.
+fn live_account_topics(config: &Config) -> Vec<&str> {
+    let tenants = config.tenants.iter().flat_map(|tenant| {
+        [
+            &tenant.update_account_topic,
+            &tenant.startup_account_topic,
+            &tenant.unrouted_account_topic,
+        ]
+        .into_iter()
+        .chain(tenant.topic_rules.iter().map(|rule| &rule.topic))
+    });
+    [
+        &config.update_account_topic,
+        &config.startup_account_topic,
+        &config.unrouted_account_topic,
+    ]
+    .into_iter()
+    .chain(config.topic_rules.iter().map(|rule| &rule.topic))
+    .chain(config.shadow.iter().map(|shadow| &shadow.topic))
+    .chain(tenants)
+    .map(String::as_str)
+    .filter(|topic| !topic.is_empty())
+    .collect()
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let mut config = Config::read_from(&args.config).map_err(|e| e.to_string())?;
+    if live_account_topics(&config).contains(&args.topic.as_str()) {
+        return Err(format!(
+            "{} gets live account updates, which must not be compacted with the snapshot",
+            args.topic
+        ));
+    }
+    config.update_account_topic = args.topic.clone();
+    // Compaction needs the pubkey as record key.
+    config.idempotency_key = IdempotencyKeyPlacement::None;
+
+    let programs = if args.programs.is_empty() {
+        Allowlist::new_from_config(&config)
+            .map_err(|e| e.to_string())?
+            .programs()
+            .iter()
+            .map(|program| bs58::encode(program).into_string())
+            .collect()
+    } else {
+        args.programs.clone()
+    };
+    if programs.is_empty() {
+        return Err("no programs to snapshot, configure an allowlist or pass --program".to_owned());
+    }
+
+    let decoders = DecoderRegistry::from_config(&config);
+    let tracker = Arc::new(SlotTracker::new());
+    let publisher = publisher(&config, tracker.clone())?;
+    let mut slots = Vec::new();
+    for program in &programs {
+        let accounts = fetch(&args, program)?;
+        let slot = accounts.first().map(|ev| ev.slot);
+        eprintln!(
+            "Publishing {} accounts of {} as of slot {}",
+            accounts.len(),
+            program,
+            slot.map_or("-".to_owned(), |slot| slot.to_string())
+        );
+        for mut ev in accounts {
+            if let Some(decoders) = &decoders {
+                decoders.enrich(&mut ev);
+            }
+            tracker.dispatched(ev.slot);
+            if let Err(e) = publisher.update_account(ev) {
+                return Err(format!("Failed to publish: {}", e));
+            }
+        }
+        slots.extend(slot);
+    }
+    for slot in &slots {
+        tracker.close(*slot);
+    }
+    // Flushes the producers.
+    drop(publisher);
+
+    let (mut delivered, mut failed) = (0, 0);
+    while let Ok(complete) = tracker.completed().try_recv() {
+        delivered += complete.messages_delivered;
+        failed += complete.messages_failed;
+    }
+    eprintln!("Delivered {} accounts, {} failed", delivered, failed);
+    if failed > 0 {
+        return Err("snapshot is incomplete".to_owned());
+    }
+    Ok(())
+}
+
+fn main() {
+    let result = Args::parse(std::env::args().skip(1)).and_then(run);
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        exit(2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_account_updates() {
+        let response: RpcResponse<ProgramAccounts> = serde_json::from_str(
+            r#"{
+                "jsonrpc": "2.0",
+                "id": 1,
+                "result": {
+                    "context": { "slot": 150000000 },
+                    "value": [{
+                        "pubkey": "11111111111111111111111111111111",
+                        "account": {
+                            "data": ["AQID", "base64"],
+                            "executable": false,
+                            "lamports": 2039280,
+                            "owner": "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+                            "rentEpoch": 361,
+                            "space": 3
+                        }
+                    }]
+                }
+            }"#,
+        )
+        .unwrap();
+        let updates = account_updates(response).unwrap();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].slot, 150000000);
+        assert_eq!(updates[0].pubkey, vec![0; 32]);
+        assert_eq!(&updates[0].data[..], [1, 2, 3]);
+        assert_eq!(updates[0].rent_epoch, 361);
+
+        let error: RpcResponse<ProgramAccounts> = serde_json::from_str(
+            r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32010,"message":"excluded from account secondary indexes"}}"#,
+        )
+        .unwrap();
+        assert!(account_updates(error).unwrap_err().contains("-32010"));
+    }
+
+    #[test]
+    fn test_live_account_topics() {
+        let args = |args: &[&str]| Args::parse(args.iter().map(|arg| arg.to_string()));
+        assert!(args(&["--config", "c.json", "--rpc-url", "http://rpc"]).is_err());
+        let parsed = args(&[
+            "--config",
+            "c.json",
+            "--rpc-url",
+            "http://rpc",
+            "--topic",
+            "state",
+        ]);
+        assert_eq!(parsed.unwrap().topic, "state");
+
+        let config = Config {
+            update_account_topic: "accounts".to_owned(),
+            topic_rules: vec![TopicRule {
+                pattern: "label:defi".to_owned(),
+                regex: false,
+                topic: "accounts.defi".to_owned(),
+            }],
+            ..Config::default()
+        };
+        assert_eq!(live_account_topics(&config), ["accounts", "accounts.defi"]);
+    }
+}
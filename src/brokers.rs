@@ -0,0 +1,150 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    log::{info, warn},
+    rdkafka::statistics::Statistics,
+    std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Mutex,
+        },
+    },
+};
+
+/// Clients not reporting statistics for this long are forgotten, as replaced producers stop reporting.
+const STALE_CLIENT_SEC: i64 = 120;
+
+/// librdkafka's state of connected brokers.
+const UP: &str = "UP";
+
+#[derive(Default)]
+struct ClientBrokers {
+    /// Unix time of the last statistics.
+    time: i64,
+    states: HashMap<String, String>,
+}
+
+/// Connection states of the brokers of all producers, learned from librdkafka statistics
+/// and `all brokers down` errors.
+#[derive(Default)]
+pub struct BrokerStates {
+    clients: Mutex<HashMap<String, ClientBrokers>>,
+    all_down: AtomicBool,
+}
+
+impl BrokerStates {
+    /// Records the broker states reported by a client, logging and counting transitions.
+    pub fn observe(&self, statistics: &Statistics) {
+        let brokers = statistics
+            .brokers
+            .values()
+            // Internal pseudo-brokers never connect anywhere.
+            .filter(|broker| broker.source != "internal")
+            .map(|broker| (broker.name.as_str(), broker.state.as_str()));
+        self.update(&statistics.name, statistics.time, brokers);
+    }
+
+    /// Records the states of the brokers of `client` at unix `time`.
+    fn update<'a>(
+        &self,
+        client_name: &str,
+        time: i64,
+        brokers: impl Iterator<Item = (&'a str, &'a str)>,
+    ) {
+        let mut clients = self.clients.lock().unwrap();
+        let client = clients.entry(client_name.to_owned()).or_default();
+        client.time = time;
+        let mut states = HashMap::new();
+        for (broker, state) in brokers {
+            let previous = client.states.get(broker).map(String::as_str);
+            if state == UP && previous != Some(UP) {
+                metrics().broker_up_transitions.inc();
+                info!(
+                    "Kafka broker state changed: client={} broker={} from={} to={}",
+                    client_name,
+                    broker,
+                    previous.unwrap_or("NEW"),
+                    state
+                );
+            } else if state != UP && previous == Some(UP) {
+                metrics().broker_down_transitions.inc();
+                warn!(
+                    "Kafka broker state changed: client={} broker={} from={} to={}",
+                    client_name, broker, UP, state
+                );
+            }
+            states.insert(broker.to_owned(), state.to_owned());
+        }
+        client.states = states;
+        clients.retain(|_, client| time - client.time < STALE_CLIENT_SEC);
+
+        let any_up = clients
+            .values()
+            .any(|client| client.states.values().any(|state| state == UP));
+        self.set_all_down(!any_up);
+    }
+
+    /// Records an `all brokers down` error of a client.
+    pub fn all_brokers_down(&self) {
+        self.set_all_down(true);
+    }
+
+    /// Whether no broker is connected, as of the last statistics or error.
+    pub fn is_all_down(&self) -> bool {
+        self.all_down.load(Ordering::Relaxed)
+    }
+
+    fn set_all_down(&self, all_down: bool) {
+        if self.all_down.swap(all_down, Ordering::Relaxed) == all_down {
+            return;
+        }
+        if all_down {
+            metrics().all_brokers_down.inc();
+            warn!("Kafka broker state changed: all brokers down");
+        } else {
+            info!("Kafka broker state changed: brokers reachable again");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_broker_states() {
+        let states = BrokerStates::default();
+        let update = |client, time, brokers: &[(&'static str, &'static str)]| {
+            states.update(client, time, brokers.iter().copied())
+        };
+        assert!(!states.is_all_down());
+        update("p1", 100, &[("a:9092/1", "UP"), ("b:9092/2", "UP")]);
+        assert!(!states.is_all_down());
+        update("p1", 110, &[("a:9092/1", "DOWN"), ("b:9092/2", "UP")]);
+        assert!(!states.is_all_down());
+        states.all_brokers_down();
+        assert!(states.is_all_down());
+        update("p1", 120, &[("a:9092/1", "UP"), ("b:9092/2", "DOWN")]);
+        assert!(!states.is_all_down());
+
+        // A replaced producer still UP when it stopped reporting is forgotten eventually.
+        update("p2", 130, &[("a:9092/1", "DOWN")]);
+        assert!(!states.is_all_down());
+        update("p2", 300, &[("a:9092/1", "CONNECT")]);
+        assert!(states.is_all_down());
+    }
+}
@@ -0,0 +1,309 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    lazy_static::lazy_static,
+    sha2::{Digest, Sha256},
+    solana_program::{instruction::CompiledInstruction, message::AccountKeys, pubkey::Pubkey},
+    solana_transaction_status::InnerInstructions,
+    std::str::FromStr,
+};
+
+/// Bubblegum instructions changing a leaf, by the name they are published with and their Anchor name.
+const OPERATIONS: &[(&str, &str)] = &[
+    ("mint", "mint_v1"),
+    ("mint_to_collection", "mint_to_collection_v1"),
+    ("transfer", "transfer"),
+    ("burn", "burn"),
+    ("delegate", "delegate"),
+    ("redeem", "redeem"),
+    ("cancel_redeem", "cancel_redeem"),
+    ("compress", "compress"),
+    ("verify_creator", "verify_creator"),
+    ("unverify_creator", "unverify_creator"),
+    ("verify_collection", "verify_collection"),
+    ("unverify_collection", "unverify_collection"),
+    ("set_and_verify_collection", "set_and_verify_collection"),
+    ("update_metadata", "update_metadata"),
+];
+
+lazy_static! {
+    static ref BUBBLEGUM_PROGRAM_ID: Pubkey =
+        Pubkey::from_str("BGUMAp9Gq7iTEuizy4pqaxsTyUCBK68MDfK752saRPUY").unwrap();
+    static ref NOOP_PROGRAM_ID: Pubkey =
+        Pubkey::from_str("noopb9bkMVfRPU8AsbpTUg8AQkHtKwMYZiFUjNRtMmV").unwrap();
+    /// Anchor instruction discriminators of the `OPERATIONS`.
+    static ref DISCRIMINATORS: Vec<([u8; 8], &'static str)> = OPERATIONS
+        .iter()
+        .map(|(operation, name)| {
+            let hash = Sha256::digest(format!("global:{}", name).as_bytes());
+            (hash[..8].try_into().unwrap(), *operation)
+        })
+        .collect();
+}
+
+/// Tag of `AccountCompressionEvent::ChangeLog` and `ApplicationData`, each followed by a `V1` tag.
+const CHANGE_LOG_EVENT: u8 = 0;
+const APPLICATION_DATA_EVENT: u8 = 1;
+
+/// Size of a Bubblegum `LeafSchemaEvent` of a `V1` leaf.
+const LEAF_SCHEMA_EVENT_SIZE: usize = 203;
+
+/// A tree change logged through the noop program.
+struct ChangeLog {
+    tree: Vec<u8>,
+    sequence: u64,
+    leaf_index: u32,
+}
+
+/// A leaf as logged by Bubblegum.
+struct Leaf {
+    owner: Vec<u8>,
+    delegate: Vec<u8>,
+    data_hash: Vec<u8>,
+    creator_hash: Vec<u8>,
+}
+
+/// Changes of compressed NFTs made by a successful transaction, in execution order.
+///
+/// Bubblegum names the operation; the changelog, which the account compression program logs through the noop
+/// program, names the tree and leaf. Bubblegum instructions changing no tree, such as decompressions, yield none.
+pub fn compressed_nft_events(
+    slot: u64,
+    signature: &[u8],
+    account_keys: &AccountKeys,
+    instructions: &[CompiledInstruction],
+    inner_instructions: &[InnerInstructions],
+) -> Vec<CompressedNftEvent> {
+    let mut events = Vec::new();
+    for (index, instruction) in instructions.iter().enumerate() {
+        let inner = inner_instructions
+            .iter()
+            .filter(|inner| inner.index as usize == index)
+            .flat_map(|inner| inner.instructions.iter());
+        // The operation under way and what its noop calls logged so far.
+        let mut pending: Option<(&str, Option<ChangeLog>, Option<Leaf>)> = None;
+        for ix in std::iter::once(instruction).chain(inner) {
+            let program = match account_keys.get(ix.program_id_index as usize) {
+                Some(program) => program,
+                None => continue,
+            };
+            if program == &*BUBBLEGUM_PROGRAM_ID {
+                events.extend(
+                    pending
+                        .take()
+                        .and_then(|pending| build_event(slot, signature, index as u32, pending)),
+                );
+                pending = operation(&ix.data).map(|operation| (operation, None, None));
+            } else if program == &*NOOP_PROGRAM_ID {
+                if let Some((_, change_log, leaf)) = pending.as_mut() {
+                    if let Some(logged) = parse_change_log(&ix.data) {
+                        *change_log = Some(logged);
+                    } else if let Some(logged) = parse_leaf(&ix.data) {
+                        *leaf = Some(logged);
+                    }
+                }
+            }
+        }
+        events.extend(
+            pending.and_then(|pending| build_event(slot, signature, index as u32, pending)),
+        );
+    }
+    events
+}
+
+fn operation(data: &[u8]) -> Option<&'static str> {
+    let discriminator = data.get(..8)?;
+    DISCRIMINATORS
+        .iter()
+        .find(|(known, _)| &known[..] == discriminator)
+        .map(|(_, operation)| *operation)
+}
+
+fn build_event(
+    slot: u64,
+    signature: &[u8],
+    instruction_index: u32,
+    (operation, change_log, leaf): (&str, Option<ChangeLog>, Option<Leaf>),
+) -> Option<CompressedNftEvent> {
+    let change_log = change_log?;
+    let (asset_id, _) = Pubkey::find_program_address(
+        &[
+            b"asset",
+            &change_log.tree,
+            &u64::from(change_log.leaf_index).to_le_bytes(),
+        ],
+        &BUBBLEGUM_PROGRAM_ID,
+    );
+    let leaf = leaf.unwrap_or(Leaf {
+        owner: Vec::new(),
+        delegate: Vec::new(),
+        data_hash: Vec::new(),
+        creator_hash: Vec::new(),
+    });
+    Some(CompressedNftEvent {
+        slot,
+        signature: signature.to_vec(),
+        instruction_index,
+        operation: operation.to_owned(),
+        tree: change_log.tree,
+        leaf_index: change_log.leaf_index,
+        sequence: change_log.sequence,
+        asset_id: asset_id.to_bytes().into(),
+        owner: leaf.owner,
+        delegate: leaf.delegate,
+        data_hash: leaf.data_hash,
+        creator_hash: leaf.creator_hash,
+    })
+}
+
+/// Parses a Borsh `AccountCompressionEvent::ChangeLog(ChangeLogEvent::V1 { id, path, seq, index })`.
+fn parse_change_log(data: &[u8]) -> Option<ChangeLog> {
+    if data.get(..2)? != [CHANGE_LOG_EVENT, 0] {
+        return None;
+    }
+    let tree = data.get(2..34)?.to_vec();
+    let path_len = u32::from_le_bytes(data.get(34..38)?.try_into().ok()?) as usize;
+    // Path nodes are a hash and a `u32` index.
+    let rest = data.get(38 + path_len.checked_mul(36)?..)?;
+    if rest.len() != 12 {
+        return None;
+    }
+    Some(ChangeLog {
+        tree,
+        sequence: u64::from_le_bytes(rest[..8].try_into().ok()?),
+        leaf_index: u32::from_le_bytes(rest[8..].try_into().ok()?),
+    })
+}
+
+/// Parses a Bubblegum `LeafSchemaEvent`, as wrapped in an `ApplicationDataEvent::V1` by current versions or as is.
+fn parse_leaf(data: &[u8]) -> Option<Leaf> {
+    let event = if data.len() == LEAF_SCHEMA_EVENT_SIZE {
+        data
+    } else if data.get(..2)? == [APPLICATION_DATA_EVENT, 0] {
+        let len = u32::from_le_bytes(data.get(2..6)?.try_into().ok()?) as usize;
+        data.get(6..).filter(|event| event.len() == len)?
+    } else {
+        return None;
+    };
+    // `BubblegumEventType::LeafSchemaEvent`, `Version::V1` and `LeafSchema::V1`.
+    if event.len() != LEAF_SCHEMA_EVENT_SIZE || event[..3] != [1, 0, 0] {
+        return None;
+    }
+    // The asset ID at 3..35 and the nonce, equal to the leaf index, at 99..107 follow from the changelog.
+    Some(Leaf {
+        owner: event[35..67].to_vec(),
+        delegate: event[67..99].to_vec(),
+        data_hash: event[107..139].to_vec(),
+        creator_hash: event[139..171].to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change_log(tree: [u8; 32], sequence: u64, leaf_index: u32) -> Vec<u8> {
+        let mut data = vec![CHANGE_LOG_EVENT, 0];
+        data.extend_from_slice(&tree);
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&[0; 72]);
+        data.extend_from_slice(&sequence.to_le_bytes());
+        data.extend_from_slice(&leaf_index.to_le_bytes());
+        data
+    }
+
+    fn leaf_schema(owner: [u8; 32]) -> Vec<u8> {
+        let mut event = vec![1, 0, 0];
+        event.extend_from_slice(&[9; 32]);
+        event.extend_from_slice(&owner);
+        event.extend_from_slice(&owner);
+        event.extend_from_slice(&7u64.to_le_bytes());
+        event.extend_from_slice(&[5; 32]);
+        event.extend_from_slice(&[6; 32]);
+        event.extend_from_slice(&[0; 32]);
+        let mut data = vec![APPLICATION_DATA_EVENT, 0];
+        data.extend_from_slice(&(event.len() as u32).to_le_bytes());
+        data.extend(event);
+        data
+    }
+
+    fn instruction(program_id_index: u8, data: Vec<u8>) -> CompiledInstruction {
+        CompiledInstruction {
+            program_id_index,
+            accounts: Vec::new(),
+            data,
+        }
+    }
+
+    fn discriminator(name: &str) -> Vec<u8> {
+        Sha256::digest(format!("global:{}", name).as_bytes())[..8].to_vec()
+    }
+
+    #[test]
+    fn test_compressed_nft_events() {
+        let keys = [
+            *BUBBLEGUM_PROGRAM_ID,
+            *NOOP_PROGRAM_ID,
+            Pubkey::new_unique(),
+        ];
+        let account_keys = AccountKeys::new(&keys, None);
+        let instructions = [
+            instruction(0, discriminator("transfer")),
+            instruction(2, Vec::new()),
+        ];
+        let inner = [
+            InnerInstructions {
+                index: 0,
+                instructions: vec![
+                    instruction(1, leaf_schema([3; 32])),
+                    instruction(1, change_log([4; 32], 11, 7)),
+                ],
+            },
+            // Burned through CPI, logging no leaf.
+            InnerInstructions {
+                index: 1,
+                instructions: vec![
+                    instruction(0, discriminator("burn")),
+                    instruction(1, change_log([4; 32], 12, 8)),
+                    // Not a leaf change.
+                    instruction(0, discriminator("create_tree")),
+                    instruction(1, change_log([8; 32], 1, 0)),
+                ],
+            },
+        ];
+
+        let events = compressed_nft_events(5, &[1; 64], &account_keys, &instructions, &inner);
+        assert_eq!(events.len(), 2);
+        let transfer = &events[0];
+        assert_eq!(transfer.operation, "transfer");
+        assert_eq!(transfer.instruction_index, 0);
+        assert_eq!(transfer.tree, vec![4; 32]);
+        assert_eq!((transfer.leaf_index, transfer.sequence), (7, 11));
+        assert_eq!(transfer.owner, vec![3; 32]);
+        assert_eq!(transfer.data_hash, vec![5; 32]);
+        let (asset_id, _) = Pubkey::find_program_address(
+            &[b"asset", &[4; 32], &7u64.to_le_bytes()],
+            &BUBBLEGUM_PROGRAM_ID,
+        );
+        assert_eq!(transfer.asset_id, asset_id.to_bytes().to_vec());
+
+        let burn = &events[1];
+        assert_eq!(burn.operation, "burn");
+        assert_eq!(burn.instruction_index, 1);
+        assert_eq!((burn.leaf_index, burn.sequence), (8, 12));
+        assert!(burn.owner.is_empty());
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    std::{
+        collections::{BTreeMap, HashMap},
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Condvar, Mutex, RwLock,
+        },
+    },
+};
+
+#[derive(Default)]
+struct InFlight {
+    messages: u64,
+    bytes: u64,
+}
+
+/// Caps the messages handed to librdkafka and not yet confirmed or rejected by the brokers.
+///
+/// Records are admitted while both the message count and their payload and key bytes
+/// stay within the limits, zero meaning unlimited. A record larger than the byte limit
+/// is still admitted once nothing else is in flight.
+pub struct InFlightBudget {
+    max_messages: u64,
+    max_bytes: u64,
+    policy: QueueOverflowPolicy,
+    in_flight: Mutex<InFlight>,
+    released: Condvar,
+}
+
+impl InFlightBudget {
+    /// Budget applying `policy` to records exceeding it, `None` if both limits are zero.
+    pub fn new(max_messages: u64, max_bytes: u64, policy: QueueOverflowPolicy) -> Option<Self> {
+        if max_messages == 0 && max_bytes == 0 {
+            return None;
+        }
+        Some(Self {
+            max_messages,
+            max_bytes,
+            policy,
+            in_flight: Mutex::default(),
+            released: Condvar::new(),
+        })
+    }
+
+    /// Admits a record of `bytes`, waiting for deliveries to free up the budget
+    /// or refusing the record, depending on the policy.
+    pub fn acquire(&self, bytes: u64) -> bool {
+        self.admit(bytes, self.policy)
+    }
+
+    /// Admits a record of `bytes`, waiting for deliveries to free up the budget whatever the policy.
+    pub fn wait(&self, bytes: u64) {
+        self.admit(bytes, QueueOverflowPolicy::Block);
+    }
+
+    fn admit(&self, bytes: u64, policy: QueueOverflowPolicy) -> bool {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if !self.fits(&in_flight, bytes) {
+            match policy {
+                // Records handed to librdkafka cannot be taken back, the new one is dropped.
+                QueueOverflowPolicy::Drop
+                | QueueOverflowPolicy::DropOldest
+                | QueueOverflowPolicy::DropLargest => {
+                    metrics().in_flight_budget_drops.inc();
+                    return false;
+                }
+                QueueOverflowPolicy::Block => {
+                    metrics().in_flight_budget_waits.inc();
+                    while !self.fits(&in_flight, bytes) {
+                        in_flight = self.released.wait(in_flight).unwrap();
+                    }
+                }
+            }
+        }
+        in_flight.messages += 1;
+        in_flight.bytes += bytes;
+        true
+    }
+
+    /// Returns the budget of a record of `bytes` once it was delivered, failed or never sent.
+    pub fn release(&self, bytes: u64) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        in_flight.messages = in_flight.messages.saturating_sub(1);
+        in_flight.bytes = in_flight.bytes.saturating_sub(bytes);
+        drop(in_flight);
+        self.released.notify_all();
+    }
+
+    /// Messages and bytes currently in flight.
+    pub fn in_flight(&self) -> (u64, u64) {
+        let in_flight = self.in_flight.lock().unwrap();
+        (in_flight.messages, in_flight.bytes)
+    }
+
+    fn fits(&self, in_flight: &InFlight, bytes: u64) -> bool {
+        if in_flight.messages == 0 {
+            return true;
+        }
+        (self.max_messages == 0 || in_flight.messages < self.max_messages)
+            && (self.max_bytes == 0 || in_flight.bytes + bytes <= self.max_bytes)
+    }
+}
+
+/// Payload and key bytes handed to librdkafka and not yet delivered or failed, per topic.
+#[derive(Default)]
+pub struct BufferedBytes {
+    topics: RwLock<HashMap<String, Arc<AtomicU64>>>,
+}
+
+impl BufferedBytes {
+    fn topic(&self, topic: &str) -> Arc<AtomicU64> {
+        if let Some(bytes) = self.topics.read().unwrap().get(topic) {
+            return bytes.clone();
+        }
+        let mut topics = self.topics.write().unwrap();
+        topics.entry(topic.to_owned()).or_default().clone()
+    }
+
+    pub fn add(&self, topic: &str, bytes: u64) {
+        self.topic(topic).fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn release(&self, topic: &str, bytes: u64) {
+        self.topic(topic).fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    /// Bytes currently buffered, by topic.
+    pub fn by_topic(&self) -> BTreeMap<String, u64> {
+        let topics = self.topics.read().unwrap();
+        topics
+            .iter()
+            .map(|(topic, bytes)| (topic.clone(), bytes.load(Ordering::Relaxed)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{sync::Arc, thread, time::Duration},
+    };
+
+    #[test]
+    fn test_in_flight_budget() {
+        assert!(InFlightBudget::new(0, 0, QueueOverflowPolicy::Drop).is_none());
+
+        let budget = InFlightBudget::new(2, 100, QueueOverflowPolicy::Drop).unwrap();
+        // Oversized records pass on their own.
+        assert!(budget.acquire(500));
+        assert!(!budget.acquire(1));
+        budget.release(500);
+        assert!(budget.acquire(60));
+        assert!(!budget.acquire(50));
+        assert!(budget.acquire(40));
+        assert!(!budget.acquire(0));
+        assert_eq!(budget.in_flight(), (2, 100));
+
+        let budget = Arc::new(InFlightBudget::new(1, 0, QueueOverflowPolicy::Block).unwrap());
+        assert!(budget.acquire(10));
+        let releaser = {
+            let budget = budget.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                budget.release(10);
+            })
+        };
+        assert!(budget.acquire(20));
+        releaser.join().unwrap();
+        assert_eq!(budget.in_flight(), (1, 20));
+    }
+
+    #[test]
+    fn test_buffered_bytes() {
+        let buffered = BufferedBytes::default();
+        buffered.add("a", 10);
+        buffered.add("b", 5);
+        buffered.add("a", 7);
+        buffered.release("a", 10);
+        let by_topic: Vec<(String, u64)> = buffered.by_topic().into_iter().collect();
+        assert_eq!(by_topic, vec![("a".to_owned(), 7), ("b".to_owned(), 5)]);
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {crate::*, serde::Deserialize, solana_program::pubkey::Pubkey, std::sync::Arc};
+
+/// Candidate filter settings, evaluated alongside the active ones without affecting what is published.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct CanaryFilterConfig {
+    #[serde(default)]
+    pub program_ignores: Vec<String>,
+    #[serde(default)]
+    pub program_allowlist: Vec<String>,
+    #[serde(default)]
+    pub program_allowlist_url: String,
+    #[serde(default)]
+    pub program_allowlist_expiry_sec: u64,
+    #[serde(default)]
+    pub filter_transactions_by_program: bool,
+    #[serde(default)]
+    pub min_transaction_fee_lamports: u64,
+    #[serde(default)]
+    pub min_compute_units: u64,
+}
+
+impl CanaryFilterConfig {
+    /// Config the candidate filter is built from, fetching a remote allowlist like the root does.
+    pub fn config(&self, root: &Config) -> Config {
+        Config {
+            program_ignores: self.program_ignores.clone(),
+            program_allowlist: self.program_allowlist.clone(),
+            program_allowlist_url: self.program_allowlist_url.clone(),
+            program_allowlist_tls_cert: root.program_allowlist_tls_cert.clone(),
+            program_allowlist_tls_key: root.program_allowlist_tls_key.clone(),
+            program_allowlist_tls_ca: root.program_allowlist_tls_ca.clone(),
+            program_allowlist_expiry_sec: self.program_allowlist_expiry_sec,
+            min_transaction_fee_lamports: self.min_transaction_fee_lamports,
+            min_compute_units: self.min_compute_units,
+            ..Config::default()
+        }
+    }
+}
+
+/// Counters of the canary decisions, labeled by `kind` of message and `decision`.
+const CANARY_METRIC: &str = "canary_filter_decisions";
+
+/// Counters of the decisions of one kind of message, by how the candidate decided compared to the active filter.
+struct Decisions {
+    accepted: Arc<Counter>,
+    rejected: Arc<Counter>,
+    /// Rejected by the active filter, accepted by the candidate.
+    newly_accepted: Arc<Counter>,
+    /// Accepted by the active filter, rejected by the candidate.
+    newly_rejected: Arc<Counter>,
+}
+
+impl Decisions {
+    fn new(kind: &str) -> Self {
+        let counter =
+            |decision| labeled_counter(CANARY_METRIC, &[("kind", kind), ("decision", decision)]);
+        Self {
+            accepted: counter("accepted"),
+            rejected: counter("rejected"),
+            newly_accepted: counter("newly_accepted"),
+            newly_rejected: counter("newly_rejected"),
+        }
+    }
+
+    fn record(&self, active: bool, candidate: bool) {
+        match (active, candidate) {
+            (true, true) => &self.accepted,
+            (false, false) => &self.rejected,
+            (false, true) => &self.newly_accepted,
+            (true, false) => &self.newly_rejected,
+        }
+        .inc()
+    }
+}
+
+/// Candidate filter whose decisions are compared to those of the active filter, and only counted.
+pub struct CanaryFilter {
+    filter: Filter,
+    filter_transactions_by_program: bool,
+    accounts: Decisions,
+    transactions: Decisions,
+}
+
+impl CanaryFilter {
+    pub fn new(config: &CanaryFilterConfig, root: &Config) -> Self {
+        Self {
+            filter: Filter::new(&config.config(root)),
+            filter_transactions_by_program: config.filter_transactions_by_program,
+            accounts: Decisions::new("account"),
+            transactions: Decisions::new("transaction"),
+        }
+    }
+
+    /// Counts the decision on an account update of `owner` the active filter decided on.
+    pub fn compare_account(&self, owner: &[u8], active: bool) {
+        self.accounts
+            .record(active, self.filter.wants_program(owner));
+        // Trigger an update of the remote allowlist but don't wait for it to complete.
+        self.filter
+            .get_allowlist()
+            .update_from_http_if_needed_async();
+    }
+
+    /// Counts the decision on a transaction the active filter decided on.
+    ///
+    /// `compute_units` and `programs` are only evaluated if the candidate filters by them.
+    pub fn compare_transaction(
+        &self,
+        active: bool,
+        fee: u64,
+        compute_units: impl FnOnce() -> u64,
+        programs: impl FnOnce() -> Vec<Pubkey>,
+    ) {
+        let compute_units = if self.filter.filters_compute_units() {
+            compute_units()
+        } else {
+            0
+        };
+        let candidate = self.filter.wants_transaction_cost(fee, compute_units)
+            && (!self.filter_transactions_by_program
+                || self
+                    .filter
+                    .wants_any_program(programs().iter().map(|p| p.as_ref())));
+        self.transactions.record(active, candidate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canary_filter() {
+        let ignored = Pubkey::new_unique();
+        let allowed = Pubkey::new_unique();
+        let config = CanaryFilterConfig {
+            program_ignores: vec![ignored.to_string()],
+            min_transaction_fee_lamports: 5000,
+            ..CanaryFilterConfig::default()
+        };
+        let canary = CanaryFilter::new(&config, &Config::default());
+        let count = |kind, decision| {
+            labeled_counter(CANARY_METRIC, &[("kind", kind), ("decision", decision)]).get()
+        };
+        let counts = |kind| {
+            ["accepted", "rejected", "newly_accepted", "newly_rejected"]
+                .map(|decision| count(kind, decision))
+        };
+        let accounts = counts("account");
+        let transactions = counts("transaction");
+
+        canary.compare_account(allowed.as_ref(), true);
+        canary.compare_account(ignored.as_ref(), true);
+        canary.compare_account(ignored.as_ref(), false);
+        canary.compare_transaction(false, 10_000, || 0, Vec::new);
+        canary.compare_transaction(true, 1_000, || 0, Vec::new);
+
+        let since = |kind, before: [u64; 4]| {
+            let after = counts(kind);
+            [0, 1, 2, 3].map(|i| after[i] - before[i])
+        };
+        assert_eq!(since("account", accounts), [1, 1, 0, 1]);
+        assert_eq!(since("transaction", transactions), [0, 0, 1, 1]);
+    }
+}
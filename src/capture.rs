@@ -0,0 +1,90 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {crate::*, serde::Deserialize};
+
+/// Where and how to capture the raw geyser callbacks.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CaptureConfig {
+    /// Directory the capture files are written to.
+    pub directory: String,
+    #[serde(flatten)]
+    pub rotation: Rotation,
+}
+
+/// Writes geyser callbacks, as received, to rotating length-prefixed files readable by [`replay`].
+pub struct CaptureSink {
+    file: RotatingFile,
+}
+
+impl CaptureSink {
+    pub fn new(config: CaptureConfig) -> std::io::Result<Self> {
+        let file = RotatingFile::new(config.directory, "callbacks-", ".rec", config.rotation)?;
+        Ok(Self { file })
+    }
+
+    pub fn record(&mut self, callback: &Callback) -> std::io::Result<()> {
+        self.file.append(|writer| callback.write_framed(writer))?;
+        metrics().callbacks_captured.inc();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::{fs, path::PathBuf},
+    };
+
+    #[test]
+    fn test_capture_rotation() {
+        let dir = std::env::temp_dir().join(format!("kafka-plugin-capture-{}", std::process::id()));
+        let mut sink = CaptureSink::new(CaptureConfig {
+            directory: dir.to_str().unwrap().to_owned(),
+            rotation: Rotation {
+                max_file_bytes: 1,
+                max_file_age_sec: 0,
+                max_files: 2,
+            },
+        })
+        .unwrap();
+        for slot in 0..5 {
+            let callback = Callback::UpdateSlotStatus {
+                slot,
+                parent: None,
+                status: RecordedSlotStatus::Rooted,
+            };
+            sink.record(&callback).unwrap();
+        }
+        drop(sink);
+
+        let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 2);
+        let last = fs::read(&files[1]).unwrap();
+        let len = u32::from_be_bytes([last[0], last[1], last[2], last[3]]) as usize;
+        let callback: Callback = serde_json::from_slice(&last[4..]).unwrap();
+        assert_eq!(last.len(), 4 + len);
+        assert!(matches!(
+            callback,
+            Callback::UpdateSlotStatus { slot: 4, .. }
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    log::{error, info, warn},
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        fs,
+        path::PathBuf,
+        sync::Mutex,
+    },
+};
+
+#[derive(Default)]
+struct Progress {
+    /// Rooted slots whose account records are still in flight.
+    rooted: BTreeSet<u64>,
+    /// Whether the account records of a slot were all delivered, for slots not known to be rooted yet.
+    completed: BTreeMap<u64, bool>,
+    last: Option<u64>,
+    /// A rooted slot was not fully published, so the checkpoint cannot advance anymore.
+    broken: bool,
+    first_root_seen: bool,
+}
+
+/// Persists the highest rooted slot up to which all account records were delivered.
+///
+/// The checkpoint only advances while every rooted slot since startup was fully published,
+/// so after a restart the slots following it are the ones to backfill.
+pub struct Checkpoint {
+    path: PathBuf,
+    previous: Option<u64>,
+    progress: Mutex<Progress>,
+}
+
+impl Checkpoint {
+    /// Opens the checkpoint at `path`, logging the slot it was left at.
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let previous = match fs::read_to_string(&path) {
+            Ok(contents) => match contents.trim().parse() {
+                Ok(slot) => {
+                    info!("Last fully published rooted slot: {}", slot);
+                    Some(slot)
+                }
+                Err(e) => {
+                    warn!("Ignoring invalid checkpoint {}: {}", path.display(), e);
+                    None
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                info!("No checkpoint at {} yet", path.display());
+                None
+            }
+            Err(e) => {
+                warn!("Failed to read checkpoint {}: {}", path.display(), e);
+                None
+            }
+        };
+        Self {
+            path,
+            previous,
+            progress: Mutex::default(),
+        }
+    }
+
+    /// The checkpoint left by the previous run.
+    pub fn previous(&self) -> Option<u64> {
+        self.previous
+    }
+
+    /// The checkpoint reached by this run.
+    pub fn last(&self) -> Option<u64> {
+        self.progress.lock().unwrap().last
+    }
+
+    /// Registers that `slot` was rooted.
+    pub fn rooted(&self, slot: u64) {
+        let mut progress = self.progress.lock().unwrap();
+        if !progress.first_root_seen {
+            progress.first_root_seen = true;
+            if let Some(previous) = self.previous {
+                if slot > previous + 1 {
+                    warn!(
+                        "Slots {} to {} were rooted while the plugin was not running",
+                        previous + 1,
+                        slot - 1
+                    );
+                }
+            }
+        }
+        if !progress.broken {
+            progress.rooted.insert(slot);
+            self.advance(&mut progress);
+        }
+    }
+
+    /// Registers that all account records of a slot were delivered or failed.
+    pub fn completed(&self, ev: &SlotAccountsComplete) {
+        let mut progress = self.progress.lock().unwrap();
+        if !progress.broken {
+            progress.completed.insert(ev.slot, ev.messages_failed == 0);
+            self.advance(&mut progress);
+        }
+    }
+
+    fn advance(&self, progress: &mut Progress) {
+        let start = progress.last;
+        while let Some(&slot) = progress.rooted.iter().next() {
+            match progress.completed.remove(&slot) {
+                Some(true) => {
+                    progress.rooted.remove(&slot);
+                    progress.last = Some(slot);
+                }
+                Some(false) => {
+                    error!(
+                        "Rooted slot {} was not fully published, checkpoint stays at {:?} until restarted",
+                        slot, progress.last
+                    );
+                    progress.broken = true;
+                    progress.rooted.clear();
+                    progress.completed.clear();
+                    break;
+                }
+                None => break,
+            }
+        }
+        if let Some(last) = progress.last {
+            // Slots before the checkpoint that were never rooted are abandoned forks.
+            progress.completed = progress.completed.split_off(&last);
+            if progress.last != start {
+                self.persist(last);
+            }
+        }
+    }
+
+    fn persist(&self, slot: u64) {
+        // Replaced atomically, so a crash never leaves a partial checkpoint behind.
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let written = fs::write(&temporary, format!("{}\n", slot))
+            .and_then(|()| fs::rename(&temporary, &self.path));
+        if let Err(e) = written {
+            error!("Failed to write checkpoint {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complete(slot: u64, messages_failed: u64) -> SlotAccountsComplete {
+        SlotAccountsComplete {
+            slot,
+            messages_delivered: 1,
+            messages_failed,
+        }
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let path =
+            std::env::temp_dir().join(format!("kafka-plugin-checkpoint-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let checkpoint = Checkpoint::open(&path);
+        assert_eq!(checkpoint.previous(), None);
+
+        checkpoint.completed(&complete(10, 0));
+        checkpoint.completed(&complete(11, 0));
+        assert_eq!(checkpoint.last(), None);
+        checkpoint.rooted(10);
+        assert_eq!(checkpoint.last(), Some(10));
+
+        // Rooted before its records are delivered.
+        checkpoint.rooted(12);
+        assert_eq!(checkpoint.last(), Some(10));
+        checkpoint.completed(&complete(12, 0));
+        assert_eq!(checkpoint.last(), Some(12));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "12\n");
+
+        checkpoint.rooted(13);
+        checkpoint.completed(&complete(13, 1));
+        checkpoint.completed(&complete(14, 0));
+        checkpoint.rooted(14);
+        assert_eq!(checkpoint.last(), Some(12));
+
+        assert_eq!(Checkpoint::open(&path).previous(), Some(12));
+        fs::remove_file(&path).unwrap();
+    }
+}
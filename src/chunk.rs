@@ -0,0 +1,196 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    rand::RngCore,
+    sha2::{Digest, Sha256},
+    std::{collections::HashMap, fmt::Write},
+};
+
+/// Header identifying the payload a chunk belongs to.
+pub const CHUNK_ID_HEADER: &str = "chunk-id";
+
+/// Header carrying the zero-based position of a chunk.
+pub const CHUNK_INDEX_HEADER: &str = "chunk-index";
+
+/// Header carrying the number of chunks of the payload.
+pub const CHUNK_COUNT_HEADER: &str = "chunk-count";
+
+/// Header carrying the `sha256:` hex digest of the whole payload.
+pub const CHUNK_CHECKSUM_HEADER: &str = "chunk-checksum";
+
+/// Where a chunk belongs in the payload it was split off.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkInfo {
+    pub id: String,
+    pub index: usize,
+    pub count: usize,
+    pub checksum: String,
+}
+
+impl ChunkInfo {
+    pub fn headers(&self) -> [(&'static str, String); 4] {
+        [
+            (CHUNK_ID_HEADER, self.id.clone()),
+            (CHUNK_INDEX_HEADER, self.index.to_string()),
+            (CHUNK_COUNT_HEADER, self.count.to_string()),
+            (CHUNK_CHECKSUM_HEADER, self.checksum.clone()),
+        ]
+    }
+
+    /// Reads the chunk headers through `header`, `None` if the record is not a chunk.
+    pub fn from_headers<'a>(
+        header: impl Fn(&str) -> Option<&'a [u8]>,
+    ) -> Option<Result<Self, String>> {
+        let id = header(CHUNK_ID_HEADER)?;
+        let text = |name| {
+            header(name)
+                .and_then(|value| std::str::from_utf8(value).ok())
+                .ok_or_else(|| format!("chunk without valid {} header", name))
+        };
+        let number = |name| {
+            text(name)?
+                .parse()
+                .map_err(|e| format!("invalid {} header: {}", name, e))
+        };
+        Some((|| {
+            Ok(Self {
+                id: String::from_utf8_lossy(id).into_owned(),
+                index: number(CHUNK_INDEX_HEADER)?,
+                count: number(CHUNK_COUNT_HEADER)?,
+                checksum: text(CHUNK_CHECKSUM_HEADER)?.to_owned(),
+            })
+        })())
+    }
+}
+
+/// `sha256:` hex digest of `payload`.
+pub fn checksum(payload: &[u8]) -> String {
+    let mut checksum = "sha256:".to_owned();
+    for byte in Sha256::digest(payload) {
+        write!(checksum, "{:02x}", byte).unwrap();
+    }
+    checksum
+}
+
+/// Splits `payload` into chunks of at most `max_bytes`, under a random chunk id.
+pub fn split(payload: &[u8], max_bytes: usize) -> Vec<(ChunkInfo, &[u8])> {
+    let mut id = [0; 16];
+    rand::thread_rng().fill_bytes(&mut id);
+    let id: String = id.iter().map(|b| format!("{:02x}", b)).collect();
+    let checksum = checksum(payload);
+    let count = (payload.len() + max_bytes - 1) / max_bytes;
+    payload
+        .chunks(max_bytes)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let info = ChunkInfo {
+                id: id.clone(),
+                index,
+                count,
+                checksum: checksum.clone(),
+            };
+            (info, chunk)
+        })
+        .collect()
+}
+
+/// Reassembles chunked payloads from consumed chunks, in any order.
+#[derive(Default)]
+pub struct ChunkAssembler {
+    partial: HashMap<String, Vec<Option<Vec<u8>>>>,
+}
+
+impl ChunkAssembler {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds a chunk, returning the payload once all its chunks arrived.
+    pub fn push(&mut self, info: &ChunkInfo, chunk: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        if info.index >= info.count {
+            return Err(format!(
+                "chunk {} of {} out of range",
+                info.index, info.count
+            ));
+        }
+        let chunks = self
+            .partial
+            .entry(info.id.clone())
+            .or_insert_with(|| vec![None; info.count]);
+        if chunks.len() != info.count {
+            return Err(format!("chunk count of {} changed", info.id));
+        }
+        chunks[info.index] = Some(chunk.to_vec());
+        if chunks.iter().any(Option::is_none) {
+            return Ok(None);
+        }
+        let payload: Vec<u8> = self
+            .partial
+            .remove(&info.id)
+            .unwrap_or_default()
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect();
+        if checksum(&payload) != info.checksum {
+            return Err(format!("checksum mismatch of {}", info.id));
+        }
+        Ok(Some(payload))
+    }
+
+    /// Payloads still missing chunks.
+    pub fn pending(&self) -> usize {
+        self.partial.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let payload: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let chunks = split(&payload, 300);
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks[3].1.len(), 100);
+        assert!(chunks
+            .iter()
+            .all(|(info, _)| info.count == 4 && info.id == chunks[0].0.id));
+
+        let headers = chunks[1].0.headers();
+        let info = ChunkInfo::from_headers(|name| {
+            headers
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.as_bytes())
+        });
+        assert_eq!(info, Some(Ok(chunks[1].0.clone())));
+        assert_eq!(ChunkInfo::from_headers(|_| None), None);
+
+        let mut assembler = ChunkAssembler::new();
+        for (info, chunk) in chunks.iter().rev().skip(1) {
+            assert_eq!(assembler.push(info, chunk), Ok(None));
+        }
+        assert_eq!(assembler.pending(), 1);
+        let (info, chunk) = &chunks[3];
+        assert_eq!(assembler.push(info, chunk), Ok(Some(payload.clone())));
+        assert_eq!(assembler.pending(), 0);
+
+        let (mut info, chunk) = chunks[0].clone();
+        info.count = 1;
+        assert!(assembler.push(&info, chunk).is_err());
+    }
+}
@@ -0,0 +1,184 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    serde::Deserialize,
+    sha2::{Digest, Sha256},
+    std::{error::Error, fs, io::Read},
+    zstd::dict::{DecoderDictionary, EncoderDictionary},
+};
+
+/// Header naming the codec a payload was compressed with.
+pub const COMPRESSION_HEADER: &str = "compression";
+/// Header identifying the dictionary a payload was compressed with, if any.
+pub const COMPRESSION_DICTIONARY_HEADER: &str = "compression-dictionary";
+const ZSTD: &str = "zstd";
+
+/// A compressed payload and the headers describing its compression.
+pub type Compressed = (Vec<u8>, Vec<(&'static str, String)>);
+
+/// How the plugin compresses message payloads, independently of any broker-side compression.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "codec", rename_all = "snake_case")]
+pub enum PayloadCompression {
+    Zstd {
+        #[serde(default = "PayloadCompression::default_level")]
+        level: i32,
+        /// Path of a dictionary trained with `zstd --train`, e.g. on account data.
+        #[serde(default)]
+        dictionary: Option<String>,
+    },
+}
+
+impl Default for PayloadCompression {
+    fn default() -> Self {
+        PayloadCompression::Zstd {
+            level: Self::default_level(),
+            dictionary: None,
+        }
+    }
+}
+
+impl PayloadCompression {
+    fn default_level() -> i32 {
+        3
+    }
+}
+
+struct Dictionary {
+    id: String,
+    encoder: EncoderDictionary<'static>,
+    decoder: DecoderDictionary<'static>,
+}
+
+/// Compresses message payloads with zstd, optionally with a pre-trained dictionary.
+pub struct PayloadCompressor {
+    level: i32,
+    dictionary: Option<Dictionary>,
+}
+
+impl PayloadCompressor {
+    pub fn new(config: PayloadCompression) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let PayloadCompression::Zstd { level, dictionary } = config;
+        let dictionary = match dictionary {
+            None => None,
+            Some(path) => {
+                let bytes = fs::read(&path)
+                    .map_err(|e| format!("Failed to read zstd dictionary {}: {}", path, e))?;
+                Some(Dictionary {
+                    id: dictionary_id(&bytes),
+                    encoder: EncoderDictionary::copy(&bytes, level),
+                    decoder: DecoderDictionary::copy(&bytes),
+                })
+            }
+        };
+        Ok(Self { level, dictionary })
+    }
+
+    /// Identifier of the dictionary, as sent in the `compression-dictionary` header.
+    pub fn dictionary_id(&self) -> Option<&str> {
+        self.dictionary
+            .as_ref()
+            .map(|dictionary| dictionary.id.as_str())
+    }
+
+    /// Compresses `payload` and returns it with the headers consumers need to decompress it.
+    pub fn compress(&self, payload: &[u8]) -> Result<Compressed, Box<dyn Error + Send + Sync>> {
+        let mut headers = vec![(COMPRESSION_HEADER, ZSTD.to_owned())];
+        let compressed = match &self.dictionary {
+            None => zstd::bulk::compress(payload, self.level)?,
+            Some(dictionary) => {
+                headers.push((COMPRESSION_DICTIONARY_HEADER, dictionary.id.clone()));
+                zstd::bulk::Compressor::with_prepared_dictionary(&dictionary.encoder)?
+                    .compress(payload)?
+            }
+        };
+        Ok((compressed, headers))
+    }
+
+    /// Decompresses a payload compressed by [`Self::compress`] with the `codec` and `dictionary`
+    /// named by its headers.
+    pub fn decompress(
+        &self,
+        codec: &str,
+        dictionary: Option<&str>,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        if codec != ZSTD {
+            return Err(format!("unknown compression codec {}", codec).into());
+        }
+        let mut decompressed = Vec::new();
+        match (dictionary, &self.dictionary) {
+            (None, _) => zstd::stream::Decoder::new(payload)?.read_to_end(&mut decompressed)?,
+            (Some(id), Some(dictionary)) if id == dictionary.id => {
+                zstd::stream::Decoder::with_prepared_dictionary(payload, &dictionary.decoder)?
+                    .read_to_end(&mut decompressed)?
+            }
+            (Some(id), _) => return Err(format!("zstd dictionary {} is not loaded", id).into()),
+        };
+        Ok(decompressed)
+    }
+}
+
+/// Leading 8 bytes of the SHA-256 digest of `dictionary`, in hex.
+fn dictionary_id(dictionary: &[u8]) -> String {
+    Sha256::digest(dictionary)[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zstd_roundtrip() {
+        let payload = [b"account data ".as_slice(); 64].concat();
+        let plain = PayloadCompressor::new(PayloadCompression::Zstd {
+            level: 3,
+            dictionary: None,
+        })
+        .unwrap();
+        let (compressed, headers) = plain.compress(&payload).unwrap();
+        assert!(compressed.len() < payload.len());
+        assert_eq!(headers, vec![(COMPRESSION_HEADER, "zstd".to_owned())]);
+        assert_eq!(plain.decompress(ZSTD, None, &compressed).unwrap(), payload);
+        assert!(plain.decompress("lz4", None, &compressed).is_err());
+
+        let samples: Vec<Vec<u8>> = (0..1000u32)
+            .map(|i| format!("owner TokenkegQfeZyi account {} lamports {}", i, i * 7).into_bytes())
+            .collect();
+        let path = std::env::temp_dir().join(format!("kafka-plugin-dict-{}", std::process::id()));
+        fs::write(&path, zstd::dict::from_samples(&samples, 4096).unwrap()).unwrap();
+        let with_dictionary = PayloadCompressor::new(PayloadCompression::Zstd {
+            level: 3,
+            dictionary: Some(path.to_str().unwrap().to_owned()),
+        })
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+        let id = with_dictionary.dictionary_id().unwrap().to_owned();
+        assert_eq!(id.len(), 16);
+
+        let (compressed, headers) = with_dictionary.compress(&samples[5]).unwrap();
+        assert_eq!(headers[1], (COMPRESSION_DICTIONARY_HEADER, id.clone()));
+        assert_eq!(
+            with_dictionary
+                .decompress(ZSTD, Some(&id), &compressed)
+                .unwrap(),
+            samples[5]
+        );
+        assert!(plain.decompress(ZSTD, Some(&id), &compressed).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    solana_program::{instruction::CompiledInstruction, message::SanitizedMessage, pubkey::Pubkey},
+    std::str::FromStr,
+};
+
+const COMPUTE_BUDGET_PROGRAM_ID: &str = "ComputeBudget111111111111111111111111111111";
+const DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT: u64 = 200_000;
+const MAX_COMPUTE_UNIT_LIMIT: u64 = 1_400_000;
+const MICRO_LAMPORTS_PER_LAMPORT: u64 = 1_000_000;
+
+/// Compute budget requested by a transaction's compute budget instructions.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ComputeBudget {
+    /// Explicitly requested compute unit limit.
+    pub unit_limit: Option<u32>,
+    /// Compute unit price in micro-lamports.
+    pub unit_price: u64,
+    /// Additional fee of the deprecated `RequestUnits` instruction.
+    pub deprecated_additional_fee: u64,
+    /// Number of instructions not targeting the compute budget program.
+    pub other_instructions: u64,
+}
+
+impl ComputeBudget {
+    pub fn from_message(message: &SanitizedMessage) -> Self {
+        let program_id = Pubkey::from_str(COMPUTE_BUDGET_PROGRAM_ID).unwrap();
+        let account_keys = message.account_keys();
+        let mut this = Self::default();
+        for ix in message.instructions() {
+            if account_keys.get(ix.program_id_index as usize) == Some(&program_id) {
+                this.apply(ix);
+            } else {
+                this.other_instructions += 1;
+            }
+        }
+        this
+    }
+
+    /// Applies a borsh-encoded `ComputeBudgetInstruction`.
+    fn apply(&mut self, ix: &CompiledInstruction) {
+        let data = &ix.data;
+        let u32_at = |i: usize| {
+            data.get(i..i + 4)
+                .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        };
+        match data.first() {
+            // RequestUnitsDeprecated { units, additional_fee }
+            Some(0) => {
+                if let (Some(units), Some(fee)) = (u32_at(1), u32_at(5)) {
+                    self.unit_limit = Some(units);
+                    self.deprecated_additional_fee = fee as u64;
+                }
+            }
+            // SetComputeUnitLimit
+            Some(2) => {
+                if let Some(units) = u32_at(1) {
+                    self.unit_limit = Some(units);
+                }
+            }
+            // SetComputeUnitPrice
+            Some(3) => {
+                if let Some(b) = data.get(1..9) {
+                    self.unit_price = u64::from_le_bytes(b.try_into().unwrap());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Compute unit limit the transaction runs with, requested or default.
+    pub fn effective_unit_limit(&self) -> u64 {
+        match self.unit_limit {
+            Some(limit) => limit as u64,
+            None => DEFAULT_INSTRUCTION_COMPUTE_UNIT_LIMIT * self.other_instructions,
+        }
+        .min(MAX_COMPUTE_UNIT_LIMIT)
+    }
+
+    /// Fee paid on top of the signature fees, in lamports.
+    pub fn priority_fee(&self) -> u64 {
+        if self.deprecated_additional_fee > 0 {
+            return self.deprecated_additional_fee;
+        }
+        let micro_lamports = self.unit_price as u128 * self.effective_unit_limit() as u128;
+        let lamports = (micro_lamports + MICRO_LAMPORTS_PER_LAMPORT as u128 - 1)
+            / MICRO_LAMPORTS_PER_LAMPORT as u128;
+        lamports as u64
+    }
+}
+
+/// Sums the compute units consumed by top-level instructions, as reported in the logs.
+///
+/// Best effort: truncated logs undercount.
+pub fn compute_units_consumed(logs: &[String]) -> u64 {
+    let mut depth = 0usize;
+    let mut consumed = 0u64;
+    for line in logs {
+        // Only the runtime's own "Program <id> ..." lines; program output such as
+        // "Program log: ..." or "Program data: ..." has no program id there.
+        let rest = match line
+            .strip_prefix("Program ")
+            .and_then(|rest| rest.split_once(' '))
+        {
+            Some((program_id, rest)) if Pubkey::from_str(program_id).is_ok() => rest,
+            _ => continue,
+        };
+        if rest.starts_with("invoke [") && rest.ends_with(']') {
+            depth += 1;
+        } else if rest == "success" || rest.starts_with("failed: ") {
+            depth = depth.saturating_sub(1);
+        } else if depth == 1 {
+            // "consumed <n> of <m> compute units"
+            let mut words = rest.split(' ');
+            if let (Some("consumed"), Some(n)) = (words.next(), words.next()) {
+                consumed += n.parse::<u64>().unwrap_or(0);
+            }
+        }
+    }
+    consumed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compute_units_consumed() {
+        let logs: Vec<String> = [
+            "Program ComputeBudget111111111111111111111111111111 invoke [1]",
+            "Program ComputeBudget111111111111111111111111111111 success",
+            "Program 9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin invoke [1]",
+            "Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA invoke [2]",
+            "Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA consumed 4000 of 190000 compute units",
+            "Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA success",
+            "Program 9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin consumed 12000 of 200000 compute units",
+            "Program 9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin success",
+            "Program 11111111111111111111111111111111 invoke [1]",
+            "Program 11111111111111111111111111111111 success",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert_eq!(compute_units_consumed(&logs), 12000);
+    }
+
+    #[test]
+    fn test_compute_units_consumed_ignores_program_output() {
+        let logs: Vec<String> = [
+            "Program 9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin invoke [1]",
+            "Program log: swap success",
+            "Program log: retry failed: slippage",
+            "Program log: fake invoke [2]",
+            "Program data: c3VjY2Vzcw== success",
+            "Program 9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin consumed 12000 of 200000 compute units",
+            "Program 9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin success",
+            "Program 11111111111111111111111111111111 invoke [1]",
+            "Program 11111111111111111111111111111111 consumed 150 of 188000 compute units",
+            "Program 11111111111111111111111111111111 failed: custom program error: 0x1",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert_eq!(compute_units_consumed(&logs), 12150);
+    }
+
+    #[test]
+    fn test_compute_budget_instructions() {
+        let ix = |data: Vec<u8>| CompiledInstruction {
+            program_id_index: 0,
+            accounts: vec![],
+            data,
+        };
+
+        let mut budget = ComputeBudget {
+            other_instructions: 2,
+            ..ComputeBudget::default()
+        };
+        assert_eq!(budget.effective_unit_limit(), 400_000);
+        assert_eq!(budget.priority_fee(), 0);
+
+        let mut limit = vec![2];
+        limit.extend_from_slice(&300_000u32.to_le_bytes());
+        budget.apply(&ix(limit));
+        let mut price = vec![3];
+        price.extend_from_slice(&10_000u64.to_le_bytes());
+        budget.apply(&ix(price));
+
+        assert_eq!(budget.unit_limit, Some(300_000));
+        assert_eq!(budget.unit_price, 10_000);
+        assert_eq!(budget.priority_fee(), 3_000);
+    }
+}
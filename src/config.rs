@@ -13,11 +13,15 @@
 // limitations under the License.
 
 use {
+    crate::{
+        check_topic_rules, redact, AlertThresholds, CanaryFilterConfig, CaptureConfig, DataSlice,
+        DeliveryContext, ErrorReporting, InFlightBudget, OAuthBearer, PartitionPinner,
+        PartitionPinning, PayloadCompression, PayloadEncryption, Rotation, ShadowConfig,
+        SpoolConfig, TenantConfig, TopicRule,
+    },
     rdkafka::{
-        config::FromClientConfig,
-        error::KafkaResult,
-        producer::{DefaultProducerContext, ThreadedProducer},
-        ClientConfig,
+        config::FromClientConfigAndContext, consumer::BaseConsumer, error::KafkaResult,
+        producer::ThreadedProducer, ClientConfig,
     },
     serde::Deserialize,
     solana_geyser_plugin_interface::geyser_plugin_interface::{
@@ -26,23 +30,221 @@ use {
     std::{collections::HashMap, fs::File, path::Path},
 };
 
+/// librdkafka options only applying to producers, left out of consumer configs.
+const PRODUCER_OPTIONS: &[&str] = &[
+    "acks",
+    "request.required.acks",
+    "message.timeout.ms",
+    "compression.type",
+    "compression.codec",
+    "partitioner",
+    "linger.ms",
+    "queue.buffering.max.ms",
+    "queue.buffering.max.messages",
+    "queue.buffering.max.kbytes",
+    "batch.num.messages",
+    "batch.size",
+    "enable.idempotence",
+    "message.send.max.retries",
+    "retries",
+];
+
 /// Plugin config.
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct Config {
     /// Kafka config.
     pub kafka: HashMap<String, String>,
+    /// Named sets of `kafka` options, such as SASL credentials and `client.id`, producers can be bound to.
+    #[serde(default)]
+    pub producer_identities: HashMap<String, HashMap<String, String>>,
+    /// The `producer_identities` entry the producers use, its options taking precedence over `kafka`.
+    #[serde(default)]
+    pub producer_identity: String,
+    /// Recommended `kafka` settings for a hosted Kafka service.
+    #[serde(default)]
+    pub preset: Option<Preset>,
+    /// SASL GSSAPI (Kerberos) authentication.
+    #[serde(default)]
+    pub kerberos: Option<Kerberos>,
+    /// How to obtain SASL `OAUTHBEARER` tokens.
+    #[serde(default)]
+    pub sasl_oauthbearer: Option<OAuthBearer>,
+    /// Recreate the producers after fatal errors, such as a fenced idempotent producer or expired credentials.
+    #[serde(default = "Config::default_true")]
+    pub recreate_failed_producers: bool,
+    /// Spool Kafka records to disk while all brokers are down.
+    #[serde(default)]
+    pub broker_down_spool: Option<SpoolConfig>,
+    /// Repeats of an error message within this many seconds are counted and summarized instead of logged.
+    /// Zero logs every error.
+    #[serde(default = "Config::default_log_suppression_interval_sec")]
+    pub log_suppression_interval_sec: u64,
+    /// How often to log a summary of the messages dropped, by topic, reason and filter group. Zero disables it.
+    #[serde(default = "Config::default_drop_summary_interval_sec")]
+    pub drop_summary_interval_sec: u64,
+    /// Where to report panics and fatal errors of the plugin to.
+    #[serde(default)]
+    pub error_reporting: Option<ErrorReporting>,
+    /// Thresholds that fire alerts and flip `/health` of the admin endpoint to unhealthy.
+    #[serde(default)]
+    pub alerts: Option<AlertThresholds>,
+    /// Address to serve the admin endpoint on, e.g. `127.0.0.1:9101`. Empty disables it.
+    #[serde(default)]
+    pub admin_address: String,
+    /// How often to check the TLS certificate, key and CA files for changes, zero disables reloading.
+    #[serde(default)]
+    pub tls_reload_interval_sec: u64,
+    /// Encrypt message payloads with keys from this source.
+    #[serde(default)]
+    pub payload_encryption: Option<PayloadEncryption>,
+    /// Compress message payloads in the plugin, before any encryption.
+    #[serde(default)]
+    pub payload_compression: Option<PayloadCompression>,
+    /// Where to publish events to.
+    #[serde(default)]
+    pub sink: Sink,
+    /// Write all geyser callbacks, before filtering, to capture files.
+    #[serde(default)]
+    pub capture: Option<CaptureConfig>,
     /// Graceful shutdown timeout.
     #[serde(default)]
     pub shutdown_timeout_ms: u64,
+    /// Interval of forced producer flushes, regardless of `linger.ms`. Zero disables them.
+    #[serde(default)]
+    pub flush_interval_ms: u64,
     /// Kafka topic to send account updates to.
     #[serde(default)]
     pub update_account_topic: String,
+    /// Kafka topic to send the accounts published on startup to instead of `update_account_topic`.
+    #[serde(default)]
+    pub startup_account_topic: String,
     /// Kafka topic to send slot status updates to.
     #[serde(default)]
     pub slot_status_topic: String,
     /// Kafka topic to send transaction to.
     #[serde(default)]
     pub transaction_topic: String,
+    /// Kafka topic to send vote transactions to, instead of `transaction_topic`.
+    #[serde(default)]
+    pub vote_transaction_topic: String,
+    /// Kafka topic to send lightweight transaction status events to.
+    #[serde(default)]
+    pub transaction_status_topic: String,
+    /// Kafka topic to send compressed NFT changes of Bubblegum transactions to.
+    #[serde(default)]
+    pub compressed_nft_topic: String,
+    /// Kafka topic to send `ProgramUpgraded` events to.
+    #[serde(default)]
+    pub program_upgrade_topic: String,
+    /// Kafka topic to send `TokenBalanceChange`s of token accounts to.
+    #[serde(default)]
+    pub token_balance_topic: String,
+    /// Number of token accounts whose last published amount is remembered for `token_balance_topic`.
+    #[serde(default = "Config::default_token_balance_cache_size")]
+    pub token_balance_cache_size: usize,
+    /// Kafka topic to send `OwnerChanged` events of reassigned accounts to.
+    #[serde(default)]
+    pub owner_change_topic: String,
+    /// Number of accounts whose last owner is remembered for `owner_change_topic`.
+    #[serde(default = "Config::default_owner_change_cache_size")]
+    pub owner_change_cache_size: usize,
+    /// With `program_upgrade_topic`, still publish the programdata account updates of upgrades.
+    #[serde(default = "Config::default_true")]
+    pub publish_programdata_updates: bool,
+    /// Kafka topic to send block metadata to.
+    #[serde(default)]
+    pub block_metadata_topic: String,
+    /// Kafka topic to send `SlotAccountsComplete` markers to.
+    #[serde(default)]
+    pub slot_accounts_complete_topic: String,
+    /// Number the published updates of every account.
+    #[serde(default)]
+    pub account_sequence_numbers: bool,
+    /// Report account updates lost on their way to the publisher.
+    #[serde(default)]
+    pub detect_account_update_gaps: bool,
+    /// Where records carry their idempotency key, if at all.
+    #[serde(default)]
+    pub idempotency_key: IdempotencyKeyPlacement,
+    /// Payload of account update records on Kafka.
+    #[serde(default)]
+    pub account_payload_format: AccountPayloadFormat,
+    /// Schema of the account, slot status and transaction records on Kafka.
+    #[serde(default)]
+    pub event_schema: EventSchema,
+    /// Kafka topic to republish records timing out on their way to Kafka to. Empty disables dead-lettering.
+    #[serde(default)]
+    pub dead_letter_topic: String,
+    /// Kafka payloads larger than this are split into chunks of at most this size. Zero disables chunking.
+    #[serde(default)]
+    pub max_message_bytes: usize,
+    /// Partitions of the account update topics reserved to the accounts of given owner programs.
+    #[serde(default)]
+    pub partition_pinning: Option<PartitionPinning>,
+    /// Mirror of a sample of the records, or of the unfiltered account updates, to a shadow topic.
+    #[serde(default)]
+    pub shadow: Option<ShadowConfig>,
+    /// Kafka topic to send delivery `Watermark`s to.
+    #[serde(default)]
+    pub watermark_topic: String,
+    /// Interval between watermarks.
+    #[serde(default = "Config::default_watermark_interval_ms")]
+    pub watermark_interval_ms: u64,
+    /// Kafka topic to send `StatsSnapshot`s of the plugin's metrics to.
+    #[serde(default)]
+    pub stats_topic: String,
+    /// Interval between stats snapshots.
+    #[serde(default = "Config::default_stats_interval_ms")]
+    pub stats_interval_ms: u64,
+    /// Node name of the stats snapshots, and their record key. Empty uses the host name.
+    #[serde(default)]
+    pub stats_node_name: String,
+    /// File the highest fully published rooted slot is persisted to. Empty disables the checkpoint.
+    #[serde(default)]
+    pub checkpoint_file: String,
+    /// Attach the leader identity learned from block metadata to slot status events.
+    #[serde(default)]
+    pub include_slot_leader: bool,
+    /// Only publish transactions with at least one (possibly inner) instruction
+    /// invoking a program accepted by the program filters.
+    #[serde(default)]
+    pub filter_transactions_by_program: bool,
+    /// Only publish transactions paying at least this fee.
+    #[serde(default)]
+    pub min_transaction_fee_lamports: u64,
+    /// Only publish transactions consuming at least this many compute units.
+    #[serde(default)]
+    pub min_compute_units: u64,
+    /// Suppress transactions whose signature was already published within this window.
+    /// Zero disables deduplication.
+    #[serde(default)]
+    pub transaction_dedup_window_ms: u64,
+    /// Number of accounts whose last published state is remembered to suppress
+    /// updates repeating it. Zero disables the cache.
+    #[serde(default)]
+    pub account_dedup_cache_size: usize,
+    /// Hold account updates back for this window to publish a state notified
+    /// on several forks once. Zero disables the window.
+    #[serde(default)]
+    pub account_fork_dedup_window_ms: u64,
+    /// Byte ranges of account data published instead of the whole data, per owner program.
+    #[serde(default)]
+    pub account_data_slices: Vec<DataSlice>,
+    /// Decode instructions of known native and SPL programs into transaction events.
+    #[serde(default)]
+    pub include_parsed_instructions: bool,
+    /// Decode the data of accounts owned by known programs into account events.
+    #[serde(default)]
+    pub decode_accounts: bool,
+    /// With `decode_accounts`, also decode vote accounts.
+    #[serde(default)]
+    pub decode_vote_accounts: bool,
+    /// Include log messages in transaction events.
+    #[serde(default = "Config::default_true")]
+    pub include_transaction_logs: bool,
+    /// Include inner instructions in transaction events.
+    #[serde(default = "Config::default_true")]
+    pub include_inner_instructions: bool,
     /// List of programs to ignore.
     #[serde(default)]
     pub program_ignores: Vec<String>,
@@ -59,24 +261,166 @@ pub struct Config {
     /// If not empty, only accounts owned by programs in this list are published.
     #[serde(default)]
     pub program_allowlist_url: String,
+    /// Client certificate to present when fetching the allowlist over HTTPS.
+    #[serde(default)]
+    pub program_allowlist_tls_cert: String,
+    /// Private key of `program_allowlist_tls_cert`.
+    #[serde(default)]
+    pub program_allowlist_tls_key: String,
+    /// CA to trust for the allowlist server instead of the public web roots.
+    #[serde(default)]
+    pub program_allowlist_tls_ca: String,
     /// Update iterval for allowlist from http url.
     #[serde(default)]
     pub program_allowlist_expiry_sec: u64,
+    /// Rules routing account updates to topics by the labels or pubkey of their owner, first match wins.
+    #[serde(default)]
+    pub topic_rules: Vec<TopicRule>,
+    /// Topic of the account updates no route or rule matches, instead of `update_account_topic`.
+    #[serde(default)]
+    pub unrouted_account_topic: String,
+    /// Drop the account updates no route or rule matches.
+    #[serde(default)]
+    pub drop_unrouted_accounts: bool,
+    /// Candidate filter settings whose decisions are compared to the active ones, in metrics only.
+    #[serde(default)]
+    pub canary_filter: Option<CanaryFilterConfig>,
+    /// File every change of the program allowlist and ignores is appended to. Empty disables it.
+    #[serde(default)]
+    pub filter_audit_log: String,
+    /// Maximum number of events buffered between the geyser callbacks and the publisher thread.
+    #[serde(default = "Config::default_queue_capacity")]
+    pub queue_capacity: usize,
+    /// What to do with new events when the publisher queue is full.
+    #[serde(default)]
+    pub queue_overflow_policy: QueueOverflowPolicy,
+    /// Publish account updates as one `SlotAccountsBatch` per slot
+    /// once the slot status advances, instead of one message per update.
+    #[serde(default)]
+    pub batch_account_updates: bool,
+    /// Hold back account updates until the slot status advances and only publish
+    /// the highest write_version of every account per slot.
+    #[serde(default)]
+    pub coalesce_account_updates: bool,
+    /// Hold back account updates until their slot is rooted and publish their net effect per account.
+    #[serde(default)]
+    pub rooted_account_updates: bool,
+    /// Maximum number of records handed to librdkafka and not yet delivered. Zero means unlimited.
+    #[serde(default)]
+    pub max_in_flight_messages: u64,
+    /// Maximum payload and key bytes of the records in flight. Zero means unlimited.
+    #[serde(default)]
+    pub max_in_flight_bytes: u64,
+    /// Bytes the dedup caches, slot buffers, spool index and queued events may hold together
+    /// before `queue_overflow_policy` applies. Zero means unlimited.
+    #[serde(default)]
+    pub max_memory_bytes: u64,
+    /// Publish slot status events, completion markers and watermarks through their own
+    /// queue and producer, ahead of any backlog of account updates.
+    #[serde(default)]
+    pub priority_lane: bool,
+    /// Number of independent Kafka producer instances events are sharded across by key.
+    #[serde(default = "Config::default_producer_shards")]
+    pub producer_shards: usize,
+    /// Number of threads serializing and producing events in parallel.
+    #[serde(default = "Config::default_serialization_workers")]
+    pub serialization_workers: usize,
+    /// Customers sharing the validator, each filtering and publishing on its own.
+    #[serde(default)]
+    pub tenants: Vec<TenantConfig>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             kafka: HashMap::new(),
+            producer_identities: HashMap::new(),
+            producer_identity: "".to_owned(),
+            preset: None,
+            kerberos: None,
+            sasl_oauthbearer: None,
+            recreate_failed_producers: true,
+            broker_down_spool: None,
+            log_suppression_interval_sec: Self::default_log_suppression_interval_sec(),
+            drop_summary_interval_sec: Self::default_drop_summary_interval_sec(),
+            error_reporting: None,
+            alerts: None,
+            admin_address: "".to_owned(),
+            tls_reload_interval_sec: 0,
+            payload_encryption: None,
+            payload_compression: None,
+            sink: Sink::default(),
+            capture: None,
             shutdown_timeout_ms: 30_000,
+            flush_interval_ms: 0,
             update_account_topic: "".to_owned(),
+            startup_account_topic: "".to_owned(),
             slot_status_topic: "".to_owned(),
             transaction_topic: "".to_owned(),
+            vote_transaction_topic: "".to_owned(),
+            transaction_status_topic: "".to_owned(),
+            compressed_nft_topic: "".to_owned(),
+            program_upgrade_topic: "".to_owned(),
+            token_balance_topic: "".to_owned(),
+            token_balance_cache_size: Config::default_token_balance_cache_size(),
+            owner_change_topic: "".to_owned(),
+            owner_change_cache_size: Config::default_owner_change_cache_size(),
+            publish_programdata_updates: true,
+            block_metadata_topic: "".to_owned(),
+            slot_accounts_complete_topic: "".to_owned(),
+            account_sequence_numbers: false,
+            detect_account_update_gaps: false,
+            idempotency_key: IdempotencyKeyPlacement::default(),
+            account_payload_format: AccountPayloadFormat::default(),
+            event_schema: EventSchema::default(),
+            dead_letter_topic: "".to_owned(),
+            max_message_bytes: 0,
+            partition_pinning: None,
+            shadow: None,
+            watermark_topic: "".to_owned(),
+            watermark_interval_ms: Self::default_watermark_interval_ms(),
+            stats_topic: "".to_owned(),
+            stats_interval_ms: Self::default_stats_interval_ms(),
+            stats_node_name: "".to_owned(),
+            checkpoint_file: "".to_owned(),
+            include_slot_leader: false,
+            filter_transactions_by_program: false,
+            min_transaction_fee_lamports: 0,
+            min_compute_units: 0,
+            transaction_dedup_window_ms: 0,
+            account_dedup_cache_size: 0,
+            account_fork_dedup_window_ms: 0,
+            account_data_slices: Vec::new(),
+            include_parsed_instructions: false,
+            decode_accounts: false,
+            decode_vote_accounts: false,
+            include_transaction_logs: true,
+            include_inner_instructions: true,
             program_ignores: Vec::new(),
             publish_all_accounts: false,
             program_allowlist: Vec::new(),
             program_allowlist_url: "".to_owned(),
+            topic_rules: Vec::new(),
+            unrouted_account_topic: "".to_owned(),
+            drop_unrouted_accounts: false,
+            canary_filter: None,
+            filter_audit_log: "".to_owned(),
+            program_allowlist_tls_cert: "".to_owned(),
+            program_allowlist_tls_key: "".to_owned(),
+            program_allowlist_tls_ca: "".to_owned(),
             program_allowlist_expiry_sec: 60,
+            queue_capacity: Self::default_queue_capacity(),
+            queue_overflow_policy: QueueOverflowPolicy::default(),
+            batch_account_updates: false,
+            coalesce_account_updates: false,
+            rooted_account_updates: false,
+            max_in_flight_messages: 0,
+            max_in_flight_bytes: 0,
+            max_memory_bytes: 0,
+            priority_lane: false,
+            producer_shards: Self::default_producer_shards(),
+            serialization_workers: Self::default_serialization_workers(),
+            tenants: Vec::new(),
         }
     }
 }
@@ -85,33 +429,625 @@ impl Config {
     /// Read plugin from JSON file.
     pub fn read_from<P: AsRef<Path>>(config_path: P) -> PluginResult<Self> {
         let file = File::open(config_path)?;
-        let mut this: Self = serde_json::from_reader(file)
-            .map_err(|e| GeyserPluginError::ConfigFileReadError { msg: e.to_string() })?;
+        // serde_json quotes offending values in its errors, which may be credentials.
+        let mut this: Self =
+            serde_json::from_reader(file).map_err(|e| GeyserPluginError::ConfigFileReadError {
+                msg: redact::redact_quoted(&e.to_string()),
+            })?;
+        this.apply_preset()
+            .and_then(|()| this.apply_kerberos())
+            .and_then(|()| this.check_features())
+            .and_then(|()| this.check_partition_pinning())
+            .and_then(|()| this.shadow.as_ref().map_or(Ok(()), ShadowConfig::check))
+            .and_then(|()| this.check_tenants())
+            .and_then(|()| this.check_producer_identities())
+            .and_then(|()| {
+                let tenant_rules = this.tenants.iter().flat_map(|t| t.topic_rules.iter());
+                let rules: Vec<TopicRule> = this
+                    .topic_rules
+                    .iter()
+                    .chain(tenant_rules)
+                    .cloned()
+                    .collect();
+                check_topic_rules(&rules)
+            })
+            .and_then(|()| this.check_unrouted_accounts())
+            .and_then(|()| {
+                this.tenants
+                    .iter()
+                    .try_for_each(|tenant| tenant.config(&this).check_unrouted_accounts())
+            })
+            .map_err(|msg| GeyserPluginError::ConfigFileReadError { msg })?;
         this.fill_defaults();
         Ok(this)
     }
 
+    /// Cap on the records in flight, if any, applying the `queue_overflow_policy` to those exceeding it.
+    pub fn in_flight_budget(&self) -> Option<InFlightBudget> {
+        InFlightBudget::new(
+            self.max_in_flight_messages,
+            self.max_in_flight_bytes,
+            self.queue_overflow_policy,
+        )
+    }
+
     /// Create rdkafka::FutureProducer from config.
-    pub fn producer(&self) -> KafkaResult<Producer> {
+    pub fn producer(&self, context: DeliveryContext) -> KafkaResult<Producer> {
+        let context = context.with_oauth_bearer(self.sasl_oauthbearer.clone());
+        ThreadedProducer::from_config_and_context(&self.client_config(), context)
+    }
+
+    /// Create `producer_shards` producers from config.
+    ///
+    /// Each shard gets its own `client.id` suffix so they can be told apart on the brokers.
+    /// The producer of the `priority_lane` comes last.
+    pub fn producers(&self, context: DeliveryContext) -> KafkaResult<Vec<Producer>> {
+        let shards = self.producer_shards.max(1);
+        let mut producers = if shards == 1 {
+            vec![self.producer(context.clone())?]
+        } else {
+            let context = context
+                .clone()
+                .with_oauth_bearer(self.sasl_oauthbearer.clone());
+            (0..shards)
+                .map(|i| {
+                    let mut config = self.client_config();
+                    config.set("client.id", format!("{}-{}", self.client_id(), i));
+                    ThreadedProducer::from_config_and_context(&config, context.clone())
+                })
+                .collect::<KafkaResult<_>>()?
+        };
+        if self.priority_lane {
+            producers.push(self.priority_producer(context)?);
+        }
+        Ok(producers)
+    }
+
+    /// Producer of the priority lane, sending right away and exempt from the in-flight budget.
+    fn priority_producer(&self, context: DeliveryContext) -> KafkaResult<Producer> {
+        let mut config = self.client_config();
+        config
+            .set("client.id", format!("{}-priority", self.client_id()))
+            .remove("queue.buffering.max.ms")
+            .set("linger.ms", "0");
+        let context = context
+            .with_in_flight_budget(None)
+            .with_oauth_bearer(self.sasl_oauthbearer.clone());
+        ThreadedProducer::from_config_and_context(&config, context)
+    }
+
+    /// `client.id` of the producers, as set by the `producer_identity` or in `kafka`.
+    pub fn client_id(&self) -> &str {
+        self.producer_identities
+            .get(&self.producer_identity)
+            .and_then(|options| options.get("client.id"))
+            .or_else(|| self.kafka.get("client.id"))
+            .map(String::as_str)
+            .unwrap_or("rdkafka")
+    }
+
+    /// The `kafka` options with those of the `producer_identity` applied.
+    pub fn producer_options(&self) -> HashMap<String, String> {
+        let mut options = self.kafka.clone();
+        if let Some(identity) = self.producer_identities.get(&self.producer_identity) {
+            options.extend(identity.clone());
+        }
+        options
+    }
+
+    /// Client config for consuming the plugin's topics with the connection settings of `kafka`.
+    ///
+    /// Offsets are not committed, consumers of companion tools only ever read.
+    pub fn consumer_config(&self, group_id: &str) -> ClientConfig {
         let mut config = ClientConfig::new();
         for (k, v) in self.kafka.iter() {
+            if !PRODUCER_OPTIONS.contains(&k.as_str()) {
+                config.set(k, v);
+            }
+        }
+        config
+            .set("group.id", group_id)
+            .set("enable.auto.commit", "false");
+        config
+    }
+
+    /// Create a consumer from a [`Config::consumer_config`], authenticating like the producers.
+    pub fn consumer(&self, config: &ClientConfig) -> KafkaResult<BaseConsumer<DeliveryContext>> {
+        let context = DeliveryContext::default().with_oauth_bearer(self.sasl_oauthbearer.clone());
+        BaseConsumer::from_config_and_context(config, context)
+    }
+
+    fn client_config(&self) -> ClientConfig {
+        let mut config = ClientConfig::new();
+        for (k, v) in self.producer_options().iter() {
             config.set(k, v);
         }
-        ThreadedProducer::from_config(&config)
+        config
+    }
+
+    fn default_true() -> bool {
+        true
+    }
+
+    fn default_log_suppression_interval_sec() -> u64 {
+        10
+    }
+
+    fn default_drop_summary_interval_sec() -> u64 {
+        60
+    }
+
+    fn default_watermark_interval_ms() -> u64 {
+        1000
+    }
+
+    fn default_stats_interval_ms() -> u64 {
+        60_000
+    }
+
+    fn default_queue_capacity() -> usize {
+        100_000
+    }
+
+    fn default_token_balance_cache_size() -> usize {
+        100_000
+    }
+
+    fn default_owner_change_cache_size() -> usize {
+        100_000
+    }
+
+    fn default_producer_shards() -> usize {
+        1
     }
 
-    fn set_default(&mut self, k: &'static str, v: &'static str) {
+    fn default_serialization_workers() -> usize {
+        1
+    }
+
+    fn set_default(&mut self, k: &str, v: &str) {
         if !self.kafka.contains_key(k) {
             self.kafka.insert(k.to_owned(), v.to_owned());
         }
     }
 
+    /// Fills in the preset's settings not overridden in `kafka`.
+    fn apply_preset(&mut self) -> Result<(), String> {
+        match self.preset {
+            None => Ok(()),
+            Some(Preset::ConfluentCloud) => {
+                self.set_default("security.protocol", "SASL_SSL");
+                self.set_default("sasl.mechanism", "PLAIN");
+                self.set_default("request.required.acks", "all");
+                self.set_default("socket.keepalive.enable", "true");
+                self.set_default("metadata.max.age.ms", "60000");
+                self.set_default("request.timeout.ms", "30000");
+                self.set_default("message.timeout.ms", "300000");
+                self.require(&["bootstrap.servers", "sasl.username", "sasl.password"])
+            }
+        }
+    }
+
+    /// Rejects options needing a cargo feature the plugin was built without.
+    fn check_features(&self) -> Result<(), String> {
+        let tenants = || self.tenants.iter();
+        let required = [
+            (
+                !self.transaction_topic.is_empty()
+                    || tenants().any(|t| !t.transaction_topic.is_empty()),
+                "transaction_topic",
+                "transactions",
+                cfg!(feature = "transactions"),
+            ),
+            (
+                !self.vote_transaction_topic.is_empty()
+                    || tenants().any(|t| !t.vote_transaction_topic.is_empty()),
+                "vote_transaction_topic",
+                "transactions",
+                cfg!(feature = "transactions"),
+            ),
+            (
+                !self.transaction_status_topic.is_empty()
+                    || tenants().any(|t| !t.transaction_status_topic.is_empty()),
+                "transaction_status_topic",
+                "transactions",
+                cfg!(feature = "transactions"),
+            ),
+            (
+                !self.compressed_nft_topic.is_empty(),
+                "compressed_nft_topic",
+                "transactions",
+                cfg!(feature = "transactions"),
+            ),
+            (
+                self.include_parsed_instructions,
+                "include_parsed_instructions",
+                "parsed-instructions",
+                cfg!(feature = "parsed-instructions"),
+            ),
+            (
+                !self.program_allowlist_url.is_empty()
+                    || tenants().any(|t| !t.program_allowlist_url.is_empty()),
+                "program_allowlist_url",
+                "http-allowlist",
+                cfg!(feature = "http-allowlist"),
+            ),
+            (
+                matches!(
+                    self.sasl_oauthbearer,
+                    Some(OAuthBearer::ClientCredentials(_))
+                ),
+                "sasl_oauthbearer client_credentials",
+                "http",
+                cfg!(feature = "http"),
+            ),
+            (
+                matches!(
+                    self.payload_encryption,
+                    Some(PayloadEncryption::VaultTransit { .. })
+                ),
+                "payload_encryption vault_transit",
+                "http",
+                cfg!(feature = "http"),
+            ),
+            (
+                self.error_reporting.is_some(),
+                "error_reporting",
+                "http",
+                cfg!(feature = "http"),
+            ),
+            (
+                !self.admin_address.is_empty(),
+                "admin_address",
+                "admin",
+                cfg!(feature = "admin"),
+            ),
+            (
+                self.decode_accounts,
+                "decode_accounts",
+                "decoders",
+                cfg!(feature = "decoders"),
+            ),
+        ];
+        for (used, option, feature, enabled) in required {
+            if used && !enabled {
+                return Err(format!(
+                    "{} requires the plugin to be built with the `{}` feature",
+                    option, feature
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_partition_pinning(&self) -> Result<(), String> {
+        match &self.partition_pinning {
+            Some(pinning) => PartitionPinner::new(pinning).map(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Rejects tenants without a name, or sharing one, as their producers are told apart by it.
+    fn check_tenants(&self) -> Result<(), String> {
+        let mut names = std::collections::HashSet::new();
+        for tenant in &self.tenants {
+            if tenant.name.is_empty() {
+                return Err("tenants require a name".to_owned());
+            }
+            if !names.insert(tenant.name.as_str()) {
+                return Err(format!("tenant {} is defined more than once", tenant.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects handling unrouted account updates without any routing, or both ways at once.
+    fn check_unrouted_accounts(&self) -> Result<(), String> {
+        if self.unrouted_account_topic.is_empty() && !self.drop_unrouted_accounts {
+            return Ok(());
+        }
+        if self.program_allowlist_url.is_empty() && self.topic_rules.is_empty() {
+            return Err(
+                "unrouted_account_topic and drop_unrouted_accounts require program_allowlist_url or topic_rules"
+                    .to_owned(),
+            );
+        }
+        if !self.unrouted_account_topic.is_empty() && self.drop_unrouted_accounts {
+            return Err(
+                "unrouted_account_topic and drop_unrouted_accounts are mutually exclusive"
+                    .to_owned(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Rejects bindings to producer identities that are not defined.
+    fn check_producer_identities(&self) -> Result<(), String> {
+        let bound = std::iter::once(&self.producer_identity)
+            .chain(self.tenants.iter().map(|tenant| &tenant.producer_identity));
+        for identity in bound {
+            if !identity.is_empty() && !self.producer_identities.contains_key(identity) {
+                return Err(format!("producer identity {} is not defined", identity));
+            }
+        }
+        Ok(())
+    }
+
+    /// Translates `kerberos` into librdkafka's `sasl.kerberos.*` options not set in `kafka`.
+    fn apply_kerberos(&mut self) -> Result<(), String> {
+        let kerberos = match self.kerberos.take() {
+            Some(kerberos) => kerberos,
+            None => return Ok(()),
+        };
+        if !cfg!(feature = "kerberos") {
+            return Err(
+                "kerberos requires the plugin to be built with the `kerberos` feature".to_owned(),
+            );
+        }
+        self.set_default("security.protocol", "SASL_SSL");
+        self.set_default("sasl.mechanism", "GSSAPI");
+        self.set_default("sasl.kerberos.principal", &kerberos.principal);
+        self.set_default("sasl.kerberos.keytab", &kerberos.keytab);
+        self.set_default("sasl.kerberos.service.name", &kerberos.service_name);
+        if let Some(kinit_cmd) = &kerberos.kinit_cmd {
+            self.set_default("sasl.kerberos.kinit.cmd", kinit_cmd);
+        }
+        self.kerberos = Some(kerberos);
+        Ok(())
+    }
+
+    fn require(&self, keys: &[&str]) -> Result<(), String> {
+        let missing: Vec<&str> = keys
+            .iter()
+            .copied()
+            .filter(|k| self.kafka.get(*k).map_or(true, |v| v.is_empty()))
+            .collect();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "Preset requires kafka options: {}",
+                missing.join(", ")
+            ))
+        }
+    }
+
     fn fill_defaults(&mut self) {
         self.set_default("request.required.acks", "1");
         self.set_default("message.timeout.ms", "30000");
         self.set_default("compression.type", "lz4");
         self.set_default("partitioner", "murmur2_random");
+        // Brokers coming back up are only learned from the statistics.
+        if self.broker_down_spool.is_some() {
+            self.set_default("statistics.interval.ms", "1000");
+        }
+        if !self.stats_topic.is_empty() && self.stats_node_name.is_empty() {
+            self.stats_node_name = std::fs::read_to_string("/proc/sys/kernel/hostname")
+                .map(|name| name.trim_end().to_owned())
+                .or_else(|_| std::env::var("HOSTNAME"))
+                .unwrap_or_else(|_| "unknown".to_owned());
+        }
+        if self.sasl_oauthbearer.is_some() {
+            self.set_default("security.protocol", "SASL_SSL");
+            self.set_default("sasl.mechanism", "OAUTHBEARER");
+        }
+    }
+}
+
+/// Output backend of the published events.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Sink {
+    /// Produce protobuf encoded events to Kafka.
+    Kafka,
+    /// Write events as JSON lines to stdout.
+    Stdout,
+    /// Write events as JSON lines to a named pipe or file.
+    Pipe(String),
+    /// Write events to rotating files, one directory per topic.
+    File(FileSink),
+}
+
+impl Default for Sink {
+    fn default() -> Self {
+        Self::Kafka
+    }
+}
+
+/// Archival output used in place of Kafka.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct FileSink {
+    /// Directory holding a subdirectory per topic.
+    pub directory: String,
+    #[serde(default)]
+    pub format: FileFormat,
+    #[serde(flatten)]
+    pub rotation: Rotation,
+}
+
+/// Record encoding of the file sink.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileFormat {
+    /// One JSON event per line.
+    Json,
+    /// Protobuf events, each preceded by its length as a big endian `u32`.
+    Protobuf,
+}
+
+impl Default for FileFormat {
+    fn default() -> Self {
+        Self::Json
+    }
+}
+
+/// SASL GSSAPI (Kerberos) client identity.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Kerberos {
+    /// Client principal, e.g. `solana/validator.example.com@EXAMPLE.COM`.
+    pub principal: String,
+    /// Keytab holding the principal's keys.
+    pub keytab: String,
+    /// Kerberos principal name the brokers run as.
+    #[serde(default = "Kerberos::default_service_name")]
+    pub service_name: String,
+    /// Overrides librdkafka's command for refreshing the ticket.
+    #[serde(default)]
+    pub kinit_cmd: Option<String>,
+}
+
+impl Kerberos {
+    fn default_service_name() -> String {
+        "kafka".to_owned()
+    }
+}
+
+/// Settings bundle for a hosted Kafka service.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum Preset {
+    /// Confluent Cloud with API key authentication.
+    /// `sasl.username` and `sasl.password` take the API key and secret.
+    #[serde(rename = "confluent-cloud")]
+    ConfluentCloud,
+}
+
+/// Behavior of the publisher queue when it is full.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueOverflowPolicy {
+    /// Drop the new event and report an error to the validator.
+    #[serde(alias = "drop_newest")]
+    Drop,
+    /// Drop the oldest queued event to make room for the new one.
+    DropOldest,
+    /// Drop the queued events with the largest payloads to make room for the new one.
+    DropLargest,
+    /// Block the geyser callback until the publisher thread catches up.
+    Block,
+}
+
+impl Default for QueueOverflowPolicy {
+    fn default() -> Self {
+        Self::Drop
+    }
+}
+
+/// Where records carry the deterministic identity of their event.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IdempotencyKeyPlacement {
+    /// Records carry no idempotency key.
+    None,
+    /// In the `idempotency-key` header.
+    Header,
+    /// As the record key, replacing the default key.
+    Key,
+}
+
+impl Default for IdempotencyKeyPlacement {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+/// Payload of account update records on Kafka.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountPayloadFormat {
+    /// The protobuf encoded `UpdateAccountEvent`.
+    Protobuf,
+    /// JSON shaped like the `result` of RPC `accountSubscribe` notifications.
+    RpcAccount,
+    /// JSON shaped like the `result` of RPC `programSubscribe` notifications.
+    RpcProgram,
+}
+
+impl Default for AccountPayloadFormat {
+    fn default() -> Self {
+        Self::Protobuf
+    }
+}
+
+/// Schema of the account, slot status and transaction records on Kafka.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventSchema {
+    /// This plugin's own `event.proto`.
+    Native,
+    /// `geyser.SubscribeUpdate` messages of Yellowstone gRPC.
+    Yellowstone,
+}
+
+impl Default for EventSchema {
+    fn default() -> Self {
+        Self::Native
     }
 }
 
-pub type Producer = ThreadedProducer<DefaultProducerContext>;
+pub type Producer = ThreadedProducer<DeliveryContext>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_confluent_cloud_preset() {
+        let mut config: Config = serde_json::from_str(
+            r#"{
+                "preset": "confluent-cloud",
+                "kafka": {
+                    "bootstrap.servers": "pkc-123.us-east-1.aws.confluent.cloud:9092",
+                    "sasl.username": "KEY",
+                    "sasl.password": "SECRET",
+                    "request.required.acks": "1"
+                }
+            }"#,
+        )
+        .unwrap();
+        config.apply_preset().unwrap();
+        config.fill_defaults();
+        assert_eq!(config.kafka["security.protocol"], "SASL_SSL");
+        assert_eq!(config.kafka["sasl.mechanism"], "PLAIN");
+        assert_eq!(config.kafka["request.required.acks"], "1");
+        assert_eq!(config.kafka["message.timeout.ms"], "300000");
+
+        config.kafka.remove("sasl.password");
+        let err = config.apply_preset().unwrap_err();
+        assert!(err.contains("sasl.password"));
+        assert!(!err.contains("sasl.username"));
+    }
+
+    #[cfg(feature = "kerberos")]
+    #[test]
+    fn test_kerberos() {
+        let mut config: Config = serde_json::from_str(
+            r#"{
+                "kafka": { "security.protocol": "SASL_PLAINTEXT" },
+                "kerberos": { "principal": "solana@EXAMPLE.COM", "keytab": "/etc/solana.keytab" }
+            }"#,
+        )
+        .unwrap();
+        config.apply_kerberos().unwrap();
+        assert_eq!(config.kafka["security.protocol"], "SASL_PLAINTEXT");
+        assert_eq!(config.kafka["sasl.mechanism"], "GSSAPI");
+        assert_eq!(
+            config.kafka["sasl.kerberos.principal"],
+            "solana@EXAMPLE.COM"
+        );
+        assert_eq!(config.kafka["sasl.kerberos.keytab"], "/etc/solana.keytab");
+        assert_eq!(config.kafka["sasl.kerberos.service.name"], "kafka");
+        assert!(!config.kafka.contains_key("sasl.kerberos.kinit.cmd"));
+
+        // Options set in `kafka` win over the `kerberos` ones.
+        let mut config: Config = serde_json::from_str(
+            r#"{
+                "kafka": { "sasl.kerberos.service.name": "broker" },
+                "kerberos": { "principal": "solana@EXAMPLE.COM", "keytab": "/etc/solana.keytab" }
+            }"#,
+        )
+        .unwrap();
+        config.apply_kerberos().unwrap();
+        assert_eq!(config.kafka["sasl.kerberos.service.name"], "broker");
+        assert_eq!(config.kafka["sasl.kerberos.keytab"], "/etc/solana.keytab");
+    }
+}
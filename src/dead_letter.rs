@@ -0,0 +1,125 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    crossbeam_channel::{bounded, Receiver, Sender},
+    rdkafka::{
+        error::KafkaError,
+        message::{BorrowedMessage, Headers, Message},
+    },
+};
+
+/// Header naming why a record was dead-lettered.
+pub const DEAD_LETTER_REASON_HEADER: &str = "dead-letter-reason";
+
+/// Header naming the topic a dead-lettered record was meant for.
+pub const DEAD_LETTER_TOPIC_HEADER: &str = "dead-letter-topic";
+
+/// Dead letters waiting to be published, beyond which they are dropped.
+const CAPACITY: usize = 10_000;
+
+/// A record Kafka gave up on, as it was produced.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DeadLetter {
+    pub topic: String,
+    pub key: Option<Vec<u8>>,
+    pub headers: Vec<(String, Option<Vec<u8>>)>,
+    pub payload: Option<Vec<u8>>,
+    pub reason: String,
+}
+
+impl DeadLetter {
+    pub fn new(message: &BorrowedMessage<'_>, error: &KafkaError) -> Self {
+        let headers = message.headers().map_or_else(Vec::new, |headers| {
+            (0..headers.count())
+                .map(|i| {
+                    let header = headers.get(i);
+                    (header.key.to_owned(), header.value.map(<[u8]>::to_vec))
+                })
+                .collect()
+        });
+        Self {
+            topic: message.topic().to_owned(),
+            key: message.key().map(<[u8]>::to_vec),
+            headers,
+            payload: message.payload().map(<[u8]>::to_vec),
+            reason: error
+                .rdkafka_error_code()
+                .map_or_else(|| error.to_string(), |code| code.to_string()),
+        }
+    }
+}
+
+/// Records that timed out on their way to Kafka, to be republished to the dead-letter topic.
+pub struct DeadLetters {
+    topic: String,
+    sender: Sender<DeadLetter>,
+    receiver: Receiver<DeadLetter>,
+}
+
+impl DeadLetters {
+    pub fn new(topic: String) -> Self {
+        let (sender, receiver) = bounded(CAPACITY);
+        Self {
+            topic,
+            sender,
+            receiver,
+        }
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Dead letters ready to be published.
+    pub fn receiver(&self) -> &Receiver<DeadLetter> {
+        &self.receiver
+    }
+
+    /// Queues `letter` for publication, unless it failed on the dead-letter topic itself.
+    ///
+    /// Never blocks, as it is called from the producers' delivery callbacks.
+    pub fn push(&self, letter: DeadLetter) {
+        if letter.topic == self.topic {
+            metrics().dead_letters_dropped.inc();
+            return;
+        }
+        match self.sender.try_send(letter) {
+            Ok(()) => metrics().dead_letters_queued.inc(),
+            Err(_) => metrics().dead_letters_dropped.inc(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dead_letters() {
+        let letters = DeadLetters::new("dlq".to_owned());
+        let letter = |topic: &str| DeadLetter {
+            topic: topic.to_owned(),
+            key: None,
+            headers: Vec::new(),
+            payload: Some(vec![1]),
+            reason: "Local: Message timed out".to_owned(),
+        };
+        letters.push(letter("accounts"));
+        letters.push(letter("dlq"));
+        assert_eq!(letters.receiver().try_recv().unwrap(), letter("accounts"));
+        assert!(letters.receiver().try_recv().is_err());
+    }
+}
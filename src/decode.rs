@@ -0,0 +1,195 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{
+        token::{TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID},
+        *,
+    },
+    lazy_static::lazy_static,
+    solana_program::{pubkey::Pubkey, stake, system_program, vote},
+    std::{collections::HashMap, str::FromStr, sync::Arc},
+};
+
+lazy_static! {
+    static ref ADDRESS_LOOKUP_TABLE_PROGRAM_ID: Pubkey =
+        Pubkey::from_str("AddressLookupTab1e1111111111111111111111111").unwrap();
+}
+
+/// Decodes the accounts of one program into structured form.
+pub trait ProgramDecoder: Send + Sync {
+    /// The program owning the accounts decoded.
+    fn program_id(&self) -> Pubkey;
+
+    /// Decodes the data of an account, `None` if not initialized or of unknown layout.
+    fn decode(&self, data: &[u8]) -> Option<decoded_account::Account>;
+}
+
+/// Decoders of the programs whose accounts are decoded, by program ID.
+#[derive(Clone, Default)]
+pub struct DecoderRegistry {
+    decoders: HashMap<Pubkey, Arc<dyn ProgramDecoder>>,
+}
+
+impl DecoderRegistry {
+    /// The decoders built into the plugin: the SPL Token programs, stake, nonce and address lookup table
+    /// accounts, vote accounts if `vote_accounts` is set, and the third-party programs enabled by cargo features.
+    pub fn builtin(vote_accounts: bool) -> Self {
+        let mut registry = Self::default();
+        registry.register(Arc::new(TokenDecoder { token_2022: false }));
+        registry.register(Arc::new(TokenDecoder { token_2022: true }));
+        registry.register(Arc::new(NonceDecoder));
+        registry.register(Arc::new(StakeDecoder));
+        registry.register(Arc::new(LookupTableDecoder));
+        if vote_accounts {
+            registry.register(Arc::new(VoteDecoder));
+        }
+        #[cfg(feature = "openbook")]
+        registry.register(Arc::new(OpenBookDecoder));
+        registry
+    }
+
+    /// The built-in decoders as configured, `None` unless `decode_accounts` is set.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config
+            .decode_accounts
+            .then(|| Self::builtin(config.decode_vote_accounts))
+    }
+
+    /// Adds `decoder`, replacing any decoder registered for the same program.
+    pub fn register(&mut self, decoder: Arc<dyn ProgramDecoder>) {
+        self.decoders.insert(decoder.program_id(), decoder);
+    }
+
+    /// Decodes the data of an account owned by `owner`, if a decoder is registered for it.
+    pub fn decode(&self, owner: &[u8], data: &[u8]) -> Option<DecodedAccount> {
+        let owner = Pubkey::new_from_array(owner.try_into().ok()?);
+        let account = self.decoders.get(&owner)?.decode(data)?;
+        Some(DecodedAccount {
+            account: Some(account),
+        })
+    }
+
+    /// Sets `decoded` of an account event from its data, which must not be sliced.
+    pub fn enrich(&self, event: &mut UpdateAccountEvent) {
+        event.decoded = self.decode(&event.owner, &event.data);
+    }
+}
+
+struct TokenDecoder {
+    token_2022: bool,
+}
+
+impl ProgramDecoder for TokenDecoder {
+    fn program_id(&self) -> Pubkey {
+        if self.token_2022 {
+            *TOKEN_2022_PROGRAM_ID
+        } else {
+            *TOKEN_PROGRAM_ID
+        }
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<decoded_account::Account> {
+        decode_token(self.token_2022, data)
+    }
+}
+
+struct NonceDecoder;
+
+impl ProgramDecoder for NonceDecoder {
+    fn program_id(&self) -> Pubkey {
+        system_program::id()
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<decoded_account::Account> {
+        decode_nonce(data)
+    }
+}
+
+struct StakeDecoder;
+
+impl ProgramDecoder for StakeDecoder {
+    fn program_id(&self) -> Pubkey {
+        stake::program::id()
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<decoded_account::Account> {
+        decode_stake(data)
+    }
+}
+
+struct VoteDecoder;
+
+impl ProgramDecoder for VoteDecoder {
+    fn program_id(&self) -> Pubkey {
+        vote::program::id()
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<decoded_account::Account> {
+        decode_vote(data)
+    }
+}
+
+struct LookupTableDecoder;
+
+impl ProgramDecoder for LookupTableDecoder {
+    fn program_id(&self) -> Pubkey {
+        *ADDRESS_LOOKUP_TABLE_PROGRAM_ID
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<decoded_account::Account> {
+        decode_lookup_table(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixedDecoder(Pubkey);
+
+    impl ProgramDecoder for FixedDecoder {
+        fn program_id(&self) -> Pubkey {
+            self.0
+        }
+
+        fn decode(&self, _data: &[u8]) -> Option<decoded_account::Account> {
+            Some(decoded_account::Account::NonceAccount(
+                NonceAccount::default(),
+            ))
+        }
+    }
+
+    #[test]
+    fn test_decoder_registry() {
+        let nonce_account = Some(DecodedAccount {
+            account: Some(decoded_account::Account::NonceAccount(
+                NonceAccount::default(),
+            )),
+        });
+        let vote_program = vote::program::id();
+        let mut registry = DecoderRegistry::builtin(false);
+        assert_eq!(registry.decode(vote_program.as_ref(), &[]), None);
+        assert_eq!(registry.decode(&[1, 2, 3], &[]), None);
+
+        registry.register(Arc::new(FixedDecoder(vote_program)));
+        assert_eq!(registry.decode(vote_program.as_ref(), &[]), nonce_account);
+        // Registering replaces the built-in decoder of a program.
+        registry.register(Arc::new(FixedDecoder(stake::program::id())));
+        assert_eq!(
+            registry.decode(stake::program::id().as_ref(), &[]),
+            nonce_account
+        );
+    }
+}
@@ -0,0 +1,417 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    std::{
+        collections::{hash_map::DefaultHasher, HashMap, HashSet, VecDeque},
+        hash::{Hash, Hasher},
+        mem::size_of,
+        time::{Duration, Instant},
+    },
+};
+
+/// Remembers recently published transaction signatures for a fixed time window.
+///
+/// The same transaction is notified once for every fork it is replayed on,
+/// so publishing it only on first sight suppresses the duplicates.
+pub struct SignatureCache {
+    ttl: Duration,
+    seen: HashSet<[u8; 64]>,
+    expiry: VecDeque<(Instant, [u8; 64])>,
+    memory: MemoryUsage,
+}
+
+impl SignatureCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            seen: HashSet::new(),
+            expiry: VecDeque::new(),
+            memory: MemoryUsage::new(MemoryComponent::DedupCaches),
+        }
+    }
+
+    /// Records `signature`, returning whether it was not seen within the window.
+    pub fn insert(&mut self, signature: &[u8]) -> bool {
+        self.insert_at(signature, Instant::now())
+    }
+
+    fn insert_at(&mut self, signature: &[u8], now: Instant) -> bool {
+        while let Some((inserted, key)) = self.expiry.front() {
+            if now.duration_since(*inserted) < self.ttl {
+                break;
+            }
+            self.seen.remove(key);
+            self.expiry.pop_front();
+        }
+
+        let mut key = [0u8; 64];
+        let len = signature.len().min(key.len());
+        key[..len].copy_from_slice(&signature[..len]);
+        if !self.seen.insert(key) {
+            metrics().duplicate_transactions_suppressed.inc();
+            return false;
+        }
+        self.expiry.push_back((now, key));
+        self.memory.set(
+            self.seen.capacity() * size_of::<[u8; 64]>()
+                + self.expiry.capacity() * size_of::<(Instant, [u8; 64])>(),
+        );
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+/// Remembers the last published state of the most recently updated accounts.
+///
+/// Programs frequently rewrite accounts with the very same bytes,
+/// so an update matching the last published state carries nothing new.
+pub struct AccountStateCache {
+    capacity: usize,
+    tick: u64,
+    /// State hash and last use of every cached account.
+    states: HashMap<[u8; 32], (u64, u64)>,
+    /// Accounts in order of use. Entries superseded by a later use are skipped on eviction.
+    recency: VecDeque<([u8; 32], u64)>,
+    memory: MemoryUsage,
+}
+
+impl AccountStateCache {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be positive");
+        Self {
+            capacity,
+            tick: 0,
+            states: HashMap::with_capacity(capacity),
+            recency: VecDeque::with_capacity(capacity),
+            memory: MemoryUsage::new(MemoryComponent::DedupCaches),
+        }
+    }
+
+    /// Records the state of account `pubkey`, returning whether it differs from the last one recorded.
+    pub fn insert(
+        &mut self,
+        pubkey: &[u8],
+        owner: &[u8],
+        lamports: u64,
+        executable: bool,
+        data: &[u8],
+    ) -> bool {
+        let hash = state_hash(owner, lamports, executable, data);
+        let key = account_key(pubkey);
+
+        self.tick += 1;
+        self.recency.push_back((key, self.tick));
+        let changed = match self.states.insert(key, (hash, self.tick)) {
+            Some((previous, _)) => previous != hash,
+            None => true,
+        };
+        if !changed {
+            metrics().duplicate_account_states_suppressed.inc();
+        }
+        self.evict();
+        self.memory.set(
+            self.states.capacity() * size_of::<([u8; 32], (u64, u64))>()
+                + self.recency.capacity() * size_of::<([u8; 32], u64)>(),
+        );
+        changed
+    }
+
+    fn evict(&mut self) {
+        while self.states.len() > self.capacity {
+            let (key, tick) = self.recency.pop_front().expect("every state has a use");
+            if self
+                .states
+                .get(&key)
+                .map_or(false, |(_, last)| *last == tick)
+            {
+                self.states.remove(&key);
+                metrics().account_state_cache_evictions.inc();
+            }
+        }
+        // Hot accounts pile up superseded uses, drop them before they outgrow the states.
+        if self.recency.len() > 2 * self.capacity {
+            let states = &self.states;
+            self.recency
+                .retain(|(key, tick)| states.get(key).map_or(false, |(_, last)| last == tick));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+}
+
+/// An account state held back by [`ForkDedupWindow`], with every slot it was seen in.
+struct HeldState {
+    key: ([u8; 32], u64),
+    event: UpdateAccountEvent,
+    slots: Vec<u64>,
+    held: Instant,
+}
+
+/// Holds account updates back for a short window to publish states seen on several forks once.
+///
+/// Competing forks often write the very same state, so an update matching
+/// the newest state held of its account only adds its slot to the list published along with it.
+pub struct ForkDedupWindow {
+    window: Duration,
+    /// Sequence number of the front of `held`.
+    front: u64,
+    held: VecDeque<HeldState>,
+    /// Sequence number of the newest held update of every account and state hash.
+    index: HashMap<([u8; 32], u64), u64>,
+    /// Sequence number of the newest held update of every account.
+    latest: HashMap<[u8; 32], u64>,
+    memory: MemoryUsage,
+}
+
+impl ForkDedupWindow {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            front: 0,
+            held: VecDeque::new(),
+            index: HashMap::new(),
+            latest: HashMap::new(),
+            memory: MemoryUsage::new(MemoryComponent::DedupCaches),
+        }
+    }
+
+    /// Holds `event` back, returning whether it is a new state rather than the newest one held of
+    /// the account.
+    ///
+    /// An account changing back to a state held before gets a new entry, so the states are
+    /// released in the order they were written.
+    pub fn push(&mut self, event: UpdateAccountEvent) -> bool {
+        self.push_at(event, Instant::now())
+    }
+
+    fn push_at(&mut self, event: UpdateAccountEvent, now: Instant) -> bool {
+        let hash = state_hash(&event.owner, event.lamports, event.executable, &event.data);
+        let pubkey = account_key(&event.pubkey);
+        let key = (pubkey, hash);
+        let latest = self.latest.get(&pubkey);
+        if let Some(seq) = self.index.get(&key).filter(|&seq| Some(seq) == latest) {
+            let held = &mut self.held[(seq - self.front) as usize];
+            if !held.slots.contains(&event.slot) {
+                held.slots.push(event.slot);
+            }
+            metrics().fork_duplicate_account_updates_suppressed.inc();
+            return false;
+        }
+        let seq = self.front + self.held.len() as u64;
+        self.index.insert(key, seq);
+        self.latest.insert(pubkey, seq);
+        self.memory.add(Self::held_bytes(&event));
+        self.held.push_back(HeldState {
+            key,
+            slots: vec![event.slot],
+            event,
+            held: now,
+        });
+        true
+    }
+
+    /// Releases the updates held for the whole window, in the order they were first seen.
+    pub fn expired(&mut self) -> Vec<(UpdateAccountEvent, Vec<u64>)> {
+        self.expired_at(Instant::now())
+    }
+
+    fn expired_at(&mut self, now: Instant) -> Vec<(UpdateAccountEvent, Vec<u64>)> {
+        let window = self.window;
+        let count = self
+            .held
+            .iter()
+            .take_while(|held| now.duration_since(held.held) >= window)
+            .count();
+        self.release(count)
+    }
+
+    /// Releases every held update.
+    pub fn take_all(&mut self) -> Vec<(UpdateAccountEvent, Vec<u64>)> {
+        self.release(self.held.len())
+    }
+
+    fn release(&mut self, count: usize) -> Vec<(UpdateAccountEvent, Vec<u64>)> {
+        let front = self.front;
+        self.front += count as u64;
+        let (index, latest, memory) = (&mut self.index, &mut self.latest, &mut self.memory);
+        self.held
+            .drain(..count)
+            .zip(front..)
+            .map(|(held, seq)| {
+                // Newer entries of the same state or account stay indexed.
+                if index.get(&held.key) == Some(&seq) {
+                    index.remove(&held.key);
+                }
+                if latest.get(&held.key.0) == Some(&seq) {
+                    latest.remove(&held.key.0);
+                }
+                memory.sub(Self::held_bytes(&held.event));
+                (held.event, held.slots)
+            })
+            .collect()
+    }
+
+    /// Approximate bytes of a held update and its index entries.
+    fn held_bytes(event: &UpdateAccountEvent) -> usize {
+        size_of::<HeldState>()
+            + size_of::<(([u8; 32], u64), u64)>()
+            + size_of::<([u8; 32], u64)>()
+            + account_update_bytes(event)
+    }
+
+    pub fn len(&self) -> usize {
+        self.held.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.held.is_empty()
+    }
+}
+
+fn state_hash(owner: &[u8], lamports: u64, executable: bool, data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    lamports.hash(&mut hasher);
+    owner.hash(&mut hasher);
+    executable.hash(&mut hasher);
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn account_key(pubkey: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    let len = pubkey.len().min(key.len());
+    key[..len].copy_from_slice(&pubkey[..len]);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_cache() {
+        let mut cache = SignatureCache::new(Duration::from_secs(10));
+        let start = Instant::now();
+        assert!(cache.insert_at(&[1; 64], start));
+        assert!(cache.insert_at(&[2; 64], start + Duration::from_secs(5)));
+        assert!(!cache.insert_at(&[1; 64], start + Duration::from_secs(9)));
+        assert_eq!(cache.len(), 2);
+
+        // The first signature expires, the second is still within its window.
+        assert!(cache.insert_at(&[1; 64], start + Duration::from_secs(10)));
+        assert!(!cache.insert_at(&[2; 64], start + Duration::from_secs(11)));
+        assert_eq!(cache.len(), 2);
+
+        assert!(cache.insert_at(&[3; 64], start + Duration::from_secs(30)));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_account_state_cache() {
+        let mut cache = AccountStateCache::new(2);
+        assert!(cache.insert(&[1; 32], &[0; 32], 10, false, b"a"));
+        assert!(!cache.insert(&[1; 32], &[0; 32], 10, false, b"a"));
+        assert!(cache.insert(&[1; 32], &[0; 32], 11, false, b"a"));
+        assert!(cache.insert(&[1; 32], &[0; 32], 11, false, b"b"));
+        assert!(cache.insert(&[2; 32], &[0; 32], 10, false, b"a"));
+
+        // Account 1 was used last, so account 2 is evicted.
+        assert!(!cache.insert(&[1; 32], &[0; 32], 11, false, b"b"));
+        assert!(cache.insert(&[3; 32], &[0; 32], 10, false, b"a"));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.insert(&[2; 32], &[0; 32], 10, false, b"a"));
+        assert!(!cache.insert(&[2; 32], &[0; 32], 10, false, b"a"));
+
+        for _ in 0..10 {
+            cache.insert(&[2; 32], &[0; 32], 10, false, b"a");
+        }
+        assert!(cache.recency.len() <= 4);
+    }
+
+    #[test]
+    fn test_fork_dedup_window() {
+        let update = |slot, lamports| UpdateAccountEvent {
+            slot,
+            pubkey: vec![1; 32].into(),
+            lamports,
+            ..UpdateAccountEvent::default()
+        };
+        let mut window = ForkDedupWindow::new(Duration::from_millis(400));
+        let start = Instant::now();
+        assert!(window.push_at(update(10, 5), start));
+        assert!(window.push_at(update(10, 6), start));
+        // Slot 11 competes with slot 10 and wrote the same state.
+        assert!(!window.push_at(update(11, 6), start + Duration::from_millis(100)));
+        assert!(!window.push_at(update(11, 6), start + Duration::from_millis(100)));
+        assert!(window
+            .expired_at(start + Duration::from_millis(399))
+            .is_empty());
+
+        let released = window.expired_at(start + Duration::from_millis(400));
+        let released: Vec<(u64, Vec<u64>)> = released
+            .into_iter()
+            .map(|(ev, slots)| (ev.lamports, slots))
+            .collect();
+        assert_eq!(released, vec![(5, vec![10]), (6, vec![10, 11])]);
+        assert!(window.is_empty());
+
+        // Released states are held again when seen once more.
+        assert!(window.push_at(update(12, 5), start + Duration::from_millis(500)));
+        assert_eq!(window.take_all().len(), 1);
+    }
+
+    #[test]
+    fn test_fork_dedup_window_state_changed_back() {
+        let update = |slot, lamports| UpdateAccountEvent {
+            slot,
+            pubkey: vec![1; 32].into(),
+            lamports,
+            ..UpdateAccountEvent::default()
+        };
+        let mut window = ForkDedupWindow::new(Duration::from_millis(400));
+        let start = Instant::now();
+        assert!(window.push_at(update(10, 5), start));
+        assert!(window.push_at(update(11, 6), start));
+        // Back to the first state, which must be released last.
+        assert!(window.push_at(update(12, 5), start));
+        assert!(!window.push_at(update(13, 5), start));
+
+        let released: Vec<(u64, Vec<u64>)> = window
+            .take_all()
+            .into_iter()
+            .map(|(ev, slots)| (ev.lamports, slots))
+            .collect();
+        assert_eq!(
+            released,
+            vec![(5, vec![10]), (6, vec![11]), (5, vec![12, 13])]
+        );
+        assert!(window.index.is_empty());
+        assert!(window.latest.is_empty());
+    }
+}
@@ -0,0 +1,387 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    crossbeam_channel::{bounded, unbounded, Receiver, Sender},
+    rdkafka::{
+        client::OAuthToken,
+        consumer::ConsumerContext,
+        error::{KafkaError, RDKafkaErrorCode},
+        message::Message,
+        producer::{DeliveryResult, ProducerContext},
+        statistics::Statistics,
+        ClientContext,
+    },
+    std::{
+        collections::BTreeMap,
+        error::Error,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// Delivery opaque of records not tracked by a [`SlotTracker`].
+pub const UNTRACKED: usize = 0;
+
+/// Delivery opaque of an account record of `slot`.
+pub fn slot_opaque(slot: u64) -> usize {
+    slot as usize + 1
+}
+
+pub(crate) fn opaque_slot(opaque: usize) -> Option<u64> {
+    opaque.checked_sub(1).map(|slot| slot as u64)
+}
+
+#[derive(Default)]
+struct SlotDeliveries {
+    pending: u64,
+    delivered: u64,
+    failed: u64,
+    closed: bool,
+}
+
+/// Counts the account records of every slot until Kafka confirmed or rejected them.
+///
+/// Once a slot is closed and nothing is pending anymore,
+/// a `SlotAccountsComplete` marker is queued for publication.
+pub struct SlotTracker {
+    slots: Mutex<BTreeMap<u64, SlotDeliveries>>,
+    newest_delivered: AtomicU64,
+    sender: Sender<SlotAccountsComplete>,
+    receiver: Receiver<SlotAccountsComplete>,
+}
+
+impl Default for SlotTracker {
+    fn default() -> Self {
+        let (sender, receiver) = unbounded();
+        Self {
+            slots: Default::default(),
+            newest_delivered: AtomicU64::new(0),
+            sender,
+            receiver,
+        }
+    }
+}
+
+impl SlotTracker {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Markers of completed slots, ready to be published.
+    pub fn completed(&self) -> &Receiver<SlotAccountsComplete> {
+        &self.receiver
+    }
+
+    /// Registers an account record of `slot` on its way to Kafka.
+    pub fn dispatched(&self, slot: u64) {
+        let mut slots = self.slots.lock().unwrap();
+        slots.entry(slot).or_default().pending += 1;
+    }
+
+    /// Registers the outcome of a previously dispatched record.
+    pub fn finished(&self, slot: u64, delivered: bool) {
+        let mut slots = self.slots.lock().unwrap();
+        let deliveries = match slots.get_mut(&slot) {
+            Some(deliveries) => deliveries,
+            // Pruned already, the slot was abandoned.
+            None => return,
+        };
+        deliveries.pending = deliveries.pending.saturating_sub(1);
+        if delivered {
+            deliveries.delivered += 1;
+            self.newest_delivered.fetch_max(slot, Ordering::Relaxed);
+        } else {
+            deliveries.failed += 1;
+        }
+        if deliveries.closed && deliveries.pending == 0 {
+            self.complete(slot, deliveries);
+        }
+    }
+
+    /// Marks that no more account records of `slot` will be dispatched.
+    ///
+    /// Only the first call per slot has an effect.
+    pub fn close(&self, slot: u64) {
+        let mut slots = self.slots.lock().unwrap();
+        let deliveries = slots.entry(slot).or_default();
+        if deliveries.closed {
+            return;
+        }
+        deliveries.closed = true;
+        if deliveries.pending == 0 {
+            self.complete(slot, deliveries);
+        }
+    }
+
+    /// Forgets all slots older than the new root, either completed or abandoned.
+    pub fn prune(&self, root: u64) {
+        let mut slots = self.slots.lock().unwrap();
+        *slots = slots.split_off(&root);
+    }
+
+    /// The oldest slot still in flight and the newest one delivered.
+    pub fn watermark(&self) -> Watermark {
+        let slots = self.slots.lock().unwrap();
+        let oldest_in_flight_slot = slots
+            .iter()
+            .find(|(_, deliveries)| deliveries.pending > 0)
+            .map_or(0, |(slot, _)| *slot);
+        Watermark {
+            oldest_in_flight_slot,
+            newest_delivered_slot: self.newest_delivered.load(Ordering::Relaxed),
+            unix_time_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+        }
+    }
+
+    fn complete(&self, slot: u64, deliveries: &SlotDeliveries) {
+        // Unbounded, so this never blocks the producer's delivery callbacks.
+        let _ = self.sender.send(SlotAccountsComplete {
+            slot,
+            messages_delivered: deliveries.delivered,
+            messages_failed: deliveries.failed,
+        });
+    }
+}
+
+/// Producer context reporting deliveries of tracked records to a [`SlotTracker`],
+/// returning the [`InFlightBudget`] of delivered records, reporting fatal errors
+/// and generating `OAUTHBEARER` tokens.
+#[derive(Clone, Default)]
+pub struct DeliveryContext {
+    tracker: Option<Arc<SlotTracker>>,
+    budget: Option<Arc<InFlightBudget>>,
+    buffered: Arc<BufferedBytes>,
+    latency: Option<Arc<DeliveryLatency>>,
+    brokers: Arc<BrokerStates>,
+    dead_letters: Option<Arc<DeadLetters>>,
+    /// Reasons of fatal errors, at most one waiting to be handled.
+    fatal_errors: Option<(Sender<String>, Receiver<String>)>,
+    oauth_bearer: Option<OAuthBearer>,
+    error_reporter: Option<Arc<ErrorReporter>>,
+}
+
+impl DeliveryContext {
+    pub fn tracking(tracker: Arc<SlotTracker>) -> Self {
+        Self {
+            tracker: Some(tracker),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_oauth_bearer(mut self, oauth_bearer: Option<OAuthBearer>) -> Self {
+        self.oauth_bearer = oauth_bearer;
+        self
+    }
+
+    pub fn with_in_flight_budget(mut self, budget: Option<Arc<InFlightBudget>>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    pub fn in_flight_budget(&self) -> Option<&Arc<InFlightBudget>> {
+        self.budget.as_ref()
+    }
+
+    /// Records the latency of every delivered record in `latency`.
+    pub fn with_delivery_latency(mut self, latency: Option<Arc<DeliveryLatency>>) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Bytes of the records handed to the producers and not yet delivered or failed.
+    pub fn buffered_bytes(&self) -> &Arc<BufferedBytes> {
+        &self.buffered
+    }
+
+    /// Reports errors the producers cannot recover from to [`Self::fatal_errors`].
+    pub fn with_fatal_errors(mut self) -> Self {
+        self.fatal_errors = Some(bounded(1));
+        self
+    }
+
+    /// Reports fatal errors of the producers to `reporter` too.
+    pub fn with_error_reporter(mut self, reporter: Option<Arc<ErrorReporter>>) -> Self {
+        self.error_reporter = reporter;
+        self
+    }
+
+    /// Fatal errors of the producers, if reported.
+    ///
+    /// Errors reported while one is still waiting to be handled are dropped.
+    pub fn fatal_errors(&self) -> Option<&Receiver<String>> {
+        self.fatal_errors.as_ref().map(|(_, receiver)| receiver)
+    }
+
+    /// Routes records timing out to `dead_letters`.
+    pub fn with_dead_letters(mut self, dead_letters: Option<Arc<DeadLetters>>) -> Self {
+        self.dead_letters = dead_letters;
+        self
+    }
+
+    pub fn dead_letters(&self) -> Option<&Arc<DeadLetters>> {
+        self.dead_letters.as_ref()
+    }
+
+    /// Connection states of the brokers of all producers sharing this context.
+    pub fn brokers(&self) -> &Arc<BrokerStates> {
+        &self.brokers
+    }
+
+    pub fn tracker(&self) -> Option<&Arc<SlotTracker>> {
+        self.tracker.as_ref()
+    }
+
+    /// Reports the outcome of a record carrying `opaque`.
+    pub fn finished(&self, opaque: usize, delivered: bool) {
+        if let (Some(tracker), Some(slot)) = (&self.tracker, opaque_slot(opaque)) {
+            tracker.finished(slot, delivered);
+        }
+    }
+}
+
+impl ClientContext for DeliveryContext {
+    // librdkafka only asks for tokens when `sasl.mechanism` is `OAUTHBEARER`.
+    const ENABLE_REFRESH_OAUTH_TOKEN: bool = true;
+
+    fn stats(&self, statistics: Statistics) {
+        self.brokers.observe(&statistics);
+    }
+
+    fn error(&self, error: KafkaError, reason: &str) {
+        limited_error!("librdkafka: {}: {}", error, reason);
+        if error.rdkafka_error_code() == Some(RDKafkaErrorCode::AllBrokersDown) {
+            self.brokers.all_brokers_down();
+        }
+        // Fenced idempotent producers and expired credentials never recover on their own.
+        let fatal = matches!(
+            error.rdkafka_error_code(),
+            Some(RDKafkaErrorCode::Fatal | RDKafkaErrorCode::Authentication)
+        );
+        if let (true, Some(reporter)) = (fatal, &self.error_reporter) {
+            reporter.report(
+                ReportLevel::Fatal,
+                &format!("librdkafka: {}: {}", error, reason),
+            );
+        }
+        if let (
+            Some((sender, _)),
+            Some(RDKafkaErrorCode::Fatal | RDKafkaErrorCode::Authentication),
+        ) = (&self.fatal_errors, error.rdkafka_error_code())
+        {
+            metrics().producer_fatal_errors.inc();
+            let _ = sender.try_send(format!("{}: {}", error, reason));
+        }
+    }
+
+    fn generate_oauth_token(&self, _: Option<&str>) -> Result<OAuthToken, Box<dyn Error>> {
+        match &self.oauth_bearer {
+            Some(oauth_bearer) => oauth_bearer.generate_token(),
+            None => {
+                Err("sasl.mechanism is OAUTHBEARER but sasl_oauthbearer is not configured".into())
+            }
+        }
+    }
+}
+
+impl ProducerContext for DeliveryContext {
+    type DeliveryOpaque = usize;
+
+    fn delivery(&self, result: &DeliveryResult<'_>, opaque: usize) {
+        let message = match result {
+            Ok(message) | Err((_, message)) => message,
+        };
+        let size = record_size(message.key(), message.payload());
+        self.buffered.release(message.topic(), size);
+        if let Some(budget) = &self.budget {
+            budget.release(size);
+        }
+        if let (Some(latency), Ok(message)) = (&self.latency, result) {
+            latency.delivered(message.topic(), message.timestamp().to_millis());
+        }
+        if result.is_err() {
+            drops().dropped(message.topic(), DropReason::DeliveryFailed, NO_FILTER);
+        }
+        if let (Some(dead_letters), Err((e, message))) = (&self.dead_letters, result) {
+            if e.rdkafka_error_code() == Some(RDKafkaErrorCode::MessageTimedOut) {
+                dead_letters.push(DeadLetter::new(message, e));
+            }
+        }
+        self.finished(opaque, result.is_ok());
+    }
+}
+
+/// Lets consumers of the plugin's topics authenticate like the producers.
+impl ConsumerContext for DeliveryContext {}
+
+/// Bytes of a record counted against the [`InFlightBudget`].
+pub(crate) fn record_size(key: Option<&[u8]>, payload: Option<&[u8]>) -> u64 {
+    (key.map_or(0, <[u8]>::len) + payload.map_or(0, <[u8]>::len)) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slot_tracker() {
+        let tracker = SlotTracker::new();
+        tracker.dispatched(5);
+        tracker.dispatched(5);
+        tracker.dispatched(6);
+        tracker.finished(5, true);
+        tracker.close(5);
+        assert!(tracker.completed().try_recv().is_err());
+
+        tracker.finished(5, false);
+        assert_eq!(
+            tracker.completed().try_recv().unwrap(),
+            SlotAccountsComplete {
+                slot: 5,
+                messages_delivered: 1,
+                messages_failed: 1,
+            }
+        );
+        tracker.close(5);
+        assert!(tracker.completed().try_recv().is_err());
+
+        // Slots without account records complete right away.
+        tracker.close(7);
+        assert_eq!(tracker.completed().try_recv().unwrap().slot, 7);
+
+        let watermark = tracker.watermark();
+        assert_eq!(watermark.oldest_in_flight_slot, 6);
+        assert_eq!(watermark.newest_delivered_slot, 5);
+
+        // Slot 6 was abandoned and never completes.
+        tracker.prune(7);
+        tracker.finished(6, true);
+        assert!(tracker.completed().try_recv().is_err());
+        assert_eq!(tracker.watermark().oldest_in_flight_slot, 0);
+    }
+
+    #[test]
+    fn test_slot_opaque() {
+        assert_eq!(opaque_slot(UNTRACKED), None);
+        assert_eq!(opaque_slot(slot_opaque(0)), Some(0));
+        assert_eq!(opaque_slot(slot_opaque(123)), Some(123));
+    }
+}
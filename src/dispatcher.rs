@@ -0,0 +1,863 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    crossbeam_channel::{
+        bounded, never, select, tick, Receiver, RecvTimeoutError, Sender, TrySendError,
+    },
+    log::error,
+    prost::Message,
+    rdkafka::error::KafkaError,
+    serde::Serialize,
+    simple_error::SimpleError,
+    std::{
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+        thread::{self, JoinHandle},
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// Occupancy of a bounded queue.
+#[derive(Debug, Serialize)]
+pub struct QueueLength {
+    pub name: String,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+/// Newest slots of the events dispatched and of those the publisher threads picked up.
+#[derive(Default)]
+struct SlotProgress {
+    dispatched: AtomicU64,
+    published: AtomicU64,
+}
+
+/// Reads the lengths of the dispatcher's queues from other threads.
+#[derive(Clone)]
+pub struct QueueGauges {
+    queues: Vec<(String, Receiver<Queued>)>,
+    dead_letters: Option<Receiver<DeadLetter>>,
+    slots: Arc<SlotProgress>,
+}
+
+impl QueueGauges {
+    pub fn lengths(&self) -> Vec<QueueLength> {
+        let length = |name: &str, len, capacity: Option<usize>| QueueLength {
+            name: name.to_owned(),
+            len,
+            capacity: capacity.unwrap_or_default(),
+        };
+        let mut lengths: Vec<QueueLength> = self
+            .queues
+            .iter()
+            .map(|(name, queue)| length(name, queue.len(), queue.capacity()))
+            .collect();
+        if let Some(dead_letters) = &self.dead_letters {
+            lengths.push(length(
+                "dead_letters",
+                dead_letters.len(),
+                dead_letters.capacity(),
+            ));
+        }
+        lengths
+    }
+
+    /// Slots between the newest event dispatched and the newest event picked up by the publisher
+    /// threads, zero while the publisher queues are empty.
+    pub fn slot_lag(&self) -> u64 {
+        if self.queues.iter().all(|(_, queue)| queue.is_empty()) {
+            return 0;
+        }
+        let dispatched = self.slots.dispatched.load(Ordering::Relaxed);
+        dispatched.saturating_sub(self.slots.published.load(Ordering::Relaxed))
+    }
+}
+
+/// Capacity of the queue of the priority lane, which only carries slot status events.
+const PRIORITY_QUEUE_CAPACITY: usize = 1024;
+
+/// How often a callback blocked by `max_memory_bytes` checks whether the buffers shrank.
+const MEMORY_BUDGET_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Event handed from the geyser callbacks to the publisher thread.
+pub enum Event {
+    UpdateAccount(UpdateAccountEvent),
+    StartupAccount(UpdateAccountEvent),
+    /// Account update seen in all of the slots listed with it.
+    ForkedAccount(UpdateAccountEvent, Vec<u64>),
+    SlotAccountsBatch(SlotAccountsBatch),
+    SlotStatus(SlotStatusEvent),
+    Transaction(Box<TransactionEvent>),
+    TransactionStatus(TransactionStatusEvent),
+    CompressedNft(CompressedNftEvent),
+    ProgramUpgraded(ProgramUpgraded),
+    TokenBalance(TokenBalanceChange),
+    OwnerChanged(OwnerChanged),
+    BlockMetadata(BlockMetadataEvent),
+    /// Unfiltered account update mirrored to the shadow topic.
+    ShadowAccount(UpdateAccountEvent),
+}
+
+impl Event {
+    /// Picks one of `workers` for this event.
+    ///
+    /// Events sharing a key always land on the same worker,
+    /// which preserves per-account ordering.
+    fn worker_index(&self, workers: usize) -> usize {
+        if workers <= 1 {
+            return 0;
+        }
+        let mut hasher = DefaultHasher::new();
+        match self {
+            Event::UpdateAccount(ev)
+            | Event::StartupAccount(ev)
+            | Event::ForkedAccount(ev, _)
+            | Event::ShadowAccount(ev) => ev.pubkey.hash(&mut hasher),
+            Event::SlotAccountsBatch(ev) => ev.slot.hash(&mut hasher),
+            Event::SlotStatus(ev) => ev.slot.hash(&mut hasher),
+            Event::Transaction(ev) => ev.signature.hash(&mut hasher),
+            Event::TransactionStatus(ev) => ev.signature.hash(&mut hasher),
+            Event::CompressedNft(ev) => ev.asset_id.hash(&mut hasher),
+            Event::ProgramUpgraded(ev) => ev.programdata.hash(&mut hasher),
+            Event::TokenBalance(ev) => ev.account.hash(&mut hasher),
+            Event::OwnerChanged(ev) => ev.account.hash(&mut hasher),
+            Event::BlockMetadata(ev) => ev.slot.hash(&mut hasher),
+        }
+        (hasher.finish() % workers as u64) as usize
+    }
+
+    /// Encoded size of the event, which `drop_largest` ranks queued events by and tenant quotas count.
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Event::UpdateAccount(ev)
+            | Event::StartupAccount(ev)
+            | Event::ForkedAccount(ev, _)
+            | Event::ShadowAccount(ev) => ev.encoded_len(),
+            Event::SlotAccountsBatch(ev) => ev.encoded_len(),
+            Event::SlotStatus(ev) => ev.encoded_len(),
+            Event::Transaction(ev) => ev.encoded_len(),
+            Event::TransactionStatus(ev) => ev.encoded_len(),
+            Event::CompressedNft(ev) => ev.encoded_len(),
+            Event::ProgramUpgraded(ev) => ev.encoded_len(),
+            Event::TokenBalance(ev) => ev.encoded_len(),
+            Event::OwnerChanged(ev) => ev.encoded_len(),
+            Event::BlockMetadata(ev) => ev.encoded_len(),
+        }
+    }
+
+    fn slot(&self) -> u64 {
+        match self {
+            Event::UpdateAccount(ev)
+            | Event::StartupAccount(ev)
+            | Event::ForkedAccount(ev, _)
+            | Event::ShadowAccount(ev) => ev.slot,
+            Event::SlotAccountsBatch(ev) => ev.slot,
+            Event::SlotStatus(ev) => ev.slot,
+            Event::Transaction(ev) => ev.slot,
+            Event::TransactionStatus(ev) => ev.slot,
+            Event::CompressedNft(ev) => ev.slot,
+            Event::ProgramUpgraded(ev) => ev.slot,
+            Event::TokenBalance(ev) => ev.slot,
+            Event::OwnerChanged(ev) => ev.slot,
+            Event::BlockMetadata(ev) => ev.slot,
+        }
+    }
+
+    /// Slot of an account event counted towards the slot's completion.
+    fn tracked_slot(&self) -> Option<u64> {
+        match self {
+            Event::UpdateAccount(ev) | Event::StartupAccount(ev) | Event::ForkedAccount(ev, _) => {
+                Some(ev.slot)
+            }
+            Event::SlotAccountsBatch(ev) => Some(ev.slot),
+            _ => None,
+        }
+    }
+}
+
+/// Work a publisher thread picks up besides the events of its queue.
+struct Chores {
+    watermarks: Receiver<Instant>,
+    stats: Receiver<Instant>,
+    completed: Receiver<SlotAccountsComplete>,
+    dead_letters: Receiver<DeadLetter>,
+}
+
+/// An event waiting in a queue, with the time it was dispatched in milliseconds since the epoch.
+struct Queued {
+    event: Event,
+    dispatched_ms: i64,
+    /// Encoded size of the event, held until a publisher thread picks it up.
+    memory: MemoryUsage,
+}
+
+impl Queued {
+    fn now(event: Event) -> Self {
+        let dispatched_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as i64);
+        let mut memory = MemoryUsage::new(MemoryComponent::QueuedEvents);
+        memory.set(event.encoded_len());
+        Self {
+            event,
+            dispatched_ms,
+            memory,
+        }
+    }
+}
+
+/// Bounded channel feeding a publisher thread.
+struct Queue {
+    sender: Sender<Queued>,
+    /// Lets the overflow policies take queued events back out.
+    receiver: Receiver<Queued>,
+    /// Held by every send with `drop_largest`, which takes the whole queue out and back in.
+    evicting: Mutex<()>,
+}
+
+impl Queue {
+    /// The queue and the receiving end of its publisher thread.
+    fn new(capacity: usize) -> (Self, Receiver<Queued>) {
+        let (sender, receiver) = bounded(capacity);
+        let queue = Self {
+            sender,
+            receiver: receiver.clone(),
+            evicting: Mutex::default(),
+        };
+        (queue, receiver)
+    }
+
+    /// Drops the queued events with the largest payloads, keeping the others in order.
+    ///
+    /// A tenth of the queue is freed at once, sparing a scan of the whole queue for every new event.
+    fn evict_largest(&self, mut evicted: impl FnMut(Event)) {
+        let queued: Vec<Queued> = self.receiver.try_iter().collect();
+        let mut by_size: Vec<(usize, usize)> = queued
+            .iter()
+            .map(|queued| queued.event.encoded_len())
+            .enumerate()
+            .collect();
+        by_size.sort_unstable_by_key(|&(_, size)| std::cmp::Reverse(size));
+        let mut evict = vec![false; queued.len()];
+        for (i, _) in by_size.into_iter().take((queued.len() / 10).max(1)) {
+            evict[i] = true;
+        }
+        for (queued, evict) in queued.into_iter().zip(evict) {
+            if evict {
+                evicted(queued.event);
+            } else if let Err(e) = self.sender.try_send(queued) {
+                // Only sends holding `evicting` add events, there is room for all that were taken out.
+                evicted(e.into_inner().event);
+            }
+        }
+    }
+}
+
+/// Dispatcher decouples the geyser callbacks from Kafka.
+///
+/// Events are pushed into bounded channels and serialized and produced
+/// by a pool of publisher threads, one channel per thread.
+pub struct Dispatcher {
+    publisher: Arc<Publisher>,
+    queues: Vec<Queue>,
+    /// Queue of the `priority_lane`, if enabled.
+    priority: Option<Queue>,
+    workers: Vec<JoinHandle<()>>,
+    overflow_policy: QueueOverflowPolicy,
+    checkpoint: Option<Arc<Checkpoint>>,
+    slots: Arc<SlotProgress>,
+    /// Stops the periodic producer flushes when dropped.
+    flusher: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+impl Dispatcher {
+    pub fn new(publisher: Publisher, config: &Config) -> Self {
+        let publisher = Arc::new(publisher);
+        let checkpoint = if config.checkpoint_file.is_empty() {
+            None
+        } else {
+            Some(Arc::new(Checkpoint::open(&config.checkpoint_file)))
+        };
+        let worker_count = config.serialization_workers.max(1);
+        // The queue capacity is shared between all workers.
+        let capacity = (config.queue_capacity / worker_count).max(1);
+
+        let watermarks = || {
+            if publisher.wants_watermark() {
+                tick(Duration::from_millis(config.watermark_interval_ms.max(1)))
+            } else {
+                never()
+            }
+        };
+        let stats = || {
+            if publisher.wants_stats_snapshot() {
+                tick(Duration::from_millis(config.stats_interval_ms.max(1)))
+            } else {
+                never()
+            }
+        };
+        // Every worker competes for the completion markers and dead letters, whichever is idle publishes them.
+        let completed = || match publisher.slot_tracker() {
+            Some(tracker) => tracker.completed().clone(),
+            None => never(),
+        };
+        let dead_letters = || match publisher.dead_letters() {
+            Some(dead_letters) => dead_letters.receiver().clone(),
+            None => never(),
+        };
+
+        let slots = Arc::new(SlotProgress::default());
+        let mut queues = Vec::with_capacity(worker_count);
+        let mut workers = Vec::with_capacity(worker_count + 1);
+        // The priority lane takes over the watermarks, stats snapshots and completion markers.
+        let priority = if config.priority_lane {
+            let (queue, receiver) = Queue::new(PRIORITY_QUEUE_CAPACITY);
+            workers.push(Self::spawn_worker(
+                "kafka-publisher-priority".to_owned(),
+                publisher.clone(),
+                checkpoint.clone(),
+                slots.clone(),
+                Chores {
+                    watermarks: watermarks(),
+                    stats: stats(),
+                    completed: completed(),
+                    dead_letters: never(),
+                },
+                receiver,
+            ));
+            Some(queue)
+        } else {
+            None
+        };
+        for i in 0..worker_count {
+            let (queue, receiver) = Queue::new(capacity);
+            // A single worker publishes the watermarks and stats snapshots.
+            let (watermarks, stats, completed) = match priority {
+                Some(_) => (never(), never(), never()),
+                None if i == 0 => (watermarks(), stats(), completed()),
+                None => (never(), never(), completed()),
+            };
+            let chores = Chores {
+                watermarks,
+                stats,
+                completed,
+                dead_letters: dead_letters(),
+            };
+            workers.push(Self::spawn_worker(
+                format!("kafka-publisher-{}", i),
+                publisher.clone(),
+                checkpoint.clone(),
+                slots.clone(),
+                chores,
+                receiver,
+            ));
+            queues.push(queue);
+        }
+
+        let flusher = match config.flush_interval_ms {
+            0 => None,
+            interval => Some(Self::spawn_flusher(
+                publisher.clone(),
+                Duration::from_millis(interval),
+            )),
+        };
+
+        Self {
+            publisher,
+            queues,
+            priority,
+            workers,
+            flusher,
+            overflow_policy: config.queue_overflow_policy,
+            checkpoint,
+            slots,
+        }
+    }
+
+    fn spawn_worker(
+        name: String,
+        publisher: Arc<Publisher>,
+        checkpoint: Option<Arc<Checkpoint>>,
+        slots: Arc<SlotProgress>,
+        chores: Chores,
+        receiver: Receiver<Queued>,
+    ) -> JoinHandle<()> {
+        thread::Builder::new()
+            .name(name)
+            .spawn(move || Self::run(publisher, checkpoint, slots, chores, receiver))
+            .expect("failed to spawn publisher thread")
+    }
+
+    /// Flushes the producers every `interval`, bounding latency on topics too quiet to fill a batch.
+    fn spawn_flusher(
+        publisher: Arc<Publisher>,
+        interval: Duration,
+    ) -> (Sender<()>, JoinHandle<()>) {
+        let (stop, stopped) = bounded::<()>(0);
+        let flusher = thread::Builder::new()
+            .name("kafka-flusher".to_owned())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    publisher.flush_producers(interval);
+                }
+            })
+            .expect("failed to spawn flusher thread");
+        (stop, flusher)
+    }
+
+    pub fn publisher(&self) -> &Arc<Publisher> {
+        &self.publisher
+    }
+
+    /// Handle on the occupancy of the publisher queues, and of the dead letters waiting.
+    pub fn queue_gauges(&self) -> QueueGauges {
+        let mut queues: Vec<(String, Receiver<Queued>)> = self
+            .queues
+            .iter()
+            .enumerate()
+            .map(|(i, queue)| (format!("publisher-{}", i), queue.receiver.clone()))
+            .collect();
+        if let Some(priority) = &self.priority {
+            queues.push(("priority".to_owned(), priority.receiver.clone()));
+        }
+        QueueGauges {
+            queues,
+            dead_letters: self
+                .publisher
+                .dead_letters()
+                .map(|dead_letters| dead_letters.receiver().clone()),
+            slots: self.slots.clone(),
+        }
+    }
+
+    pub fn update_account(&self, ev: UpdateAccountEvent) -> Result<(), SimpleError> {
+        let slot = ev.slot;
+        self.dispatch_tracked(slot, Event::UpdateAccount(ev))
+    }
+
+    pub fn update_startup_account(&self, ev: UpdateAccountEvent) -> Result<(), SimpleError> {
+        let slot = ev.slot;
+        self.dispatch_tracked(slot, Event::StartupAccount(ev))
+    }
+
+    /// Counts an account update held back before dispatch towards the completion of `slot`.
+    pub fn reserve_account(&self, slot: u64) {
+        if let Some(tracker) = self.publisher.slot_tracker() {
+            tracker.dispatched(slot);
+        }
+    }
+
+    /// Dispatches an account update reserved with [`Self::reserve_account`],
+    /// seen in all of `fork_slots`.
+    pub fn update_reserved_account(
+        &self,
+        ev: UpdateAccountEvent,
+        fork_slots: Vec<u64>,
+    ) -> Result<(), SimpleError> {
+        let slot = ev.slot;
+        let result = self.dispatch(Event::ForkedAccount(ev, fork_slots));
+        if let (Err(_), Some(tracker)) = (&result, self.publisher.slot_tracker()) {
+            tracker.finished(slot, false);
+        }
+        result
+    }
+
+    pub fn update_slot_accounts_batch(&self, ev: SlotAccountsBatch) -> Result<(), SimpleError> {
+        let slot = ev.slot;
+        self.dispatch_tracked(slot, Event::SlotAccountsBatch(ev))
+    }
+
+    /// Declares that all account updates of `slot` were dispatched,
+    /// so its `SlotAccountsComplete` marker follows once they are delivered.
+    pub fn close_slot(&self, slot: u64) {
+        if let Some(tracker) = self.publisher.slot_tracker() {
+            tracker.close(slot);
+        }
+    }
+
+    /// Forgets delivery tracking of slots older than the new root
+    /// and advances the checkpoint once the root is fully published.
+    pub fn root_slot(&self, root: u64) {
+        if let Some(tracker) = self.publisher.slot_tracker() {
+            tracker.prune(root);
+        }
+        if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.rooted(root);
+        }
+    }
+
+    pub fn update_slot_status(&self, ev: SlotStatusEvent) -> Result<(), SimpleError> {
+        self.dispatch(Event::SlotStatus(ev))
+    }
+
+    pub fn update_transaction(&self, ev: TransactionEvent) -> Result<(), SimpleError> {
+        self.dispatch(Event::Transaction(Box::new(ev)))
+    }
+
+    pub fn update_transaction_status(&self, ev: TransactionStatusEvent) -> Result<(), SimpleError> {
+        self.dispatch(Event::TransactionStatus(ev))
+    }
+
+    pub fn update_compressed_nft(&self, ev: CompressedNftEvent) -> Result<(), SimpleError> {
+        self.dispatch(Event::CompressedNft(ev))
+    }
+
+    pub fn update_program_upgraded(&self, ev: ProgramUpgraded) -> Result<(), SimpleError> {
+        self.dispatch(Event::ProgramUpgraded(ev))
+    }
+
+    pub fn update_token_balance(&self, ev: TokenBalanceChange) -> Result<(), SimpleError> {
+        self.dispatch(Event::TokenBalance(ev))
+    }
+
+    pub fn update_owner_changed(&self, ev: OwnerChanged) -> Result<(), SimpleError> {
+        self.dispatch(Event::OwnerChanged(ev))
+    }
+
+    pub fn update_block_metadata(&self, ev: BlockMetadataEvent) -> Result<(), SimpleError> {
+        self.dispatch(Event::BlockMetadata(ev))
+    }
+
+    /// Mirrors an account update to the shadow topic, before any filter.
+    pub fn update_shadow_account(&self, ev: UpdateAccountEvent) -> Result<(), SimpleError> {
+        self.dispatch(Event::ShadowAccount(ev))
+    }
+
+    pub fn wants_update_account(&self) -> bool {
+        self.publisher.wants_update_account()
+    }
+
+    pub fn wants_startup_account(&self) -> bool {
+        self.publisher.wants_startup_account()
+    }
+
+    pub fn wants_slot_status(&self) -> bool {
+        self.publisher.wants_slot_status()
+    }
+
+    pub fn wants_transaction(&self) -> bool {
+        self.publisher.wants_transaction()
+    }
+
+    pub fn wants_transaction_kind(&self, is_vote: bool) -> bool {
+        self.publisher.wants_transaction_kind(is_vote)
+    }
+
+    pub fn wants_transaction_status(&self) -> bool {
+        self.publisher.wants_transaction_status()
+    }
+
+    pub fn wants_compressed_nft(&self) -> bool {
+        self.publisher.wants_compressed_nft()
+    }
+
+    pub fn wants_program_upgrade(&self) -> bool {
+        self.publisher.wants_program_upgrade()
+    }
+
+    pub fn wants_token_balance(&self) -> bool {
+        self.publisher.wants_token_balance()
+    }
+
+    pub fn wants_owner_changed(&self) -> bool {
+        self.publisher.wants_owner_changed()
+    }
+
+    pub fn wants_block_metadata(&self) -> bool {
+        self.publisher.wants_block_metadata()
+    }
+
+    /// Dispatches an account event, counting it towards its slot's completion.
+    fn dispatch_tracked(&self, slot: u64, event: Event) -> Result<(), SimpleError> {
+        let tracker = match self.publisher.slot_tracker() {
+            Some(tracker) => tracker,
+            None => return self.dispatch(event),
+        };
+        tracker.dispatched(slot);
+        let result = self.dispatch(event);
+        if result.is_err() {
+            tracker.finished(slot, false);
+        }
+        result
+    }
+
+    /// Hands `event` to the publisher thread of its key.
+    pub fn dispatch(&self, event: Event) -> Result<(), SimpleError> {
+        let queue = match (&self.priority, &event) {
+            (Some(priority), Event::SlotStatus(_)) => priority,
+            _ => &self.queues[event.worker_index(self.queues.len())],
+        };
+        let stopped = || SimpleError::new("publisher thread has stopped");
+        self.slots
+            .dispatched
+            .fetch_max(event.slot(), Ordering::Relaxed);
+        let event = Queued::now(event);
+        if memory().over_budget() {
+            self.relieve_memory(queue, &event)?;
+        }
+        match self.overflow_policy {
+            QueueOverflowPolicy::Block => queue.sender.send(event).map_err(|_| stopped()),
+            QueueOverflowPolicy::Drop => queue.sender.try_send(event).map_err(|e| match e {
+                TrySendError::Full(newest) => {
+                    metrics().queue_dropped_newest.inc();
+                    self.count_drop(&newest.event, DropReason::QueueFull);
+                    SimpleError::new("publisher queue is full")
+                }
+                TrySendError::Disconnected(_) => stopped(),
+            }),
+            QueueOverflowPolicy::DropOldest => {
+                let mut event = event;
+                loop {
+                    match queue.sender.try_send(event) {
+                        Ok(()) => return Ok(()),
+                        Err(TrySendError::Full(e)) => event = e,
+                        Err(TrySendError::Disconnected(_)) => return Err(stopped()),
+                    }
+                    // The publisher thread may have made room in the meantime.
+                    if let Ok(oldest) = queue.receiver.try_recv() {
+                        metrics().queue_dropped_oldest.inc();
+                        self.count_drop(&oldest.event, DropReason::QueueFull);
+                        self.abandon(&oldest.event);
+                    }
+                }
+            }
+            QueueOverflowPolicy::DropLargest => {
+                let _evicting = queue.evicting.lock().unwrap();
+                let event = match queue.sender.try_send(event) {
+                    Err(TrySendError::Full(event)) => event,
+                    result => return result.map_err(|_| stopped()),
+                };
+                queue.evict_largest(|evicted| {
+                    metrics().queue_dropped_largest.inc();
+                    self.count_drop(&evicted, DropReason::QueueFull);
+                    self.abandon(&evicted);
+                });
+                queue.sender.try_send(event).map_err(|e| match e {
+                    TrySendError::Full(_) => SimpleError::new("publisher queue is full"),
+                    TrySendError::Disconnected(_) => stopped(),
+                })
+            }
+        }
+    }
+
+    /// Applies the overflow policy to `queue` while the buffers exceed `max_memory_bytes`.
+    ///
+    /// Only queued events can be given up, so nothing is done once `queue` is empty.
+    fn relieve_memory(&self, queue: &Queue, event: &Queued) -> Result<(), SimpleError> {
+        let mut waited = false;
+        while memory().over_budget() && !queue.receiver.is_empty() {
+            match self.overflow_policy {
+                QueueOverflowPolicy::Block => {
+                    if !waited {
+                        metrics().memory_budget_waits.inc();
+                        waited = true;
+                    }
+                    thread::sleep(MEMORY_BUDGET_POLL_INTERVAL);
+                }
+                QueueOverflowPolicy::Drop => {
+                    metrics().memory_budget_drops.inc();
+                    self.count_drop(&event.event, DropReason::MemoryBudget);
+                    return Err(SimpleError::new("memory budget exceeded"));
+                }
+                QueueOverflowPolicy::DropOldest => {
+                    if let Ok(oldest) = queue.receiver.try_recv() {
+                        metrics().memory_budget_drops.inc();
+                        self.count_drop(&oldest.event, DropReason::MemoryBudget);
+                        self.abandon(&oldest.event);
+                    }
+                }
+                QueueOverflowPolicy::DropLargest => {
+                    let _evicting = queue.evicting.lock().unwrap();
+                    queue.evict_largest(|evicted| {
+                        metrics().memory_budget_drops.inc();
+                        self.count_drop(&evicted, DropReason::MemoryBudget);
+                        self.abandon(&evicted);
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Accounts for an event dropped from a full queue or for the memory budget.
+    fn count_drop(&self, event: &Event, reason: DropReason) {
+        let topic = self.publisher.topic_of(event);
+        drops().dropped(topic, reason, NO_FILTER);
+    }
+
+    /// Counts a dropped account event as failed, so its slot still completes.
+    fn abandon(&self, event: &Event) {
+        if let (Some(tracker), Some(slot)) = (self.publisher.slot_tracker(), event.tracked_slot()) {
+            tracker.finished(slot, false);
+        }
+    }
+
+    fn run(
+        publisher: Arc<Publisher>,
+        checkpoint: Option<Arc<Checkpoint>>,
+        slots: Arc<SlotProgress>,
+        chores: Chores,
+        receiver: Receiver<Queued>,
+    ) {
+        let Chores {
+            watermarks,
+            stats,
+            completed,
+            dead_letters,
+        } = chores;
+        loop {
+            let result = select! {
+                recv(receiver) -> queued => match queued {
+                    Ok(Queued {
+                        event,
+                        dispatched_ms,
+                        memory,
+                    }) => {
+                        drop(memory);
+                        slots.published.fetch_max(event.slot(), Ordering::Relaxed);
+                        Publisher::stamped(dispatched_ms, || Self::publish(&publisher, event))
+                    }
+                    Err(_) => break,
+                },
+                recv(completed) -> ev => match ev {
+                    Ok(ev) => {
+                        if let Some(checkpoint) = &checkpoint {
+                            checkpoint.completed(&ev);
+                        }
+                        if !publisher.wants_slot_accounts_complete() {
+                            continue;
+                        }
+                        publisher.update_slot_accounts_complete(ev)
+                    }
+                    Err(_) => continue,
+                },
+                recv(watermarks) -> _ => match publisher.slot_tracker() {
+                    Some(tracker) => publisher.update_watermark(tracker.watermark()),
+                    None => continue,
+                },
+                recv(stats) -> _ => publisher.update_stats_snapshot(),
+                recv(dead_letters) -> letter => match letter {
+                    Ok(letter) => publisher.publish_dead_letter(letter),
+                    Err(_) => continue,
+                },
+            };
+            if let Err(e) = result {
+                limited_error!("Failed to publish event: {}", e);
+            }
+        }
+    }
+
+    fn publish(publisher: &Publisher, event: Event) -> Result<(), KafkaError> {
+        match event {
+            Event::UpdateAccount(ev) => publisher.update_account(ev),
+            Event::StartupAccount(ev) => publisher.update_startup_account(ev),
+            Event::ForkedAccount(ev, slots) => publisher.update_forked_account(ev, &slots),
+            Event::SlotAccountsBatch(ev) => publisher.update_slot_accounts_batch(ev),
+            Event::SlotStatus(ev) => publisher.update_slot_status(ev),
+            Event::Transaction(ev) => publisher.update_transaction(*ev),
+            Event::TransactionStatus(ev) => publisher.update_transaction_status(ev),
+            Event::CompressedNft(ev) => publisher.update_compressed_nft(ev),
+            Event::ProgramUpgraded(ev) => publisher.update_program_upgraded(ev),
+            Event::TokenBalance(ev) => publisher.update_token_balance(ev),
+            Event::OwnerChanged(ev) => publisher.update_owner_changed(ev),
+            Event::BlockMetadata(ev) => publisher.update_block_metadata(ev),
+            Event::ShadowAccount(ev) => publisher.update_shadow_account(ev),
+        }
+    }
+}
+
+impl Drop for Dispatcher {
+    fn drop(&mut self) {
+        if let Some((stop, flusher)) = self.flusher.take() {
+            drop(stop);
+            if flusher.join().is_err() {
+                error!("Flusher thread panicked");
+            }
+        }
+        // Closing the channels lets the publisher threads drain their queues and exit.
+        self.queues.clear();
+        self.priority = None;
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                error!("Publisher thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_worker_index_is_stable_per_account() {
+        let event = |pubkey: Vec<u8>| {
+            Event::UpdateAccount(UpdateAccountEvent {
+                pubkey: pubkey.into(),
+                ..UpdateAccountEvent::default()
+            })
+        };
+
+        assert_eq!(event(vec![1; 32]).worker_index(1), 0);
+        for workers in 2..16 {
+            let a = event(vec![1; 32]).worker_index(workers);
+            assert!(a < workers);
+            assert_eq!(a, event(vec![1; 32]).worker_index(workers));
+        }
+
+        let used: std::collections::HashSet<usize> = (0..64u8)
+            .map(|i| event(vec![i; 32]).worker_index(4))
+            .collect();
+        assert!(used.len() > 1);
+    }
+
+    #[test]
+    fn test_evict_largest() {
+        let (queue, receiver) = Queue::new(20);
+        for i in 0..20u8 {
+            let data = vec![i; if i == 3 || i == 10 { 1000 } else { 10 }];
+            let ev = UpdateAccountEvent {
+                data: data.into(),
+                write_version: i.into(),
+                ..UpdateAccountEvent::default()
+            };
+            queue
+                .sender
+                .try_send(Queued::now(Event::UpdateAccount(ev)))
+                .unwrap();
+        }
+
+        let mut evicted = Vec::new();
+        queue.evict_largest(|event| evicted.push(event));
+        let write_version = |event: &Event| match event {
+            Event::UpdateAccount(ev) => ev.write_version,
+            _ => unreachable!(),
+        };
+        let mut evicted: Vec<u64> = evicted.iter().map(write_version).collect();
+        evicted.sort_unstable();
+        assert_eq!(evicted, [3, 10]);
+        let kept: Vec<u64> = receiver
+            .try_iter()
+            .map(|queued| write_version(&queued.event))
+            .collect();
+        assert_eq!(kept.len(), 18);
+        assert!(kept.windows(2).all(|w| w[0] < w[1]));
+    }
+}
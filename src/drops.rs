@@ -0,0 +1,286 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    lazy_static::lazy_static,
+    log::info,
+    std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex, RwLock,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+/// Filter group of drops no filter is responsible for.
+pub const NO_FILTER: &str = "none";
+
+/// Why a message was not published.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DropReason {
+    /// Rejected by a filter.
+    Filtered,
+    /// Repeats a message already published.
+    Duplicate,
+    /// Replaced by a later update of the same account in the same slot.
+    Superseded,
+    /// Account update of a slot that was never rooted, with `rooted_account_updates`.
+    Unrooted,
+    /// The publisher queue was full, with a dropping `queue_overflow_policy`.
+    QueueFull,
+    /// The record did not fit into the in-flight budget.
+    InFlightBudget,
+    /// The buffers exceeded `max_memory_bytes`, with a dropping `queue_overflow_policy`.
+    MemoryBudget,
+    /// The tenant exceeded its `max_messages_per_sec` or `max_bytes_per_sec`.
+    RateLimited,
+    /// The payload could not be compressed or encrypted.
+    EncodingFailed,
+    /// The producer refused the record, or it could not be written to the sink or the spool.
+    ProduceFailed,
+    /// The brokers did not acknowledge the record.
+    DeliveryFailed,
+}
+
+impl DropReason {
+    pub fn name(self) -> &'static str {
+        match self {
+            DropReason::Filtered => "filtered",
+            DropReason::Duplicate => "duplicate",
+            DropReason::Superseded => "superseded",
+            DropReason::Unrooted => "unrooted",
+            DropReason::QueueFull => "queue_full",
+            DropReason::InFlightBudget => "in_flight_budget",
+            DropReason::MemoryBudget => "memory_budget",
+            DropReason::RateLimited => "rate_limited",
+            DropReason::EncodingFailed => "encoding_failed",
+            DropReason::ProduceFailed => "produce_failed",
+            DropReason::DeliveryFailed => "delivery_failed",
+        }
+    }
+}
+
+/// Counters of one reason and filter group, by topic.
+type ByTopic = HashMap<String, Arc<Counter>>;
+
+/// Counts every dropped message by topic, reason and filter group, in the
+/// `messages_dropped{topic=..,reason=..,filter=..}` metrics and a periodic summary log.
+///
+/// The filter group is the option whose filter or deduplication dropped the message, e.g.
+/// `program_allowlist` or `transaction_dedup_window_ms`, and [`NO_FILTER`] for the other reasons.
+/// The summary of an interval is logged with the first drop after it, or [`Self::flush`].
+pub struct DropAccounting {
+    counters: RwLock<HashMap<(DropReason, &'static str), ByTopic>>,
+    /// Counts at the last summary.
+    summarized: Mutex<HashMap<(String, DropReason, &'static str), u64>>,
+    start: Instant,
+    interval_ms: AtomicU64,
+    /// Milliseconds since `start` the next summary is due at.
+    next_summary_ms: AtomicU64,
+}
+
+impl DropAccounting {
+    fn new(interval: Duration) -> Self {
+        Self {
+            counters: RwLock::default(),
+            summarized: Mutex::default(),
+            start: Instant::now(),
+            interval_ms: AtomicU64::new(interval.as_millis() as u64),
+            next_summary_ms: AtomicU64::new(interval.as_millis() as u64),
+        }
+    }
+
+    /// Sets the interval between summaries, zero disables them.
+    pub fn set_summary_interval(&self, interval: Duration) {
+        let interval_ms = interval.as_millis() as u64;
+        self.interval_ms.store(interval_ms, Ordering::Relaxed);
+        self.next_summary_ms
+            .store(self.elapsed_ms() + interval_ms, Ordering::Relaxed);
+    }
+
+    /// Counts a message of `topic` dropped for `reason` by the filter group `filter`.
+    pub fn dropped(&self, topic: &str, reason: DropReason, filter: &'static str) {
+        self.dropped_many(topic, reason, filter, 1)
+    }
+
+    pub fn dropped_many(&self, topic: &str, reason: DropReason, filter: &'static str, count: u64) {
+        if count == 0 {
+            return;
+        }
+        let counter = self
+            .counters
+            .read()
+            .unwrap()
+            .get(&(reason, filter))
+            .and_then(|by_topic| by_topic.get(topic))
+            .cloned();
+        let counter = counter.unwrap_or_else(|| {
+            let labels = [
+                ("topic", topic),
+                ("reason", reason.name()),
+                ("filter", filter),
+            ];
+            let counter = labeled_counter("messages_dropped", &labels);
+            self.counters
+                .write()
+                .unwrap()
+                .entry((reason, filter))
+                .or_default()
+                .insert(topic.to_owned(), counter.clone());
+            counter
+        });
+        counter.add(count);
+
+        let interval_ms = self.interval_ms.load(Ordering::Relaxed);
+        let due_ms = self.next_summary_ms.load(Ordering::Relaxed);
+        let now_ms = self.elapsed_ms();
+        if interval_ms > 0
+            && now_ms >= due_ms
+            && self
+                .next_summary_ms
+                .compare_exchange(
+                    due_ms,
+                    now_ms + interval_ms,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+        {
+            self.flush();
+        }
+    }
+
+    /// Messages dropped so far for reasons other than a filter, i.e. in the [`NO_FILTER`] group.
+    pub fn unfiltered_total(&self) -> u64 {
+        self.counters
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|((_, filter), _)| *filter == NO_FILTER)
+            .flat_map(|(_, by_topic)| by_topic.values())
+            .map(|counter| counter.get())
+            .sum()
+    }
+
+    /// Logs the drops since the last summary, if any.
+    pub fn flush(&self) {
+        if let Some(summary) = self.summary() {
+            info!("{}", summary);
+        }
+    }
+
+    /// Drops since the last summary, most frequent first.
+    fn summary(&self) -> Option<String> {
+        let mut summarized = self.summarized.lock().unwrap();
+        let mut dropped: Vec<(u64, String)> = Vec::new();
+        for (&(reason, filter), by_topic) in self.counters.read().unwrap().iter() {
+            for (topic, counter) in by_topic {
+                let count = counter.get();
+                let last = summarized
+                    .entry((topic.clone(), reason, filter))
+                    .or_default();
+                if count > *last {
+                    let group =
+                        format!("topic={} reason={} filter={}", topic, reason.name(), filter);
+                    dropped.push((count - *last, group));
+                    *last = count;
+                }
+            }
+        }
+        if dropped.is_empty() {
+            return None;
+        }
+        dropped.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        let total: u64 = dropped.iter().map(|(count, _)| count).sum();
+        let groups: Vec<String> = dropped
+            .into_iter()
+            .map(|(count, group)| format!("{} {}", count, group))
+            .collect();
+        Some(format!(
+            "Dropped {} messages since the last summary: {}",
+            total,
+            groups.join(", ")
+        ))
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+}
+
+lazy_static! {
+    static ref DROPS: DropAccounting = DropAccounting::new(Duration::from_secs(60));
+}
+
+/// Process-wide accounting of dropped messages.
+pub fn drops() -> &'static DropAccounting {
+    &DROPS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_accounting() {
+        let drops = DropAccounting::new(Duration::ZERO);
+        drops.dropped(
+            "test-drops-accounts",
+            DropReason::Filtered,
+            "program_allowlist",
+        );
+        drops.dropped(
+            "test-drops-accounts",
+            DropReason::Filtered,
+            "program_allowlist",
+        );
+        drops.dropped_many(
+            "test-drops-transactions",
+            DropReason::QueueFull,
+            NO_FILTER,
+            3,
+        );
+        drops.dropped_many(
+            "test-drops-transactions",
+            DropReason::QueueFull,
+            NO_FILTER,
+            0,
+        );
+
+        let snapshot: HashMap<String, u64> = metrics().snapshot().into_iter().collect();
+        let key = "messages_dropped{topic=\"test-drops-accounts\",reason=\"filtered\",filter=\"program_allowlist\"}";
+        assert_eq!(snapshot[key], 2);
+        assert_eq!(drops.unfiltered_total(), 3);
+        assert_eq!(
+            drops.summary().unwrap(),
+            "Dropped 5 messages since the last summary: \
+             3 topic=test-drops-transactions reason=queue_full filter=none, \
+             2 topic=test-drops-accounts reason=filtered filter=program_allowlist"
+        );
+        assert_eq!(drops.summary(), None);
+        drops.dropped(
+            "test-drops-accounts",
+            DropReason::Filtered,
+            "program_allowlist",
+        );
+        assert_eq!(
+            drops.summary().unwrap(),
+            "Dropped 1 messages since the last summary: \
+             1 topic=test-drops-accounts reason=filtered filter=program_allowlist"
+        );
+    }
+}
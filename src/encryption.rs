@@ -0,0 +1,430 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{redact, REDACTED},
+    aes_gcm::{
+        aead::{Aead, NewAead},
+        Aes256Gcm, Nonce,
+    },
+    log::{error, info},
+    rand::RngCore,
+    rdkafka::message::{Header, OwnedHeaders},
+    serde::Deserialize,
+    std::{
+        error::Error,
+        fmt,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex, RwLock,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+const NONCE_LEN: usize = 12;
+#[cfg(feature = "http")]
+const VAULT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to keep the old key after a failed rotation, before trying again.
+const ROTATION_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Header naming the payload cipher.
+pub const ENCRYPTION_HEADER: &str = "encryption";
+/// Header identifying the key a payload was encrypted with.
+pub const KEY_ID_HEADER: &str = "encryption-key-id";
+/// Header carrying the data key wrapped by the key management service, if any.
+pub const WRAPPED_KEY_HEADER: &str = "encryption-wrapped-key";
+const CIPHER: &str = "aes-256-gcm";
+
+/// Where payload encryption keys come from.
+#[derive(Clone, Deserialize)]
+#[serde(tag = "key_source", rename_all = "snake_case")]
+pub enum PayloadEncryption {
+    /// A fixed key shared with consumers out of band.
+    Static {
+        key_id: String,
+        /// Base64 encoded 256 bit key.
+        key_base64: String,
+    },
+    /// Data keys generated by the Vault transit secrets engine and rotated periodically.
+    /// Consumers unwrap the data key from the message header with Vault.
+    VaultTransit {
+        vault_url: String,
+        /// Falls back to `VAULT_TOKEN`.
+        #[serde(default)]
+        vault_token: String,
+        key_name: String,
+        #[serde(default = "PayloadEncryption::default_rotation_interval_sec")]
+        rotation_interval_sec: u64,
+    },
+}
+
+impl PayloadEncryption {
+    fn default_rotation_interval_sec() -> u64 {
+        3600
+    }
+}
+
+impl fmt::Debug for PayloadEncryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayloadEncryption::Static { key_id, .. } => f
+                .debug_struct("Static")
+                .field("key_id", key_id)
+                .field("key_base64", &REDACTED)
+                .finish(),
+            PayloadEncryption::VaultTransit {
+                vault_url,
+                key_name,
+                rotation_interval_sec,
+                ..
+            } => f
+                .debug_struct("VaultTransit")
+                .field("vault_url", &redact::redact_url(vault_url))
+                .field("vault_token", &REDACTED)
+                .field("key_name", key_name)
+                .field("rotation_interval_sec", rotation_interval_sec)
+                .finish(),
+        }
+    }
+}
+
+struct DataKey {
+    cipher: Aes256Gcm,
+    key_id: String,
+    wrapped: Option<String>,
+    created: Instant,
+}
+
+impl DataKey {
+    fn new(
+        key: &[u8],
+        key_id: String,
+        wrapped: Option<String>,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let cipher = Aes256Gcm::new_from_slice(key)
+            .map_err(|_| format!("encryption key must be 32 bytes, got {}", key.len()))?;
+        Ok(Self {
+            cipher,
+            key_id,
+            wrapped,
+            created: Instant::now(),
+        })
+    }
+}
+
+#[cfg(feature = "http")]
+#[derive(Deserialize)]
+struct VaultDataKeyResponse {
+    data: VaultDataKey,
+}
+
+#[cfg(feature = "http")]
+#[derive(Deserialize)]
+struct VaultDataKey {
+    plaintext: String,
+    ciphertext: String,
+}
+
+/// Encrypts message payloads with AES-256-GCM.
+///
+/// Encrypted payloads are the random 96 bit nonce followed by the ciphertext and tag.
+pub struct PayloadEncryptor {
+    config: PayloadEncryption,
+    key: RwLock<Arc<DataKey>>,
+    /// Set while one thread fetches the next data key.
+    rotating: AtomicBool,
+    /// When the last rotation failed, if it did.
+    rotation_failed: Mutex<Option<Instant>>,
+}
+
+impl PayloadEncryptor {
+    pub fn new(config: PayloadEncryption) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let key = Self::fetch_key(&config)?;
+        info!("Encrypting payloads with key {}", key.key_id);
+        Ok(Self {
+            config,
+            key: RwLock::new(Arc::new(key)),
+            rotating: AtomicBool::new(false),
+            rotation_failed: Mutex::new(None),
+        })
+    }
+
+    fn fetch_key(config: &PayloadEncryption) -> Result<DataKey, Box<dyn Error + Send + Sync>> {
+        match config {
+            PayloadEncryption::Static { key_id, key_base64 } => {
+                DataKey::new(&base64::decode(key_base64)?, key_id.clone(), None)
+            }
+            #[cfg(feature = "http")]
+            PayloadEncryption::VaultTransit {
+                vault_url,
+                vault_token,
+                key_name,
+                ..
+            } => {
+                let token = if vault_token.is_empty() {
+                    std::env::var("VAULT_TOKEN").map_err(|_| "no Vault token configured")?
+                } else {
+                    vault_token.clone()
+                };
+                let url = format!(
+                    "{}/v1/transit/datakey/plaintext/{}",
+                    vault_url.trim_end_matches('/'),
+                    key_name
+                );
+                let response = ureq::post(&url)
+                    .timeout(VAULT_TIMEOUT)
+                    .set("X-Vault-Token", &token)
+                    .call()
+                    .map_err(|e| {
+                        format!(
+                            "Failed to generate data key with Vault: {}",
+                            redact::redact_url_in(&e.to_string(), &url)
+                        )
+                    })?;
+                let response: VaultDataKeyResponse =
+                    serde_json::from_str(&response.into_string()?)?;
+                DataKey::new(
+                    &base64::decode(response.data.plaintext)?,
+                    key_name.clone(),
+                    Some(response.data.ciphertext),
+                )
+            }
+            // Configs using Vault are rejected without the feature.
+            #[cfg(not(feature = "http"))]
+            PayloadEncryption::VaultTransit { .. } => {
+                Err("vault_transit requires the plugin to be built with the `http` feature".into())
+            }
+        }
+    }
+
+    /// The current data key, rotated first if it is due.
+    ///
+    /// Only one thread fetches the next key, without holding the lock, while the others
+    /// keep encrypting with the old one. After a failed rotation, the old key is kept for
+    /// `ROTATION_RETRY_INTERVAL`.
+    fn current_key(&self) -> Arc<DataKey> {
+        let key = self.key.read().unwrap().clone();
+        let rotation_interval_sec = match &self.config {
+            PayloadEncryption::VaultTransit {
+                rotation_interval_sec,
+                ..
+            } => *rotation_interval_sec,
+            PayloadEncryption::Static { .. } => return key,
+        };
+        let retrying = self
+            .rotation_failed
+            .lock()
+            .unwrap()
+            .map_or(false, |failed| failed.elapsed() < ROTATION_RETRY_INTERVAL);
+        if key.created.elapsed() < Duration::from_secs(rotation_interval_sec)
+            || retrying
+            || self.rotating.swap(true, Ordering::AcqRel)
+        {
+            return key;
+        }
+        let (key, failed) = match Self::fetch_key(&self.config) {
+            Ok(new_key) => {
+                let new_key = Arc::new(new_key);
+                *self.key.write().unwrap() = new_key.clone();
+                (new_key, None)
+            }
+            // Keep encrypting with the old key rather than dropping messages.
+            Err(e) => {
+                error!("Failed to rotate payload encryption key: {}", e);
+                (key, Some(Instant::now()))
+            }
+        };
+        *self.rotation_failed.lock().unwrap() = failed;
+        self.rotating.store(false, Ordering::Release);
+        key
+    }
+
+    /// Encrypts `payload` and returns it with the headers consumers need to decrypt it.
+    pub fn seal(
+        &self,
+        payload: &[u8],
+    ) -> Result<(Vec<u8>, OwnedHeaders), Box<dyn Error + Send + Sync>> {
+        let key = self.current_key();
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = key
+            .cipher
+            .encrypt(&Nonce::from(nonce), payload)
+            .map_err(|_| "payload encryption failed")?;
+
+        let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+
+        let mut headers = OwnedHeaders::new()
+            .insert(Header {
+                key: ENCRYPTION_HEADER,
+                value: Some(CIPHER),
+            })
+            .insert(Header {
+                key: KEY_ID_HEADER,
+                value: Some(key.key_id.as_str()),
+            });
+        if let Some(wrapped) = &key.wrapped {
+            headers = headers.insert(Header {
+                key: WRAPPED_KEY_HEADER,
+                value: Some(wrapped.as_str()),
+            });
+        }
+        Ok((sealed, headers))
+    }
+}
+
+/// Decrypts a payload sealed by [`PayloadEncryptor::seal`] with the 256 bit `key`.
+pub fn open_payload(key: &[u8], sealed: &[u8]) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    if sealed.len() < NONCE_LEN {
+        return Err("payload is too short".into());
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let nonce: [u8; NONCE_LEN] = nonce.try_into().unwrap();
+    Aes256Gcm::new_from_slice(key)
+        .map_err(|_| "decryption key must be 32 bytes")?
+        .decrypt(&Nonce::from(nonce), ciphertext)
+        .map_err(|_| "payload decryption failed".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, rdkafka::message::Headers};
+
+    fn header<'a>(headers: &'a OwnedHeaders, key: &str) -> Option<&'a [u8]> {
+        (0..headers.count())
+            .map(|i| headers.get(i))
+            .find(|h| h.key == key)
+            .and_then(|h| h.value)
+    }
+
+    #[test]
+    fn test_static_key_roundtrip() {
+        let key = [7u8; 32];
+        let encryptor = PayloadEncryptor::new(PayloadEncryption::Static {
+            key_id: "k1".to_owned(),
+            key_base64: base64::encode(key),
+        })
+        .unwrap();
+
+        let (sealed, headers) = encryptor.seal(b"account data").unwrap();
+        assert_eq!(sealed.len(), NONCE_LEN + b"account data".len() + 16);
+        assert_eq!(header(&headers, KEY_ID_HEADER), Some(&b"k1"[..]));
+        assert_eq!(header(&headers, ENCRYPTION_HEADER), Some(CIPHER.as_bytes()));
+        assert_eq!(header(&headers, WRAPPED_KEY_HEADER), None);
+        assert_eq!(open_payload(&key, &sealed).unwrap(), b"account data");
+        assert!(open_payload(&[8u8; 32], &sealed).is_err());
+
+        // Every message gets a fresh nonce.
+        let (again, _) = encryptor.seal(b"account data").unwrap();
+        assert_ne!(sealed, again);
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_vault_transit_key() {
+        let key = [9u8; 32];
+        let _m = mockito::mock("POST", "/v1/transit/datakey/plaintext/kafka")
+            .match_header("x-vault-token", "s.token")
+            .with_status(200)
+            .with_body(format!(
+                "{{\"data\":{{\"plaintext\":\"{}\",\"ciphertext\":\"vault:v1:wrapped\"}}}}",
+                base64::encode(key)
+            ))
+            .create();
+
+        let encryptor = PayloadEncryptor::new(PayloadEncryption::VaultTransit {
+            vault_url: format!("{}/", mockito::server_url()),
+            vault_token: "s.token".to_owned(),
+            key_name: "kafka".to_owned(),
+            rotation_interval_sec: 3600,
+        })
+        .unwrap();
+        let (sealed, headers) = encryptor.seal(b"tx").unwrap();
+        assert_eq!(header(&headers, KEY_ID_HEADER), Some(&b"kafka"[..]));
+        assert_eq!(
+            header(&headers, WRAPPED_KEY_HEADER),
+            Some(&b"vault:v1:wrapped"[..])
+        );
+        assert_eq!(open_payload(&key, &sealed).unwrap(), b"tx");
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_vault_transit_rotation() {
+        let first = [1u8; 32];
+        let second = [2u8; 32];
+        let body = |key: [u8; 32]| {
+            format!(
+                "{{\"data\":{{\"plaintext\":\"{}\",\"ciphertext\":\"vault:v1:wrapped\"}}}}",
+                base64::encode(key)
+            )
+        };
+        let m = mockito::mock("POST", "/v1/transit/datakey/plaintext/rotating")
+            .with_status(200)
+            .with_body(body(first))
+            .create();
+        let encryptor = PayloadEncryptor::new(PayloadEncryption::VaultTransit {
+            vault_url: mockito::server_url(),
+            vault_token: "s.token".to_owned(),
+            key_name: "rotating".to_owned(),
+            rotation_interval_sec: 0,
+        })
+        .unwrap();
+        drop(m);
+
+        let _m = mockito::mock("POST", "/v1/transit/datakey/plaintext/rotating")
+            .with_status(200)
+            .with_body(body(second))
+            .create();
+        let (sealed, _) = encryptor.seal(b"tx").unwrap();
+        assert_eq!(open_payload(&second, &sealed).unwrap(), b"tx");
+        assert!(!encryptor.rotating.load(Ordering::Acquire));
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn test_vault_transit_rotation_failed() {
+        let key = [1u8; 32];
+        let m = mockito::mock("POST", "/v1/transit/datakey/plaintext/failing")
+            .with_status(200)
+            .with_body(format!(
+                "{{\"data\":{{\"plaintext\":\"{}\",\"ciphertext\":\"vault:v1:wrapped\"}}}}",
+                base64::encode(key)
+            ))
+            .create();
+        let encryptor = PayloadEncryptor::new(PayloadEncryption::VaultTransit {
+            vault_url: mockito::server_url(),
+            vault_token: "s.token".to_owned(),
+            key_name: "failing".to_owned(),
+            rotation_interval_sec: 0,
+        })
+        .unwrap();
+        drop(m);
+
+        // Vault is asked once, the old key kept until the retry interval passed.
+        let m = mockito::mock("POST", "/v1/transit/datakey/plaintext/failing")
+            .with_status(503)
+            .expect(1)
+            .create();
+        for _ in 0..3 {
+            let (sealed, _) = encryptor.seal(b"tx").unwrap();
+            assert_eq!(open_payload(&key, &sealed).unwrap(), b"tx");
+        }
+        m.assert();
+        assert!(encryptor.rotation_failed.lock().unwrap().is_some());
+    }
+}
@@ -12,19 +12,152 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use solana_geyser_plugin_interface::geyser_plugin_interface::SlotStatus as PluginSlotStatus;
-
 include!(concat!(
     env!("OUT_DIR"),
     "/blockdaemon.solana.accountsdb_plugin_kafka.types.rs"
 ));
 
-impl From<PluginSlotStatus> for SlotStatus {
-    fn from(other: PluginSlotStatus) -> Self {
-        match other {
-            PluginSlotStatus::Processed => SlotStatus::Processed,
-            PluginSlotStatus::Rooted => SlotStatus::Rooted,
-            PluginSlotStatus::Confirmed => SlotStatus::Confirmed,
+/// Events with a deterministic identity, the same however often the event is published.
+pub trait Idempotent {
+    fn idempotency_key(&self) -> String;
+}
+
+impl Idempotent for UpdateAccountEvent {
+    fn idempotency_key(&self) -> String {
+        format!(
+            "{}:{}:{}",
+            self.slot,
+            bs58::encode(&self.pubkey).into_string(),
+            self.write_version
+        )
+    }
+}
+
+impl Idempotent for SlotAccountsBatch {
+    fn idempotency_key(&self) -> String {
+        self.slot.to_string()
+    }
+}
+
+impl Idempotent for SlotStatusEvent {
+    fn idempotency_key(&self) -> String {
+        format!("{}:{:?}", self.slot, self.status()).to_lowercase()
+    }
+}
+
+impl Idempotent for TransactionEvent {
+    fn idempotency_key(&self) -> String {
+        format!(
+            "{}:{}",
+            self.slot,
+            bs58::encode(&self.signature).into_string()
+        )
+    }
+}
+
+impl Idempotent for TransactionStatusEvent {
+    fn idempotency_key(&self) -> String {
+        format!(
+            "{}:{}",
+            self.slot,
+            bs58::encode(&self.signature).into_string()
+        )
+    }
+}
+
+impl Idempotent for CompressedNftEvent {
+    fn idempotency_key(&self) -> String {
+        format!(
+            "{}:{}:{}",
+            bs58::encode(&self.tree).into_string(),
+            self.sequence,
+            bs58::encode(&self.signature).into_string()
+        )
+    }
+}
+
+impl Idempotent for ProgramUpgraded {
+    fn idempotency_key(&self) -> String {
+        format!(
+            "{}:{}:{}",
+            self.slot,
+            bs58::encode(&self.programdata).into_string(),
+            self.write_version
+        )
+    }
+}
+
+impl Idempotent for TokenBalanceChange {
+    fn idempotency_key(&self) -> String {
+        format!(
+            "{}:{}:{}",
+            self.slot,
+            bs58::encode(&self.account).into_string(),
+            self.write_version
+        )
+    }
+}
+
+impl Idempotent for OwnerChanged {
+    fn idempotency_key(&self) -> String {
+        format!(
+            "{}:{}:{}",
+            self.slot,
+            bs58::encode(&self.account).into_string(),
+            self.write_version
+        )
+    }
+}
+
+impl Idempotent for BlockMetadataEvent {
+    fn idempotency_key(&self) -> String {
+        self.slot.to_string()
+    }
+}
+
+impl Idempotent for Watermark {
+    fn idempotency_key(&self) -> String {
+        self.unix_time_ms.to_string()
+    }
+}
+
+impl Idempotent for TopicSwitch {
+    fn idempotency_key(&self) -> String {
+        format!(
+            "{}:{}:{}:{:?}",
+            self.from_topic,
+            self.to_topic,
+            self.unix_time_ms,
+            self.phase()
+        )
+        .to_lowercase()
+    }
+}
+
+impl Idempotent for StatsSnapshot {
+    fn idempotency_key(&self) -> String {
+        format!("{}:{}", self.node, self.unix_time_ms)
+    }
+}
+
+impl StatsSnapshot {
+    /// Current metrics of the plugin, as of now.
+    pub fn take(node: String) -> Self {
+        let unix_time_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        Self {
+            node,
+            plugin_version: env!("CARGO_PKG_VERSION").to_owned(),
+            unix_time_ms,
+            metrics: crate::metrics().snapshot().into_iter().collect(),
         }
     }
 }
+
+impl Idempotent for SlotAccountsComplete {
+    fn idempotency_key(&self) -> String {
+        self.slot.to_string()
+    }
+}
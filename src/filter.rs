@@ -12,19 +12,121 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use log::{debug, error, info, warn};
-use std::sync::{Arc, Mutex};
+use log::info;
+use std::{
+    hash::{BuildHasherDefault, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
 use {
     crate::*,
-    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError as PluginError,
     solana_geyser_plugin_interface::geyser_plugin_interface::Result as PluginResult,
     solana_program::pubkey::Pubkey,
-    std::{collections::HashSet, str::FromStr},
+    std::{
+        collections::{HashMap, HashSet},
+        str::FromStr,
+    },
+};
+#[cfg(feature = "http-allowlist")]
+use {
+    log::{error, warn},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError as PluginError,
 };
 
+/// Hasher for 32-byte keys such as pubkeys.
+///
+/// Keys are already uniformly distributed, so instead of SipHash this just folds
+/// the key into a single word and mixes it with a multiplicative FxHash step.
+/// Not resistant to HashDoS; only use it for keys that are hashes or pubkeys.
+#[derive(Default)]
+pub struct KeyHasher(u64);
+
+impl Hasher for KeyHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+        for chunk in bytes.chunks(8) {
+            let mut word = [0u8; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.0 = (self.0.rotate_left(5) ^ u64::from_le_bytes(word)).wrapping_mul(SEED);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// `HashSet` of 32-byte keys using `KeyHasher`.
+pub type KeyHashSet = HashSet<[u8; 32], BuildHasherDefault<KeyHasher>>;
+
+/// Topic and labels the entry of a remote allowlist gives its program.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProgramRoute {
+    /// Topic of the program's account updates, instead of `update_account_topic`, if not empty.
+    pub topic: String,
+    /// Labels `topic_rules` can match.
+    pub labels: Vec<String>,
+}
+
+/// Routes of programs, as listed by the entries of a remote allowlist.
+type RouteMap = HashMap<[u8; 32], ProgramRoute, BuildHasherDefault<KeyHasher>>;
+
+/// Destination topics and labels of programs, kept up to date with the remote allowlist.
+#[derive(Clone, Default)]
+pub struct TopicRoutes {
+    routes: Arc<RwLock<RouteMap>>,
+    /// Bumped on every refresh, so whatever was derived from the routes can be recomputed.
+    generation: Arc<AtomicUsize>,
+}
+
+impl TopicRoutes {
+    /// The topic account updates of `program` go to instead of `update_account_topic`, if any.
+    pub fn get(&self, program: &[u8]) -> Option<String> {
+        let key = <&[u8; 32]>::try_from(program).ok()?;
+        let routes = self.routes.read().unwrap();
+        let route = routes.get(key)?;
+        (!route.topic.is_empty()).then(|| route.topic.clone())
+    }
+
+    /// Labels of `program`.
+    pub fn labels(&self, program: &[u8]) -> Vec<String> {
+        let key = match <&[u8; 32]>::try_from(program) {
+            Ok(key) => key,
+            _ => return Vec::new(),
+        };
+        let routes = self.routes.read().unwrap();
+        routes
+            .get(key)
+            .map(|route| route.labels.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn generation(&self) -> usize {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    pub fn len(&self) -> usize {
+        self.routes.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[cfg_attr(not(feature = "http-allowlist"), allow(dead_code))]
+    pub(crate) fn replace(&self, routes: RouteMap) {
+        *self.routes.write().unwrap() = routes;
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
 pub struct Filter {
-    program_ignores: HashSet<[u8; 32]>,
+    program_ignores: KeyHashSet,
     program_allowlist: Allowlist,
+    min_transaction_fee_lamports: u64,
+    min_compute_units: u64,
 }
 // Copy for Filter
 impl Clone for Filter {
@@ -32,6 +134,8 @@ impl Clone for Filter {
         Self {
             program_ignores: self.program_ignores.clone(),
             program_allowlist: self.program_allowlist.clone(),
+            min_transaction_fee_lamports: self.min_transaction_fee_lamports,
+            min_compute_units: self.min_compute_units,
         }
     }
 }
@@ -40,7 +144,7 @@ impl Filter {
     pub fn new(config: &Config) -> Self {
         info!("Creating filter");
 
-        if config.program_allowlist.len() > 0 {
+        if !config.program_allowlist.is_empty() {
             info!(
                 "Using program allowlist, with {} entries",
                 config.program_allowlist.len()
@@ -52,7 +156,7 @@ impl Filter {
         if !config.program_allowlist_url.is_empty() {
             info!(
                 "Using program allowlist from url: {}",
-                config.program_allowlist_url
+                redact_url(&config.program_allowlist_url)
             );
         } else {
             info!("program_allowlist_url is empty");
@@ -74,16 +178,27 @@ impl Filter {
                 .flat_map(|p| Pubkey::from_str(p).ok().map(|p| p.to_bytes()))
                 .collect(),
             program_allowlist: Allowlist::new_from_config(config).unwrap(),
+            min_transaction_fee_lamports: config.min_transaction_fee_lamports,
+            min_compute_units: config.min_compute_units,
         }
     }
 
+    /// Records the initial key sets, and later changes of the allowlist, in `audit_log`.
+    pub fn with_audit_log(mut self, audit_log: Arc<FilterAuditLog>) -> Self {
+        audit_log.record_load("program_allowlist", &self.program_allowlist.programs());
+        let ignores: Vec<[u8; 32]> = self.program_ignores.iter().copied().collect();
+        audit_log.record_load("program_ignores", &ignores);
+        self.program_allowlist.audit_log = Some(audit_log);
+        self
+    }
+
     pub fn get_allowlist(&self) -> Allowlist {
         self.program_allowlist.clone()
     }
 
     pub fn wants_program(&self, program: &[u8]) -> bool {
         // If allowlist is not empty, only allowlist is used.
-        if self.program_allowlist.len() > 0 {
+        if !self.program_allowlist.is_empty() {
             return self.program_allowlist.wants_program(program);
         }
         let key = match <&[u8; 32]>::try_from(program) {
@@ -92,11 +207,136 @@ impl Filter {
         };
         !self.program_ignores.contains(key)
     }
+
+    /// Whether transactions need their consumed compute units to be checked.
+    pub fn filters_compute_units(&self) -> bool {
+        self.min_compute_units > 0
+    }
+
+    /// Returns true if a transaction's fee and consumed compute units meet the configured minimums.
+    pub fn wants_transaction_cost(&self, fee: u64, compute_units: u64) -> bool {
+        self.transaction_cost_filter(fee, compute_units).is_none()
+    }
+
+    /// The option whose minimum a transaction's fee or consumed compute units fall short of, if any.
+    pub fn transaction_cost_filter(&self, fee: u64, compute_units: u64) -> Option<&'static str> {
+        if fee < self.min_transaction_fee_lamports {
+            Some("min_transaction_fee_lamports")
+        } else if compute_units < self.min_compute_units {
+            Some("min_compute_units")
+        } else {
+            None
+        }
+    }
+
+    /// The option programs are filtered by, for the accounting of dropped messages.
+    pub fn program_filter(&self) -> &'static str {
+        if self.program_allowlist.is_empty() {
+            "program_ignores"
+        } else {
+            "program_allowlist"
+        }
+    }
+
+    /// Returns true if any of `programs` is wanted, e.g. one of the programs invoked by a transaction.
+    pub fn wants_any_program<'a>(&self, mut programs: impl Iterator<Item = &'a [u8]>) -> bool {
+        programs.any(|program| self.wants_program(program))
+    }
+}
+
+/// Number of shards in a `KeySet`, one per possible first key byte.
+const KEY_SET_SHARDS: usize = 256;
+
+/// Set of 32-byte keys sharded by the first key byte,
+/// so concurrent lookups from several geyser threads don't contend on a single lock.
+pub struct KeySet {
+    shards: Vec<RwLock<KeyHashSet>>,
+    /// Total number of keys, kept separately so `len` doesn't have to visit every shard.
+    len: AtomicUsize,
+}
+
+impl KeySet {
+    pub fn new() -> Self {
+        Self::from_set(HashSet::new())
+    }
+
+    pub fn from_set(keys: HashSet<[u8; 32]>) -> Self {
+        let this = Self {
+            shards: (0..KEY_SET_SHARDS)
+                .map(|_| RwLock::new(KeyHashSet::default()))
+                .collect(),
+            len: AtomicUsize::new(0),
+        };
+        this.replace(keys);
+        this
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, key: &[u8; 32]) -> bool {
+        self.shard(key).read().unwrap().contains(key)
+    }
+
+    /// All keys, in no particular order.
+    pub fn keys(&self) -> Vec<[u8; 32]> {
+        self.shards
+            .iter()
+            .flat_map(|shard| shard.read().unwrap().iter().copied().collect::<Vec<_>>())
+            .collect()
+    }
+
+    #[cfg_attr(not(feature = "http-allowlist"), allow(dead_code))]
+    pub fn insert(&self, key: [u8; 32]) {
+        if self.shard(&key).write().unwrap().insert(key) {
+            self.len.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Replaces the contents of the set, returning the keys added and removed.
+    ///
+    /// Shards are swapped one at a time, so concurrent readers may briefly see a mix
+    /// of the old and the new keys.
+    pub fn replace(&self, keys: HashSet<[u8; 32]>) -> (Vec<[u8; 32]>, Vec<[u8; 32]>) {
+        let mut shards = vec![KeyHashSet::default(); KEY_SET_SHARDS];
+        for key in keys {
+            shards[key[0] as usize].insert(key);
+        }
+        let (mut added, mut removed) = (Vec::new(), Vec::new());
+        for (shard, keys) in self.shards.iter().zip(shards) {
+            let mut shard = shard.write().unwrap();
+            added.extend(keys.difference(&shard).copied());
+            removed.extend(shard.difference(&keys).copied());
+            let (old, new) = (shard.len(), keys.len());
+            *shard = keys;
+            if new >= old {
+                self.len.fetch_add(new - old, Ordering::Relaxed);
+            } else {
+                self.len.fetch_sub(old - new, Ordering::Relaxed);
+            }
+        }
+        (added, removed)
+    }
+
+    fn shard(&self, key: &[u8; 32]) -> &RwLock<KeyHashSet> {
+        &self.shards[key[0] as usize]
+    }
+}
+
+impl Default for KeySet {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 pub struct Allowlist {
     /// List of programs to allow.
-    list: Arc<Mutex<HashSet<[u8; 32]>>>,
+    list: Arc<KeySet>,
     /// Url to fetch allowlist from.
     http_url: String,
     /// Last time the allowlist was updated from the remote server.
@@ -105,7 +345,16 @@ pub struct Allowlist {
     http_update_interval: std::time::Duration,
     // http_updater_one is used to ensure that only one thread is fetching the allowlist from the
     // remote server at a time.
+    #[cfg_attr(not(feature = "http-allowlist"), allow(dead_code))]
     http_updater_one: Arc<Mutex<()>>,
+    /// HTTP client, possibly with client certificate and pinned CA.
+    #[cfg(feature = "http-allowlist")]
+    http_agent: ureq::Agent,
+    /// Where changes fetched from the remote server are recorded.
+    #[cfg_attr(not(feature = "http-allowlist"), allow(dead_code))]
+    audit_log: Option<Arc<FilterAuditLog>>,
+    /// Topics of the remote entries naming one.
+    topic_routes: TopicRoutes,
 }
 
 // Copy
@@ -117,45 +366,142 @@ impl Clone for Allowlist {
             http_last_updated: self.http_last_updated.clone(),
             http_update_interval: self.http_update_interval,
             http_updater_one: self.http_updater_one.clone(),
+            #[cfg(feature = "http-allowlist")]
+            http_agent: self.http_agent.clone(),
+            audit_log: self.audit_log.clone(),
+            topic_routes: self.topic_routes.clone(),
         }
     }
 }
 
+#[cfg(feature = "http-allowlist")]
 use serde::Deserialize;
 
+#[cfg(feature = "http-allowlist")]
 #[derive(Deserialize, Debug)]
 struct RemoteAllowlist {
     #[serde(rename = "programAllowlist")]
-    program_allowlist: Vec<String>,
+    program_allowlist: Vec<RemoteAllowlistEntry>,
+}
+
+/// A program id, or an object with the program id and the topic and labels of its account updates.
+#[cfg(feature = "http-allowlist")]
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum RemoteAllowlistEntry {
+    Program(String),
+    Routed {
+        program: String,
+        #[serde(default)]
+        topic: String,
+        #[serde(default)]
+        labels: Vec<String>,
+    },
+}
+
+/// Parses a remote allowlist, either JSON or CSV lines of `program[,topic[,labels]]` with labels separated
+/// by `;`, into its programs and routes.
+///
+/// Entries that are not valid program ids, such as a CSV header, are skipped.
+#[cfg(feature = "http-allowlist")]
+fn parse_remote_allowlist(body: &str) -> Result<(HashSet<[u8; 32]>, RouteMap), String> {
+    let entries: Vec<(String, ProgramRoute)> = if body.trim_start().starts_with('{') {
+        let list: RemoteAllowlist = serde_json::from_str(body).map_err(|e| e.to_string())?;
+        list.program_allowlist
+            .into_iter()
+            .map(|entry| match entry {
+                RemoteAllowlistEntry::Program(program) => (program, ProgramRoute::default()),
+                RemoteAllowlistEntry::Routed {
+                    program,
+                    topic,
+                    labels,
+                } => (program, ProgramRoute { topic, labels }),
+            })
+            .collect()
+    } else {
+        body.lines()
+            .map(|line| {
+                let mut fields = line.split(',').map(str::trim);
+                let program = fields.next().unwrap_or_default().to_owned();
+                let topic = fields.next().unwrap_or_default().to_owned();
+                let labels = fields
+                    .next()
+                    .unwrap_or_default()
+                    .split(';')
+                    .map(str::trim)
+                    .filter(|label| !label.is_empty())
+                    .map(str::to_owned)
+                    .collect();
+                (program, ProgramRoute { topic, labels })
+            })
+            .collect()
+    };
+    let mut programs = HashSet::new();
+    let mut routes = RouteMap::default();
+    for (program, route) in entries {
+        let program = match Pubkey::from_str(&program) {
+            Ok(program) => program.to_bytes(),
+            Err(_) => continue,
+        };
+        programs.insert(program);
+        if route != ProgramRoute::default() {
+            routes.insert(program, route);
+        }
+    }
+    Ok((programs, routes))
 }
 
 // new() is a constructor for Allowlist
 impl Allowlist {
     pub fn len(&self) -> usize {
-        let list = self.list.lock().unwrap();
-        list.len()
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Programs currently allowed.
+    pub fn programs(&self) -> Vec<[u8; 32]> {
+        self.list.keys()
+    }
+
+    /// Topics the remote allowlist routes the account updates of its programs to.
+    pub fn topic_routes(&self) -> TopicRoutes {
+        self.topic_routes.clone()
     }
+
     pub fn new_from_config(config: &Config) -> PluginResult<Self> {
         info!("Creating allowlist");
 
+        // Configs with a remote allowlist are rejected without the feature.
+        #[cfg(feature = "http-allowlist")]
         if !config.program_allowlist_url.is_empty() {
             info!(
                 "Using program allowlist from url: {}",
-                config.program_allowlist_url,
+                redact_url(&config.program_allowlist_url),
             );
-            let out = Self::new_from_http(
+            let http_agent = tls::http_agent(
+                &config.program_allowlist_tls_cert,
+                &config.program_allowlist_tls_key,
+                &config.program_allowlist_tls_ca,
+            )
+            .map_err(|e| PluginError::Custom(Box::new(simple_error::SimpleError::new(e))))?;
+            let out = Self::new_from_http_with_agent(
                 &config.program_allowlist_url.clone(),
                 std::time::Duration::from_secs(config.program_allowlist_expiry_sec),
+                http_agent.clone(),
             );
             if out.is_err() {
                 let err = out.as_ref().err().unwrap();
                 error!(
                     "Failed to fetch allowlist from url at creation {}: {:?}",
-                    config.program_allowlist_url, err
+                    redact_url(&config.program_allowlist_url),
+                    err
                 );
 
                 return Ok(Self {
-                    list: Arc::new(Mutex::new(HashSet::new())),
+                    list: Arc::new(KeySet::new()),
                     // last updated: now
                     http_last_updated: Arc::new(Mutex::new(std::time::Instant::now())),
                     http_url: config.program_allowlist_url.to_string(),
@@ -163,6 +509,9 @@ impl Allowlist {
                         config.program_allowlist_expiry_sec,
                     ),
                     http_updater_one: Arc::new(Mutex::new(())),
+                    http_agent,
+                    audit_log: None,
+                    topic_routes: TopicRoutes::default(),
                 });
             }
 
@@ -176,8 +525,10 @@ impl Allowlist {
                 unwrap.push_vec(config.program_allowlist.clone());
             }
 
-            Ok(unwrap)
-        } else if !config.program_allowlist.is_empty() {
+            return Ok(unwrap);
+        }
+
+        if !config.program_allowlist.is_empty() {
             info!(
                 "Using program allowlist, with {} entries",
                 config.program_allowlist.len()
@@ -186,11 +537,15 @@ impl Allowlist {
         } else {
             info!("program_allowlist is empty");
             Ok(Self {
-                list: Arc::new(Mutex::new(HashSet::new())),
+                list: Arc::new(KeySet::new()),
                 http_last_updated: Arc::new(Mutex::new(std::time::Instant::now())),
                 http_url: "".to_string(),
                 http_update_interval: std::time::Duration::from_secs(0),
                 http_updater_one: Arc::new(Mutex::new(())),
+                #[cfg(feature = "http-allowlist")]
+                http_agent: ureq::agent(),
+                audit_log: None,
+                topic_routes: TopicRoutes::default(),
             })
         }
     }
@@ -202,16 +557,20 @@ impl Allowlist {
             .flat_map(|p| Pubkey::from_str(p).ok().map(|p| p.to_bytes()))
             .collect();
         Ok(Self {
-            list: Arc::new(Mutex::new(program_allowlist)),
+            list: Arc::new(KeySet::from_set(program_allowlist)),
             http_last_updated: Arc::new(Mutex::new(std::time::Instant::now())),
             http_url: "".to_string(),
             http_update_interval: std::time::Duration::from_secs(0),
             http_updater_one: Arc::new(Mutex::new(())),
+            #[cfg(feature = "http-allowlist")]
+            http_agent: ureq::agent(),
+            audit_log: None,
+            topic_routes: TopicRoutes::default(),
         })
     }
 
+    #[cfg(feature = "http-allowlist")]
     fn push_vec(&mut self, program_allowlist: Vec<String>) {
-        let mut list = self.list.lock().unwrap();
         for pubkey_string in program_allowlist {
             let pubkey = Pubkey::from_str(&pubkey_string);
             if pubkey.is_err() {
@@ -222,16 +581,18 @@ impl Allowlist {
                 );
                 continue;
             }
-            list.insert(pubkey.unwrap().to_bytes());
+            self.list.insert(pubkey.unwrap().to_bytes());
         }
     }
 
     // fetch_remote_allowlist fetches the allowlist from the remote server,
-    // and returns a HashSet of program ids.
-    fn fetch_remote_allowlist(url: &str) -> PluginResult<HashSet<[u8; 32]>> {
-        let mut program_allowlist = HashSet::new();
-
-        match ureq::get(url).call() {
+    // and returns a HashSet of program ids along with the topics of the entries naming one.
+    #[cfg(feature = "http-allowlist")]
+    fn fetch_remote_allowlist(
+        agent: &ureq::Agent,
+        url: &str,
+    ) -> PluginResult<(HashSet<[u8; 32]>, RouteMap)> {
+        match agent.get(url).call() {
             Ok(response) => {
                 if response.status() != 200 {
                     return Err(PluginError::Custom(Box::new(
@@ -251,44 +612,26 @@ impl Allowlist {
                         )),
                     )));
                 }
-                // parse the response body as json:
-                let raw = serde_json::from_str(&body.unwrap());
-                if raw.is_err() {
-                    return Err(PluginError::Custom(Box::new(
-                        simple_error::SimpleError::new(format!(
-                            "Failed to fetch allowlist from remote server: {}",
-                            raw.err().unwrap()
-                        )),
-                    )));
-                }
-                let list: RemoteAllowlist = raw.unwrap();
-                for pubkey in list.program_allowlist {
-                    let pubkey = Pubkey::from_str(&pubkey);
-                    if pubkey.is_err() {
-                        continue;
-                    }
-                    program_allowlist.insert(pubkey.unwrap().to_bytes());
-                }
-            }
-            Err(ureq::Error::Status(code, _response)) => {
-                return Err(PluginError::Custom(Box::new(
-                    simple_error::SimpleError::new(format!(
-                        "Failed to fetch allowlist from remote server: status {}",
-                        code
-                    )),
-                )));
-            }
-            Err(e) => {
-                return Err(PluginError::Custom(Box::new(
-                    simple_error::SimpleError::new(format!(
-                        "Failed to fetch allowlist from remote server: status {}",
+                parse_remote_allowlist(&body.unwrap()).map_err(|e| {
+                    PluginError::Custom(Box::new(simple_error::SimpleError::new(format!(
+                        "Failed to fetch allowlist from remote server: {}",
                         e
-                    )),
-                )));
+                    ))))
+                })
             }
+            Err(ureq::Error::Status(code, _response)) => Err(PluginError::Custom(Box::new(
+                simple_error::SimpleError::new(format!(
+                    "Failed to fetch allowlist from remote server: status {}",
+                    code
+                )),
+            ))),
+            Err(e) => Err(PluginError::Custom(Box::new(
+                simple_error::SimpleError::new(format!(
+                    "Failed to fetch allowlist from remote server: status {}",
+                    redact::redact_url_in(&e.to_string(), url)
+                )),
+            ))),
         }
-
-        Ok(program_allowlist)
     }
 
     pub fn get_last_updated(&self) -> std::time::Instant {
@@ -296,24 +639,28 @@ impl Allowlist {
         *v
     }
 
+    #[cfg(feature = "http-allowlist")]
     fn is_updating(&self) -> bool {
         let v = self.http_last_updated.try_lock();
         v.is_err()
     }
 
+    #[cfg(feature = "http-allowlist")]
     pub fn update_from_http(&mut self) -> PluginResult<()> {
         if self.http_url.is_empty() {
             return Ok(());
         }
         let _once = self.http_updater_one.lock().unwrap();
 
-        let program_allowlist = Self::fetch_remote_allowlist(&self.http_url);
+        let program_allowlist = Self::fetch_remote_allowlist(&self.http_agent, &self.http_url);
         if program_allowlist.is_err() {
             return Err(program_allowlist.err().unwrap());
         }
 
-        let mut list = self.list.lock().unwrap();
-        *list = program_allowlist.unwrap();
+        let (program_allowlist, routes) = program_allowlist.unwrap();
+        let (added, removed) = self.list.replace(program_allowlist);
+        self.topic_routes.replace(routes);
+        Self::audit_refresh(&self.audit_log, &added, &removed);
 
         let mut http_last_updated = self.http_last_updated.lock().unwrap();
         *http_last_updated = std::time::Instant::now();
@@ -322,6 +669,7 @@ impl Allowlist {
 
     // update_from_http_non_blocking updates the allowlist from a remote URL
     // without blocking the main thread.
+    #[cfg(feature = "http-allowlist")]
     pub fn update_from_http_non_blocking(&self) {
         if self.http_url.is_empty() {
             return;
@@ -334,26 +682,46 @@ impl Allowlist {
         let list = self.list.clone();
         let http_last_updated = self.http_last_updated.clone();
         let url = self.http_url.clone();
+        let agent = self.http_agent.clone();
+        let audit_log = self.audit_log.clone();
+        let topic_routes = self.topic_routes.clone();
         std::thread::spawn(move || {
-            info!("Updating program allowlist from remote server: {}", url);
-            let program_allowlist = Self::fetch_remote_allowlist(&url);
-            if program_allowlist.is_err() {
-                error!(
-                    "Failed to update program allowlist from remote server {}: {}",
-                    url,
-                    program_allowlist.unwrap_err(),
-                );
-                return;
-            }
+            info!(
+                "Updating program allowlist from remote server: {}",
+                redact_url(&url)
+            );
+            let (program_allowlist, routes) = match Self::fetch_remote_allowlist(&agent, &url) {
+                Ok(fetched) => fetched,
+                Err(e) => {
+                    error!(
+                        "Failed to update program allowlist from remote server {}: {}",
+                        redact_url(&url),
+                        e,
+                    );
+                    return;
+                }
+            };
 
-            let mut list = list.lock().unwrap();
-            *list = program_allowlist.unwrap();
+            let (added, removed) = list.replace(program_allowlist);
+            topic_routes.replace(routes);
+            Self::audit_refresh(&audit_log, &added, &removed);
 
             let mut http_last_updated = http_last_updated.lock().unwrap();
             *http_last_updated = std::time::Instant::now();
         });
     }
 
+    #[cfg(feature = "http-allowlist")]
+    fn audit_refresh(
+        audit_log: &Option<Arc<FilterAuditLog>>,
+        added: &[[u8; 32]],
+        removed: &[[u8; 32]],
+    ) {
+        if let Some(audit_log) = audit_log {
+            audit_log.record_change("http_refresh", "program_allowlist", added, removed);
+        }
+    }
+
     pub fn is_remote_allowlist_expired(&self) -> bool {
         if self.http_url.is_empty() {
             return false;
@@ -364,31 +732,48 @@ impl Allowlist {
     }
 
     pub fn update_from_http_if_needed_async(&mut self) {
+        #[cfg(feature = "http-allowlist")]
         if self.is_remote_allowlist_expired() {
             info!(
                 "List expired; updating program allowlist from remote server: {}",
-                self.http_url
+                redact_url(&self.http_url)
             );
             self.update_from_http_non_blocking();
         }
     }
 
+    #[cfg(feature = "http-allowlist")]
     pub fn new_from_http(url: &str, interval: std::time::Duration) -> PluginResult<Self> {
+        Self::new_from_http_with_agent(url, interval, ureq::agent())
+    }
+
+    #[cfg(feature = "http-allowlist")]
+    pub fn new_from_http_with_agent(
+        url: &str,
+        interval: std::time::Duration,
+        http_agent: ureq::Agent,
+    ) -> PluginResult<Self> {
         let mut interval = interval;
         if interval < std::time::Duration::from_secs(1) {
             interval = std::time::Duration::from_secs(1);
         }
-        let program_allowlist = Self::fetch_remote_allowlist(url);
+        let program_allowlist = Self::fetch_remote_allowlist(&http_agent, url);
         if program_allowlist.is_err() {
             return Err(program_allowlist.err().unwrap());
         }
+        let (program_allowlist, routes) = program_allowlist.unwrap();
+        let topic_routes = TopicRoutes::default();
+        topic_routes.replace(routes);
         Ok(Self {
-            list: Arc::new(Mutex::new(program_allowlist.unwrap())),
+            list: Arc::new(KeySet::from_set(program_allowlist)),
             // last updated: now
             http_last_updated: Arc::new(Mutex::new(std::time::Instant::now())),
             http_url: url.to_string(),
             http_update_interval: interval,
             http_updater_one: Arc::new(Mutex::new(())),
+            http_agent,
+            audit_log: None,
+            topic_routes,
         })
     }
 
@@ -397,8 +782,7 @@ impl Allowlist {
             Ok(key) => key,
             _ => return true,
         };
-        let list = self.list.lock().unwrap();
-        list.is_empty() || list.contains(key)
+        self.list.is_empty() || self.list.contains(key)
     }
 }
 
@@ -431,6 +815,60 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_filter_wants_any_program() {
+        let config = Config {
+            program_allowlist: vec!["Vote111111111111111111111111111111111111111".to_owned()],
+            ..Config::default()
+        };
+        let filter = Filter::new(&config);
+
+        let vote = Pubkey::from_str("Vote111111111111111111111111111111111111111").unwrap();
+        let other = Pubkey::from_str("9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin").unwrap();
+        assert!(filter.wants_any_program([other.as_ref(), vote.as_ref()].into_iter()));
+        assert!(!filter.wants_any_program([other.as_ref()].into_iter()));
+        assert!(!filter.wants_any_program(std::iter::empty()));
+    }
+
+    #[test]
+    fn test_filter_transaction_cost() {
+        let config = Config {
+            min_transaction_fee_lamports: 10_000,
+            min_compute_units: 50_000,
+            ..Config::default()
+        };
+        let filter = Filter::new(&config);
+        assert!(filter.filters_compute_units());
+        assert!(filter.wants_transaction_cost(10_000, 50_000));
+        assert!(!filter.wants_transaction_cost(5_000, 50_000));
+        assert!(!filter.wants_transaction_cost(10_000, 1_000));
+
+        let filter = Filter::new(&Config::default());
+        assert!(!filter.filters_compute_units());
+        assert!(filter.wants_transaction_cost(0, 0));
+    }
+
+    #[test]
+    fn test_key_set() {
+        let set = KeySet::new();
+        assert!(set.is_empty());
+
+        set.insert([1; 32]);
+        set.insert([1; 32]);
+        set.insert([2; 32]);
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&[1; 32]));
+        assert!(!set.contains(&[3; 32]));
+
+        let (added, removed) = set.replace([[2; 32], [3; 32]].into_iter().collect());
+        assert_eq!(added, vec![[3; 32]]);
+        assert_eq!(removed, vec![[1; 32]]);
+        set.replace([[3; 32]].into_iter().collect());
+        assert_eq!(set.len(), 1);
+        assert!(set.contains(&[3; 32]));
+        assert!(!set.contains(&[1; 32]));
+    }
+
     #[test]
     fn test_allowlist_from_vec() {
         let config = Config {
@@ -462,6 +900,41 @@ mod tests {
         ));
     }
 
+    #[cfg(feature = "http-allowlist")]
+    #[test]
+    fn test_parse_remote_allowlist() {
+        let sysvar = Pubkey::from_str("Sysvar1111111111111111111111111111111111111")
+            .unwrap()
+            .to_bytes();
+        let vote = Pubkey::from_str("Vote111111111111111111111111111111111111111")
+            .unwrap()
+            .to_bytes();
+
+        let (programs, routes) = parse_remote_allowlist(
+            r#"{"programAllowlist": [
+                "Sysvar1111111111111111111111111111111111111",
+                {"program": "Vote111111111111111111111111111111111111111", "topic": "votes"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(programs, [sysvar, vote].into_iter().collect());
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[&vote].topic, "votes");
+
+        let (programs, routes) = parse_remote_allowlist(
+            "program,topic\nSysvar1111111111111111111111111111111111111, sysvars\n\
+             Vote111111111111111111111111111111111111111,, core; consensus\n",
+        )
+        .unwrap();
+        assert_eq!(programs, [sysvar, vote].into_iter().collect());
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[&sysvar].topic, "sysvars");
+        assert_eq!(routes[&vote].labels, ["core", "consensus"]);
+
+        assert!(parse_remote_allowlist("{\"programAllowlist\": 1}").is_err());
+    }
+
+    #[cfg(feature = "http-allowlist")]
     #[test]
     fn test_allowlist_from_http() {
         // create fake http server
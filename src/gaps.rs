@@ -0,0 +1,107 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {crate::*, log::warn, std::collections::HashMap};
+
+#[derive(Default)]
+struct AccountUpdates {
+    /// `write_version` of the last update handed to the publisher.
+    last_published: Option<u64>,
+    /// First `write_version` and number of the updates lost since.
+    dropped: Option<(u64, u64)>,
+}
+
+/// Watches the updates of every published account for ones that were lost
+/// on their way to the publisher, such as on a full queue.
+///
+/// A gap is reported with the next update published after the lost ones,
+/// so consumers' reconciliation is not the first to notice.
+#[derive(Default)]
+pub struct GapDetector {
+    accounts: HashMap<[u8; 32], AccountUpdates>,
+}
+
+impl GapDetector {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Registers an update of `pubkey` that was handed to the publisher.
+    pub fn published(&mut self, pubkey: &[u8], write_version: u64) {
+        let account = self.accounts.entry(Self::key(pubkey)).or_default();
+        if let Some((first, count)) = account.dropped.take() {
+            metrics().account_update_gaps.inc();
+            warn!(
+                "Gap in updates of account {}: {} update(s) lost from write_version {} until {}",
+                bs58::encode(pubkey).into_string(),
+                count,
+                first,
+                write_version
+            );
+        }
+        if let Some(last) = account.last_published {
+            if write_version < last {
+                metrics().account_updates_reordered.inc();
+                warn!(
+                    "Update of account {} with write_version {} published after {}",
+                    bs58::encode(pubkey).into_string(),
+                    write_version,
+                    last
+                );
+            }
+        }
+        account.last_published = Some(write_version);
+    }
+
+    /// Registers an update of `pubkey` that could not be handed to the publisher.
+    pub fn dropped(&mut self, pubkey: &[u8], write_version: u64) {
+        let account = self.accounts.entry(Self::key(pubkey)).or_default();
+        let (_, count) = account.dropped.get_or_insert((write_version, 0));
+        *count += 1;
+    }
+
+    fn key(pubkey: &[u8]) -> [u8; 32] {
+        let mut key = [0u8; 32];
+        let len = pubkey.len().min(key.len());
+        key[..len].copy_from_slice(&pubkey[..len]);
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gap_detector() {
+        let mut gaps = GapDetector::new();
+        let before = metrics().account_update_gaps.get();
+        gaps.published(&[1; 32], 10);
+        gaps.published(&[1; 32], 12);
+        assert_eq!(metrics().account_update_gaps.get(), before);
+
+        gaps.dropped(&[1; 32], 13);
+        gaps.dropped(&[1; 32], 15);
+        gaps.published(&[2; 32], 16);
+        assert_eq!(metrics().account_update_gaps.get(), before);
+        gaps.published(&[1; 32], 17);
+        assert_eq!(metrics().account_update_gaps.get(), before + 1);
+        gaps.published(&[1; 32], 18);
+        assert_eq!(metrics().account_update_gaps.get(), before + 1);
+
+        let reordered = metrics().account_updates_reordered.get();
+        gaps.published(&[2; 32], 14);
+        assert_eq!(metrics().account_updates_reordered.get(), reordered + 1);
+    }
+}
@@ -0,0 +1,115 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Adapters between the geyser plugin interface and the plugin.
+//!
+//! The plugin builds against the Solana 1.13 interface only. Its account notifications are
+//! converted to [`AccountInfo`] here, so moving to another interface version touches the
+//! conversions rather than every notification handler.
+//! Likewise, slot statuses map onto the published [`SlotStatus`].
+
+use {
+    crate::SlotStatus,
+    solana_geyser_plugin_interface::geyser_plugin_interface::{
+        ReplicaAccountInfo, ReplicaAccountInfoVersions, SlotStatus as PluginSlotStatus,
+    },
+};
+
+/// Account notification, independent of the interface version it came from.
+#[derive(Clone, Copy, Debug)]
+pub struct AccountInfo<'a> {
+    pub pubkey: &'a [u8],
+    pub lamports: u64,
+    pub owner: &'a [u8],
+    pub executable: bool,
+    pub rent_epoch: u64,
+    pub data: &'a [u8],
+    pub write_version: u64,
+}
+
+impl<'a> From<&'a ReplicaAccountInfo<'a>> for AccountInfo<'a> {
+    fn from(info: &'a ReplicaAccountInfo<'a>) -> Self {
+        Self {
+            pubkey: info.pubkey,
+            lamports: info.lamports,
+            owner: info.owner,
+            executable: info.executable,
+            rent_epoch: info.rent_epoch,
+            data: info.data,
+            write_version: info.write_version,
+        }
+    }
+}
+
+impl<'a> From<ReplicaAccountInfoVersions<'a>> for AccountInfo<'a> {
+    fn from(account: ReplicaAccountInfoVersions<'a>) -> Self {
+        match account {
+            ReplicaAccountInfoVersions::V0_0_1(info) => info.into(),
+        }
+    }
+}
+
+impl AccountInfo<'_> {
+    /// Hands `self` to `f` as the newest `ReplicaAccountInfo` version of the interface.
+    pub fn with_replica<R>(&self, f: impl FnOnce(ReplicaAccountInfoVersions) -> R) -> R {
+        let info = ReplicaAccountInfo {
+            pubkey: self.pubkey,
+            lamports: self.lamports,
+            owner: self.owner,
+            executable: self.executable,
+            rent_epoch: self.rent_epoch,
+            data: self.data,
+            write_version: self.write_version,
+        };
+        f(ReplicaAccountInfoVersions::V0_0_1(&info))
+    }
+}
+
+impl From<PluginSlotStatus> for SlotStatus {
+    fn from(other: PluginSlotStatus) -> Self {
+        match other {
+            PluginSlotStatus::Processed => SlotStatus::Processed,
+            PluginSlotStatus::Rooted => SlotStatus::Rooted,
+            PluginSlotStatus::Confirmed => SlotStatus::Confirmed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_account_info_round_trip() {
+        let account = AccountInfo {
+            pubkey: &[1; 32],
+            lamports: 5,
+            owner: &[2; 32],
+            executable: true,
+            rent_epoch: 7,
+            data: b"data",
+            write_version: 9,
+        };
+        let converted = account.with_replica(|versions| {
+            let info = AccountInfo::from(versions);
+            (
+                info.pubkey.to_vec(),
+                info.lamports,
+                info.data.to_vec(),
+                info.write_version,
+            )
+        });
+        assert_eq!(converted, (vec![1; 32], 5, b"data".to_vec(), 9));
+    }
+}
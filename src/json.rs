@@ -0,0 +1,128 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Serializers rendering binary event fields in the JSON output of the non-Kafka sinks,
+//! and the RPC shaped JSON payloads of account updates.
+
+use {
+    crate::{topic_switch, SlotStatus, UpdateAccountEvent},
+    serde::Serializer,
+    serde_json::{json, Value},
+};
+
+pub fn base58<T: AsRef<[u8]>, S: Serializer>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&bs58::encode(bytes).into_string())
+}
+
+pub fn base58_list<S: Serializer>(list: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(list.iter().map(|bytes| bs58::encode(bytes).into_string()))
+}
+
+pub fn base64<T: AsRef<[u8]>, S: Serializer>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&::base64::encode(bytes))
+}
+
+pub fn hex<T: AsRef<[u8]>, S: Serializer>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    let hex: String = bytes
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    serializer.serialize_str(&hex)
+}
+
+pub fn slot_status<S: Serializer>(status: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+    match SlotStatus::from_i32(*status) {
+        Some(status) => serializer.serialize_str(&format!("{:?}", status).to_lowercase()),
+        None => serializer.serialize_i32(*status),
+    }
+}
+
+pub fn topic_switch_phase<S: Serializer>(phase: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+    match topic_switch::Phase::from_i32(*phase) {
+        Some(phase) => serializer.serialize_str(&format!("{:?}", phase).to_lowercase()),
+        None => serializer.serialize_i32(*phase),
+    }
+}
+
+/// `ev` as the `result` of an RPC `accountSubscribe` notification with `base64` encoding.
+pub fn rpc_account_notification(ev: &UpdateAccountEvent) -> Value {
+    json!({
+        "context": { "slot": ev.slot },
+        "value": rpc_account(ev),
+    })
+}
+
+/// `ev` as the `result` of an RPC `programSubscribe` notification with `base64` encoding.
+pub fn rpc_program_notification(ev: &UpdateAccountEvent) -> Value {
+    json!({
+        "context": { "slot": ev.slot },
+        "value": {
+            "pubkey": bs58::encode(&ev.pubkey).into_string(),
+            "account": rpc_account(ev),
+        },
+    })
+}
+
+fn rpc_account(ev: &UpdateAccountEvent) -> Value {
+    json!({
+        "data": [::base64::encode(&ev.data), "base64"],
+        "executable": ev.executable,
+        "lamports": ev.lamports,
+        "owner": bs58::encode(&ev.owner).into_string(),
+        "rentEpoch": ev.rent_epoch,
+        "space": ev.data.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn test_json() {
+        let ev = UpdateAccountEvent {
+            slot: 3,
+            pubkey: vec![0; 32].into(),
+            data: vec![1, 2, 3].into(),
+            ..UpdateAccountEvent::default()
+        };
+        let json = serde_json::to_value(&ev).unwrap();
+        assert_eq!(json["pubkey"], "11111111111111111111111111111111");
+        assert_eq!(json["data"], "AQID");
+        assert_eq!(json["slot"], 3);
+
+        let notification = json::rpc_program_notification(&ev);
+        assert_eq!(notification["context"]["slot"], 3);
+        assert_eq!(
+            notification["value"]["pubkey"],
+            "11111111111111111111111111111111"
+        );
+        assert_eq!(
+            notification["value"]["account"]["data"],
+            serde_json::json!(["AQID", "base64"])
+        );
+        assert_eq!(notification["value"]["account"]["space"], 3);
+        assert_eq!(
+            json::rpc_account_notification(&ev)["value"],
+            notification["value"]["account"]
+        );
+
+        let ev = SlotStatusEvent {
+            status: SlotStatus::Confirmed.into(),
+            ..SlotStatusEvent::default()
+        };
+        assert_eq!(serde_json::to_value(&ev).unwrap()["status"], "confirmed");
+    }
+}
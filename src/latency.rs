@@ -0,0 +1,106 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    std::{
+        collections::HashMap,
+        sync::Arc,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// Histograms of the time from the dispatch of events to the acknowledgement of their
+/// Kafka records by the brokers, in milliseconds, by event type.
+///
+/// Records carry their dispatch time as timestamp, see [`Publisher::stamped`].
+pub struct DeliveryLatency {
+    topics: HashMap<String, Arc<Histogram>>,
+}
+
+impl DeliveryLatency {
+    pub fn new(config: &Config) -> Self {
+        let event_types = [
+            (&config.update_account_topic, "account"),
+            (&config.startup_account_topic, "startup_account"),
+            (&config.slot_status_topic, "slot_status"),
+            (&config.transaction_topic, "transaction"),
+            (&config.vote_transaction_topic, "vote_transaction"),
+            (&config.transaction_status_topic, "transaction_status"),
+            (&config.compressed_nft_topic, "compressed_nft"),
+            (&config.program_upgrade_topic, "program_upgraded"),
+            (&config.token_balance_topic, "token_balance"),
+            (&config.owner_change_topic, "owner_changed"),
+            (&config.block_metadata_topic, "block_metadata"),
+            (
+                &config.slot_accounts_complete_topic,
+                "slot_accounts_complete",
+            ),
+            (&config.watermark_topic, "watermark"),
+            (&config.stats_topic, "stats_snapshot"),
+            (&config.dead_letter_topic, "dead_letter"),
+        ];
+        let mut topics = HashMap::new();
+        for (topic, event) in event_types {
+            if !topic.is_empty() {
+                topics.entry(topic.clone()).or_insert_with(|| {
+                    labeled_histogram("delivery_latency_ms", &[("event", event)])
+                });
+            }
+        }
+        Self { topics }
+    }
+
+    /// Records the acknowledgement, now, of a record of `topic` timestamped `timestamp_ms`.
+    pub fn delivered(&self, topic: &str, timestamp_ms: Option<i64>) {
+        let (histogram, timestamp_ms) = match (self.topics.get(topic), timestamp_ms) {
+            (Some(histogram), Some(timestamp_ms)) => (histogram, timestamp_ms),
+            _ => return,
+        };
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as i64);
+        histogram.observe(now_ms.saturating_sub(timestamp_ms).max(0) as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delivery_latency() {
+        let config = Config {
+            update_account_topic: "accounts".to_owned(),
+            startup_account_topic: "accounts".to_owned(),
+            ..Config::default()
+        };
+        let latency = DeliveryLatency::new(&config);
+        let histogram = labeled_histogram("delivery_latency_ms", &[("event", "account")]);
+        let (count, sum) = (histogram.count(), histogram.sum());
+
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+        latency.delivered("accounts", Some(now_ms - 250));
+        latency.delivered("accounts", None);
+        latency.delivered("unknown", Some(now_ms));
+        assert_eq!(histogram.count(), count + 1);
+        assert!(histogram.sum() - sum >= 250);
+        assert!(
+            labeled_histogram("delivery_latency_ms", &[("event", "startup_account")]).count() == 0
+        );
+    }
+}
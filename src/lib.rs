@@ -14,20 +14,175 @@
 
 use solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPlugin;
 
+#[cfg(feature = "admin")]
+mod admin;
+mod alerts;
+mod audit;
+mod balances;
+mod batch;
+mod brokers;
+mod bubblegum;
+mod budget;
+mod canary;
+mod capture;
+mod checkpoint;
+mod chunk;
+mod compression;
+mod compute_budget;
 mod config;
+mod dead_letter;
+#[cfg(feature = "decoders")]
+mod decode;
+mod dedup;
+mod delivery;
+mod dispatcher;
+mod drops;
+mod encryption;
+mod error_report;
 mod event;
 mod filter;
+mod gaps;
+mod interface;
+mod json;
+mod latency;
+mod log_limit;
+#[cfg(feature = "decoders")]
+mod lookup_table;
+mod memory;
+mod metrics;
+#[cfg(feature = "decoders")]
+mod nonce;
+mod oauth;
+#[cfg(feature = "openbook")]
+mod openbook;
+mod ownership;
+mod partition;
 mod plugin;
+mod profiling;
 mod publisher;
+mod quota;
+mod recent;
+mod recording;
+mod recovery;
+mod redact;
+mod reload;
+mod rooted;
+mod rotation;
+mod routing;
+mod schema;
+mod sequence;
+mod shadow;
+mod slice;
+mod spool;
+#[cfg(feature = "decoders")]
+mod stake;
+mod tenant;
+mod timing;
+#[cfg(feature = "http-allowlist")]
+mod tls;
+mod token;
+mod upgrade;
+#[cfg(feature = "decoders")]
+mod vote;
+mod yellowstone;
+
+pub(crate) use log_limit::limited_error;
 
 pub use {
-    config::{Config, Producer},
+    alerts::{AlertMonitor, AlertSample, AlertThresholds, FiringAlert, Health, ALERT_LOG_TARGET},
+    audit::FilterAuditLog,
+    balances::TokenBalances,
+    batch::SlotBatcher,
+    brokers::BrokerStates,
+    bubblegum::compressed_nft_events,
+    budget::{BufferedBytes, InFlightBudget},
+    canary::{CanaryFilter, CanaryFilterConfig},
+    capture::{CaptureConfig, CaptureSink},
+    checkpoint::Checkpoint,
+    chunk::{
+        ChunkAssembler, ChunkInfo, CHUNK_CHECKSUM_HEADER, CHUNK_COUNT_HEADER, CHUNK_ID_HEADER,
+        CHUNK_INDEX_HEADER,
+    },
+    compression::{
+        PayloadCompression, PayloadCompressor, COMPRESSION_DICTIONARY_HEADER, COMPRESSION_HEADER,
+    },
+    compute_budget::{compute_units_consumed, ComputeBudget},
+    config::{
+        AccountPayloadFormat, Config, EventSchema, FileFormat, FileSink, IdempotencyKeyPlacement,
+        Kerberos, Preset, Producer, QueueOverflowPolicy, Sink,
+    },
+    dead_letter::{DeadLetter, DeadLetters, DEAD_LETTER_REASON_HEADER, DEAD_LETTER_TOPIC_HEADER},
+    dedup::{AccountStateCache, ForkDedupWindow, SignatureCache},
+    delivery::{DeliveryContext, SlotTracker},
+    dispatcher::{Dispatcher, Event, QueueGauges, QueueLength},
+    drops::{drops, DropAccounting, DropReason, NO_FILTER},
+    encryption::{open_payload, PayloadEncryption, PayloadEncryptor},
+    error_report::{
+        config_fingerprint, plugin_call, report_panics, stop_reporting_panics, ErrorReporter,
+        ErrorReporting, PluginCall, ReportLevel,
+    },
     event::*,
-    filter::Filter,
+    filter::{Allowlist, Filter, KeyHashSet, KeyHasher, ProgramRoute, TopicRoutes},
+    gaps::GapDetector,
+    interface::AccountInfo,
+    latency::DeliveryLatency,
+    log_limit::{log_limiter, LogLimiter},
+    memory::{account_update_bytes, memory, MemoryAccounting, MemoryComponent, MemoryUsage},
+    metrics::{
+        labeled_counter, labeled_histogram, metrics, Counter, Histogram, Metrics, QUANTILES,
+    },
+    oauth::{MskIam, OAuthBearer},
+    ownership::OwnerChanges,
+    partition::{murmur2, PartitionPinner, PartitionPinning, ProgramPartitions},
     plugin::KafkaPlugin,
-    publisher::Publisher,
+    profiling::{profile_scope, profiled, ProfileScope, ProfileSection},
+    publisher::{Publisher, CONTENT_TYPE_HEADER, FORK_SLOTS_HEADER, IDEMPOTENCY_KEY_HEADER},
+    quota::Quota,
+    recent::RecentAccounts,
+    recording::{
+        replay, Callback, RecordedAccount, RecordedBlock, RecordedSlotStatus, RecordedTokenBalance,
+        RecordedTransaction, ReplayStats,
+    },
+    recovery::ProducerRecovery,
+    redact::{is_secret, redact_options, redact_url, REDACTED},
+    reload::{FileWatcher, TlsReloader},
+    rooted::RootedDiff,
+    rotation::{RotatingFile, Rotation},
+    routing::{check_topic_rules, TopicRouter, TopicRule},
+    schema::{topic_schemas, TopicSchema, EVENT_FILE_DESCRIPTOR_SET, EVENT_PROTO, PROTO_PACKAGE},
+    sequence::AccountSequences,
+    shadow::{Sampler, ShadowConfig, ShadowSource},
+    slice::{DataSlice, DataSlicer},
+    spool::{read_spool_file, Spool, SpoolConfig, SpoolFile, SpoolReplayer, SpooledRecord},
+    tenant::{Tenant, TenantConfig},
+    timing::{SlotElapsed, SlotTimer},
+    token::{decode_token, token_program},
+    upgrade::ProgramUpgrades,
+    yellowstone::{geyser, solana::storage::confirmed_block, SUBSCRIBE_UPDATE},
+};
+
+#[cfg(feature = "admin")]
+pub use admin::{AdminServer, AdminSources};
+
+#[cfg(feature = "decoders")]
+pub use {
+    decode::{DecoderRegistry, ProgramDecoder},
+    lookup_table::decode_lookup_table,
+    nonce::decode_nonce,
+    stake::decode_stake,
+    vote::decode_vote,
 };
 
+#[cfg(feature = "openbook")]
+pub use openbook::OpenBookDecoder;
+
+#[cfg(feature = "profiling")]
+pub use profiling::{heap_profile, profile_cpu, ProfilingAllocator, MAX_PROFILE_DURATION};
+
+#[cfg(feature = "profiling")]
+#[global_allocator]
+static ALLOCATOR: ProfilingAllocator = ProfilingAllocator;
+
 #[no_mangle]
 #[allow(improper_ctypes_definitions)]
 /// # Safety
@@ -0,0 +1,233 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    lazy_static::lazy_static,
+    log::Level,
+    std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+/// Distinct messages whose repeats are counted at once, more are logged as they come.
+const MAX_TRACKED: usize = 1024;
+/// How often expired repeats are looked for.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Logs `format!($($arg)*)` at error level through the [`log_limiter`].
+macro_rules! limited_error {
+    ($($arg:tt)*) => {
+        $crate::log_limiter().log(module_path!(), log::Level::Error, format!($($arg)*))
+    };
+}
+pub(crate) use limited_error;
+
+struct Repeats {
+    target: &'static str,
+    level: Level,
+    since: Instant,
+    suppressed: u64,
+}
+
+/// A log line to write.
+#[derive(Debug, PartialEq, Eq)]
+struct Line {
+    target: &'static str,
+    level: Level,
+    message: String,
+}
+
+struct Tracked {
+    repeats: HashMap<String, Repeats>,
+    last_sweep: Instant,
+}
+
+/// Collapses identical log messages repeated within an interval into one summary with their count.
+///
+/// The first occurrence is logged right away, the repeats within the interval are counted and
+/// summarized once the interval is over, with the next limited message or [`Self::flush`].
+pub struct LogLimiter {
+    interval_ms: AtomicU64,
+    tracked: Mutex<Tracked>,
+}
+
+impl LogLimiter {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval_ms: AtomicU64::new(interval.as_millis() as u64),
+            tracked: Mutex::new(Tracked {
+                repeats: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    /// Zero logs every message.
+    pub fn set_interval(&self, interval: Duration) {
+        self.interval_ms
+            .store(interval.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms.load(Ordering::Relaxed))
+    }
+
+    pub fn log(&self, target: &'static str, level: Level, message: String) {
+        for line in self.admit(Instant::now(), target, level, message) {
+            log::log!(target: line.target, line.level, "{}", line.message);
+        }
+    }
+
+    /// Logs the summaries of all repeats counted so far.
+    pub fn flush(&self) {
+        let mut tracked = self.tracked.lock().unwrap();
+        let lines: Vec<Line> = tracked
+            .repeats
+            .drain()
+            .filter_map(|(message, repeats)| summary(message, repeats, Instant::now()))
+            .collect();
+        drop(tracked);
+        for line in lines {
+            log::log!(target: line.target, line.level, "{}", line.message);
+        }
+    }
+
+    /// The lines to log for `message` at `now`: summaries of expired repeats, then `message` unless it is a repeat.
+    fn admit(
+        &self,
+        now: Instant,
+        target: &'static str,
+        level: Level,
+        message: String,
+    ) -> Vec<Line> {
+        let interval = self.interval();
+        if interval.is_zero() {
+            return vec![Line {
+                target,
+                level,
+                message,
+            }];
+        }
+        let mut tracked = self.tracked.lock().unwrap();
+        let mut lines = Vec::new();
+        if now.saturating_duration_since(tracked.last_sweep) >= SWEEP_INTERVAL {
+            tracked.last_sweep = now;
+            let expired: Vec<String> = tracked
+                .repeats
+                .iter()
+                .filter(|(_, repeats)| now.saturating_duration_since(repeats.since) >= interval)
+                .map(|(message, _)| message.clone())
+                .collect();
+            for message in expired {
+                let repeats = tracked.repeats.remove(&message).unwrap();
+                lines.extend(summary(message, repeats, now));
+            }
+        }
+        match tracked.repeats.get_mut(&message) {
+            Some(repeats) if now.saturating_duration_since(repeats.since) < interval => {
+                repeats.suppressed += 1;
+                metrics().log_messages_suppressed.inc();
+                return lines;
+            }
+            Some(_) => {
+                let repeats = tracked.repeats.remove(&message).unwrap();
+                lines.extend(summary(message.clone(), repeats, now));
+            }
+            None => {}
+        }
+        if tracked.repeats.len() < MAX_TRACKED {
+            let repeats = Repeats {
+                target,
+                level,
+                since: now,
+                suppressed: 0,
+            };
+            tracked.repeats.insert(message.clone(), repeats);
+        }
+        lines.push(Line {
+            target,
+            level,
+            message,
+        });
+        lines
+    }
+}
+
+/// Summary of the repeats of `message`, if there were any.
+fn summary(message: String, repeats: Repeats, now: Instant) -> Option<Line> {
+    (repeats.suppressed > 0).then(|| Line {
+        target: repeats.target,
+        level: repeats.level,
+        message: format!(
+            "{} (repeated {} more times in {:.1}s)",
+            message,
+            repeats.suppressed,
+            now.saturating_duration_since(repeats.since).as_secs_f64()
+        ),
+    })
+}
+
+lazy_static! {
+    static ref LOG_LIMITER: LogLimiter = LogLimiter::new(Duration::from_secs(10));
+}
+
+/// Process-wide limiter of repeated error messages.
+pub fn log_limiter() -> &'static LogLimiter {
+    &LOG_LIMITER
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_limiter() {
+        let limiter = LogLimiter::new(Duration::from_secs(10));
+        let start = Instant::now();
+        let at = |sec| start + Duration::from_secs(sec);
+        let admit = |sec, message: &str| {
+            limiter
+                .admit(at(sec), "test", Level::Error, message.to_owned())
+                .into_iter()
+                .map(|line| line.message)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(admit(0, "queue full"), ["queue full"]);
+        assert!(admit(1, "queue full").is_empty());
+        assert!(admit(2, "queue full").is_empty());
+        assert_eq!(admit(3, "timed out"), ["timed out"]);
+        // The next occurrence after the interval summarizes the last one.
+        assert_eq!(
+            admit(10, "queue full"),
+            ["queue full (repeated 2 more times in 10.0s)", "queue full"]
+        );
+        assert!(admit(11, "queue full").is_empty());
+        // Expired repeats are summarized with any other message.
+        assert_eq!(
+            admit(25, "other"),
+            ["queue full (repeated 1 more times in 15.0s)", "other"]
+        );
+
+        limiter.set_interval(Duration::ZERO);
+        assert_eq!(admit(25, "other"), ["other"]);
+        assert_eq!(admit(25, "other"), ["other"]);
+    }
+}
@@ -0,0 +1,91 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::*;
+
+/// Bincode tag of `ProgramState::LookupTable`, following the `Uninitialized` state.
+const LOOKUP_TABLE_STATE: u32 = 1;
+
+/// Size of the table metadata; the addresses follow.
+const LOOKUP_TABLE_META_SIZE: usize = 56;
+
+/// Decodes an address lookup table account.
+pub fn decode_lookup_table(data: &[u8]) -> Option<decoded_account::Account> {
+    if data.len() < LOOKUP_TABLE_META_SIZE
+        || (data.len() - LOOKUP_TABLE_META_SIZE) % 32 != 0
+        || u32::from_le_bytes(data[0..4].try_into().ok()?) != LOOKUP_TABLE_STATE
+    {
+        return None;
+    }
+    let u64_at = |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+    let authority = match data[21] {
+        0 => Vec::new(),
+        _ => data[22..54].to_vec(),
+    };
+    Some(decoded_account::Account::AddressLookupTable(
+        AddressLookupTable {
+            authority,
+            deactivation_slot: u64_at(4),
+            last_extended_slot: u64_at(12),
+            last_extended_slot_start_index: data[20].into(),
+            addresses: data[LOOKUP_TABLE_META_SIZE..]
+                .chunks_exact(32)
+                .map(Vec::from)
+                .collect(),
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_lookup_table() {
+        let mut data = Vec::new();
+        data.extend_from_slice(&LOOKUP_TABLE_STATE.to_le_bytes());
+        data.extend_from_slice(&u64::MAX.to_le_bytes());
+        data.extend_from_slice(&42u64.to_le_bytes());
+        data.push(1);
+        data.push(1);
+        data.extend_from_slice(&[9; 32]);
+        data.extend_from_slice(&[0; 2]);
+        data.extend_from_slice(&[1; 32]);
+        data.extend_from_slice(&[2; 32]);
+
+        let table = match decode_lookup_table(&data) {
+            Some(decoded_account::Account::AddressLookupTable(table)) => table,
+            other => panic!("unexpected decoding {:?}", other),
+        };
+        assert_eq!(table.authority, vec![9; 32]);
+        assert_eq!(table.deactivation_slot, u64::MAX);
+        assert_eq!(table.last_extended_slot, 42);
+        assert_eq!(table.last_extended_slot_start_index, 1);
+        assert_eq!(table.addresses, vec![vec![1; 32], vec![2; 32]]);
+
+        // Frozen tables have no authority.
+        data[21] = 0;
+        match decode_lookup_table(&data) {
+            Some(decoded_account::Account::AddressLookupTable(table)) => {
+                assert!(table.authority.is_empty())
+            }
+            other => panic!("unexpected decoding {:?}", other),
+        }
+        assert_eq!(
+            decode_lookup_table(&data[..LOOKUP_TABLE_META_SIZE + 1]),
+            None
+        );
+        assert_eq!(decode_lookup_table(&[0; LOOKUP_TABLE_META_SIZE]), None);
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    lazy_static::lazy_static,
+    std::{
+        mem::size_of,
+        sync::atomic::{AtomicU64, Ordering},
+    },
+};
+
+/// Internal buffer holding memory on behalf of the plugin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryComponent {
+    /// Duplicate transaction and account state caches, and account updates held back across forks.
+    DedupCaches,
+    /// Account updates buffered per slot until the slot advances or is rooted.
+    SlotBuffers,
+    /// Spool files known to await replay.
+    SpoolIndex,
+    /// Events waiting in the publisher queues.
+    QueuedEvents,
+    /// Last published amounts of token accounts.
+    TokenBalances,
+    /// Last seen owner programs of accounts.
+    AccountOwners,
+}
+
+impl MemoryComponent {
+    pub const ALL: [MemoryComponent; 6] = [
+        MemoryComponent::DedupCaches,
+        MemoryComponent::SlotBuffers,
+        MemoryComponent::SpoolIndex,
+        MemoryComponent::QueuedEvents,
+        MemoryComponent::TokenBalances,
+        MemoryComponent::AccountOwners,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MemoryComponent::DedupCaches => "dedup_caches",
+            MemoryComponent::SlotBuffers => "slot_buffers",
+            MemoryComponent::SpoolIndex => "spool_index",
+            MemoryComponent::QueuedEvents => "queued_events",
+            MemoryComponent::TokenBalances => "token_balances",
+            MemoryComponent::AccountOwners => "account_owners",
+        }
+    }
+}
+
+/// Approximate bytes held by every [`MemoryComponent`], against an optional budget.
+///
+/// The figures are estimates of the buffers' contents, not exact heap usage.
+pub struct MemoryAccounting {
+    bytes: [AtomicU64; 6],
+    /// Bytes all components may hold together, zero for unlimited.
+    budget: AtomicU64,
+}
+
+impl MemoryAccounting {
+    fn new() -> Self {
+        Self {
+            bytes: Default::default(),
+            budget: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_budget(&self, bytes: u64) {
+        self.budget.store(bytes, Ordering::Relaxed);
+    }
+
+    pub fn budget(&self) -> u64 {
+        self.budget.load(Ordering::Relaxed)
+    }
+
+    fn add(&self, component: MemoryComponent, bytes: u64) {
+        self.bytes[component as usize].fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn sub(&self, component: MemoryComponent, bytes: u64) {
+        self.bytes[component as usize].fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    pub fn bytes(&self, component: MemoryComponent) -> u64 {
+        self.bytes[component as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> u64 {
+        MemoryComponent::ALL
+            .iter()
+            .map(|&component| self.bytes(component))
+            .sum()
+    }
+
+    /// Whether the components hold more than the budget together.
+    pub fn over_budget(&self) -> bool {
+        let budget = self.budget();
+        budget > 0 && self.total() > budget
+    }
+
+    /// Bytes of every component, the total and the budget, as served on `/memory`.
+    pub fn report(&self) -> serde_json::Value {
+        let components: serde_json::Map<String, serde_json::Value> = MemoryComponent::ALL
+            .iter()
+            .map(|&component| (component.name().to_owned(), self.bytes(component).into()))
+            .collect();
+        serde_json::json!({
+            "components": components,
+            "total": self.total(),
+            "budget": self.budget(),
+        })
+    }
+}
+
+lazy_static! {
+    static ref MEMORY: MemoryAccounting = MemoryAccounting::new();
+}
+
+/// Process-wide accounting of the memory held by the plugin's buffers.
+pub fn memory() -> &'static MemoryAccounting {
+    &MEMORY
+}
+
+/// Bytes one buffer holds of a component, returned to the accounting when dropped.
+pub struct MemoryUsage {
+    component: MemoryComponent,
+    bytes: u64,
+}
+
+impl MemoryUsage {
+    pub fn new(component: MemoryComponent) -> Self {
+        Self {
+            component,
+            bytes: 0,
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn set(&mut self, bytes: usize) {
+        let bytes = bytes as u64;
+        if bytes > self.bytes {
+            memory().add(self.component, bytes - self.bytes);
+        } else {
+            memory().sub(self.component, self.bytes - bytes);
+        }
+        self.bytes = bytes;
+    }
+
+    pub fn add(&mut self, bytes: usize) {
+        self.set(self.bytes as usize + bytes);
+    }
+
+    pub fn sub(&mut self, bytes: usize) {
+        self.set((self.bytes as usize).saturating_sub(bytes));
+    }
+}
+
+impl Drop for MemoryUsage {
+    fn drop(&mut self) {
+        self.set(0);
+    }
+}
+
+/// Approximate bytes held by a buffered account update.
+pub fn account_update_bytes(ev: &UpdateAccountEvent) -> usize {
+    size_of::<UpdateAccountEvent>() + ev.pubkey.len() + ev.owner.len() + ev.data.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_accounting() {
+        let accounting = MemoryAccounting::new();
+        accounting.add(MemoryComponent::DedupCaches, 600);
+        accounting.add(MemoryComponent::QueuedEvents, 500);
+        assert!(!accounting.over_budget());
+
+        accounting.set_budget(1000);
+        assert!(accounting.over_budget());
+        accounting.sub(MemoryComponent::QueuedEvents, 200);
+        assert!(!accounting.over_budget());
+        assert_eq!(
+            accounting.report(),
+            serde_json::json!({
+                "components": {
+                    "dedup_caches": 600,
+                    "slot_buffers": 0,
+                    "spool_index": 0,
+                    "queued_events": 300,
+                    "token_balances": 0,
+                    "account_owners": 0,
+                },
+                "total": 900,
+                "budget": 1000,
+            })
+        );
+
+        let before = memory().bytes(MemoryComponent::SpoolIndex);
+        let mut usage = MemoryUsage::new(MemoryComponent::SpoolIndex);
+        usage.add(100);
+        usage.sub(30);
+        assert_eq!(usage.bytes(), 70);
+        drop(usage);
+        assert_eq!(memory().bytes(MemoryComponent::SpoolIndex), before);
+    }
+}
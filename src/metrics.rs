@@ -0,0 +1,333 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    lazy_static::lazy_static,
+    std::{
+        collections::BTreeMap,
+        fmt::Write,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+    },
+};
+
+/// Monotonically increasing counter.
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+macro_rules! define_metrics {
+    ($($(#[doc = $doc:literal])* $name:ident,)*) => {
+        /// Process-wide plugin metrics.
+        pub struct Metrics {
+            $($(#[doc = $doc])* pub $name: Counter,)*
+        }
+
+        impl Metrics {
+            const fn new() -> Self {
+                Self {
+                    $($name: Counter::new(),)*
+                }
+            }
+
+            /// Current value of every metric, by name.
+            ///
+            /// Histograms contribute their `_count`, `_sum` and the `quantile`s of [`QUANTILES`].
+            pub fn snapshot(&self) -> Vec<(String, u64)> {
+                let mut snapshot = vec![$((stringify!($name).to_owned(), self.$name.get()),)*];
+                let labeled = LABELED_COUNTERS.lock().unwrap();
+                snapshot.extend(labeled.iter().map(|(k, v)| (k.clone(), v.get())));
+                snapshot_histograms(&mut snapshot);
+                snapshot
+            }
+        }
+    };
+}
+
+define_metrics! {
+    /// Timed out records queued for the dead-letter topic.
+    dead_letters_queued,
+    /// Timed out records not dead-lettered, as the queue was full or they were dead letters already.
+    dead_letters_dropped,
+    /// Dead letters handed to the producers.
+    dead_letters_published,
+    /// Records split into chunks for exceeding `max_message_bytes`.
+    chunked_messages,
+    /// Chunks of split records handed to the producers or the spool.
+    message_chunks_sent,
+    /// Repeated error messages counted instead of logged, see `log_suppression_interval_sec`.
+    log_messages_suppressed,
+    /// Alerts that started firing, see `alerts`.
+    alerts_fired,
+    /// Payload bytes handed to the plugin's own compression.
+    payload_bytes_before_compression,
+    /// Payload bytes coming out of the plugin's own compression.
+    payload_bytes_after_compression,
+    /// Brokers coming up, as reported by librdkafka statistics.
+    broker_up_transitions,
+    /// Brokers going down after being up, as reported by librdkafka statistics.
+    broker_down_transitions,
+    /// Transitions into a state with no broker reachable.
+    all_brokers_down,
+    /// Records written to the spool while all brokers were down.
+    records_spooled,
+    /// Spooled records handed to the producers once the brokers were back.
+    records_unspooled,
+    /// Fatal or authentication errors reported by the producers.
+    producer_fatal_errors,
+    /// Producers recreated after fatal errors.
+    producer_recreations,
+    /// Periodic flushes of the producers with `flush_interval_ms`.
+    producer_flushes,
+    /// Total time spent in periodic flushes, in microseconds.
+    producer_flush_time_us,
+    /// Producer shards still holding messages at the end of a periodic flush.
+    producer_flush_timeouts,
+    /// New events dropped because the publisher queue was full.
+    queue_dropped_newest,
+    /// Queued events dropped with `drop_oldest` to make room for new ones.
+    queue_dropped_oldest,
+    /// Queued events dropped with `drop_largest` to make room for new ones.
+    queue_dropped_largest,
+    /// Events that had to wait for the publisher threads to bring the buffers within `max_memory_bytes`.
+    memory_budget_waits,
+    /// Queued or new events dropped to bring the buffers within `max_memory_bytes`.
+    memory_budget_drops,
+    /// Records that had to wait for deliveries to fit into the in-flight budget.
+    in_flight_budget_waits,
+    /// Records dropped because they did not fit into the in-flight budget.
+    in_flight_budget_drops,
+    /// Messages encoded into an already large enough thread-local buffer.
+    encode_buffers_reused,
+    /// Messages that needed the thread-local encode buffer to grow.
+    encode_buffers_allocated,
+    /// Account updates superseded by a higher write_version within the same slot.
+    coalesced_account_updates,
+    /// Slots never rooted whose account updates were discarded with `rooted_account_updates`.
+    optimistic_slots_discarded,
+    /// Account updates of slots never rooted that were discarded with `rooted_account_updates`.
+    optimistic_account_updates_discarded,
+    /// Transaction notifications dropped because their signature was published recently.
+    duplicate_transactions_suppressed,
+    /// Account updates dropped because they repeat the last published state of the account.
+    duplicate_account_states_suppressed,
+    /// Account updates dropped because the same state was seen on another fork within the window.
+    fork_duplicate_account_updates_suppressed,
+    /// Accounts whose last published state was evicted from the duplicate-state cache.
+    account_state_cache_evictions,
+    /// Published account updates following updates of the same account that were lost.
+    account_update_gaps,
+    /// Account updates published after an update of the same account with a higher write_version.
+    account_updates_reordered,
+    /// Account updates of a routing publisher no remote allowlist entry or topic rule matched.
+    account_updates_unrouted,
+    /// Topics switched over at runtime by the admin endpoint.
+    topic_switches,
+    /// Geyser callbacks written to capture files.
+    callbacks_captured,
+}
+
+static METRICS: Metrics = Metrics::new();
+
+/// Upper bounds of the histogram buckets, chosen for latencies in milliseconds.
+const BUCKETS: [u64; 20] = [
+    1, 2, 5, 10, 20, 50, 100, 150, 200, 250, 300, 400, 500, 750, 1_000, 2_000, 5_000, 10_000,
+    30_000, 60_000,
+];
+
+/// Quantiles reported for every histogram, with their label values.
+pub const QUANTILES: &[(f64, &str)] = &[(0.5, "0.5"), (0.95, "0.95"), (0.99, "0.99")];
+
+/// Distribution of observed values, in fixed buckets.
+pub struct Histogram {
+    /// Observations per bucket of [`BUCKETS`], plus one for larger values.
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    sum: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            buckets: Default::default(),
+            sum: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: u64) {
+        let bucket = BUCKETS.partition_point(|&bound| bound < value);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).sum()
+    }
+
+    pub fn sum(&self) -> u64 {
+        self.sum.load(Ordering::Relaxed)
+    }
+
+    /// Upper bound of the bucket holding the `quantile`, capped at the largest value observed.
+    pub fn quantile(&self, quantile: f64) -> u64 {
+        let count = self.count();
+        if count == 0 {
+            return 0;
+        }
+        let rank = ((count as f64 * quantile).ceil() as u64).max(1);
+        let max = self.max.load(Ordering::Relaxed);
+        let mut seen = 0;
+        for (bucket, bound) in self.buckets.iter().zip(BUCKETS) {
+            seen += bucket.load(Ordering::Relaxed);
+            if seen >= rank {
+                return bound.min(max);
+            }
+        }
+        max
+    }
+}
+
+type Labels = Vec<(String, String)>;
+
+lazy_static! {
+    static ref LABELED_COUNTERS: Mutex<BTreeMap<String, Arc<Counter>>> = Default::default();
+    static ref HISTOGRAMS: Mutex<BTreeMap<(String, Labels), Arc<Histogram>>> = Default::default();
+}
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+/// Returns the counter registered for `name` and `labels`, creating it on first use.
+///
+/// Callers on hot paths should keep the returned handle rather than looking it up every time.
+pub fn labeled_counter(name: &str, labels: &[(&str, &str)]) -> Arc<Counter> {
+    LABELED_COUNTERS
+        .lock()
+        .unwrap()
+        .entry(metric_key(name, labels))
+        .or_insert_with(|| Arc::new(Counter::new()))
+        .clone()
+}
+
+/// Returns the histogram registered for `name` and `labels`, creating it on first use.
+pub fn labeled_histogram(name: &str, labels: &[(&str, &str)]) -> Arc<Histogram> {
+    let labels = labels
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    HISTOGRAMS
+        .lock()
+        .unwrap()
+        .entry((name.to_owned(), labels))
+        .or_insert_with(|| Arc::new(Histogram::new()))
+        .clone()
+}
+
+/// Appends the `_count`, `_sum` and quantiles of every histogram to `snapshot`.
+fn snapshot_histograms(snapshot: &mut Vec<(String, u64)>) {
+    for ((name, labels), histogram) in HISTOGRAMS.lock().unwrap().iter() {
+        let labels: Vec<(&str, &str)> = labels
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        let count = metric_key(&format!("{}_count", name), &labels);
+        snapshot.push((count, histogram.count()));
+        snapshot.push((
+            metric_key(&format!("{}_sum", name), &labels),
+            histogram.sum(),
+        ));
+        for (quantile, label) in QUANTILES {
+            let mut labels = labels.clone();
+            labels.push(("quantile", label));
+            snapshot.push((metric_key(name, &labels), histogram.quantile(*quantile)));
+        }
+    }
+}
+
+/// `name{k="v",...}`, or just `name` without labels.
+fn metric_key(name: &str, labels: &[(&str, &str)]) -> String {
+    let mut key = name.to_owned();
+    if !labels.is_empty() {
+        key.push('{');
+        for (i, (k, v)) in labels.iter().enumerate() {
+            if i > 0 {
+                key.push(',');
+            }
+            write!(key, "{}=\"{}\"", k, v).unwrap();
+        }
+        key.push('}');
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_labeled_counter() {
+        let a = labeled_counter("test_sent", &[("shard", "0"), ("topic", "t")]);
+        a.add(3);
+        let b = labeled_counter("test_sent", &[("shard", "0"), ("topic", "t")]);
+        assert_eq!(b.get(), 3);
+
+        let snapshot = metrics().snapshot();
+        assert!(snapshot
+            .iter()
+            .any(|(k, v)| k == "test_sent{shard=\"0\",topic=\"t\"}" && *v == 3));
+    }
+
+    #[test]
+    fn test_histogram() {
+        let histogram = labeled_histogram("test_latency_ms", &[("event", "account")]);
+        for value in 1..=100 {
+            histogram.observe(value);
+        }
+        histogram.observe(70_000);
+        assert_eq!(histogram.count(), 101);
+        assert_eq!(histogram.sum(), 5050 + 70_000);
+        assert_eq!(histogram.quantile(0.5), 100);
+        assert_eq!(histogram.quantile(0.01), 2);
+        assert_eq!(histogram.quantile(1.0), 70_000);
+
+        let snapshot = metrics().snapshot();
+        let get = |key: &str| snapshot.iter().find(|(k, _)| k == key).map(|(_, v)| *v);
+        assert_eq!(get("test_latency_ms_count{event=\"account\"}"), Some(101));
+        assert_eq!(
+            get("test_latency_ms{event=\"account\",quantile=\"0.99\"}"),
+            Some(100)
+        );
+    }
+}
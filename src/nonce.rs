@@ -0,0 +1,75 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    solana_program::nonce::{state::Versions, State},
+};
+
+/// Decodes an initialized nonce account, the only system program accounts holding data.
+pub fn decode_nonce(data: &[u8]) -> Option<decoded_account::Account> {
+    if data.len() != State::size() {
+        return None;
+    }
+    let versions: Versions = bincode::deserialize(data).ok()?;
+    let data = match versions.state() {
+        State::Uninitialized => return None,
+        State::Initialized(data) => data,
+    };
+    Some(decoded_account::Account::NonceAccount(NonceAccount {
+        authority: data.authority.to_bytes().into(),
+        blockhash: data.blockhash().to_bytes().into(),
+        lamports_per_signature: data.get_lamports_per_signature(),
+        legacy: !versions.separate_domains(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_program::{
+            hash::Hash,
+            nonce::state::{Data, DurableNonce},
+            pubkey::Pubkey,
+        },
+    };
+
+    #[test]
+    fn test_decode_nonce() {
+        let durable_nonce = DurableNonce::from_blockhash(&Hash::new_from_array([7; 32]), true);
+        let state = State::Initialized(Data::new(
+            Pubkey::new_from_array([1; 32]),
+            durable_nonce,
+            5_000,
+        ));
+        let data = bincode::serialize(&Versions::new(state, true)).unwrap();
+
+        let account = match decode_nonce(&data) {
+            Some(decoded_account::Account::NonceAccount(account)) => account,
+            other => panic!("unexpected decoding {:?}", other),
+        };
+        assert_eq!(account.authority, vec![1; 32]);
+        assert_eq!(
+            account.blockhash,
+            durable_nonce.as_hash().to_bytes().to_vec()
+        );
+        assert_eq!(account.lamports_per_signature, 5_000);
+        assert!(!account.legacy);
+
+        let mut data = bincode::serialize(&Versions::new(State::Uninitialized, true)).unwrap();
+        data.resize(State::size(), 0);
+        assert_eq!(decode_nonce(&data), None);
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {crate::*, lazy_static::lazy_static, solana_program::pubkey::Pubkey, std::str::FromStr};
+
+lazy_static! {
+    static ref OPENBOOK_PROGRAM_ID: Pubkey =
+        Pubkey::from_str("srmqPvymJeFKQ4zGQed1GxZFiM6F7nqru6aRJGnX4WG").unwrap();
+}
+
+/// Padding around every account of the Serum DEX and its OpenBook fork.
+const HEAD_PADDING: &[u8] = b"serum";
+const TAIL_PADDING: &[u8] = b"padding";
+
+/// Size of the `MarketState`, between the paddings; permissioned markets extend it.
+const MARKET_STATE_SIZE: usize = 376;
+
+const ACCOUNT_FLAG_INITIALIZED: u64 = 1;
+const ACCOUNT_FLAG_MARKET: u64 = 1 << 1;
+const ACCOUNT_FLAG_DISABLED: u64 = 1 << 7;
+
+/// Decodes the markets of the OpenBook DEX (v1), which are also what its open orders accounts, queues and order
+/// books refer to.
+pub struct OpenBookDecoder;
+
+impl ProgramDecoder for OpenBookDecoder {
+    fn program_id(&self) -> Pubkey {
+        *OPENBOOK_PROGRAM_ID
+    }
+
+    fn decode(&self, data: &[u8]) -> Option<decoded_account::Account> {
+        decode_market(data)
+    }
+}
+
+fn decode_market(data: &[u8]) -> Option<decoded_account::Account> {
+    let state = data
+        .strip_prefix(HEAD_PADDING)?
+        .strip_suffix(TAIL_PADDING)?
+        .get(..MARKET_STATE_SIZE)?;
+    let u64_at = |offset: usize| u64::from_le_bytes(state[offset..offset + 8].try_into().unwrap());
+    let pubkey_at = |offset: usize| state[offset..offset + 32].to_vec();
+    let flags = u64_at(0);
+    if flags & (ACCOUNT_FLAG_INITIALIZED | ACCOUNT_FLAG_MARKET)
+        != ACCOUNT_FLAG_INITIALIZED | ACCOUNT_FLAG_MARKET
+    {
+        return None;
+    }
+    Some(decoded_account::Account::OpenbookMarket(OpenBookMarket {
+        disabled: flags & ACCOUNT_FLAG_DISABLED != 0,
+        own_address: pubkey_at(8),
+        vault_signer_nonce: u64_at(40),
+        base_mint: pubkey_at(48),
+        quote_mint: pubkey_at(80),
+        base_vault: pubkey_at(112),
+        base_deposits_total: u64_at(144),
+        base_fees_accrued: u64_at(152),
+        quote_vault: pubkey_at(160),
+        quote_deposits_total: u64_at(192),
+        quote_fees_accrued: u64_at(200),
+        quote_dust_threshold: u64_at(208),
+        request_queue: pubkey_at(216),
+        event_queue: pubkey_at(248),
+        bids: pubkey_at(280),
+        asks: pubkey_at(312),
+        base_lot_size: u64_at(344),
+        quote_lot_size: u64_at(352),
+        fee_rate_bps: u64_at(360),
+        referrer_rebates_accrued: u64_at(368),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_market() {
+        let mut state = vec![0; MARKET_STATE_SIZE];
+        state[0..8]
+            .copy_from_slice(&(ACCOUNT_FLAG_INITIALIZED | ACCOUNT_FLAG_MARKET).to_le_bytes());
+        state[48..80].copy_from_slice(&[1; 32]);
+        state[80..112].copy_from_slice(&[2; 32]);
+        state[344..352].copy_from_slice(&100u64.to_le_bytes());
+        let data = [HEAD_PADDING, &state, TAIL_PADDING].concat();
+
+        let market = match OpenBookDecoder.decode(&data) {
+            Some(decoded_account::Account::OpenbookMarket(market)) => market,
+            other => panic!("unexpected decoding {:?}", other),
+        };
+        assert!(!market.disabled);
+        assert_eq!(market.base_mint, vec![1; 32]);
+        assert_eq!(market.quote_mint, vec![2; 32]);
+        assert_eq!(market.base_lot_size, 100);
+
+        // Open orders accounts and the like are not decoded.
+        state[0..8].copy_from_slice(&(ACCOUNT_FLAG_INITIALIZED | 1 << 2).to_le_bytes());
+        let data = [HEAD_PADDING, &state, TAIL_PADDING].concat();
+        assert_eq!(OpenBookDecoder.decode(&data), None);
+    }
+}
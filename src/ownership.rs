@@ -0,0 +1,94 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::*;
+
+/// Remembers the owner programs of the most recently updated accounts, to report reassignments as
+/// [`OwnerChanged`] events.
+pub struct OwnerChanges {
+    owners: RecentAccounts<[u8; 32]>,
+}
+
+impl OwnerChanges {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            owners: RecentAccounts::new(capacity, MemoryComponent::AccountOwners),
+        }
+    }
+
+    /// Records the owner of an account update, returning the change if its owner differs from the one last seen.
+    ///
+    /// Accounts start being tracked with an update to `track`, and stay tracked whatever they are reassigned to.
+    pub fn observe(&mut self, slot: u64, info: &AccountInfo, track: bool) -> Option<OwnerChanged> {
+        let key: [u8; 32] = info.pubkey.try_into().ok()?;
+        let owner: [u8; 32] = info.owner.try_into().ok()?;
+        if !track && self.owners.get(&key).is_none() {
+            return None;
+        }
+        let previous = self.owners.insert(key, owner)?;
+        if previous == owner {
+            return None;
+        }
+        Some(OwnerChanged {
+            slot,
+            account: key.to_vec(),
+            previous_owner: previous.to_vec(),
+            owner: owner.to_vec(),
+            lamports: info.lamports,
+            data_len: info.data.len() as u64,
+            write_version: info.write_version,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.owners.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.owners.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_owner_changes() {
+        let mut changes = OwnerChanges::new(10);
+        let mut observe = |pubkey: [u8; 32], owner: [u8; 32], track| {
+            let info = AccountInfo {
+                pubkey: &pubkey,
+                lamports: 1,
+                owner: &owner,
+                executable: false,
+                rent_epoch: 0,
+                data: &[],
+                write_version: 0,
+            };
+            changes.observe(2, &info, track)
+        };
+
+        // Untracked accounts are not reported.
+        assert_eq!(observe([1; 32], [7; 32], false), None);
+        assert_eq!(observe([1; 32], [8; 32], true), None);
+        assert_eq!(observe([1; 32], [8; 32], true), None);
+        // Reassigned to a program the filters reject.
+        let change = observe([1; 32], [9; 32], false).unwrap();
+        assert_eq!(change.previous_owner, vec![8; 32]);
+        assert_eq!(change.owner, vec![9; 32]);
+        let change = observe([1; 32], [8; 32], true).unwrap();
+        assert_eq!(change.previous_owner, vec![9; 32]);
+    }
+}
@@ -0,0 +1,165 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::KeyHasher,
+    serde::Deserialize,
+    solana_program::pubkey::Pubkey,
+    std::{collections::HashMap, hash::BuildHasherDefault, str::FromStr},
+};
+
+/// Accounts of `owner` pinned to the partitions `first_partition` to `last_partition`, inclusive.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ProgramPartitions {
+    pub owner: String,
+    pub first_partition: i32,
+    pub last_partition: i32,
+}
+
+/// Partitions of the account update topics, some of which are reserved to the accounts of given programs.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PartitionPinning {
+    /// Number of partitions of the account update topics.
+    pub partitions: i32,
+    pub programs: Vec<ProgramPartitions>,
+}
+
+/// Picks the partitions of account updates, keeping pinned programs apart from everything else.
+///
+/// Accounts are spread across their partitions by the Kafka murmur2 hash of the record key,
+/// so a key always lands on the same partition.
+pub struct PartitionPinner {
+    pinned: HashMap<[u8; 32], Vec<i32>, BuildHasherDefault<KeyHasher>>,
+    /// Partitions not pinned to any program.
+    rest: Vec<i32>,
+}
+
+impl PartitionPinner {
+    pub fn new(config: &PartitionPinning) -> Result<Self, String> {
+        if config.partitions <= 0 {
+            return Err("partition_pinning needs a positive number of partitions".to_owned());
+        }
+        let mut pinned = HashMap::default();
+        let mut is_pinned = vec![false; config.partitions as usize];
+        for program in &config.programs {
+            let owner = Pubkey::from_str(&program.owner)
+                .map_err(|e| format!("invalid pinned program {}: {}", program.owner, e))?;
+            let range = program.first_partition..=program.last_partition;
+            if range.is_empty()
+                || program.first_partition < 0
+                || program.last_partition >= config.partitions
+            {
+                return Err(format!(
+                    "partitions {} to {} of program {} are not within the {} partitions",
+                    program.first_partition,
+                    program.last_partition,
+                    program.owner,
+                    config.partitions
+                ));
+            }
+            for partition in range.clone() {
+                is_pinned[partition as usize] = true;
+            }
+            if pinned.insert(owner.to_bytes(), range.collect()).is_some() {
+                return Err(format!("program {} is pinned twice", program.owner));
+            }
+        }
+        let rest: Vec<i32> = (0..config.partitions)
+            .filter(|&partition| !is_pinned[partition as usize])
+            .collect();
+        if rest.is_empty() {
+            return Err("partition_pinning leaves no partition for unpinned programs".to_owned());
+        }
+        Ok(Self { pinned, rest })
+    }
+
+    /// Partition of the record keyed by `key` of an account owned by `owner`.
+    pub fn partition(&self, owner: &[u8], key: &[u8]) -> i32 {
+        let partitions = <[u8; 32]>::try_from(owner)
+            .ok()
+            .and_then(|owner| self.pinned.get(&owner))
+            .unwrap_or(&self.rest);
+        let hash = murmur2(key) & 0x7fff_ffff;
+        partitions[hash as usize % partitions.len()]
+    }
+}
+
+/// The murmur2 hash of the Java Kafka client's and `librdkafka`'s `murmur2` partitioners.
+pub fn murmur2(data: &[u8]) -> u32 {
+    const SEED: u32 = 0x9747_b28c;
+    const M: u32 = 0x5bd1_e995;
+    const R: u32 = 24;
+
+    let mut h = SEED ^ data.len() as u32;
+    let mut blocks = data.chunks_exact(4);
+    for block in &mut blocks {
+        let mut k = u32::from_le_bytes(block.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M) ^ k;
+    }
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate() {
+            h ^= (byte as u32) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^ (h >> 15)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partition_pinning() {
+        // Test vectors of the Java Kafka client.
+        assert_eq!(murmur2(b"21") as i32, -973932308);
+        assert_eq!(murmur2(b"foobar") as i32, -790332482);
+        assert_eq!(murmur2(b"a-little-bit-long-string") as i32, -985981536);
+        assert_eq!(murmur2(b"abc") as i32, 479470107);
+
+        let hot = Pubkey::new_unique();
+        let config = PartitionPinning {
+            partitions: 12,
+            programs: vec![ProgramPartitions {
+                owner: hot.to_string(),
+                first_partition: 0,
+                last_partition: 7,
+            }],
+        };
+        let pinner = PartitionPinner::new(&config).unwrap();
+        let other = Pubkey::new_unique();
+        for i in 0..100u8 {
+            let key = [i; 32];
+            assert!((0..8).contains(&pinner.partition(hot.as_ref(), &key)));
+            assert!((8..12).contains(&pinner.partition(other.as_ref(), &key)));
+            assert_eq!(
+                pinner.partition(other.as_ref(), &key),
+                pinner.partition(other.as_ref(), &key)
+            );
+        }
+
+        let mut all = config.clone();
+        all.programs[0].last_partition = 11;
+        assert!(PartitionPinner::new(&all).is_err());
+        let mut outside = config;
+        outside.programs[0].last_partition = 12;
+        assert!(PartitionPinner::new(&outside).is_err());
+    }
+}
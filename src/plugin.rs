@@ -14,21 +14,75 @@
 
 use {
     crate::*,
-    log::info,
+    log::{error, info, warn},
+    prost::bytes::Bytes,
     rdkafka::util::get_rdkafka_version,
     simple_error::simple_error,
     solana_geyser_plugin_interface::geyser_plugin_interface::{
-        GeyserPlugin, GeyserPluginError as PluginError, ReplicaAccountInfo,
-        ReplicaAccountInfoVersions, ReplicaTransactionInfoVersions, Result as PluginResult,
-        SlotStatus as PluginSlotStatus,
+        GeyserPlugin, GeyserPluginError as PluginError, ReplicaAccountInfoVersions,
+        ReplicaBlockInfoVersions, Result as PluginResult, SlotStatus as PluginSlotStatus,
     },
-    std::fmt::{Debug, Formatter},
+    solana_transaction_status::RewardType,
+    std::{
+        collections::BTreeMap,
+        fmt::{Debug, Formatter},
+        fs::OpenOptions,
+        io::LineWriter,
+        sync::Arc,
+        time::Duration,
+    },
+};
+#[cfg(feature = "transactions")]
+use {
+    solana_geyser_plugin_interface::geyser_plugin_interface::{
+        ReplicaTransactionInfo, ReplicaTransactionInfoVersions,
+    },
+    solana_program::pubkey::Pubkey,
 };
 
 #[derive(Default)]
 pub struct KafkaPlugin {
-    publisher: Option<Publisher>,
+    dispatcher: Option<Dispatcher>,
+    tls_reloader: Option<TlsReloader>,
+    producer_recovery: Option<ProducerRecovery>,
+    spool_replayer: Option<SpoolReplayer>,
+    #[cfg(feature = "admin")]
+    admin_server: Option<AdminServer>,
+    alert_monitor: Option<AlertMonitor>,
+    error_reporter: Option<Arc<ErrorReporter>>,
     filter: Option<Filter>,
+    batcher: Option<SlotBatcher>,
+    rooted_diff: Option<RootedDiff>,
+    slot_timer: SlotTimer,
+    #[cfg(feature = "transactions")]
+    signature_cache: Option<SignatureCache>,
+    account_state_cache: Option<AccountStateCache>,
+    fork_dedup: Option<ForkDedupWindow>,
+    data_slicer: Option<DataSlicer>,
+    account_sequences: Option<AccountSequences>,
+    gap_detector: Option<GapDetector>,
+    /// Leader of every replayed slot not yet rooted, when `include_slot_leader` is set.
+    slot_leaders: Option<BTreeMap<u64, String>>,
+    capture: Option<CaptureSink>,
+    batch_account_updates: bool,
+    #[cfg(feature = "transactions")]
+    filter_transactions_by_program: bool,
+    #[cfg(feature = "parsed-instructions")]
+    include_parsed_instructions: bool,
+    #[cfg(feature = "decoders")]
+    decoders: Option<DecoderRegistry>,
+    program_upgrades: Option<ProgramUpgrades>,
+    token_balances: Option<TokenBalances>,
+    owner_changes: Option<OwnerChanges>,
+    tenants: Vec<Tenant>,
+    canary_filter: Option<CanaryFilter>,
+    /// Picks the account updates mirrored to the shadow topic before any filter.
+    shadow_sampler: Option<Sampler>,
+    publish_programdata_updates: bool,
+    #[cfg(feature = "transactions")]
+    include_transaction_logs: bool,
+    #[cfg(feature = "transactions")]
+    include_inner_instructions: bool,
     publish_all_accounts: bool,
 }
 
@@ -44,7 +98,8 @@ impl GeyserPlugin for KafkaPlugin {
     }
 
     fn on_load(&mut self, config_file: &str) -> PluginResult<()> {
-        if self.publisher.is_some() {
+        let _call = plugin_call();
+        if self.dispatcher.is_some() {
             let err = simple_error!("plugin already loaded");
             return Err(PluginError::Custom(Box::new(err)));
         }
@@ -56,27 +111,89 @@ impl GeyserPlugin for KafkaPlugin {
             config_file
         );
         let config = Config::read_from(config_file)?;
-        self.publish_all_accounts = config.publish_all_accounts;
-
-        let (version_n, version_s) = get_rdkafka_version();
-        info!("rd_kafka_version: {:#08x}, {}", version_n, version_s);
-
-        let producer = config
-            .producer()
-            .map_err(|e| PluginError::Custom(Box::new(e)))?;
-        info!("Created rdkafka::FutureProducer");
-
-        let publisher = Publisher::new(producer, &config);
-        self.publisher = Some(publisher);
-        self.filter = Some(Filter::new(&config));
-        info!("Spawned producer");
-
-        Ok(())
+        log_limiter().set_interval(Duration::from_secs(config.log_suppression_interval_sec));
+        drops().set_summary_interval(Duration::from_secs(config.drop_summary_interval_sec));
+        memory().set_budget(config.max_memory_bytes);
+        if let Some(reporting) = &config.error_reporting {
+            let fingerprint = std::fs::read_to_string(config_file)
+                .map(|json| config_fingerprint(&json))
+                .unwrap_or_default();
+            let reporter = ErrorReporter::new(reporting, fingerprint)
+                .map_err(|msg| PluginError::ConfigFileReadError { msg })?;
+            let reporter = Arc::new(reporter);
+            report_panics(reporter.clone());
+            self.error_reporter = Some(reporter);
+        }
+        let loaded = self.load(&config);
+        if let (Err(e), Some(reporter)) = (&loaded, &self.error_reporter) {
+            reporter.report(ReportLevel::Fatal, &format!("failed to load plugin: {}", e));
+            // The validator drops the plugin without unloading it.
+            stop_reporting_panics();
+        }
+        loaded
     }
 
     fn on_unload(&mut self) {
-        self.publisher = None;
+        let _call = plugin_call();
+        if self.dispatcher.is_some() {
+            if let Err(e) = self.release_fork_duplicates(true) {
+                error!("Failed to publish held account updates on unload: {}", e);
+            }
+            let batches = self
+                .batcher
+                .as_mut()
+                .map(SlotBatcher::take_all)
+                .unwrap_or_default();
+            for batch in batches {
+                if let Err(e) = self.publish_slot_batch(batch) {
+                    error!("Failed to publish slot accounts batch on unload: {}", e);
+                }
+            }
+        }
+        if let Some(diff) = self.rooted_diff.take() {
+            if diff.pending_slots() > 0 {
+                info!(
+                    "Dropping account updates of {} slots not rooted yet",
+                    diff.pending_slots()
+                );
+            }
+        }
+        self.batcher = None;
+        #[cfg(feature = "admin")]
+        {
+            self.admin_server = None;
+        }
+        self.alert_monitor = None;
+        self.tls_reloader = None;
+        self.producer_recovery = None;
+        self.spool_replayer = None;
+        #[cfg(feature = "transactions")]
+        {
+            self.signature_cache = None;
+        }
+        self.account_state_cache = None;
+        self.token_balances = None;
+        self.owner_changes = None;
+        self.program_upgrades = None;
+        self.fork_dedup = None;
+        self.data_slicer = None;
+        self.account_sequences = None;
+        self.gap_detector = None;
+        self.slot_leaders = None;
+        self.capture = None;
+        self.tenants.clear();
+        self.shadow_sampler = None;
+        self.canary_filter = None;
+        self.dispatcher = None;
         self.filter = None;
+        log_limiter().flush();
+        drops().flush();
+        stop_reporting_panics();
+        self.error_reporter = None;
+
+        for (name, value) in metrics().snapshot() {
+            info!("{}: {}", name, value);
+        }
     }
 
     fn update_account(
@@ -85,12 +202,82 @@ impl GeyserPlugin for KafkaPlugin {
         slot: u64,
         is_startup: bool,
     ) -> PluginResult<()> {
+        let _call = plugin_call();
+        let info = AccountInfo::from(account);
+        self.capture(|| Callback::UpdateAccount {
+            account: (&info).into(),
+            slot,
+            is_startup,
+        });
+        // Programs are learned from the snapshot, as their accounts rarely change afterwards.
+        if let Some(upgrades) = self.program_upgrades.as_mut().filter(|_| is_startup) {
+            upgrades.learn(&info);
+        }
         if is_startup && !self.publish_all_accounts {
             return Ok(());
         }
+        self.mirror_account(slot, &info);
+        // Tenants filter and publish on their own, whatever the root pipeline does.
+        self.publish_account_to_tenants(slot, &info, is_startup)?;
+        if !is_startup && self.publish_program_upgrade(slot, &info)? {
+            return Ok(());
+        }
+        // Notifications may only be enabled for the capture.
+        let dispatcher = self.unwrap_dispatcher();
+        let wanted = if is_startup {
+            dispatcher.wants_startup_account()
+        } else {
+            dispatcher.wants_update_account()
+        };
+        if !wanted && self.token_balances.is_none() && self.owner_changes.is_none() {
+            return Ok(());
+        }
 
-        let info = Self::unwrap_update_account(account);
-        if !self.unwrap_filter().wants_program(info.owner) {
+        let filter = self.unwrap_filter();
+        let wanted_program = profiled(ProfileSection::Filter, || filter.wants_program(info.owner));
+        if let Some(canary) = self.canary_filter.as_ref().filter(|_| wanted) {
+            canary.compare_account(info.owner, wanted_program);
+        }
+        // Reassignments away from a wanted program are seen before the filter drops them.
+        self.publish_owner_change(slot, &info, wanted_program)?;
+        if !wanted_program {
+            // Updates only feeding the derived events are not account messages dropped.
+            if wanted {
+                let filter = self.unwrap_filter().program_filter();
+                self.drop_account(is_startup, DropReason::Filtered, filter);
+            }
+            return Ok(());
+        }
+
+        if let Some(cache) = self.account_state_cache.as_mut() {
+            if !cache.insert(
+                info.pubkey,
+                info.owner,
+                info.lamports,
+                info.executable,
+                info.data,
+            ) {
+                if wanted {
+                    self.drop_account(
+                        is_startup,
+                        DropReason::Duplicate,
+                        "account_dedup_cache_size",
+                    );
+                }
+                return Ok(());
+            }
+        }
+
+        if let Some(change) = self
+            .token_balances
+            .as_mut()
+            .and_then(|balances| balances.observe(slot, &info, is_startup))
+        {
+            self.unwrap_dispatcher()
+                .update_token_balance(change)
+                .map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })?;
+        }
+        if !wanted {
             return Ok(());
         }
 
@@ -100,21 +287,35 @@ impl GeyserPlugin for KafkaPlugin {
             .get_allowlist()
             .update_from_http_if_needed_async();
 
-        let event = UpdateAccountEvent {
-            slot,
-            pubkey: info.pubkey.to_vec(),
-            lamports: info.lamports,
-            owner: info.owner.to_vec(),
-            executable: info.executable,
-            rent_epoch: info.rent_epoch,
-            data: info.data.to_vec(),
-            write_version: info.write_version,
-        };
+        let event = self.build_account_event(slot, &info);
 
-        let publisher = self.unwrap_publisher();
-        publisher
-            .update_account(event)
-            .map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })
+        // Startup accounts are not part of any replayed slot, so they are never batched.
+        if !is_startup {
+            if let Some(diff) = self.rooted_diff.as_mut() {
+                if !diff.push(event) {
+                    self.drop_account(false, DropReason::Superseded, "rooted_account_updates");
+                }
+                return Ok(());
+            }
+            if let Some(batcher) = self.batcher.as_mut() {
+                if !batcher.push(event) {
+                    self.drop_account(false, DropReason::Superseded, "coalesce_account_updates");
+                }
+                return Ok(());
+            }
+            if let Some(window) = self.fork_dedup.as_mut() {
+                let slot = event.slot;
+                if window.push(event) {
+                    // Held updates still count towards their slot's completion.
+                    self.unwrap_dispatcher().reserve_account(slot);
+                } else {
+                    self.drop_account(false, DropReason::Duplicate, "account_fork_dedup_window_ms");
+                }
+                return self.release_fork_duplicates(false);
+            }
+        }
+
+        self.dispatch_account(event, is_startup, Vec::new())
     }
 
     fn update_slot_status(
@@ -123,67 +324,899 @@ impl GeyserPlugin for KafkaPlugin {
         parent: Option<u64>,
         status: PluginSlotStatus,
     ) -> PluginResult<()> {
-        let publisher = self.unwrap_publisher();
-        if !publisher.wants_slot_status() {
+        let _call = plugin_call();
+        self.capture(|| Callback::UpdateSlotStatus {
+            slot,
+            parent,
+            status: (&status).into(),
+        });
+        self.publish_slot_batches(slot, status)?;
+        self.release_fork_duplicates(false)?;
+        let rooted = matches!(status, PluginSlotStatus::Rooted);
+        let mut rooted_ancestors = Vec::new();
+        if let (Some(diff), Some(parent)) = (self.rooted_diff.as_mut(), parent) {
+            diff.set_parent(slot, parent);
+        }
+        if rooted {
+            if let Some(diff) = self.rooted_diff.as_mut() {
+                let abandoned = diff.abandoned_updates(slot) as u64;
+                let batches = diff.root(slot);
+                let topic = self.unwrap_dispatcher().publisher().account_topic(false);
+                drops().dropped_many(
+                    topic,
+                    DropReason::Unrooted,
+                    "rooted_account_updates",
+                    abandoned,
+                );
+                for batch in batches {
+                    if batch.slot != slot {
+                        rooted_ancestors.push(batch.slot);
+                    }
+                    if !batch.accounts.is_empty() {
+                        self.publish_slot_batch(batch)?;
+                    }
+                }
+            }
+        }
+        let dispatcher = self.unwrap_dispatcher();
+        // Held back account updates only complete their slot once rooted.
+        for ancestor in rooted_ancestors {
+            dispatcher.close_slot(ancestor);
+        }
+        if self.rooted_diff.is_none() || rooted {
+            dispatcher.close_slot(slot);
+        }
+        if let PluginSlotStatus::Rooted = status {
+            dispatcher.root_slot(slot);
+        }
+
+        let leader = match self.slot_leaders.as_mut() {
+            Some(leaders) => {
+                let leader = leaders.get(&slot).cloned().unwrap_or_default();
+                if let PluginSlotStatus::Rooted = status {
+                    // Slots older than the root are either rooted already or abandoned.
+                    *leaders = leaders.split_off(&slot);
+                }
+                leader
+            }
+            None => "".to_owned(),
+        };
+
+        let dispatcher = self.dispatcher.as_ref().expect("dispatcher is unavailable");
+        let wanted = dispatcher.wants_slot_status();
+        let tenants = &self.tenants;
+        if !wanted && !tenants.iter().any(|t| t.dispatcher().wants_slot_status()) {
             return Ok(());
         }
 
+        let elapsed = self.slot_timer.record(slot, status);
+        let millis = |d: Duration| d.as_millis() as u64;
         let event = SlotStatusEvent {
             slot,
             parent: parent.unwrap_or(0),
             status: SlotStatus::from(status).into(),
+            leader,
+            elapsed_since_processed_ms: elapsed.since_processed.map(millis),
+            elapsed_since_confirmed_ms: elapsed.since_confirmed.map(millis),
         };
 
-        publisher
+        for tenant in tenants {
+            if tenant.dispatcher().wants_slot_status() {
+                tenant
+                    .publish(Event::SlotStatus(event.clone()))
+                    .map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })?;
+            }
+        }
+        if !wanted {
+            return Ok(());
+        }
+        dispatcher
             .update_slot_status(event)
             .map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })
     }
 
+    #[cfg(feature = "transactions")]
     fn notify_transaction(
         &mut self,
         transaction: ReplicaTransactionInfoVersions,
         slot: u64,
     ) -> PluginResult<()> {
-        let publisher = self.unwrap_publisher();
-        if !publisher.wants_transaction() {
+        let _call = plugin_call();
+        let ReplicaTransactionInfoVersions::V0_0_1(info) = &transaction;
+        self.capture(|| Callback::NotifyTransaction {
+            transaction: Box::new((*info).into()),
+            slot,
+        });
+        self.publish_transaction_to_tenants(slot, info)?;
+        let dispatcher = self.unwrap_dispatcher();
+        if !dispatcher.wants_transaction()
+            && !dispatcher.wants_transaction_status()
+            && !dispatcher.wants_compressed_nft()
+        {
             return Ok(());
         }
 
-        let event = Self::build_transaction_event(slot, transaction);
+        let filter = self.unwrap_filter();
+        let rejected_by = profiled(ProfileSection::Filter, || {
+            let compute_units = if filter.filters_compute_units() {
+                Self::transaction_compute_units_consumed(info)
+            } else {
+                0
+            };
+            let fee = info.transaction_status_meta.fee;
+            if let Some(rejected_by) = filter.transaction_cost_filter(fee, compute_units) {
+                return Some(rejected_by);
+            }
+            if self.filter_transactions_by_program {
+                let programs = Self::invoked_programs(info);
+                if !filter.wants_any_program(programs.iter().map(|p| p.as_ref())) {
+                    return Some(filter.program_filter());
+                }
+            }
+            None
+        });
+        if let Some(canary) = &self.canary_filter {
+            canary.compare_transaction(
+                rejected_by.is_none(),
+                info.transaction_status_meta.fee,
+                || Self::transaction_compute_units_consumed(info),
+                || Self::invoked_programs(info),
+            );
+        }
+        if let Some(rejected_by) = rejected_by {
+            self.drop_transaction(info.is_vote, DropReason::Filtered, rejected_by);
+            return Ok(());
+        }
+
+        if let Some(cache) = self.signature_cache.as_mut() {
+            if !cache.insert(info.signature.as_ref()) {
+                self.drop_transaction(
+                    info.is_vote,
+                    DropReason::Duplicate,
+                    "transaction_dedup_window_ms",
+                );
+                return Ok(());
+            }
+        }
+
+        let dispatcher = self.unwrap_dispatcher();
+        if dispatcher.wants_transaction_status() {
+            let event = Self::build_transaction_status_event(slot, info);
+            dispatcher
+                .update_transaction_status(event)
+                .map_err(|e| PluginError::TransactionUpdateError { msg: e.to_string() })?;
+        }
 
-        publisher
+        if dispatcher.wants_compressed_nft() && info.transaction_status_meta.status.is_ok() {
+            let message = info.transaction.message();
+            let events = compressed_nft_events(
+                slot,
+                info.signature.as_ref(),
+                &message.account_keys(),
+                message.instructions(),
+                info.transaction_status_meta
+                    .inner_instructions
+                    .as_deref()
+                    .unwrap_or_default(),
+            );
+            for event in events {
+                dispatcher
+                    .update_compressed_nft(event)
+                    .map_err(|e| PluginError::TransactionUpdateError { msg: e.to_string() })?;
+            }
+        }
+
+        if !dispatcher.wants_transaction_kind(info.is_vote) {
+            return Ok(());
+        }
+
+        let event = self.build_transaction_event(slot, transaction);
+
+        dispatcher
             .update_transaction(event)
             .map_err(|e| PluginError::TransactionUpdateError { msg: e.to_string() })
     }
 
+    fn notify_block_metadata(&mut self, blockinfo: ReplicaBlockInfoVersions) -> PluginResult<()> {
+        let _call = plugin_call();
+        let ReplicaBlockInfoVersions::V0_0_1(info) = blockinfo;
+        self.capture(|| Callback::NotifyBlockMetadata { block: info.into() });
+        let leader = info
+            .rewards
+            .iter()
+            .find(|r| r.reward_type == Some(RewardType::Fee))
+            .map(|r| r.pubkey.clone())
+            .unwrap_or_default();
+        if let Some(leaders) = self.slot_leaders.as_mut() {
+            if !leader.is_empty() {
+                leaders.insert(info.slot, leader.clone());
+            }
+        }
+
+        let dispatcher = self.unwrap_dispatcher();
+        let wanted = dispatcher.wants_block_metadata();
+        let tenants = &self.tenants;
+        if !wanted
+            && !tenants
+                .iter()
+                .any(|t| t.dispatcher().wants_block_metadata())
+        {
+            return Ok(());
+        }
+
+        let event = BlockMetadataEvent {
+            slot: info.slot,
+            blockhash: info.blockhash.to_owned(),
+            rewards: info
+                .rewards
+                .iter()
+                .cloned()
+                .map(Self::build_reward)
+                .collect(),
+            block_time: info.block_time,
+            block_height: info.block_height,
+            leader,
+        };
+
+        for tenant in tenants {
+            if tenant.dispatcher().wants_block_metadata() {
+                tenant
+                    .publish(Event::BlockMetadata(event.clone()))
+                    .map_err(|e| PluginError::SlotStatusUpdateError { msg: e.to_string() })?;
+            }
+        }
+        if !wanted {
+            return Ok(());
+        }
+        dispatcher
+            .update_block_metadata(event)
+            .map_err(|e| PluginError::SlotStatusUpdateError { msg: e.to_string() })
+    }
+
+    fn notify_end_of_startup(&mut self) -> PluginResult<()> {
+        let _call = plugin_call();
+        self.capture(|| Callback::NotifyEndOfStartup);
+        Ok(())
+    }
+
     fn account_data_notifications_enabled(&self) -> bool {
-        self.unwrap_publisher().wants_update_account()
+        let dispatcher = self.unwrap_dispatcher();
+        self.capture.is_some()
+            || dispatcher.wants_update_account()
+            || (self.publish_all_accounts && dispatcher.wants_startup_account())
+            || dispatcher.wants_program_upgrade()
+            || dispatcher.wants_token_balance()
+            || dispatcher.wants_owner_changed()
+            || self.shadow_sampler.is_some()
+            || self.tenants.iter().any(|tenant| {
+                let dispatcher = tenant.dispatcher();
+                dispatcher.wants_update_account()
+                    || (self.publish_all_accounts && dispatcher.wants_startup_account())
+            })
     }
 
+    #[cfg(feature = "transactions")]
     fn transaction_notifications_enabled(&self) -> bool {
-        self.unwrap_publisher().wants_transaction()
+        let dispatcher = self.unwrap_dispatcher();
+        self.capture.is_some()
+            || dispatcher.wants_transaction()
+            || dispatcher.wants_transaction_status()
+            || dispatcher.wants_compressed_nft()
+            || self.tenants.iter().any(|tenant| {
+                let dispatcher = tenant.dispatcher();
+                dispatcher.wants_transaction() || dispatcher.wants_transaction_status()
+            })
     }
 }
 
 impl KafkaPlugin {
+    /// Starts publishing as configured.
+    fn load(&mut self, config: &Config) -> PluginResult<()> {
+        self.publish_all_accounts = config.publish_all_accounts;
+
+        let options = config.producer_options();
+        info!("librdkafka config: {:?}", redact_options(&options));
+
+        let (version_n, version_s) = get_rdkafka_version();
+        info!("rd_kafka_version: {:#08x}, {}", version_n, version_s);
+
+        // Deliveries are tracked for the completion markers, watermarks and the checkpoint.
+        let tracker = if config.slot_accounts_complete_topic.is_empty()
+            && config.watermark_topic.is_empty()
+            && config.checkpoint_file.is_empty()
+        {
+            None
+        } else {
+            Some(Arc::new(SlotTracker::new()))
+        };
+        let mut filter = Filter::new(config);
+        let audit_log = if config.filter_audit_log.is_empty() {
+            None
+        } else {
+            let audit_log = FilterAuditLog::open(&config.filter_audit_log).map_err(|e| {
+                PluginError::Custom(Box::new(simple_error!(
+                    "failed to open filter audit log {}: {}",
+                    config.filter_audit_log,
+                    e
+                )))
+            })?;
+            let audit_log = Arc::new(audit_log);
+            filter = filter.with_audit_log(audit_log.clone());
+            Some(audit_log)
+        };
+        let (mut publisher, context) = self.build_publisher(config, tracker)?;
+        if let Some(router) = Self::topic_router(config, &filter) {
+            publisher = publisher.with_topic_router(router);
+        }
+        let dispatcher = Dispatcher::new(publisher, config);
+        if let Some(context) = &context {
+            self.producer_recovery =
+                ProducerRecovery::spawn(config, context.clone(), dispatcher.publisher().clone());
+        }
+        if config.broker_down_spool.is_some() {
+            self.spool_replayer = Some(SpoolReplayer::spawn(dispatcher.publisher().clone()));
+        }
+        self.alert_monitor = config
+            .alerts
+            .clone()
+            .map(|thresholds| AlertMonitor::spawn(thresholds, dispatcher.queue_gauges()));
+        for tenant in &config.tenants {
+            let tenant_config = tenant.config(config);
+            let mut filter = Filter::new(&tenant_config);
+            if let Some(audit_log) = &audit_log {
+                filter = filter.with_audit_log(Arc::new(audit_log.for_tenant(&tenant.name)));
+            }
+            let (publisher, _) = self.build_publisher(&tenant_config, None)?;
+            let mut publisher = publisher.with_labels(tenant.sorted_labels());
+            if let Some(router) = Self::topic_router(&tenant_config, &filter) {
+                publisher = publisher.with_topic_router(router);
+            }
+            let dispatcher = Dispatcher::new(publisher, &tenant_config);
+            info!("Created the pipeline of tenant {}", tenant.name);
+            self.tenants.push(Tenant::new(tenant, filter, dispatcher));
+        }
+        #[cfg(feature = "admin")]
+        if !config.admin_address.is_empty() {
+            let sources = AdminSources {
+                queues: dispatcher.queue_gauges(),
+                publisher: dispatcher.publisher().clone(),
+                tenants: self
+                    .tenants
+                    .iter()
+                    .map(|tenant| {
+                        let publisher = tenant.dispatcher().publisher().clone();
+                        (tenant.name().to_owned(), publisher)
+                    })
+                    .collect(),
+                context: context.clone(),
+                health: self
+                    .alert_monitor
+                    .as_ref()
+                    .map(|monitor| monitor.health().clone()),
+            };
+            let server = AdminServer::spawn(&config.admin_address, sources).map_err(|e| {
+                PluginError::Custom(Box::new(simple_error!(
+                    "failed to serve admin endpoint on {}: {}",
+                    config.admin_address,
+                    e
+                )))
+            })?;
+            self.admin_server = Some(server);
+        }
+        self.tls_reloader = context.and_then(|context| {
+            TlsReloader::spawn(config, context, dispatcher.publisher().clone())
+        });
+        self.dispatcher = Some(dispatcher);
+        self.filter = Some(filter);
+        self.batch_account_updates = config.batch_account_updates;
+        #[cfg(feature = "transactions")]
+        {
+            self.filter_transactions_by_program = config.filter_transactions_by_program;
+            self.include_transaction_logs = config.include_transaction_logs;
+            self.include_inner_instructions = config.include_inner_instructions;
+            if config.transaction_dedup_window_ms > 0 {
+                self.signature_cache = Some(SignatureCache::new(Duration::from_millis(
+                    config.transaction_dedup_window_ms,
+                )));
+            }
+        }
+        #[cfg(feature = "parsed-instructions")]
+        {
+            self.include_parsed_instructions = config.include_parsed_instructions;
+        }
+        #[cfg(feature = "decoders")]
+        {
+            self.decoders = DecoderRegistry::from_config(config);
+        }
+        if !config.program_upgrade_topic.is_empty() {
+            self.program_upgrades = Some(ProgramUpgrades::default());
+        }
+        self.publish_programdata_updates = config.publish_programdata_updates;
+        self.canary_filter = config
+            .canary_filter
+            .as_ref()
+            .map(|canary| CanaryFilter::new(canary, config));
+        self.shadow_sampler = config
+            .shadow
+            .as_ref()
+            .filter(|shadow| shadow.source == ShadowSource::Unfiltered)
+            .map(|shadow| Sampler::new(shadow.sample_percent));
+        if !config.owner_change_topic.is_empty() {
+            self.owner_changes = Some(OwnerChanges::new(config.owner_change_cache_size.max(1)));
+        }
+        if !config.token_balance_topic.is_empty() {
+            self.token_balances = Some(TokenBalances::new(config.token_balance_cache_size.max(1)));
+        }
+        if config.rooted_account_updates {
+            self.rooted_diff = Some(RootedDiff::new());
+        } else if config.coalesce_account_updates {
+            self.batcher = Some(SlotBatcher::new_coalescing());
+        } else if config.batch_account_updates {
+            self.batcher = Some(SlotBatcher::new());
+        }
+        if config.include_slot_leader {
+            self.slot_leaders = Some(BTreeMap::new());
+        }
+        if let Some(capture) = config.capture.clone() {
+            let directory = capture.directory.clone();
+            self.capture = Some(CaptureSink::new(capture).map_err(|e| {
+                PluginError::Custom(Box::new(simple_error!(
+                    "failed to open capture directory {}: {}",
+                    directory,
+                    e
+                )))
+            })?);
+        }
+        if config.account_dedup_cache_size > 0 {
+            self.account_state_cache =
+                Some(AccountStateCache::new(config.account_dedup_cache_size));
+        }
+        if config.account_fork_dedup_window_ms > 0 {
+            if config.batch_account_updates
+                || config.coalesce_account_updates
+                || config.rooted_account_updates
+            {
+                warn!("account_fork_dedup_window_ms is ignored, account updates are published in slot batches");
+            } else {
+                self.fork_dedup = Some(ForkDedupWindow::new(Duration::from_millis(
+                    config.account_fork_dedup_window_ms,
+                )));
+            }
+        }
+        let data_slicer = DataSlicer::new(&config.account_data_slices)
+            .map_err(|msg| PluginError::ConfigFileReadError { msg })?;
+        if !data_slicer.is_empty() {
+            self.data_slicer = Some(data_slicer);
+        }
+        if config.account_sequence_numbers {
+            self.account_sequences = Some(AccountSequences::new());
+        }
+        if config.detect_account_update_gaps {
+            self.gap_detector = Some(GapDetector::new());
+        }
+        info!("Spawned producer");
+        // The validator skips the callbacks for notifications the plugin doesn't ask for.
+        info!(
+            "Account notifications enabled: {}, transaction notifications enabled: {}",
+            self.account_data_notifications_enabled(),
+            self.transaction_notifications_enabled()
+        );
+
+        Ok(())
+    }
+
+    /// Publisher of the configured sink, with the delivery context of its producers if producing to Kafka.
+    fn build_publisher(
+        &self,
+        config: &Config,
+        tracker: Option<Arc<SlotTracker>>,
+    ) -> PluginResult<(Publisher, Option<DeliveryContext>)> {
+        Ok(match &config.sink {
+            Sink::Kafka => {
+                let context =
+                    tracker.map_or_else(DeliveryContext::default, DeliveryContext::tracking);
+                let mut context = context
+                    .with_in_flight_budget(config.in_flight_budget().map(Arc::new))
+                    .with_delivery_latency(Some(Arc::new(DeliveryLatency::new(config))))
+                    .with_error_reporter(self.error_reporter.clone());
+                if !config.dead_letter_topic.is_empty() {
+                    let dead_letters = DeadLetters::new(config.dead_letter_topic.clone());
+                    context = context.with_dead_letters(Some(Arc::new(dead_letters)));
+                }
+                if config.recreate_failed_producers {
+                    context = context.with_fatal_errors();
+                }
+                let producers = config
+                    .producers(context.clone())
+                    .map_err(|e| PluginError::Custom(Box::new(e)))?;
+                info!("Created {} rdkafka::ThreadedProducer(s)", producers.len());
+
+                let encryptor = match config.payload_encryption.clone() {
+                    Some(encryption) => {
+                        Some(PayloadEncryptor::new(encryption).map_err(PluginError::Custom)?)
+                    }
+                    None => None,
+                };
+                let mut publisher = Publisher::new(producers, encryptor, config);
+                if let Some(compression) = config.payload_compression.clone() {
+                    let compressor =
+                        PayloadCompressor::new(compression).map_err(PluginError::Custom)?;
+                    if let Some(id) = compressor.dictionary_id() {
+                        info!("Compressing payloads with zstd dictionary {}", id);
+                    }
+                    publisher = publisher.with_compressor(compressor);
+                }
+                if let Some(spool) = config.broker_down_spool.clone() {
+                    info!(
+                        "Spooling records to {} while all brokers are down",
+                        spool.directory
+                    );
+                    publisher = publisher.with_spool(Spool::new(spool)?);
+                }
+                (publisher, Some(context))
+            }
+            Sink::Stdout => {
+                let writer = Box::new(LineWriter::new(std::io::stdout()));
+                (Publisher::new_json(writer, tracker, config), None)
+            }
+            Sink::Pipe(path) => {
+                let pipe = OpenOptions::new().append(true).create(true).open(path)?;
+                let writer = Box::new(LineWriter::new(pipe));
+                info!("Writing events to {}", path);
+                (Publisher::new_json(writer, tracker, config), None)
+            }
+            Sink::File(sink) => {
+                info!("Writing events to {}", sink.directory);
+                (Publisher::new_files(sink.clone(), tracker, config), None)
+            }
+        })
+    }
+
     pub fn new() -> Self {
         Default::default()
     }
 
-    fn unwrap_publisher(&self) -> &Publisher {
-        self.publisher.as_ref().expect("publisher is unavailable")
+    /// Router of the account updates, if the allowlist is remote or `topic_rules` are configured.
+    fn topic_router(config: &Config, filter: &Filter) -> Option<TopicRouter> {
+        let routes = if config.program_allowlist_url.is_empty() {
+            None
+        } else {
+            Some(filter.get_allowlist().topic_routes())
+        };
+        if routes.is_none() && config.topic_rules.is_empty() {
+            return None;
+        }
+        Some(TopicRouter::new(routes, &config.topic_rules))
+    }
+
+    /// Writes a callback to the capture files, if capturing.
+    ///
+    /// Capture errors never fail the callback, capturing stops instead.
+    fn capture(&mut self, callback: impl FnOnce() -> Callback) {
+        if let Some(capture) = self.capture.as_mut() {
+            if let Err(e) = capture.record(&callback()) {
+                error!("Failed to capture callback, capturing stopped: {}", e);
+                self.capture = None;
+            }
+        }
+    }
+
+    fn unwrap_dispatcher(&self) -> &Dispatcher {
+        self.dispatcher.as_ref().expect("dispatcher is unavailable")
+    }
+
+    /// Publishes the buffered account updates of `slot` once its status advances.
+    /// Rooting a slot also publishes leftovers of older slots, e.g. from abandoned forks.
+    fn publish_slot_batches(&mut self, slot: u64, status: PluginSlotStatus) -> PluginResult<()> {
+        let batcher = match self.batcher.as_mut() {
+            Some(batcher) => batcher,
+            None => return Ok(()),
+        };
+        let batches = match status {
+            PluginSlotStatus::Rooted => batcher.take_until(slot),
+            _ => batcher.take(slot).into_iter().collect(),
+        };
+
+        for batch in batches {
+            self.publish_slot_batch(batch)?;
+        }
+        Ok(())
+    }
+
+    /// Publishes a slot's buffered account updates, either as one message or one by one.
+    fn publish_slot_batch(&mut self, mut batch: SlotAccountsBatch) -> PluginResult<()> {
+        if !self.batch_account_updates {
+            for ev in batch.accounts {
+                self.dispatch_account(ev, false, Vec::new())?;
+            }
+            return Ok(());
+        }
+        if let Some(sequences) = self.account_sequences.as_mut() {
+            for ev in &mut batch.accounts {
+                sequences.assign(ev);
+            }
+        }
+        let versions: Vec<(Bytes, u64)> = match self.gap_detector {
+            Some(_) => batch
+                .accounts
+                .iter()
+                .map(|ev| (ev.pubkey.clone(), ev.write_version))
+                .collect(),
+            None => Vec::new(),
+        };
+        let result = self.unwrap_dispatcher().update_slot_accounts_batch(batch);
+        if let Some(gaps) = self.gap_detector.as_mut() {
+            for (pubkey, write_version) in versions {
+                match result {
+                    Ok(()) => gaps.published(&pubkey, write_version),
+                    Err(_) => gaps.dropped(&pubkey, write_version),
+                }
+            }
+        }
+        result.map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })
+    }
+
+    /// Publishes the account updates held back by the fork dedup window for its whole length,
+    /// or all of them if `all` is set.
+    fn release_fork_duplicates(&mut self, all: bool) -> PluginResult<()> {
+        let released = match self.fork_dedup.as_mut() {
+            Some(window) if all => window.take_all(),
+            Some(window) => window.expired(),
+            None => return Ok(()),
+        };
+        for (event, slots) in released {
+            self.dispatch_account(event, false, slots)?;
+        }
+        Ok(())
+    }
+
+    /// Numbers an account update and hands it to the publisher.
+    ///
+    /// Updates released by the fork dedup window carry the `fork_slots` they were seen in.
+    fn dispatch_account(
+        &mut self,
+        mut event: UpdateAccountEvent,
+        is_startup: bool,
+        fork_slots: Vec<u64>,
+    ) -> PluginResult<()> {
+        if let Some(sequences) = self.account_sequences.as_mut() {
+            sequences.assign(&mut event);
+        }
+        // Bytes are reference counted, so keeping the pubkey costs no copy.
+        let (pubkey, write_version) = (event.pubkey.clone(), event.write_version);
+        let dispatcher = self.unwrap_dispatcher();
+        let result = if is_startup {
+            dispatcher.update_startup_account(event)
+        } else if !fork_slots.is_empty() {
+            dispatcher.update_reserved_account(event, fork_slots)
+        } else {
+            dispatcher.update_account(event)
+        };
+        if let Some(gaps) = self.gap_detector.as_mut() {
+            match result {
+                Ok(()) => gaps.published(&pubkey, write_version),
+                Err(_) => gaps.dropped(&pubkey, write_version),
+            }
+        }
+        result.map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })
+    }
+
+    /// Account update message of `info`, its data possibly sliced and decoded.
+    fn build_account_event(&self, slot: u64, info: &AccountInfo) -> UpdateAccountEvent {
+        UpdateAccountEvent {
+            slot,
+            pubkey: Bytes::copy_from_slice(info.pubkey),
+            lamports: info.lamports,
+            owner: Bytes::copy_from_slice(info.owner),
+            executable: info.executable,
+            rent_epoch: info.rent_epoch,
+            // This is the only copy of the account data until it is encoded for Kafka.
+            data: match self
+                .data_slicer
+                .as_ref()
+                .and_then(|slicer| slicer.slice(info.owner, info.data))
+            {
+                Some(sliced) => sliced.into(),
+                None => Bytes::copy_from_slice(info.data),
+            },
+            write_version: info.write_version,
+            // Numbered once it is certain to be published.
+            sequence: 0,
+            discarded_slots: Vec::new(),
+            // Decoded from the whole data, before any slicing.
+            #[cfg(feature = "decoders")]
+            decoded: self.decoders.as_ref().and_then(|decoders| {
+                profiled(ProfileSection::Serialize, || {
+                    decoders.decode(info.owner, info.data)
+                })
+            }),
+            // Configs asking for decoded accounts are rejected without the feature.
+            #[cfg(not(feature = "decoders"))]
+            decoded: None,
+        }
+    }
+
+    /// Mirrors a sample of the account updates to the shadow topic, before any filter.
+    ///
+    /// Failing to mirror an update is counted as a drop of the shadow topic, and fails nothing.
+    fn mirror_account(&self, slot: u64, info: &AccountInfo) {
+        match self.shadow_sampler {
+            Some(sampler) if sampler.sampled(info.pubkey) => {}
+            _ => return,
+        }
+        let event = self.build_account_event(slot, info);
+        let _ = self.unwrap_dispatcher().update_shadow_account(event);
+    }
+
+    /// Publishes an account update to every tenant it passes the filters of.
+    fn publish_account_to_tenants(
+        &self,
+        slot: u64,
+        info: &AccountInfo,
+        is_startup: bool,
+    ) -> PluginResult<()> {
+        let mut event = None;
+        for tenant in &self.tenants {
+            if !tenant.wants_account(is_startup, info.owner) {
+                continue;
+            }
+            // Built once for all tenants, the data is shared between them.
+            let event = event
+                .get_or_insert_with(|| self.build_account_event(slot, info))
+                .clone();
+            let event = if is_startup {
+                Event::StartupAccount(event)
+            } else {
+                Event::UpdateAccount(event)
+            };
+            tenant
+                .publish(event)
+                .map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })?;
+        }
+        Ok(())
+    }
+
+    /// Publishes a transaction, and its status event, to every tenant it passes the filters of.
+    #[cfg(feature = "transactions")]
+    fn publish_transaction_to_tenants(
+        &self,
+        slot: u64,
+        info: &ReplicaTransactionInfo,
+    ) -> PluginResult<()> {
+        let mut event = None;
+        for tenant in &self.tenants {
+            let wanted = tenant.wants_transaction(
+                info.is_vote,
+                info.transaction_status_meta.fee,
+                || Self::transaction_compute_units_consumed(info),
+                || Self::invoked_programs(info),
+            );
+            if !wanted {
+                continue;
+            }
+            let dispatcher = tenant.dispatcher();
+            if dispatcher.wants_transaction_status() {
+                let status = Self::build_transaction_status_event(slot, info);
+                tenant
+                    .publish(Event::TransactionStatus(status))
+                    .map_err(|e| PluginError::TransactionUpdateError { msg: e.to_string() })?;
+            }
+            if dispatcher.wants_transaction_kind(info.is_vote) {
+                let event = event
+                    .get_or_insert_with(|| {
+                        self.build_transaction_event(
+                            slot,
+                            ReplicaTransactionInfoVersions::V0_0_1(info),
+                        )
+                    })
+                    .clone();
+                tenant
+                    .publish(Event::Transaction(Box::new(event)))
+                    .map_err(|e| PluginError::TransactionUpdateError { msg: e.to_string() })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Accounts for an account update dropped for `reason` by the filter group `filter`.
+    fn drop_account(&self, is_startup: bool, reason: DropReason, filter: &'static str) {
+        let topic = self
+            .unwrap_dispatcher()
+            .publisher()
+            .account_topic(is_startup);
+        drops().dropped(topic, reason, filter);
+    }
+
+    /// Publishes an `OwnerChanged` event if the owner of `info` differs from the one last seen.
+    ///
+    /// Only accounts of programs passing the filters, `wanted_program`, start being tracked.
+    fn publish_owner_change(
+        &mut self,
+        slot: u64,
+        info: &AccountInfo,
+        wanted_program: bool,
+    ) -> PluginResult<()> {
+        let event = match self.owner_changes.as_mut() {
+            Some(changes) => changes.observe(slot, info, wanted_program),
+            None => None,
+        };
+        if let Some(event) = event {
+            self.unwrap_dispatcher()
+                .update_owner_changed(event)
+                .map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })?;
+        }
+        Ok(())
+    }
+
+    /// Publishes a `ProgramUpgraded` event if `info` is the programdata of an upgraded program.
+    ///
+    /// Returns whether the account update itself is to be dropped.
+    fn publish_program_upgrade(&mut self, slot: u64, info: &AccountInfo) -> PluginResult<bool> {
+        let filter = self.filter.as_ref().expect("filter is unavailable");
+        let event = match self.program_upgrades.as_mut() {
+            Some(upgrades) => upgrades.detect(slot, info, filter),
+            None => None,
+        };
+        let event = match event {
+            Some(event) => event,
+            None => return Ok(false),
+        };
+        self.unwrap_dispatcher()
+            .update_program_upgraded(event)
+            .map_err(|e| PluginError::AccountsUpdateError { msg: e.to_string() })?;
+        if self.publish_programdata_updates {
+            return Ok(false);
+        }
+        // Only counted if it would have been published.
+        if self.unwrap_dispatcher().wants_update_account()
+            && self.unwrap_filter().wants_program(info.owner)
+        {
+            self.drop_account(false, DropReason::Filtered, "publish_programdata_updates");
+        }
+        Ok(true)
+    }
+
+    /// Accounts for a transaction dropped for `reason` by the filter group `filter`, along with its
+    /// status event.
+    #[cfg(feature = "transactions")]
+    fn drop_transaction(&self, is_vote: bool, reason: DropReason, filter: &'static str) {
+        let publisher = self.unwrap_dispatcher().publisher();
+        if publisher.wants_transaction_kind(is_vote) {
+            drops().dropped(publisher.transaction_topic_for(is_vote), reason, filter);
+        }
+        if publisher.wants_transaction_status() {
+            drops().dropped(publisher.transaction_status_topic(), reason, filter);
+        }
     }
 
     fn unwrap_filter(&self) -> &Filter {
         self.filter.as_ref().expect("filter is unavailable")
     }
 
-    fn unwrap_update_account(account: ReplicaAccountInfoVersions) -> &ReplicaAccountInfo {
-        match account {
-            ReplicaAccountInfoVersions::V0_0_1(info) => info,
-        }
+    /// Program ids invoked by a transaction's instructions, including inner instructions.
+    #[cfg(feature = "transactions")]
+    fn invoked_programs(transaction: &ReplicaTransactionInfo) -> Vec<Pubkey> {
+        let message = transaction.transaction.message();
+        let account_keys = message.account_keys();
+        let inner_instructions = transaction
+            .transaction_status_meta
+            .inner_instructions
+            .iter()
+            .flatten()
+            .flat_map(|inner| inner.instructions.iter());
+        let mut programs: Vec<Pubkey> = message
+            .instructions()
+            .iter()
+            .chain(inner_instructions)
+            .filter_map(|ix| account_keys.get(ix.program_id_index as usize))
+            .copied()
+            .collect();
+        programs.sort_unstable();
+        programs.dedup();
+        programs
     }
 
+    #[cfg(feature = "transactions")]
     fn build_compiled_instruction(
         ix: &solana_program::instruction::CompiledInstruction,
     ) -> CompiledInstruction {
@@ -194,6 +1227,7 @@ impl KafkaPlugin {
         }
     }
 
+    #[cfg(feature = "transactions")]
     fn build_message_header(header: &solana_program::message::MessageHeader) -> MessageHeader {
         MessageHeader {
             num_required_signatures: header.num_required_signatures as u32,
@@ -202,6 +1236,7 @@ impl KafkaPlugin {
         }
     }
 
+    #[cfg(feature = "transactions")]
     fn build_transaction_token_balance(
         transaction_token_account_balance: solana_transaction_status::TransactionTokenBalance,
     ) -> TransactionTokenBalance {
@@ -220,18 +1255,129 @@ impl KafkaPlugin {
         }
     }
 
+    fn build_reward(reward: solana_transaction_status::Reward) -> Reward {
+        Reward {
+            pubkey: reward.pubkey,
+            lamports: reward.lamports,
+            post_balance: reward.post_balance,
+            reward_type: match reward.reward_type {
+                Some(r) => r as i32,
+                None => 0,
+            },
+            commission: match reward.commission {
+                Some(v) => v as u32,
+                None => 0,
+            },
+        }
+    }
+
+    #[cfg(feature = "transactions")]
+    fn transaction_compute_units_consumed(transaction: &ReplicaTransactionInfo) -> u64 {
+        transaction
+            .transaction_status_meta
+            .log_messages
+            .as_deref()
+            .map(compute_units_consumed)
+            .unwrap_or(0)
+    }
+
+    #[cfg(feature = "transactions")]
+    fn build_transaction_status_event(
+        slot: u64,
+        transaction: &ReplicaTransactionInfo,
+    ) -> TransactionStatusEvent {
+        let meta = transaction.transaction_status_meta;
+        TransactionStatusEvent {
+            signature: transaction.signature.as_ref().into(),
+            slot,
+            is_status_err: meta.status.is_err(),
+            error_info: match &meta.status {
+                Err(e) => e.to_string(),
+                Ok(_) => "".to_owned(),
+            },
+            fee: meta.fee,
+        }
+    }
+
+    /// Decodes the instructions of known programs, top-level and optionally inner.
+    #[cfg(feature = "parsed-instructions")]
+    fn build_parsed_instructions(
+        transaction: &ReplicaTransactionInfo,
+        include_inner_instructions: bool,
+    ) -> Vec<ParsedInstruction> {
+        let message = transaction.transaction.message();
+        let account_keys = message.account_keys();
+        let parse = |index: u32,
+                     inner_index: Option<u32>,
+                     ix: &solana_program::instruction::CompiledInstruction| {
+            let program_id = account_keys.get(ix.program_id_index as usize)?;
+            let parsed =
+                solana_transaction_status::parse_instruction::parse(program_id, ix, &account_keys)
+                    .ok()?;
+            Some(ParsedInstruction {
+                index,
+                inner_index,
+                program: parsed.program,
+                program_id: parsed.program_id,
+                parsed_json: parsed.parsed.to_string(),
+            })
+        };
+
+        let mut parsed: Vec<ParsedInstruction> = message
+            .instructions()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, ix)| parse(i as u32, None, ix))
+            .collect();
+        if !include_inner_instructions {
+            return parsed;
+        }
+        for inner in transaction
+            .transaction_status_meta
+            .inner_instructions
+            .iter()
+            .flatten()
+        {
+            parsed.extend(
+                inner
+                    .instructions
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, ix)| parse(inner.index as u32, Some(i as u32), ix)),
+            );
+        }
+        parsed
+    }
+
+    #[cfg(feature = "transactions")]
     fn build_transaction_event(
+        &self,
         slot: u64,
         transaction: ReplicaTransactionInfoVersions,
     ) -> TransactionEvent {
         let ReplicaTransactionInfoVersions::V0_0_1(transaction) = transaction;
+        #[cfg(feature = "parsed-instructions")]
+        let parsed_instructions = if self.include_parsed_instructions {
+            Self::build_parsed_instructions(transaction, self.include_inner_instructions)
+        } else {
+            vec![]
+        };
+        // Configs asking for parsed instructions are rejected without the feature.
+        #[cfg(not(feature = "parsed-instructions"))]
+        let parsed_instructions = vec![];
         let transaction_status_meta = transaction.transaction_status_meta;
+        let compute_budget = ComputeBudget::from_message(transaction.transaction.message());
+        let compute_units_consumed = Self::transaction_compute_units_consumed(transaction);
         let signature = transaction.signature;
         let is_vote = transaction.is_vote;
         let transaction = transaction.transaction;
         TransactionEvent {
             is_vote,
             slot,
+            compute_units_consumed,
+            compute_unit_limit: compute_budget.unit_limit.unwrap_or(0),
+            compute_unit_price: compute_budget.unit_price,
+            priority_fee: compute_budget.priority_fee(),
             signature: signature.as_ref().into(),
             transaction_status_meta: Some(TransactionStatusMeta {
                 is_status_err: transaction_status_meta.status.is_err(),
@@ -239,31 +1385,24 @@ impl KafkaPlugin {
                     Err(e) => e.to_string(),
                     Ok(_) => "".to_owned(),
                 },
+                error: match &transaction_status_meta.status {
+                    Err(e) => bincode::serialize(e).unwrap_or_default(),
+                    Ok(_) => Vec::new(),
+                },
                 rewards: transaction_status_meta
                     .rewards
                     .clone()
                     .unwrap()
                     .into_iter()
-                    .map(|x| Reward {
-                        pubkey: x.pubkey,
-                        lamports: x.lamports,
-                        post_balance: x.post_balance,
-                        reward_type: match x.reward_type {
-                            Some(r) => r as i32,
-                            None => 0,
-                        },
-                        commission: match x.commission {
-                            Some(v) => v as u32,
-                            None => 0,
-                        },
-                    })
+                    .map(Self::build_reward)
                     .collect(),
                 fee: transaction_status_meta.fee,
                 log_messages: match &transaction_status_meta.log_messages {
-                    Some(v) => v.to_owned(),
-                    None => vec![],
+                    Some(v) if self.include_transaction_logs => v.to_owned(),
+                    _ => vec![],
                 },
                 inner_instructions: match &transaction_status_meta.inner_instructions {
+                    Some(_) if !self.include_inner_instructions => vec![],
                     None => vec![],
                     Some(inners) => inners
                         .clone()
@@ -383,6 +1522,13 @@ impl KafkaPlugin {
                     .copied()
                     .map(|x| x.as_ref().into())
                     .collect(),
+                parsed_instructions,
+                account_keys: transaction
+                    .message()
+                    .account_keys()
+                    .iter()
+                    .map(|k| k.as_ref().into())
+                    .collect(),
             }),
         }
     }
@@ -0,0 +1,354 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Profiling of the plugin's hot paths, with the `profiling` feature.
+//!
+//! Code marks what it is doing with [`profile_scope`], which costs nothing without the feature. With it,
+//! CPU time spent in each [`ProfileSection`] is sampled per thread while a profile is taken, and the
+//! plugin's own heap allocations are counted per section by [`ProfilingAllocator`].
+
+/// What a thread of the plugin is busy with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProfileSection {
+    Other,
+    /// Checking events against the filters.
+    Filter,
+    /// Encoding events into protobuf or JSON.
+    Serialize,
+    Compress,
+    Encrypt,
+    /// Handing records to librdkafka.
+    Produce,
+}
+
+impl ProfileSection {
+    pub const ALL: [ProfileSection; 6] = [
+        ProfileSection::Other,
+        ProfileSection::Filter,
+        ProfileSection::Serialize,
+        ProfileSection::Compress,
+        ProfileSection::Encrypt,
+        ProfileSection::Produce,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ProfileSection::Other => "other",
+            ProfileSection::Filter => "filter",
+            ProfileSection::Serialize => "serialize",
+            ProfileSection::Compress => "compress",
+            ProfileSection::Encrypt => "encrypt",
+            ProfileSection::Produce => "produce",
+        }
+    }
+}
+
+#[cfg(not(feature = "profiling"))]
+mod imp {
+    use super::ProfileSection;
+
+    pub struct ProfileScope;
+
+    #[inline(always)]
+    pub fn profile_scope(_: ProfileSection) -> ProfileScope {
+        ProfileScope
+    }
+}
+
+#[cfg(feature = "profiling")]
+mod imp {
+    use {
+        super::ProfileSection,
+        lazy_static::lazy_static,
+        std::{
+            alloc::{GlobalAlloc, Layout, System},
+            cell::Cell,
+            collections::HashMap,
+            fs,
+            sync::{
+                atomic::{AtomicBool, AtomicU64, Ordering},
+                Mutex,
+            },
+            thread,
+            time::Duration,
+        },
+    };
+
+    /// Longest CPU profile taken at once.
+    pub const MAX_PROFILE_DURATION: Duration = Duration::from_secs(60);
+
+    thread_local! {
+        static CURRENT: Cell<ProfileSection> = const { Cell::new(ProfileSection::Other) };
+        /// Thread CPU time when [`CURRENT`] was entered, and the profile it was taken in.
+        static ENTERED: Cell<(u64, u64)> = const { Cell::new((0, 0)) };
+    }
+
+    static PROFILING: AtomicBool = AtomicBool::new(false);
+    /// Number of the profile being taken, so time from before it started isn't charged to it.
+    static PROFILE: AtomicU64 = AtomicU64::new(0);
+
+    lazy_static! {
+        /// CPU nanoseconds by thread id and section, of the profile being taken.
+        static ref SAMPLES: Mutex<HashMap<(i64, usize), u64>> = Mutex::default();
+    }
+
+    /// Marks the calling thread busy with a section until dropped.
+    pub struct ProfileScope {
+        previous: ProfileSection,
+    }
+
+    #[inline]
+    pub fn profile_scope(section: ProfileSection) -> ProfileScope {
+        ProfileScope {
+            previous: switch(section),
+        }
+    }
+
+    impl Drop for ProfileScope {
+        #[inline]
+        fn drop(&mut self) {
+            switch(self.previous);
+        }
+    }
+
+    /// Makes `section` current, charging the time since the last switch to the section left.
+    fn switch(section: ProfileSection) -> ProfileSection {
+        let previous = CURRENT.with(|current| current.replace(section));
+        if PROFILING.load(Ordering::Relaxed) {
+            let now = thread_cpu_ns();
+            let profile = PROFILE.load(Ordering::Relaxed);
+            let (entered, entered_profile) =
+                ENTERED.with(|entered| entered.replace((now, profile)));
+            if entered_profile == profile && previous != ProfileSection::Other {
+                *SAMPLES
+                    .lock()
+                    .unwrap()
+                    .entry((thread_id(), previous as usize))
+                    .or_default() += now.saturating_sub(entered);
+            }
+        }
+        previous
+    }
+
+    fn thread_cpu_ns() -> u64 {
+        let mut time = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: `time` is a valid timespec to write to.
+        unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) };
+        time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
+    }
+
+    fn thread_id() -> i64 {
+        // SAFETY: gettid takes no arguments and cannot fail.
+        unsafe { libc::syscall(libc::SYS_gettid) }
+    }
+
+    /// Name and CPU nanoseconds used so far of every thread of the process.
+    fn threads() -> HashMap<i64, (String, u64)> {
+        // SAFETY: sysconf has no preconditions.
+        let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+        let tasks = match fs::read_dir("/proc/self/task") {
+            Ok(tasks) => tasks,
+            Err(_) => return HashMap::new(),
+        };
+        tasks
+            .filter_map(|task| {
+                let task = task.ok()?;
+                let tid = task.file_name().to_str()?.parse().ok()?;
+                let name = fs::read_to_string(task.path().join("comm")).ok()?;
+                let stat = fs::read_to_string(task.path().join("stat")).ok()?;
+                // utime and stime are the 14th and 15th fields, after the parenthesized name.
+                let mut fields = stat.rsplit_once(')')?.1.split_whitespace().skip(11);
+                let utime: u64 = fields.next()?.parse().ok()?;
+                let stime: u64 = fields.next()?.parse().ok()?;
+                let cpu_ns = (utime + stime) * 1_000_000_000 / ticks_per_sec;
+                Some((tid, (name.trim_end().to_owned(), cpu_ns)))
+            })
+            .collect()
+    }
+
+    /// Threads spawned by the plugin and by librdkafka for it.
+    fn is_plugin_thread(name: &str) -> bool {
+        name.starts_with("kafka-") || name.starts_with("rdk:")
+    }
+
+    /// Samples CPU time for `duration`, in the folded stack format of flame graph tools:
+    /// one `<thread name>;<section> <nanoseconds>` line per thread and section.
+    ///
+    /// Time of the plugin's own threads outside of any section is reported as `other`; validator
+    /// threads only appear with the time they spent in sections, e.g. filtering in callbacks.
+    pub fn profile_cpu(duration: Duration) -> String {
+        let before = threads();
+        SAMPLES.lock().unwrap().clear();
+        PROFILE.fetch_add(1, Ordering::Relaxed);
+        PROFILING.store(true, Ordering::Relaxed);
+        thread::sleep(duration.min(MAX_PROFILE_DURATION));
+        PROFILING.store(false, Ordering::Relaxed);
+        let samples = std::mem::take(&mut *SAMPLES.lock().unwrap());
+        let after = threads();
+
+        let mut lines: HashMap<(String, &str), u64> = HashMap::new();
+        let mut in_sections: HashMap<i64, u64> = HashMap::new();
+        for ((tid, section), ns) in samples {
+            let name = after
+                .get(&tid)
+                .map_or_else(|| format!("tid-{}", tid), |(name, _)| name.clone());
+            *lines
+                .entry((name, ProfileSection::ALL[section].name()))
+                .or_default() += ns;
+            *in_sections.entry(tid).or_default() += ns;
+        }
+        for (tid, (name, cpu_ns)) in &after {
+            if !is_plugin_thread(name) {
+                continue;
+            }
+            let used = cpu_ns.saturating_sub(before.get(tid).map_or(0, |(_, ns)| *ns));
+            let other = used.saturating_sub(in_sections.get(tid).copied().unwrap_or_default());
+            if other > 0 {
+                *lines.entry((name.clone(), "other")).or_default() += other;
+            }
+        }
+        let mut lines: Vec<String> = lines
+            .into_iter()
+            .map(|((name, section), ns)| format!("{};{} {}", name, section, ns))
+            .collect();
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    // Only copied into the statics below.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    /// Heap allocations of the plugin, counted per [`ProfileSection`]. Plain statics, as counting
+    /// must not allocate.
+    static ALLOCATIONS: [AtomicU64; ProfileSection::ALL.len()] = [ZERO; ProfileSection::ALL.len()];
+    static ALLOCATED_BYTES: [AtomicU64; ProfileSection::ALL.len()] =
+        [ZERO; ProfileSection::ALL.len()];
+    static FREED_BYTES: AtomicU64 = ZERO;
+
+    /// Wraps the system allocator to count the plugin's own allocations.
+    ///
+    /// The plugin links its own allocator into the validator, so the validator's allocations are
+    /// not counted.
+    pub struct ProfilingAllocator;
+
+    // SAFETY: defers to the system allocator.
+    unsafe impl GlobalAlloc for ProfilingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            allocated(layout.size());
+            System.alloc(layout)
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            allocated(layout.size());
+            System.alloc_zeroed(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            freed(layout.size());
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            freed(layout.size());
+            allocated(new_size);
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    fn allocated(size: usize) {
+        let section = CURRENT.try_with(Cell::get).unwrap_or(ProfileSection::Other) as usize;
+        ALLOCATIONS[section].fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES[section].fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    fn freed(size: usize) {
+        FREED_BYTES.fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    /// Heap allocations since the plugin was loaded, as JSON.
+    pub fn heap_profile() -> serde_json::Value {
+        let mut sections = serde_json::Map::new();
+        let mut allocated_bytes = 0;
+        for section in ProfileSection::ALL {
+            let bytes = ALLOCATED_BYTES[section as usize].load(Ordering::Relaxed);
+            allocated_bytes += bytes;
+            sections.insert(
+                section.name().to_owned(),
+                serde_json::json!({
+                    "allocations": ALLOCATIONS[section as usize].load(Ordering::Relaxed),
+                    "allocated_bytes": bytes,
+                }),
+            );
+        }
+        let freed_bytes = FREED_BYTES.load(Ordering::Relaxed);
+        serde_json::json!({
+            "allocated_bytes": allocated_bytes,
+            "live_bytes": allocated_bytes.saturating_sub(freed_bytes),
+            "sections": sections,
+        })
+    }
+}
+
+pub use imp::*;
+
+/// Runs `f` as `section`.
+#[inline]
+pub fn profiled<R>(section: ProfileSection, f: impl FnOnce() -> R) -> R {
+    let _scope = profile_scope(section);
+    f()
+}
+
+#[cfg(all(test, feature = "profiling"))]
+mod tests {
+    use {
+        super::*,
+        std::time::{Duration, Instant},
+    };
+
+    #[test]
+    fn test_profile_cpu() {
+        let worker = std::thread::Builder::new()
+            .name("kafka-profiled".to_owned())
+            .spawn(|| {
+                let mut encoded = Vec::new();
+                for i in 0..200 {
+                    let _scope = profile_scope(ProfileSection::Serialize);
+                    let start = Instant::now();
+                    while start.elapsed() < Duration::from_millis(1) {}
+                    encoded.push(i.to_string());
+                }
+                encoded.len()
+            })
+            .unwrap();
+        let profile = profile_cpu(Duration::from_millis(100));
+        assert_eq!(worker.join().unwrap(), 200);
+        assert!(
+            profile
+                .lines()
+                .any(|line| line.starts_with("kafka-profiled;serialize ")),
+            "{}",
+            profile
+        );
+        let heap = heap_profile();
+        assert!(
+            heap["sections"]["serialize"]["allocations"]
+                .as_u64()
+                .unwrap()
+                > 0
+        );
+    }
+}
@@ -13,70 +13,1925 @@
 // limitations under the License.
 
 use {
-    crate::*,
+    crate::{
+        delivery::{opaque_slot, record_size, slot_opaque, UNTRACKED},
+        *,
+    },
+    log::{error, info},
     prost::Message,
     rdkafka::{
-        error::KafkaError,
+        error::{KafkaError, RDKafkaErrorCode},
+        message::{Header, Headers, OwnedHeaders},
         producer::{BaseRecord, Producer as KafkaProducer},
     },
-    std::time::Duration,
+    serde::Serialize,
+    std::{
+        cell::{Cell, RefCell},
+        collections::{hash_map::DefaultHasher, HashMap},
+        hash::{Hash, Hasher},
+        io::Write,
+        path::Path,
+        sync::{Arc, Mutex, RwLock},
+        thread,
+        time::{Duration, Instant},
+    },
 };
 
-pub struct Publisher {
+/// Encode buffers larger than this are shrunk back after use,
+/// so a single huge account doesn't pin memory forever.
+const MAX_RETAINED_ENCODE_BUFFER: usize = 4 * 1024 * 1024;
+
+/// Wait before retrying records replayed from the spool that found the producer queue full.
+const QUEUE_FULL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Header carrying the idempotency key of a record.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Header listing the comma separated slots an account update was seen in,
+/// when it was notified on several forks.
+pub const FORK_SLOTS_HEADER: &str = "fork-slots";
+
+/// Header naming the payload's media type, when it is not the protobuf encoded event.
+pub const CONTENT_TYPE_HEADER: &str = "content-type";
+
+/// Header of [`TopicSwitch`] markers, `final` on the old topic and `start` on the new one.
+pub const TOPIC_SWITCH_HEADER: &str = "topic-switch";
+
+/// How long a topic switch-over waits for the partitions of each topic.
+const TOPIC_METADATA_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a topic switch-over flushes a shard at a time, holding the shards lock.
+const TOPIC_SWITCH_FLUSH_STEP: Duration = Duration::from_millis(100);
+
+/// Header naming the topic a record mirrored to the shadow topic was published to.
+pub const SHADOW_SOURCE_TOPIC_HEADER: &str = "shadow-source-topic";
+
+thread_local! {
+    static ENCODE_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    /// Timestamp of the Kafka records sent by the calling thread, see [`Publisher::stamped`].
+    static RECORD_TIMESTAMP: Cell<Option<i64>> = const { Cell::new(None) };
+}
+
+/// Encodes `msg` into the calling thread's reusable buffer and hands it to `f`.
+fn with_encoded<M: Message, R>(msg: &M, f: impl FnOnce(&[u8]) -> R) -> R {
+    ENCODE_BUFFER.with(|buf| {
+        let mut buf = buf.borrow_mut();
+        buf.clear();
+        let len = msg.encoded_len();
+        if len <= buf.capacity() {
+            metrics().encode_buffers_reused.inc();
+        } else {
+            metrics().encode_buffers_allocated.inc();
+            buf.reserve(len);
+        }
+        profiled(ProfileSection::Serialize, || msg.encode(&mut *buf))
+            .expect("buffer has enough capacity for the message");
+        let result = f(&buf);
+        if buf.capacity() > MAX_RETAINED_ENCODE_BUFFER {
+            buf.clear();
+            buf.shrink_to(MAX_RETAINED_ENCODE_BUFFER);
+        }
+        result
+    })
+}
+
+/// Which producers a record goes through.
+#[derive(Clone, Copy)]
+enum Lane {
+    /// The `producer_shards`.
+    Bulk,
+    /// The dedicated producer of `priority_lane`, if enabled.
+    Priority,
+}
+
+struct ProducerShard {
+    /// Index of the shard, or `priority`.
+    label: String,
     producer: Producer,
+    sent: Arc<Counter>,
+    failed: Arc<Counter>,
+}
+
+/// Where events go.
+enum Output {
+    Kafka(RwLock<Vec<ProducerShard>>),
+    /// One JSON object per line, for the stdout and pipe sinks.
+    Json(Mutex<Box<dyn Write + Send>>),
+    /// Rotating files in a directory per topic, for the file sink.
+    Files {
+        sink: FileSink,
+        files: Mutex<HashMap<String, RotatingFile>>,
+    },
+}
+
+pub struct Publisher {
+    output: Output,
     shutdown_timeout: Duration,
+    /// The last producer is reserved for slot and control events.
+    priority_lane: bool,
+    /// Connection states of the brokers, if producing to Kafka.
+    brokers: Option<Arc<BrokerStates>>,
+    dead_letters: Option<Arc<DeadLetters>>,
+    /// Where records go while all brokers are down.
+    spool: Option<Mutex<Spool>>,
+    /// Kafka payloads above this size are split into chunks, 0 disables chunking.
+    max_message_bytes: usize,
 
     update_account_topic: String,
+    startup_account_topic: String,
     slot_status_topic: String,
     transaction_topic: String,
+    vote_transaction_topic: String,
+    transaction_status_topic: String,
+    compressed_nft_topic: String,
+    program_upgrade_topic: String,
+    token_balance_topic: String,
+    owner_change_topic: String,
+    block_metadata_topic: String,
+    slot_accounts_complete_topic: String,
+    watermark_topic: String,
+    stats_topic: String,
+    stats_node_name: String,
+    slot_tracker: Option<Arc<SlotTracker>>,
+    encryptor: Option<PayloadEncryptor>,
+    compressor: Option<PayloadCompressor>,
+    /// Partitions of account updates by owner program, Kafka only.
+    partition_pinner: Option<PartitionPinner>,
+    idempotency_key: IdempotencyKeyPlacement,
+    account_payload_format: AccountPayloadFormat,
+    event_schema: EventSchema,
+    /// Added to every record, as headers on Kafka and as `labels` on the JSON sinks.
+    labels: Vec<(String, String)>,
+    /// Topics of account updates by owner program, from the remote allowlist and `topic_rules`.
+    topic_router: Option<TopicRouter>,
+    /// Topic of the account updates `topic_router` does not route, if not `update_account_topic`.
+    unrouted_account_topic: Option<String>,
+    drop_unrouted_accounts: bool,
+    shadow_topic: String,
+    /// Picks the records mirrored to `shadow_topic`, if it mirrors the published stream.
+    shadow_sampler: Option<Sampler>,
+    /// Topics switched over at runtime, by configured name.
+    ///
+    /// Read while writing every record, so no record passes the markers of a switch-over.
+    topic_switches: RwLock<HashMap<String, String>>,
 }
 
 impl Publisher {
-    pub fn new(producer: Producer, config: &Config) -> Self {
+    pub fn new(
+        producers: Vec<Producer>,
+        encryptor: Option<PayloadEncryptor>,
+        config: &Config,
+    ) -> Self {
+        assert!(!producers.is_empty(), "at least one producer is required");
+        let slot_tracker = producers[0].context().tracker().cloned();
+        let brokers = producers[0].context().brokers().clone();
+        let dead_letters = producers[0].context().dead_letters().cloned();
+        let shards = Self::build_shards(producers, config.priority_lane);
+        let output = Output::Kafka(RwLock::new(shards));
+        let mut this = Self::with_output(output, slot_tracker, encryptor, config);
+        this.brokers = Some(brokers);
+        this.dead_letters = dead_letters;
+        this
+    }
+
+    /// Writes Kafka records to `spool` while all brokers are down, see [`Self::replay_spool`].
+    pub fn with_spool(mut self, spool: Spool) -> Self {
+        self.spool = Some(Mutex::new(spool));
+        self
+    }
+
+    /// Compresses Kafka payloads with `compressor`, ahead of any encryption.
+    pub fn with_compressor(mut self, compressor: PayloadCompressor) -> Self {
+        self.compressor = Some(compressor);
+        self
+    }
+
+    /// Labels every record with the `labels` of a tenant.
+    pub fn with_labels(mut self, labels: Vec<(String, String)>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// Sends the account updates of programs `router` routes to their topic instead of `update_account_topic`.
+    pub fn with_topic_router(mut self, router: TopicRouter) -> Self {
+        self.topic_router = Some(router);
+        self
+    }
+
+    /// Publisher writing events as JSON lines to `writer` instead of Kafka.
+    ///
+    /// Events count as delivered once written.
+    pub fn new_json(
+        writer: Box<dyn Write + Send>,
+        slot_tracker: Option<Arc<SlotTracker>>,
+        config: &Config,
+    ) -> Self {
+        let output = Output::Json(Mutex::new(writer));
+        Self::with_output(output, slot_tracker, None, config)
+    }
+
+    /// Publisher writing events to rotating files instead of Kafka.
+    ///
+    /// Events count as delivered once written.
+    pub fn new_files(
+        sink: FileSink,
+        slot_tracker: Option<Arc<SlotTracker>>,
+        config: &Config,
+    ) -> Self {
+        let output = Output::Files {
+            sink,
+            files: Mutex::default(),
+        };
+        Self::with_output(output, slot_tracker, None, config)
+    }
+
+    fn with_output(
+        output: Output,
+        slot_tracker: Option<Arc<SlotTracker>>,
+        encryptor: Option<PayloadEncryptor>,
+        config: &Config,
+    ) -> Self {
         Self {
-            producer,
+            output,
             shutdown_timeout: Duration::from_millis(config.shutdown_timeout_ms),
+            priority_lane: config.priority_lane,
+            brokers: None,
+            dead_letters: None,
+            spool: None,
+            max_message_bytes: config.max_message_bytes,
             update_account_topic: config.update_account_topic.clone(),
+            startup_account_topic: config.startup_account_topic.clone(),
             slot_status_topic: config.slot_status_topic.clone(),
             transaction_topic: config.transaction_topic.clone(),
+            vote_transaction_topic: config.vote_transaction_topic.clone(),
+            transaction_status_topic: config.transaction_status_topic.clone(),
+            compressed_nft_topic: config.compressed_nft_topic.clone(),
+            program_upgrade_topic: config.program_upgrade_topic.clone(),
+            token_balance_topic: config.token_balance_topic.clone(),
+            owner_change_topic: config.owner_change_topic.clone(),
+            block_metadata_topic: config.block_metadata_topic.clone(),
+            slot_accounts_complete_topic: config.slot_accounts_complete_topic.clone(),
+            watermark_topic: config.watermark_topic.clone(),
+            stats_topic: config.stats_topic.clone(),
+            stats_node_name: config.stats_node_name.clone(),
+            slot_tracker,
+            encryptor,
+            compressor: None,
+            partition_pinner: config.partition_pinning.as_ref().map(|pinning| {
+                PartitionPinner::new(pinning).expect("partition pinning is checked on load")
+            }),
+            idempotency_key: config.idempotency_key,
+            account_payload_format: config.account_payload_format,
+            event_schema: config.event_schema,
+            labels: Vec::new(),
+            topic_router: None,
+            unrouted_account_topic: Some(config.unrouted_account_topic.clone())
+                .filter(|topic| !topic.is_empty()),
+            drop_unrouted_accounts: config.drop_unrouted_accounts,
+            shadow_topic: config
+                .shadow
+                .as_ref()
+                .map(|shadow| shadow.topic.clone())
+                .unwrap_or_default(),
+            shadow_sampler: config
+                .shadow
+                .as_ref()
+                .filter(|shadow| shadow.source == ShadowSource::Published)
+                .map(|shadow| Sampler::new(shadow.sample_percent)),
+            topic_switches: RwLock::default(),
         }
     }
 
     pub fn update_account(&self, ev: UpdateAccountEvent) -> Result<(), KafkaError> {
-        let buf = ev.encode_to_vec();
-        let record = BaseRecord::<Vec<u8>, _>::to(&self.update_account_topic)
-            .key(&ev.pubkey)
-            .payload(&buf);
-        self.producer.send(record).map(|_| ()).map_err(|(e, _)| e)
+        self.publish_account(&self.update_account_topic, &ev, false, Vec::new())
+    }
+
+    /// Publishes an account update seen in all of `slots`, listing them in its header.
+    pub fn update_forked_account(
+        &self,
+        ev: UpdateAccountEvent,
+        slots: &[u64],
+    ) -> Result<(), KafkaError> {
+        let headers = if slots.len() > 1 {
+            let slots: Vec<String> = slots.iter().map(u64::to_string).collect();
+            vec![(FORK_SLOTS_HEADER, slots.join(","))]
+        } else {
+            Vec::new()
+        };
+        self.publish_account(&self.update_account_topic, &ev, false, headers)
+    }
+
+    /// Publishes an account of the validator's startup snapshot.
+    pub fn update_startup_account(&self, ev: UpdateAccountEvent) -> Result<(), KafkaError> {
+        self.publish_account(self.account_topic(true), &ev, true, Vec::new())
+    }
+
+    /// Topic of account updates, or of startup accounts with `is_startup`.
+    pub fn account_topic(&self, is_startup: bool) -> &str {
+        if is_startup && !self.startup_account_topic.is_empty() {
+            &self.startup_account_topic
+        } else {
+            &self.update_account_topic
+        }
+    }
+
+    /// Topic `event` is published to.
+    pub fn topic_of(&self, event: &Event) -> &str {
+        match event {
+            Event::UpdateAccount(_) | Event::ForkedAccount(..) | Event::SlotAccountsBatch(_) => {
+                &self.update_account_topic
+            }
+            Event::StartupAccount(_) => self.account_topic(true),
+            Event::SlotStatus(_) => &self.slot_status_topic,
+            Event::Transaction(ev) => self.transaction_topic_for(ev.is_vote),
+            Event::TransactionStatus(_) => &self.transaction_status_topic,
+            Event::CompressedNft(_) => &self.compressed_nft_topic,
+            Event::ProgramUpgraded(_) => &self.program_upgrade_topic,
+            Event::TokenBalance(_) => &self.token_balance_topic,
+            Event::OwnerChanged(_) => &self.owner_change_topic,
+            Event::BlockMetadata(_) => &self.block_metadata_topic,
+            Event::ShadowAccount(_) => &self.shadow_topic,
+        }
+    }
+
+    /// Publishes an account update in the configured `account_payload_format`.
+    fn publish_account(
+        &self,
+        topic: &str,
+        ev: &UpdateAccountEvent,
+        is_startup: bool,
+        mut headers: Vec<(&'static str, String)>,
+    ) -> Result<(), KafkaError> {
+        let routed = match &self.topic_router {
+            Some(router) => match router.route(&ev.owner) {
+                Some(routed) => Some(routed),
+                None => {
+                    metrics().account_updates_unrouted.inc();
+                    if self.drop_unrouted_accounts {
+                        drops().dropped(topic, DropReason::Filtered, "drop_unrouted_accounts");
+                        return Ok(());
+                    }
+                    self.unrouted_account_topic.clone()
+                }
+            },
+            None => None,
+        };
+        let topic = routed.as_deref().unwrap_or(topic);
+        let opaque = self.opaque(ev.slot);
+        let notification = match self.account_payload_format {
+            // The non-Kafka outputs have formats of their own.
+            _ if !matches!(self.output, Output::Kafka(_)) => None,
+            AccountPayloadFormat::Protobuf => None,
+            AccountPayloadFormat::RpcAccount => Some(json::rpc_account_notification(ev)),
+            AccountPayloadFormat::RpcProgram => Some(json::rpc_program_notification(ev)),
+        };
+        let payload = match notification {
+            Some(notification) => {
+                headers.push((CONTENT_TYPE_HEADER, "application/json".to_owned()));
+                Some(serde_json::to_vec(&notification).expect("JSON values always serialize"))
+            }
+            None => self.yellowstone(|| yellowstone::account_update(ev, is_startup, topic)),
+        };
+        // Pinned partitions are those of the configured topic.
+        let partition = match (&self.partition_pinner, &self.output) {
+            (Some(pinner), Output::Kafka(_)) if routed.is_none() => {
+                Some(pinner.partition(&ev.owner, &ev.pubkey))
+            }
+            _ => None,
+        };
+        self.publish_with_headers(
+            Lane::Bulk,
+            topic,
+            Some(&ev.pubkey),
+            &ev.pubkey,
+            opaque,
+            ev,
+            headers,
+            payload.as_deref(),
+            partition,
+        )
+    }
+
+    pub fn update_slot_accounts_batch(&self, ev: SlotAccountsBatch) -> Result<(), KafkaError> {
+        let opaque = self.opaque(ev.slot);
+        self.publish(
+            Lane::Bulk,
+            &self.update_account_topic,
+            None,
+            &ev.slot,
+            opaque,
+            &ev,
+        )
     }
 
     pub fn update_slot_status(&self, ev: SlotStatusEvent) -> Result<(), KafkaError> {
-        let buf = ev.encode_to_vec();
-        let record = BaseRecord::<(), _>::to(&self.slot_status_topic).payload(&buf);
-        self.producer.send(record).map(|_| ()).map_err(|(e, _)| e)
+        let topic = &self.slot_status_topic;
+        let payload = self.yellowstone(|| yellowstone::slot_update(&ev, topic));
+        self.publish_with_headers(
+            Lane::Priority,
+            topic,
+            None,
+            &ev.slot,
+            UNTRACKED,
+            &ev,
+            Vec::new(),
+            payload.as_deref(),
+            None,
+        )
     }
 
     pub fn update_transaction(&self, ev: TransactionEvent) -> Result<(), KafkaError> {
-        let buf = ev.encode_to_vec();
-        let record = BaseRecord::<(), _>::to(&self.transaction_topic).payload(&buf);
-        self.producer.send(record).map(|_| ()).map_err(|(e, _)| e)
+        let topic = self.transaction_topic_for(ev.is_vote);
+        let payload = self.yellowstone(|| yellowstone::transaction_update(&ev, topic));
+        self.publish_with_headers(
+            Lane::Bulk,
+            topic,
+            None,
+            &ev.signature,
+            UNTRACKED,
+            &ev,
+            Vec::new(),
+            payload.as_deref(),
+            None,
+        )
+    }
+
+    /// The encoded Yellowstone `update`, if Kafka records follow the Yellowstone schema.
+    fn yellowstone(
+        &self,
+        update: impl FnOnce() -> yellowstone::geyser::SubscribeUpdate,
+    ) -> Option<Vec<u8>> {
+        match (&self.output, self.event_schema) {
+            (Output::Kafka(_), EventSchema::Yellowstone) => Some(update().encode_to_vec()),
+            _ => None,
+        }
+    }
+
+    pub fn update_transaction_status(&self, ev: TransactionStatusEvent) -> Result<(), KafkaError> {
+        self.publish(
+            Lane::Bulk,
+            &self.transaction_status_topic,
+            Some(&ev.signature),
+            &ev.signature,
+            UNTRACKED,
+            &ev,
+        )
+    }
+
+    /// Publishes a compressed NFT change keyed by the asset ID, so changes of an asset stay in order.
+    pub fn update_compressed_nft(&self, ev: CompressedNftEvent) -> Result<(), KafkaError> {
+        self.publish(
+            Lane::Bulk,
+            &self.compressed_nft_topic,
+            Some(&ev.asset_id),
+            &ev.asset_id,
+            UNTRACKED,
+            &ev,
+        )
+    }
+
+    /// Publishes a program upgrade keyed by the programdata address, which every event has.
+    pub fn update_program_upgraded(&self, ev: ProgramUpgraded) -> Result<(), KafkaError> {
+        self.publish(
+            Lane::Bulk,
+            &self.program_upgrade_topic,
+            Some(&ev.programdata),
+            &ev.programdata,
+            UNTRACKED,
+            &ev,
+        )
+    }
+
+    /// Publishes a token balance change keyed by the token account, like its account updates.
+    pub fn update_token_balance(&self, ev: TokenBalanceChange) -> Result<(), KafkaError> {
+        self.publish(
+            Lane::Bulk,
+            &self.token_balance_topic,
+            Some(&ev.account),
+            &ev.account,
+            UNTRACKED,
+            &ev,
+        )
+    }
+
+    /// Publishes an owner change keyed by the account, like its account updates.
+    pub fn update_owner_changed(&self, ev: OwnerChanged) -> Result<(), KafkaError> {
+        self.publish(
+            Lane::Bulk,
+            &self.owner_change_topic,
+            Some(&ev.account),
+            &ev.account,
+            UNTRACKED,
+            &ev,
+        )
+    }
+
+    pub fn update_block_metadata(&self, ev: BlockMetadataEvent) -> Result<(), KafkaError> {
+        self.publish(
+            Lane::Bulk,
+            &self.block_metadata_topic,
+            None,
+            &ev.slot,
+            UNTRACKED,
+            &ev,
+        )
+    }
+
+    /// Mirrors an unfiltered account update to the shadow topic.
+    pub fn update_shadow_account(&self, ev: UpdateAccountEvent) -> Result<(), KafkaError> {
+        self.write_record(
+            Lane::Bulk,
+            &self.shadow_topic,
+            Some(&ev.pubkey),
+            &ev.pubkey,
+            UNTRACKED,
+            &ev,
+            Vec::new(),
+            None,
+            None,
+        )
+    }
+
+    pub fn update_slot_accounts_complete(
+        &self,
+        ev: SlotAccountsComplete,
+    ) -> Result<(), KafkaError> {
+        self.publish(
+            Lane::Priority,
+            &self.slot_accounts_complete_topic,
+            None,
+            &ev.slot,
+            UNTRACKED,
+            &ev,
+        )
+    }
+
+    pub fn update_watermark(&self, ev: Watermark) -> Result<(), KafkaError> {
+        self.publish(
+            Lane::Priority,
+            &self.watermark_topic,
+            None,
+            &ev.unix_time_ms,
+            UNTRACKED,
+            &ev,
+        )
+    }
+
+    /// Publishes a [`StatsSnapshot`] of the current metrics, keyed by the node name.
+    pub fn update_stats_snapshot(&self) -> Result<(), KafkaError> {
+        let ev = StatsSnapshot::take(self.stats_node_name.clone());
+        self.publish(
+            Lane::Priority,
+            &self.stats_topic,
+            Some(self.stats_node_name.as_bytes()),
+            &ev.node,
+            UNTRACKED,
+            &ev,
+        )
+    }
+
+    /// Records timed out on their way to Kafka, if they are dead-lettered.
+    pub fn dead_letters(&self) -> Option<&Arc<DeadLetters>> {
+        self.dead_letters.as_ref()
+    }
+
+    /// Republishes a timed out record as is to the dead-letter topic,
+    /// adding headers naming the topic it was meant for and why it failed.
+    pub fn publish_dead_letter(&self, letter: DeadLetter) -> Result<(), KafkaError> {
+        let (shards, dead_letters) = match (&self.output, &self.dead_letters) {
+            (Output::Kafka(shards), Some(dead_letters)) => (shards, dead_letters),
+            _ => return Ok(()),
+        };
+        let headers = letter
+            .headers
+            .iter()
+            .fold(OwnedHeaders::new(), |headers, (key, value)| {
+                headers.insert(Header {
+                    key,
+                    value: value.as_deref(),
+                })
+            })
+            .insert(Header {
+                key: DEAD_LETTER_TOPIC_HEADER,
+                value: Some(&letter.topic),
+            })
+            .insert(Header {
+                key: DEAD_LETTER_REASON_HEADER,
+                value: Some(&letter.reason),
+            });
+        let mut record =
+            BaseRecord::with_opaque_to(dead_letters.topic(), UNTRACKED).headers(headers);
+        if let Some(key) = &letter.key {
+            record = record.key(&key[..]);
+        }
+        if let Some(payload) = &letter.payload {
+            record = record.payload(&payload[..]);
+        }
+        let shards = shards.read().unwrap();
+        let bulk = if self.priority_lane {
+            shards.len() - 1
+        } else {
+            shards.len()
+        };
+        let shard = &shards[Self::shard_index(bulk, &letter.key)];
+        self.produce(shard, record, false)?;
+        metrics().dead_letters_published.inc();
+        Ok(())
+    }
+
+    /// Number of messages in the `librdkafka` queues of every producer shard, by shard label.
+    pub fn producer_queue_lengths(&self) -> Vec<(String, i32)> {
+        match &self.output {
+            Output::Kafka(shards) => shards
+                .read()
+                .unwrap()
+                .iter()
+                .map(|shard| (shard.label.clone(), shard.producer.in_flight_count()))
+                .collect(),
+            Output::Json(_) | Output::Files { .. } => Vec::new(),
+        }
+    }
+
+    /// Topics the publisher is configured with.
+    fn configured_topics(&self) -> impl Iterator<Item = &str> {
+        [
+            &self.update_account_topic,
+            &self.startup_account_topic,
+            &self.slot_status_topic,
+            &self.transaction_topic,
+            &self.vote_transaction_topic,
+            &self.transaction_status_topic,
+            &self.compressed_nft_topic,
+            &self.program_upgrade_topic,
+            &self.token_balance_topic,
+            &self.owner_change_topic,
+            &self.block_metadata_topic,
+            &self.slot_accounts_complete_topic,
+            &self.watermark_topic,
+            &self.stats_topic,
+            &self.shadow_topic,
+        ]
+        .into_iter()
+        .chain(&self.unrouted_account_topic)
+        .map(String::as_str)
+        .filter(|topic| !topic.is_empty())
+    }
+
+    /// Redirects the records of the configured topics currently going to `from` to `to`, for all
+    /// publisher threads at once.
+    ///
+    /// Every partition of `from` gets a final [`TopicSwitch`] marker as its last record, and every
+    /// partition of `to` a start marker as its first. The publisher threads are only blocked while
+    /// the topics are swapped and the start markers queued; the final markers are written once the
+    /// records queued for `from` on any shard were delivered. Switches last until the plugin is
+    /// reloaded.
+    pub fn switch_topic(&self, from: &str, to: &str) -> Result<(), String> {
+        if to.is_empty() || to == from {
+            return Err(format!("cannot switch {} over to {:?}", from, to));
+        }
+        // Partitions are looked up before blocking the publisher threads.
+        let (from_partitions, to_partitions) = (self.partitions(from), self.partitions(to));
+
+        let mut switches = self.topic_switches.write().unwrap();
+        let current = |topic: &str| {
+            switches
+                .get(topic)
+                .cloned()
+                .unwrap_or_else(|| topic.to_owned())
+        };
+        let switched: Vec<String> = self
+            .configured_topics()
+            .filter(|topic| current(topic) == from)
+            .map(str::to_owned)
+            .collect();
+        if switched.is_empty() {
+            return Err(format!("{} is not a topic of this pipeline", from));
+        }
+        if self.configured_topics().any(|topic| current(topic) == to) {
+            return Err(format!("{} is already published to", to));
+        }
+
+        let unix_time_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let mut marker = TopicSwitch {
+            from_topic: from.to_owned(),
+            to_topic: to.to_owned(),
+            phase: topic_switch::Phase::Final as i32,
+            unix_time_ms,
+        };
+        for topic in switched {
+            if topic == to {
+                switches.remove(&topic);
+            } else {
+                switches.insert(topic, to.to_owned());
+            }
+        }
+        marker.phase = topic_switch::Phase::Start as i32;
+        let started = self.write_markers(to, to_partitions, &marker);
+        // Records of `from` were all handed to the producers before the swap.
+        drop(switches);
+        metrics().topic_switches.inc();
+        info!("Switched topic {} over to {}", from, to);
+
+        let delivered = self.flush_all(self.shutdown_timeout);
+        marker.phase = topic_switch::Phase::Final as i32;
+        let finished = self.write_markers(from, from_partitions, &marker);
+        let fail = |what: &str, e: &dyn std::fmt::Display| {
+            format!("switched {} over to {}, but {}: {}", from, to, what, e)
+        };
+        started.map_err(|e| fail("failed to publish the start marker", &e))?;
+        delivered.map_err(|e| {
+            fail(
+                "records queued for it may follow its final marker, failed to deliver them",
+                &e,
+            )
+        })?;
+        finished.map_err(|e| fail("failed to publish the final marker", &e))
+    }
+
+    /// Waits for the records queued on every producer shard to be delivered, for up to `timeout`
+    /// in total.
+    ///
+    /// Flushes in short steps, so replacing the producers never waits on it.
+    fn flush_all(&self, timeout: Duration) -> Result<(), KafkaError> {
+        let shards = match &self.output {
+            Output::Kafka(shards) => shards,
+            Output::Json(_) | Output::Files { .. } => return Ok(()),
+        };
+        let started = Instant::now();
+        let mut index = 0;
+        loop {
+            let step = TOPIC_SWITCH_FLUSH_STEP.min(timeout.saturating_sub(started.elapsed()));
+            let flushed = match shards.read().unwrap().get(index) {
+                Some(shard) => shard.producer.flush(step),
+                None => return Ok(()),
+            };
+            match flushed {
+                Ok(()) => index += 1,
+                Err(KafkaError::Flush(RDKafkaErrorCode::OperationTimedOut))
+                    if started.elapsed() < timeout => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Number of partitions of `topic`, if publishing to Kafka and the brokers know the topic.
+    fn partitions(&self, topic: &str) -> Option<i32> {
+        let shards = match &self.output {
+            Output::Kafka(shards) => shards,
+            Output::Json(_) | Output::Files { .. } => return None,
+        };
+        let metadata = shards.read().unwrap()[0]
+            .producer
+            .client()
+            .fetch_metadata(Some(topic), TOPIC_METADATA_TIMEOUT)
+            .map_err(|e| error!("Failed to fetch the partitions of {}: {}", topic, e))
+            .ok()?;
+        let partitions = metadata.topics().first()?.partitions().len() as i32;
+        Some(partitions).filter(|&partitions| partitions > 0)
+    }
+
+    /// Writes `marker` to every one of the `partitions` of `topic`, or once if they are not known.
+    fn write_markers(
+        &self,
+        topic: &str,
+        partitions: Option<i32>,
+        marker: &TopicSwitch,
+    ) -> Result<(), KafkaError> {
+        let phase = format!("{:?}", marker.phase()).to_lowercase();
+        let partitions: Vec<Option<i32>> = match partitions {
+            Some(partitions) => (0..partitions).map(Some).collect(),
+            None => vec![None],
+        };
+        for partition in partitions {
+            self.write_to(
+                Lane::Priority,
+                topic,
+                None,
+                &marker.unix_time_ms,
+                UNTRACKED,
+                marker,
+                vec![(TOPIC_SWITCH_HEADER, phase.clone())],
+                None,
+                partition,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Runs `publish`, timestamping the Kafka records it sends with `timestamp_ms` instead of
+    /// the time they are produced, so delivery latencies include the time spent queued.
+    pub fn stamped<T>(timestamp_ms: i64, publish: impl FnOnce() -> T) -> T {
+        RECORD_TIMESTAMP.with(|timestamp| timestamp.set(Some(timestamp_ms)));
+        let result = publish();
+        RECORD_TIMESTAMP.with(|timestamp| timestamp.set(None));
+        result
+    }
+
+    /// Tracker of account deliveries per slot, if slot completion markers are enabled.
+    pub fn slot_tracker(&self) -> Option<&Arc<SlotTracker>> {
+        self.slot_tracker.as_ref()
+    }
+
+    /// Delivery opaque of an account record of `slot`.
+    fn opaque(&self, slot: u64) -> usize {
+        if self.slot_tracker.is_some() {
+            slot_opaque(slot)
+        } else {
+            UNTRACKED
+        }
+    }
+
+    fn build_shards(producers: Vec<Producer>, priority_lane: bool) -> Vec<ProducerShard> {
+        let priority = if priority_lane {
+            producers.len() - 1
+        } else {
+            producers.len()
+        };
+        producers
+            .into_iter()
+            .enumerate()
+            .map(|(i, producer)| {
+                let shard = if i == priority {
+                    "priority".to_owned()
+                } else {
+                    i.to_string()
+                };
+                ProducerShard {
+                    producer,
+                    sent: labeled_counter("producer_messages_sent", &[("shard", &shard)]),
+                    failed: labeled_counter("producer_messages_failed", &[("shard", &shard)]),
+                    label: shard,
+                }
+            })
+            .collect()
+    }
+
+    /// Swaps in new producers, then drains the replaced ones.
+    ///
+    /// Events keep flowing through the new producers while the old ones flush.
+    pub fn replace_producers(&self, producers: Vec<Producer>) {
+        assert!(!producers.is_empty(), "at least one producer is required");
+        let shards = match &self.output {
+            Output::Kafka(shards) => shards,
+            Output::Json(_) | Output::Files { .. } => return,
+        };
+        let replaced = std::mem::replace(
+            &mut *shards.write().unwrap(),
+            Self::build_shards(producers, self.priority_lane),
+        );
+        Self::flush(&replaced, self.shutdown_timeout);
+    }
+
+    /// Holds back all sends until `producers` yields new producers, then drains the replaced ones.
+    ///
+    /// Events queue up in front of the publisher threads meanwhile.
+    /// The producers are kept if `producers` gives up.
+    pub fn rebuild_producers(&self, producers: impl FnOnce() -> Option<Vec<Producer>>) {
+        let shards = match &self.output {
+            Output::Kafka(shards) => shards,
+            Output::Json(_) | Output::Files { .. } => return,
+        };
+        let mut shards = shards.write().unwrap();
+        let producers = match producers() {
+            Some(producers) => producers,
+            None => return,
+        };
+        assert!(!producers.is_empty(), "at least one producer is required");
+        let replaced = std::mem::replace(
+            &mut *shards,
+            Self::build_shards(producers, self.priority_lane),
+        );
+        drop(shards);
+        Self::flush(&replaced, self.shutdown_timeout);
+    }
+
+    fn flush(shards: &[ProducerShard], timeout: Duration) {
+        for (i, shard) in shards.iter().enumerate() {
+            if let Err(e) = shard.producer.flush(timeout) {
+                error!("Failed to flush producer shard {}: {}", i, e);
+            }
+        }
+    }
+
+    /// Flushes the Kafka producers, sending queued messages without waiting for `linger.ms`.
+    ///
+    /// Gives up after `timeout`, as producers busy with new messages never drain completely.
+    pub fn flush_producers(&self, timeout: Duration) {
+        let shards = match &self.output {
+            Output::Kafka(shards) => shards.read().unwrap(),
+            Output::Json(_) | Output::Files { .. } => return,
+        };
+        let started = Instant::now();
+        for (i, shard) in shards.iter().enumerate() {
+            match shard.producer.flush(timeout) {
+                Ok(()) => {}
+                Err(KafkaError::Flush(RDKafkaErrorCode::OperationTimedOut)) => {
+                    metrics().producer_flush_timeouts.inc()
+                }
+                Err(e) => error!("Failed to flush producer shard {}: {}", i, e),
+            }
+        }
+        metrics().producer_flushes.inc();
+        metrics()
+            .producer_flush_time_us
+            .add(started.elapsed().as_micros() as u64);
+    }
+
+    /// Publishes `ev` to `topic` on whichever output is configured.
+    fn publish<M: Message + Serialize + Idempotent>(
+        &self,
+        lane: Lane,
+        topic: &str,
+        key: Option<&[u8]>,
+        shard_key: &impl Hash,
+        opaque: usize,
+        ev: &M,
+    ) -> Result<(), KafkaError> {
+        self.publish_with_headers(
+            lane,
+            topic,
+            key,
+            shard_key,
+            opaque,
+            ev,
+            Vec::new(),
+            None,
+            None,
+        )
+    }
+
+    /// Like [`Self::publish`], adding `headers` to the record.
+    ///
+    /// Kafka records carry `payload` instead of the encoded event if given, and go to `partition` if given.
+    /// Sampled records are mirrored to the shadow topic too.
+    #[allow(clippy::too_many_arguments)]
+    fn publish_with_headers<M: Message + Serialize + Idempotent>(
+        &self,
+        lane: Lane,
+        topic: &str,
+        key: Option<&[u8]>,
+        shard_key: &impl Hash,
+        opaque: usize,
+        ev: &M,
+        headers: Vec<(&'static str, String)>,
+        payload: Option<&[u8]>,
+        partition: Option<i32>,
+    ) -> Result<(), KafkaError> {
+        let shadow_headers = self
+            .shadow_sampler
+            .filter(|sampler| sampler.sampled(shard_key))
+            .map(|_| {
+                let mut headers = headers.clone();
+                headers.push((SHADOW_SOURCE_TOPIC_HEADER, topic.to_owned()));
+                headers
+            });
+        let result = self.write_record(
+            lane, topic, key, shard_key, opaque, ev, headers, payload, partition,
+        );
+        if let Some(headers) = shadow_headers {
+            // Mirrored records are not tracked, and failing to mirror one fails nothing.
+            let _ = self.write_record(
+                Lane::Bulk,
+                &self.shadow_topic,
+                key,
+                shard_key,
+                UNTRACKED,
+                ev,
+                headers,
+                payload,
+                None,
+            );
+        }
+        result
+    }
+
+    /// Writes a record to `topic`, or the topic it was switched over to, on whichever output is configured.
+    #[allow(clippy::too_many_arguments)]
+    fn write_record<M: Message + Serialize + Idempotent>(
+        &self,
+        lane: Lane,
+        topic: &str,
+        key: Option<&[u8]>,
+        shard_key: &impl Hash,
+        opaque: usize,
+        ev: &M,
+        headers: Vec<(&'static str, String)>,
+        payload: Option<&[u8]>,
+        partition: Option<i32>,
+    ) -> Result<(), KafkaError> {
+        let switches = self.topic_switches.read().unwrap();
+        let topic = switches.get(topic).map_or(topic, String::as_str);
+        self.write_to(
+            lane, topic, key, shard_key, opaque, ev, headers, payload, partition,
+        )
+    }
+
+    /// Writes a record to exactly `topic`.
+    #[allow(clippy::too_many_arguments)]
+    fn write_to<M: Message + Serialize + Idempotent>(
+        &self,
+        lane: Lane,
+        topic: &str,
+        key: Option<&[u8]>,
+        shard_key: &impl Hash,
+        opaque: usize,
+        ev: &M,
+        mut headers: Vec<(&'static str, String)>,
+        payload: Option<&[u8]>,
+        partition: Option<i32>,
+    ) -> Result<(), KafkaError> {
+        let mut record_key = key.map(<[u8]>::to_vec);
+        match self.idempotency_key {
+            IdempotencyKeyPlacement::None => {}
+            IdempotencyKeyPlacement::Header => {
+                headers.push((IDEMPOTENCY_KEY_HEADER, ev.idempotency_key()))
+            }
+            IdempotencyKeyPlacement::Key => record_key = Some(ev.idempotency_key().into_bytes()),
+        }
+        match &self.output {
+            Output::Kafka(shards) => {
+                let mut headers: Vec<(&str, String)> = headers;
+                headers.extend(
+                    self.labels
+                        .iter()
+                        .map(|(name, value)| (name.as_str(), value.clone())),
+                );
+                let send = |buf: &[u8]| {
+                    let mut record = BaseRecord::with_opaque_to(topic, opaque).payload(buf);
+                    if let Some(key) = &record_key {
+                        record = record.key(&key[..]);
+                    }
+                    if let Some(partition) = partition {
+                        record = record.partition(partition);
+                    }
+                    if let Some(timestamp) = RECORD_TIMESTAMP.with(Cell::get) {
+                        record = record.timestamp(timestamp);
+                    }
+                    self.send(&shards.read().unwrap(), lane, shard_key, record, &headers)
+                };
+                match payload {
+                    Some(payload) => send(payload),
+                    None => with_encoded(ev, send),
+                }
+            }
+            Output::Json(writer) => {
+                let mut line = serde_json::json!({ "topic": topic, "event": ev });
+                if let (IdempotencyKeyPlacement::Key, Some(key)) =
+                    (self.idempotency_key, record_key)
+                {
+                    line["idempotency_key"] = String::from_utf8_lossy(&key).into();
+                }
+                for (name, value) in headers {
+                    line[name.replace('-', "_")] = value.into();
+                }
+                if !self.labels.is_empty() {
+                    let labels: serde_json::Map<String, serde_json::Value> = self
+                        .labels
+                        .iter()
+                        .map(|(name, value)| (name.clone(), value.clone().into()))
+                        .collect();
+                    line["labels"] = labels.into();
+                }
+                let mut writer = writer.lock().unwrap();
+                let written = write_json_line(&mut *writer, &line);
+                self.written(topic, opaque, written.map(|_| ()))
+            }
+            Output::Files { sink, files } => {
+                let mut files = files.lock().unwrap();
+                let written = match files.get_mut(topic) {
+                    Some(file) => Ok(file),
+                    None => RotatingFile::new(
+                        Path::new(&sink.directory).join(topic),
+                        "events-",
+                        sink.format.suffix(),
+                        sink.rotation.clone(),
+                    )
+                    .map(|file| files.entry(topic.to_owned()).or_insert(file)),
+                }
+                .and_then(|file| {
+                    file.append(|writer| match sink.format {
+                        FileFormat::Json => write_json_line(writer, ev),
+                        FileFormat::Protobuf => with_encoded(ev, |buf| {
+                            writer.write_all(&(buf.len() as u32).to_be_bytes())?;
+                            writer.write_all(buf)?;
+                            Ok(4 + buf.len())
+                        }),
+                    })
+                });
+                self.written(topic, opaque, written.map(|_| ()))
+            }
+        }
+    }
+
+    /// Reports the outcome of writing a record to a non-Kafka output.
+    fn written(
+        &self,
+        topic: &str,
+        opaque: usize,
+        written: std::io::Result<()>,
+    ) -> Result<(), KafkaError> {
+        self.finished(opaque, written.is_ok());
+        written.map_err(|e| {
+            drops().dropped(topic, DropReason::ProduceFailed, NO_FILTER);
+            limited_error!("Failed to write event: {}", e);
+            KafkaError::MessageProduction(RDKafkaErrorCode::Fail)
+        })
+    }
+
+    /// Reports the outcome of a record carrying `opaque` to the slot tracker.
+    fn finished(&self, opaque: usize, delivered: bool) {
+        if let (Some(tracker), Some(slot)) = (&self.slot_tracker, opaque_slot(opaque)) {
+            tracker.finished(slot, delivered);
+        }
+    }
+
+    /// Sends `record` through the producer shard of `lane` picked by `shard_key`, adding `extra_headers`.
+    fn send(
+        &self,
+        shards: &[ProducerShard],
+        lane: Lane,
+        shard_key: &impl Hash,
+        record: BaseRecord<[u8], [u8], usize>,
+        extra_headers: &[(&str, String)],
+    ) -> Result<(), KafkaError> {
+        let index = match (self.priority_lane, lane) {
+            (false, _) => Self::shard_index(shards.len(), shard_key),
+            (true, Lane::Priority) => shards.len() - 1,
+            (true, Lane::Bulk) => Self::shard_index(shards.len() - 1, shard_key),
+        };
+        let shard = &shards[index];
+
+        let compressed;
+        let mut compression_headers = Vec::new();
+        let record = match &self.compressor {
+            None => record,
+            Some(compressor) => match profiled(ProfileSection::Compress, || {
+                compressor.compress(record.payload.unwrap_or_default())
+            }) {
+                Ok((payload, headers)) => {
+                    let metrics = metrics();
+                    let before = record.payload.unwrap_or_default().len() as u64;
+                    metrics.payload_bytes_before_compression.add(before);
+                    metrics
+                        .payload_bytes_after_compression
+                        .add(payload.len() as u64);
+                    compressed = payload;
+                    compression_headers = headers;
+                    record.payload(&compressed[..])
+                }
+                Err(e) => {
+                    drops().dropped(record.topic, DropReason::EncodingFailed, NO_FILTER);
+                    limited_error!("Failed to compress payload: {}", e);
+                    shard.failed.inc();
+                    self.finished(record.delivery_opaque, false);
+                    return Err(KafkaError::MessageProduction(RDKafkaErrorCode::Fail));
+                }
+            },
+        };
+
+        let sealed;
+        let mut headers = None;
+        let record = match &self.encryptor {
+            None => record,
+            Some(encryptor) => match profiled(ProfileSection::Encrypt, || {
+                encryptor.seal(record.payload.unwrap_or_default())
+            }) {
+                Ok((payload, encryption_headers)) => {
+                    sealed = payload;
+                    headers = Some(encryption_headers);
+                    record.payload(&sealed[..])
+                }
+                Err(e) => {
+                    drops().dropped(record.topic, DropReason::EncodingFailed, NO_FILTER);
+                    limited_error!("Failed to encrypt payload: {}", e);
+                    shard.failed.inc();
+                    self.finished(record.delivery_opaque, false);
+                    return Err(KafkaError::MessageProduction(RDKafkaErrorCode::Fail));
+                }
+            },
+        };
+        for (key, value) in compression_headers.iter().chain(extra_headers) {
+            headers = Some(headers.unwrap_or_else(OwnedHeaders::new).insert(Header {
+                key,
+                value: Some(value),
+            }));
+        }
+        let record = match headers {
+            Some(headers) => record.headers(headers),
+            None => record,
+        };
+
+        let payload = record.payload.unwrap_or_default();
+        if self.max_message_bytes > 0 && payload.len() > self.max_message_bytes {
+            return self.send_chunks(shard, index, record);
+        }
+        self.deliver(shard, index, record)
+    }
+
+    /// Splits the payload of `record` into chunks of at most `max_message_bytes`, each sent as
+    /// its own record carrying the original headers plus the chunk headers.
+    ///
+    /// Every chunk reports its own outcome to the slot tracker. Chunks of keyless records are keyed
+    /// by their chunk id, so the partitioner keeps them together as it does with keyed ones.
+    fn send_chunks(
+        &self,
+        shard: &ProducerShard,
+        index: usize,
+        record: BaseRecord<[u8], [u8], usize>,
+    ) -> Result<(), KafkaError> {
+        let chunks = chunk::split(record.payload.unwrap_or_default(), self.max_message_bytes);
+        let opaque = record.delivery_opaque;
+        if let (Some(tracker), Some(slot)) = (&self.slot_tracker, opaque_slot(opaque)) {
+            for _ in 1..chunks.len() {
+                tracker.dispatched(slot);
+            }
+        }
+        metrics().chunked_messages.inc();
+
+        let mut result = Ok(());
+        for (info, chunk) in chunks {
+            if result.is_err() {
+                self.finished(opaque, false);
+                continue;
+            }
+            let headers = record.headers.iter().flat_map(|headers| headers.iter());
+            let headers = headers.fold(OwnedHeaders::new(), |headers, header| {
+                headers.insert(Header {
+                    key: header.key,
+                    value: header.value,
+                })
+            });
+            let headers = info
+                .headers()
+                .iter()
+                .fold(headers, |headers, (key, value)| {
+                    headers.insert(Header {
+                        key,
+                        value: Some(value),
+                    })
+                });
+            let mut chunked = BaseRecord::with_opaque_to(record.topic, opaque)
+                .payload(chunk)
+                .headers(headers);
+            chunked.key = record.key.or(Some(info.id.as_bytes()));
+            chunked.partition = record.partition;
+            chunked.timestamp = record.timestamp;
+            result = self.deliver(shard, index, chunked);
+            if result.is_ok() {
+                metrics().message_chunks_sent.inc();
+            }
+        }
+        result
+    }
+
+    /// Hands `record` to the producer of `shard`, the `index`th shard, or to the spool while
+    /// it is in use.
+    fn deliver(
+        &self,
+        shard: &ProducerShard,
+        index: usize,
+        record: BaseRecord<[u8], [u8], usize>,
+    ) -> Result<(), KafkaError> {
+        if let Some(spool) = &self.spool {
+            let mut spool = spool.lock().unwrap();
+            if spool.is_active() || self.brokers.as_ref().map_or(false, |b| b.is_all_down()) {
+                return self.spool_record(&mut spool, shard, index, &record);
+            }
+        }
+        self.produce(shard, record, false)
+    }
+
+    /// Hands `record` to the producer of `shard` within the in-flight budget.
+    ///
+    /// With `wait`, waits for room in the budget and the producer queue instead of failing.
+    fn produce(
+        &self,
+        shard: &ProducerShard,
+        mut record: BaseRecord<[u8], [u8], usize>,
+        wait: bool,
+    ) -> Result<(), KafkaError> {
+        let budget = shard.producer.context().in_flight_budget();
+        let buffered = shard.producer.context().buffered_bytes();
+        let size = record_size(record.key, record.payload);
+        if let Some(budget) = budget {
+            if wait {
+                budget.wait(size);
+            } else if !budget.acquire(size) {
+                drops().dropped(record.topic, DropReason::InFlightBudget, NO_FILTER);
+                shard.failed.inc();
+                self.finished(record.delivery_opaque, false);
+                return Err(KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull));
+            }
+        }
+        buffered.add(record.topic, size);
+        loop {
+            let sent = {
+                let _scope = profile_scope(ProfileSection::Produce);
+                shard.producer.send(record)
+            };
+            match sent {
+                Ok(()) => {
+                    shard.sent.inc();
+                    return Ok(());
+                }
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), full)) if wait => {
+                    record = full;
+                    thread::sleep(QUEUE_FULL_BACKOFF);
+                }
+                Err((e, record)) => {
+                    drops().dropped(record.topic, DropReason::ProduceFailed, NO_FILTER);
+                    buffered.release(record.topic, size);
+                    if let Some(budget) = budget {
+                        budget.release(size);
+                    }
+                    shard.failed.inc();
+                    self.finished(record.delivery_opaque, false);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Writes `record` to the spool instead of the producer of `shard`, the `index`th shard.
+    fn spool_record(
+        &self,
+        spool: &mut Spool,
+        shard: &ProducerShard,
+        index: usize,
+        record: &BaseRecord<[u8], [u8], usize>,
+    ) -> Result<(), KafkaError> {
+        let headers = record.headers.as_ref().map_or_else(Vec::new, |headers| {
+            (0..headers.count())
+                .map(|i| {
+                    let header = headers.get(i);
+                    (header.key.to_owned(), header.value.map(<[u8]>::to_vec))
+                })
+                .collect()
+        });
+        let spooled = SpooledRecord {
+            topic: record.topic.to_owned(),
+            key: record.key.map(<[u8]>::to_vec),
+            partition: record.partition,
+            headers,
+            payload: record.payload.unwrap_or_default().to_vec(),
+            opaque: record.delivery_opaque,
+            shard: index,
+        };
+        match spool.append(&spooled) {
+            Ok(()) => {
+                metrics().records_spooled.inc();
+                Ok(())
+            }
+            Err(e) => {
+                drops().dropped(record.topic, DropReason::ProduceFailed, NO_FILTER);
+                limited_error!("Failed to spool record: {}", e);
+                shard.failed.inc();
+                self.finished(record.delivery_opaque, false);
+                Err(KafkaError::MessageProduction(RDKafkaErrorCode::Fail))
+            }
+        }
+    }
+
+    /// Hands the spooled records to the producers, oldest first, unless all brokers are down.
+    ///
+    /// Returns the number of records replayed.
+    pub fn replay_spool(&self) -> u64 {
+        let (spool, shards) = match (&self.spool, &self.output) {
+            (Some(spool), Output::Kafka(shards)) => (spool, shards),
+            _ => return 0,
+        };
+        if self.brokers.as_ref().map_or(false, |b| b.is_all_down()) {
+            return 0;
+        }
+        let files = match spool.lock().unwrap().take_files() {
+            Ok(files) => files,
+            Err(e) => {
+                error!("Failed to list spool files: {}", e);
+                return 0;
+            }
+        };
+        let mut replayed = 0;
+        for file in files {
+            let shards = shards.read().unwrap();
+            let read = read_spool_file(&file.path, |spooled| {
+                let opaque = if file.inherited {
+                    UNTRACKED
+                } else {
+                    spooled.opaque
+                };
+                let mut record = BaseRecord::with_opaque_to(&spooled.topic, opaque)
+                    .payload(&spooled.payload[..]);
+                record.partition = spooled.partition;
+                if let Some(key) = &spooled.key {
+                    record = record.key(&key[..]);
+                }
+                if !spooled.headers.is_empty() {
+                    let headers = spooled.headers.iter().fold(
+                        OwnedHeaders::new(),
+                        |headers, (key, value)| {
+                            headers.insert(Header {
+                                key,
+                                value: value.as_deref(),
+                            })
+                        },
+                    );
+                    record = record.headers(headers);
+                }
+                let shard = &shards[spooled.shard % shards.len()];
+                if self.produce(shard, record, true).is_ok() {
+                    replayed += 1;
+                }
+            });
+            drop(shards);
+            // A truncated last record is all an interrupted write leaves behind.
+            if let Err(e) = read {
+                error!("Failed to read spool file {}: {}", file.path.display(), e);
+            }
+            if let Err(e) = std::fs::remove_file(&file.path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    error!("Failed to remove spool file {}: {}", file.path.display(), e);
+                }
+            }
+        }
+        metrics().records_unspooled.add(replayed);
+        replayed
+    }
+
+    fn shard_index(shards: usize, key: &impl Hash) -> usize {
+        if shards == 1 {
+            return 0;
+        }
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % shards as u64) as usize
     }
 
     pub fn wants_update_account(&self) -> bool {
         !self.update_account_topic.is_empty()
     }
 
+    pub fn wants_startup_account(&self) -> bool {
+        !self.startup_account_topic.is_empty() || self.wants_update_account()
+    }
+
     pub fn wants_slot_status(&self) -> bool {
         !self.slot_status_topic.is_empty()
     }
 
     pub fn wants_transaction(&self) -> bool {
-        !self.transaction_topic.is_empty()
+        !self.transaction_topic.is_empty() || !self.vote_transaction_topic.is_empty()
+    }
+
+    /// Whether vote or non-vote transactions are published at all.
+    pub fn wants_transaction_kind(&self, is_vote: bool) -> bool {
+        !self.transaction_topic_for(is_vote).is_empty()
+    }
+
+    /// Vote transactions go to their own topic if one is configured.
+    pub fn transaction_topic_for(&self, is_vote: bool) -> &str {
+        if is_vote && !self.vote_transaction_topic.is_empty() {
+            &self.vote_transaction_topic
+        } else {
+            &self.transaction_topic
+        }
+    }
+
+    pub fn wants_transaction_status(&self) -> bool {
+        !self.transaction_status_topic.is_empty()
+    }
+
+    pub fn transaction_status_topic(&self) -> &str {
+        &self.transaction_status_topic
+    }
+
+    pub fn wants_compressed_nft(&self) -> bool {
+        !self.compressed_nft_topic.is_empty()
+    }
+
+    pub fn compressed_nft_topic(&self) -> &str {
+        &self.compressed_nft_topic
+    }
+
+    pub fn wants_program_upgrade(&self) -> bool {
+        !self.program_upgrade_topic.is_empty()
+    }
+
+    pub fn wants_token_balance(&self) -> bool {
+        !self.token_balance_topic.is_empty()
+    }
+
+    pub fn wants_owner_changed(&self) -> bool {
+        !self.owner_change_topic.is_empty()
+    }
+
+    pub fn wants_block_metadata(&self) -> bool {
+        !self.block_metadata_topic.is_empty()
+    }
+
+    pub fn wants_slot_accounts_complete(&self) -> bool {
+        !self.slot_accounts_complete_topic.is_empty()
+    }
+
+    pub fn wants_watermark(&self) -> bool {
+        !self.watermark_topic.is_empty()
+    }
+
+    pub fn wants_stats_snapshot(&self) -> bool {
+        !self.stats_topic.is_empty()
     }
 }
 
 impl Drop for Publisher {
     fn drop(&mut self) {
-        self.producer.flush(self.shutdown_timeout);
+        match &self.output {
+            Output::Kafka(shards) => Self::flush(&shards.read().unwrap(), self.shutdown_timeout),
+            Output::Json(writer) => {
+                if let Err(e) = writer.lock().unwrap().flush() {
+                    error!("Failed to flush events: {}", e);
+                }
+            }
+            // Each file flushes when dropped.
+            Output::Files { .. } => {}
+        }
+    }
+}
+
+/// Writes `value` as one line of JSON, returning the bytes written.
+fn write_json_line(writer: &mut impl Write, value: &impl Serialize) -> std::io::Result<usize> {
+    let line = profiled(ProfileSection::Serialize, || serde_json::to_vec(value))?;
+    writer.write_all(&line)?;
+    writer.write_all(b"\n")?;
+    Ok(line.len() + 1)
+}
+
+impl FileFormat {
+    fn suffix(self) -> &'static str {
+        match self {
+            Self::Json => ".jsonl",
+            Self::Protobuf => ".pb",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_encoded_reuses_buffer() {
+        let ev = SlotStatusEvent {
+            slot: 42,
+            parent: 41,
+            status: SlotStatus::Rooted.into(),
+            ..SlotStatusEvent::default()
+        };
+
+        let decoded = with_encoded(&ev, |buf| SlotStatusEvent::decode(buf).unwrap());
+        assert_eq!(decoded, ev);
+
+        let reused = metrics().encode_buffers_reused.get();
+        let len = with_encoded(&ev, |buf| buf.len());
+        assert_eq!(len, ev.encoded_len());
+        assert!(metrics().encode_buffers_reused.get() > reused);
+    }
+
+    /// Writer whose output stays readable after it is handed to the publisher.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_output() {
+        let config = Config {
+            update_account_topic: "accounts".to_owned(),
+            slot_status_topic: "slots".to_owned(),
+            ..Config::default()
+        };
+        let buffer = SharedBuffer::default();
+        let tracker = Arc::new(SlotTracker::new());
+        let publisher =
+            Publisher::new_json(Box::new(buffer.clone()), Some(tracker.clone()), &config);
+
+        tracker.dispatched(9);
+        let account = UpdateAccountEvent {
+            slot: 9,
+            pubkey: vec![0; 32].into(),
+            ..UpdateAccountEvent::default()
+        };
+        publisher.update_account(account).unwrap();
+        tracker.close(9);
+        let slot = SlotStatusEvent {
+            slot: 9,
+            ..SlotStatusEvent::default()
+        };
+        publisher.update_slot_status(slot).unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["topic"], "accounts");
+        assert_eq!(
+            lines[0]["event"]["pubkey"],
+            "11111111111111111111111111111111"
+        );
+        assert_eq!(lines[1]["topic"], "slots");
+        assert_eq!(lines[1]["event"]["status"], "processed");
+
+        let complete = tracker.completed().try_recv().unwrap();
+        assert_eq!(complete.messages_delivered, 1);
+    }
+
+    #[test]
+    fn test_stats_snapshot() {
+        let config = Config {
+            stats_topic: "stats".to_owned(),
+            stats_node_name: "validator-1".to_owned(),
+            ..Config::default()
+        };
+        let buffer = SharedBuffer::default();
+        let publisher = Publisher::new_json(Box::new(buffer.clone()), None, &config);
+        metrics().chunked_messages.inc();
+        publisher.update_stats_snapshot().unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let line: serde_json::Value = serde_json::from_str(output.trim_end()).unwrap();
+        assert_eq!(line["topic"], "stats");
+        assert_eq!(line["event"]["node"], "validator-1");
+        assert_eq!(line["event"]["plugin_version"], env!("CARGO_PKG_VERSION"));
+        assert!(
+            line["event"]["metrics"]["chunked_messages"]
+                .as_u64()
+                .unwrap()
+                >= 1
+        );
+    }
+
+    #[test]
+    fn test_protobuf_files_output() {
+        let dir = std::env::temp_dir().join(format!("kafka-plugin-files-{}", std::process::id()));
+        let config = Config {
+            slot_status_topic: "slots".to_owned(),
+            ..Config::default()
+        };
+        let sink = FileSink {
+            directory: dir.to_str().unwrap().to_owned(),
+            format: FileFormat::Protobuf,
+            rotation: Rotation::default(),
+        };
+        let publisher = Publisher::new_files(sink, None, &config);
+        for slot in 0..3 {
+            let ev = SlotStatusEvent {
+                slot,
+                ..SlotStatusEvent::default()
+            };
+            publisher.update_slot_status(ev).unwrap();
+        }
+        drop(publisher);
+
+        let files: Vec<_> = std::fs::read_dir(dir.join("slots"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        let mut data = &std::fs::read(&files[0]).unwrap()[..];
+        let mut slots = Vec::new();
+        while !data.is_empty() {
+            let len = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+            slots.push(SlotStatusEvent::decode(&data[4..4 + len]).unwrap().slot);
+            data = &data[4 + len..];
+        }
+        assert_eq!(slots, [0, 1, 2]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_idempotency_key() {
+        let config = Config {
+            update_account_topic: "accounts".to_owned(),
+            idempotency_key: IdempotencyKeyPlacement::Header,
+            ..Config::default()
+        };
+        let buffer = SharedBuffer::default();
+        let publisher = Publisher::new_json(Box::new(buffer.clone()), None, &config);
+        let account = UpdateAccountEvent {
+            slot: 9,
+            pubkey: vec![0; 32].into(),
+            write_version: 3,
+            ..UpdateAccountEvent::default()
+        };
+        publisher.update_account(account.clone()).unwrap();
+        publisher.update_account(account).unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let keys: Vec<String> = output
+            .lines()
+            .map(|line| {
+                let line: serde_json::Value = serde_json::from_str(line).unwrap();
+                line["idempotency_key"].as_str().unwrap().to_owned()
+            })
+            .collect();
+        assert_eq!(keys, ["9:11111111111111111111111111111111:3"; 2]);
+    }
+
+    #[test]
+    fn test_labels() {
+        let config = Config {
+            slot_status_topic: "slots".to_owned(),
+            ..Config::default()
+        };
+        let buffer = SharedBuffer::default();
+        let publisher = Publisher::new_json(Box::new(buffer.clone()), None, &config)
+            .with_labels(vec![("tenant".to_owned(), "acme".to_owned())]);
+        publisher
+            .update_slot_status(SlotStatusEvent::default())
+            .unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let line: serde_json::Value = serde_json::from_str(output.trim_end()).unwrap();
+        assert_eq!(line["labels"], serde_json::json!({ "tenant": "acme" }));
+    }
+
+    #[test]
+    fn test_switch_topic() {
+        let config = Config {
+            slot_status_topic: "slots".to_owned(),
+            transaction_status_topic: "statuses".to_owned(),
+            ..Config::default()
+        };
+        let buffer = SharedBuffer::default();
+        let publisher = Publisher::new_json(Box::new(buffer.clone()), None, &config);
+        let slot = |slot| SlotStatusEvent {
+            slot,
+            ..SlotStatusEvent::default()
+        };
+        publisher.update_slot_status(slot(1)).unwrap();
+        publisher.switch_topic("slots", "slots.v2").unwrap();
+        publisher.update_slot_status(slot(2)).unwrap();
+
+        assert!(publisher.switch_topic("slots", "slots.v3").is_err());
+        assert!(publisher.switch_topic("slots.v2", "statuses").is_err());
+        assert!(publisher.switch_topic("slots.v2", "").is_err());
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let topics: Vec<&str> = lines
+            .iter()
+            .map(|line| line["topic"].as_str().unwrap())
+            .collect();
+        // The start marker is written before the publisher threads resume, the final one after.
+        assert_eq!(topics, ["slots", "slots.v2", "slots", "slots.v2"]);
+        assert_eq!(lines[1]["topic_switch"], "start");
+        assert_eq!(lines[2]["topic_switch"], "final");
+        assert_eq!(lines[2]["event"]["phase"], "final");
+        assert_eq!(lines[2]["event"]["to_topic"], "slots.v2");
+        assert_eq!(lines[3]["event"]["slot"], 2);
+
+        // Switching back restores the configured topic.
+        publisher.switch_topic("slots.v2", "slots").unwrap();
+        assert!(publisher.topic_switches.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_shadow() {
+        let publish = |source: ShadowSource| {
+            let config = Config {
+                slot_status_topic: "slots".to_owned(),
+                shadow: Some(ShadowConfig {
+                    topic: "shadow".to_owned(),
+                    sample_percent: 100.0,
+                    source,
+                }),
+                ..Config::default()
+            };
+            let buffer = SharedBuffer::default();
+            let publisher = Publisher::new_json(Box::new(buffer.clone()), None, &config);
+            publisher
+                .update_slot_status(SlotStatusEvent::default())
+                .unwrap();
+            publisher
+                .update_shadow_account(UpdateAccountEvent::default())
+                .unwrap();
+            let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+            output
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect::<Vec<serde_json::Value>>()
+        };
+
+        let lines = publish(ShadowSource::Published);
+        let topics: Vec<&str> = lines
+            .iter()
+            .map(|line| line["topic"].as_str().unwrap())
+            .collect();
+        assert_eq!(topics, ["slots", "shadow", "shadow"]);
+        assert_eq!(lines[1]["shadow_source_topic"], "slots");
+        assert_eq!(lines[1]["event"], lines[0]["event"]);
+
+        let lines = publish(ShadowSource::Unfiltered);
+        let topics: Vec<&str> = lines
+            .iter()
+            .map(|line| line["topic"].as_str().unwrap())
+            .collect();
+        assert_eq!(topics, ["slots", "shadow"]);
+    }
+
+    #[test]
+    fn test_topic_routes() {
+        let publish = |config: Config| {
+            let buffer = SharedBuffer::default();
+            let routes = TopicRoutes::default();
+            let router = TopicRouter::new(Some(routes.clone()), &[]);
+            let publisher = Publisher::new_json(Box::new(buffer.clone()), None, &config)
+                .with_topic_router(router);
+            let route = ProgramRoute {
+                topic: "routed".to_owned(),
+                labels: Vec::new(),
+            };
+            routes.replace([([1; 32], route)].into_iter().collect());
+            for owner in [[1; 32], [2; 32]] {
+                let account = UpdateAccountEvent {
+                    pubkey: vec![0; 32].into(),
+                    owner: owner.to_vec().into(),
+                    ..UpdateAccountEvent::default()
+                };
+                publisher.update_account(account).unwrap();
+            }
+
+            let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+            output
+                .lines()
+                .map(|line| {
+                    let line: serde_json::Value = serde_json::from_str(line).unwrap();
+                    line["topic"].as_str().unwrap().to_owned()
+                })
+                .collect::<Vec<String>>()
+        };
+        let config = Config {
+            update_account_topic: "accounts".to_owned(),
+            ..Config::default()
+        };
+        assert_eq!(publish(config.clone()), ["routed", "accounts"]);
+
+        let unrouted = metrics().account_updates_unrouted.get();
+        let fallback = Config {
+            unrouted_account_topic: "unrouted".to_owned(),
+            ..config.clone()
+        };
+        assert_eq!(publish(fallback), ["routed", "unrouted"]);
+        let dropping = Config {
+            drop_unrouted_accounts: true,
+            ..config
+        };
+        assert_eq!(publish(dropping), ["routed"]);
+        assert!(metrics().account_updates_unrouted.get() >= unrouted + 2);
+    }
+
+    #[test]
+    fn test_startup_account_topic() {
+        let mut config = Config {
+            update_account_topic: "accounts".to_owned(),
+            ..Config::default()
+        };
+        let buffer = SharedBuffer::default();
+        let publisher = Publisher::new_json(Box::new(buffer.clone()), None, &config);
+        publisher
+            .update_startup_account(UpdateAccountEvent::default())
+            .unwrap();
+        config.startup_account_topic = "accounts.latest".to_owned();
+        let publisher = Publisher::new_json(Box::new(buffer.clone()), None, &config);
+        publisher
+            .update_startup_account(UpdateAccountEvent::default())
+            .unwrap();
+        publisher
+            .update_account(UpdateAccountEvent::default())
+            .unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let topics: Vec<String> = output
+            .lines()
+            .map(|line| {
+                let line: serde_json::Value = serde_json::from_str(line).unwrap();
+                line["topic"].as_str().unwrap().to_owned()
+            })
+            .collect();
+        assert_eq!(topics, ["accounts", "accounts.latest", "accounts"]);
     }
 }
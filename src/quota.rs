@@ -0,0 +1,117 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Token buckets bounding the messages and bytes per second of a tenant.
+///
+/// Each bucket holds up to one second's worth, so short bursts pass. A full bucket admits
+/// one record of any size, which may leave it in debt.
+pub struct Quota {
+    max_messages_per_sec: u64,
+    max_bytes_per_sec: u64,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    messages: f64,
+    bytes: f64,
+    refilled: Instant,
+}
+
+impl Quota {
+    /// Quota of the given rates, 0 leaving a rate unbounded. `None` if both are unbounded.
+    pub fn new(max_messages_per_sec: u64, max_bytes_per_sec: u64) -> Option<Self> {
+        if max_messages_per_sec == 0 && max_bytes_per_sec == 0 {
+            return None;
+        }
+        Some(Self {
+            max_messages_per_sec,
+            max_bytes_per_sec,
+            buckets: Mutex::new(Buckets {
+                messages: max_messages_per_sec as f64,
+                bytes: max_bytes_per_sec as f64,
+                refilled: Instant::now(),
+            }),
+        })
+    }
+
+    /// Takes a record of `bytes` from the buckets, or returns the option it exceeds.
+    pub fn admit(&self, bytes: usize) -> Result<(), &'static str> {
+        self.admit_at(bytes, Instant::now())
+    }
+
+    fn admit_at(&self, bytes: usize, now: Instant) -> Result<(), &'static str> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let elapsed = now
+            .checked_duration_since(buckets.refilled)
+            .unwrap_or(Duration::ZERO)
+            .as_secs_f64();
+        buckets.refilled = buckets.refilled.max(now);
+        let messages_rate = self.max_messages_per_sec as f64;
+        let bytes_rate = self.max_bytes_per_sec as f64;
+        buckets.messages = (buckets.messages + elapsed * messages_rate).min(messages_rate);
+        buckets.bytes = (buckets.bytes + elapsed * bytes_rate).min(bytes_rate);
+
+        if self.max_messages_per_sec > 0 && buckets.messages < 1.0 {
+            return Err("max_messages_per_sec");
+        }
+        let bytes = bytes as f64;
+        if self.max_bytes_per_sec > 0 && buckets.bytes < bytes && buckets.bytes < bytes_rate {
+            return Err("max_bytes_per_sec");
+        }
+        if self.max_messages_per_sec > 0 {
+            buckets.messages -= 1.0;
+        }
+        if self.max_bytes_per_sec > 0 {
+            buckets.bytes -= bytes;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota() {
+        assert!(Quota::new(0, 0).is_none());
+
+        let quota = Quota::new(2, 0).unwrap();
+        let start = quota.buckets.lock().unwrap().refilled;
+        assert_eq!(quota.admit_at(1 << 20, start), Ok(()));
+        assert_eq!(quota.admit_at(1 << 20, start), Ok(()));
+        assert_eq!(quota.admit_at(1, start), Err("max_messages_per_sec"));
+        let later = start + Duration::from_millis(500);
+        assert_eq!(quota.admit_at(1, later), Ok(()));
+        assert_eq!(quota.admit_at(1, later), Err("max_messages_per_sec"));
+
+        let quota = Quota::new(0, 100).unwrap();
+        let start = quota.buckets.lock().unwrap().refilled;
+        assert_eq!(quota.admit_at(60, start), Ok(()));
+        assert_eq!(quota.admit_at(60, start), Err("max_bytes_per_sec"));
+        assert_eq!(quota.admit_at(40, start), Ok(()));
+        // A full bucket admits a record larger than a second's worth.
+        let later = start + Duration::from_secs(2);
+        assert_eq!(quota.admit_at(250, later), Ok(()));
+        assert_eq!(
+            quota.admit_at(1, later + Duration::from_secs(1)),
+            Err("max_bytes_per_sec")
+        );
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    std::{
+        collections::{HashMap, VecDeque},
+        mem::size_of,
+    },
+};
+
+/// A value remembered for each of the most recently used accounts, evicting the least recently used.
+pub struct RecentAccounts<V> {
+    capacity: usize,
+    tick: u64,
+    /// Value and last use of every cached account.
+    values: HashMap<[u8; 32], (V, u64)>,
+    /// Accounts in order of use. Entries superseded by a later use are skipped on eviction.
+    recency: VecDeque<([u8; 32], u64)>,
+    memory: MemoryUsage,
+}
+
+impl<V> RecentAccounts<V> {
+    pub fn new(capacity: usize, component: MemoryComponent) -> Self {
+        assert!(capacity > 0, "capacity must be positive");
+        Self {
+            capacity,
+            tick: 0,
+            values: HashMap::new(),
+            recency: VecDeque::new(),
+            memory: MemoryUsage::new(component),
+        }
+    }
+
+    /// Remembers `value` for `key`, returning the value remembered before.
+    pub fn insert(&mut self, key: [u8; 32], value: V) -> Option<V> {
+        self.tick += 1;
+        self.recency.push_back((key, self.tick));
+        let previous = self
+            .values
+            .insert(key, (value, self.tick))
+            .map(|(value, _)| value);
+        self.evict();
+        self.memory.set(
+            self.values.capacity() * size_of::<([u8; 32], (V, u64))>()
+                + self.recency.capacity() * size_of::<([u8; 32], u64)>(),
+        );
+        previous
+    }
+
+    pub fn get(&self, key: &[u8; 32]) -> Option<&V> {
+        self.values.get(key).map(|(value, _)| value)
+    }
+
+    fn evict(&mut self) {
+        while self.values.len() > self.capacity {
+            let (key, tick) = self.recency.pop_front().expect("every value has a use");
+            if self
+                .values
+                .get(&key)
+                .map_or(false, |(_, last)| *last == tick)
+            {
+                self.values.remove(&key);
+            }
+        }
+        // Hot accounts pile up superseded uses, drop them before they outgrow the values.
+        if self.recency.len() > 2 * self.capacity {
+            let values = &self.values;
+            self.recency
+                .retain(|(key, tick)| values.get(key).map_or(false, |(_, last)| last == tick));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_accounts() {
+        let mut recent = RecentAccounts::new(2, MemoryComponent::TokenBalances);
+        assert_eq!(recent.insert([1; 32], 10), None);
+        assert_eq!(recent.insert([2; 32], 20), None);
+        // Using the first account again makes the second the least recently used.
+        assert_eq!(recent.insert([1; 32], 11), Some(10));
+        assert_eq!(recent.insert([3; 32], 30), None);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent.get(&[1; 32]), Some(&11));
+        assert_eq!(recent.get(&[2; 32]), None);
+    }
+}
@@ -0,0 +1,602 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recorded geyser callbacks, stored as newline-delimited JSON, and their replay.
+
+use {
+    crate::AccountInfo,
+    serde::{Deserialize, Serialize},
+    solana_account_decoder::parse_token::UiTokenAmount,
+    solana_geyser_plugin_interface::geyser_plugin_interface::{
+        GeyserPlugin, GeyserPluginError as PluginError, ReplicaBlockInfo, ReplicaBlockInfoVersions,
+        ReplicaTransactionInfo, ReplicaTransactionInfoVersions, Result as PluginResult,
+        SlotStatus as PluginSlotStatus,
+    },
+    solana_program::{hash::Hash, message::v0::LoadedAddresses},
+    solana_sdk::{
+        signature::Signature,
+        transaction::{
+            SanitizedTransaction, SimpleAddressLoader, TransactionError, VersionedTransaction,
+        },
+    },
+    solana_transaction_status::{
+        InnerInstructions, Reward, TransactionStatusMeta, TransactionTokenBalance,
+    },
+    std::{
+        error::Error,
+        io::{BufRead, ErrorKind, Read, Write},
+    },
+};
+
+/// One geyser callback with its arguments.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "callback", rename_all = "snake_case")]
+pub enum Callback {
+    UpdateAccount {
+        account: RecordedAccount,
+        slot: u64,
+        is_startup: bool,
+    },
+    UpdateSlotStatus {
+        slot: u64,
+        parent: Option<u64>,
+        status: RecordedSlotStatus,
+    },
+    NotifyEndOfStartup,
+    NotifyTransaction {
+        transaction: Box<RecordedTransaction>,
+        slot: u64,
+    },
+    NotifyBlockMetadata {
+        block: RecordedBlock,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedAccount {
+    #[serde(with = "base58")]
+    pub pubkey: Vec<u8>,
+    pub lamports: u64,
+    #[serde(with = "base58")]
+    pub owner: Vec<u8>,
+    pub executable: bool,
+    pub rent_epoch: u64,
+    #[serde(with = "base64_bytes")]
+    pub data: Vec<u8>,
+    pub write_version: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordedSlotStatus {
+    Processed,
+    Rooted,
+    Confirmed,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedTransaction {
+    pub signature: Signature,
+    pub is_vote: bool,
+    /// Transaction in wire format, like RPC's `base64` encoding.
+    #[serde(with = "wire_transaction")]
+    pub transaction: VersionedTransaction,
+    pub message_hash: Hash,
+    pub is_simple_vote_transaction: bool,
+    pub loaded_addresses: LoadedAddresses,
+    pub status: Result<(), TransactionError>,
+    pub fee: u64,
+    pub pre_balances: Vec<u64>,
+    pub post_balances: Vec<u64>,
+    pub inner_instructions: Option<Vec<InnerInstructions>>,
+    pub log_messages: Option<Vec<String>>,
+    pub pre_token_balances: Option<Vec<RecordedTokenBalance>>,
+    pub post_token_balances: Option<Vec<RecordedTokenBalance>>,
+    pub rewards: Option<Vec<Reward>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedTokenBalance {
+    pub account_index: u8,
+    pub mint: String,
+    pub ui_token_amount: UiTokenAmount,
+    pub owner: String,
+    pub program_id: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedBlock {
+    pub slot: u64,
+    pub blockhash: String,
+    pub rewards: Vec<Reward>,
+    pub block_time: Option<i64>,
+    pub block_height: Option<u64>,
+}
+
+impl From<&AccountInfo<'_>> for RecordedAccount {
+    fn from(account: &AccountInfo<'_>) -> Self {
+        Self {
+            pubkey: account.pubkey.to_vec(),
+            lamports: account.lamports,
+            owner: account.owner.to_vec(),
+            executable: account.executable,
+            rent_epoch: account.rent_epoch,
+            data: account.data.to_vec(),
+            write_version: account.write_version,
+        }
+    }
+}
+
+impl From<&PluginSlotStatus> for RecordedSlotStatus {
+    fn from(status: &PluginSlotStatus) -> Self {
+        match status {
+            PluginSlotStatus::Processed => Self::Processed,
+            PluginSlotStatus::Rooted => Self::Rooted,
+            PluginSlotStatus::Confirmed => Self::Confirmed,
+        }
+    }
+}
+
+impl From<RecordedSlotStatus> for PluginSlotStatus {
+    fn from(status: RecordedSlotStatus) -> Self {
+        match status {
+            RecordedSlotStatus::Processed => Self::Processed,
+            RecordedSlotStatus::Rooted => Self::Rooted,
+            RecordedSlotStatus::Confirmed => Self::Confirmed,
+        }
+    }
+}
+
+impl From<&ReplicaTransactionInfo<'_>> for RecordedTransaction {
+    fn from(info: &ReplicaTransactionInfo<'_>) -> Self {
+        let meta = info.transaction_status_meta;
+        let token_balances = |balances: &Option<Vec<TransactionTokenBalance>>| {
+            balances.as_ref().map(|balances| {
+                balances
+                    .iter()
+                    .map(|balance| RecordedTokenBalance {
+                        account_index: balance.account_index,
+                        mint: balance.mint.clone(),
+                        ui_token_amount: balance.ui_token_amount.clone(),
+                        owner: balance.owner.clone(),
+                        program_id: balance.program_id.clone(),
+                    })
+                    .collect()
+            })
+        };
+        Self {
+            signature: *info.signature,
+            is_vote: info.is_vote,
+            transaction: info.transaction.to_versioned_transaction(),
+            message_hash: *info.transaction.message_hash(),
+            is_simple_vote_transaction: info.transaction.is_simple_vote_transaction(),
+            loaded_addresses: meta.loaded_addresses.clone(),
+            status: meta.status.clone(),
+            fee: meta.fee,
+            pre_balances: meta.pre_balances.clone(),
+            post_balances: meta.post_balances.clone(),
+            inner_instructions: meta.inner_instructions.clone(),
+            log_messages: meta.log_messages.clone(),
+            pre_token_balances: token_balances(&meta.pre_token_balances),
+            post_token_balances: token_balances(&meta.post_token_balances),
+            rewards: meta.rewards.clone(),
+        }
+    }
+}
+
+impl RecordedTransaction {
+    fn sanitized(&self) -> Result<SanitizedTransaction, TransactionError> {
+        SanitizedTransaction::try_create(
+            self.transaction.clone(),
+            self.message_hash,
+            Some(self.is_simple_vote_transaction),
+            SimpleAddressLoader::Enabled(self.loaded_addresses.clone()),
+            true,
+        )
+    }
+
+    fn meta(&self) -> TransactionStatusMeta {
+        let token_balances = |balances: &Option<Vec<RecordedTokenBalance>>| {
+            balances.as_ref().map(|balances| {
+                balances
+                    .iter()
+                    .map(|balance| TransactionTokenBalance {
+                        account_index: balance.account_index,
+                        mint: balance.mint.clone(),
+                        ui_token_amount: balance.ui_token_amount.clone(),
+                        owner: balance.owner.clone(),
+                        program_id: balance.program_id.clone(),
+                    })
+                    .collect()
+            })
+        };
+        TransactionStatusMeta {
+            status: self.status.clone(),
+            fee: self.fee,
+            pre_balances: self.pre_balances.clone(),
+            post_balances: self.post_balances.clone(),
+            inner_instructions: self.inner_instructions.clone(),
+            log_messages: self.log_messages.clone(),
+            pre_token_balances: token_balances(&self.pre_token_balances),
+            post_token_balances: token_balances(&self.post_token_balances),
+            rewards: self.rewards.clone(),
+            loaded_addresses: self.loaded_addresses.clone(),
+        }
+    }
+}
+
+impl From<&ReplicaBlockInfo<'_>> for RecordedBlock {
+    fn from(block: &ReplicaBlockInfo<'_>) -> Self {
+        Self {
+            slot: block.slot,
+            blockhash: block.blockhash.to_owned(),
+            rewards: block.rewards.to_vec(),
+            block_time: block.block_time,
+            block_height: block.block_height,
+        }
+    }
+}
+
+impl Callback {
+    /// Invokes the callback on `plugin` like the validator does.
+    pub fn invoke(&self, plugin: &mut dyn GeyserPlugin) -> PluginResult<()> {
+        match self {
+            Self::UpdateAccount {
+                account,
+                slot,
+                is_startup,
+            } => {
+                let info = AccountInfo {
+                    pubkey: &account.pubkey,
+                    lamports: account.lamports,
+                    owner: &account.owner,
+                    executable: account.executable,
+                    rent_epoch: account.rent_epoch,
+                    data: &account.data,
+                    write_version: account.write_version,
+                };
+                info.with_replica(|versions| plugin.update_account(versions, *slot, *is_startup))
+            }
+            Self::UpdateSlotStatus {
+                slot,
+                parent,
+                status,
+            } => plugin.update_slot_status(*slot, *parent, (*status).into()),
+            Self::NotifyEndOfStartup => plugin.notify_end_of_startup(),
+            Self::NotifyTransaction { transaction, slot } => {
+                let sanitized =
+                    transaction
+                        .sanitized()
+                        .map_err(|e| PluginError::TransactionUpdateError {
+                            msg: format!("Invalid recorded transaction: {}", e),
+                        })?;
+                let meta = transaction.meta();
+                let info = ReplicaTransactionInfo {
+                    signature: &transaction.signature,
+                    is_vote: transaction.is_vote,
+                    transaction: &sanitized,
+                    transaction_status_meta: &meta,
+                };
+                plugin.notify_transaction(ReplicaTransactionInfoVersions::V0_0_1(&info), *slot)
+            }
+            Self::NotifyBlockMetadata { block } => {
+                let info = ReplicaBlockInfo {
+                    slot: block.slot,
+                    blockhash: &block.blockhash,
+                    rewards: &block.rewards,
+                    block_time: block.block_time,
+                    block_height: block.block_height,
+                };
+                plugin.notify_block_metadata(ReplicaBlockInfoVersions::V0_0_1(&info))
+            }
+        }
+    }
+
+    /// Whether the validator would invoke this callback on `plugin` at all.
+    fn wanted_by(&self, plugin: &dyn GeyserPlugin) -> bool {
+        match self {
+            Self::UpdateAccount { .. } => plugin.account_data_notifications_enabled(),
+            Self::NotifyTransaction { .. } => plugin.transaction_notifications_enabled(),
+            _ => true,
+        }
+    }
+
+    /// Appends the callback to a newline-delimited recording.
+    pub fn write_to(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        serde_json::to_writer(&mut *writer, self)?;
+        writer.write_all(b"\n")
+    }
+
+    /// Appends the callback to a length-prefixed recording, returning the bytes written.
+    ///
+    /// Every record is its JSON encoding preceded by its length as a big-endian `u32`.
+    pub fn write_framed(&self, writer: &mut impl Write) -> std::io::Result<usize> {
+        let record = serde_json::to_vec(self)?;
+        writer.write_all(&(record.len() as u32).to_be_bytes())?;
+        writer.write_all(&record)?;
+        Ok(record.len() + 4)
+    }
+}
+
+/// Outcome of a replay.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReplayStats {
+    /// Callbacks invoked on the plugin.
+    pub invoked: u64,
+    /// Callbacks skipped since the plugin declined their notifications.
+    pub skipped: u64,
+    /// Invoked callbacks returning an error.
+    pub failed: u64,
+}
+
+/// Feeds the callbacks recorded in `reader` to a loaded `plugin`.
+///
+/// Reads both newline-delimited recordings and the length-prefixed ones of the capture sink.
+/// Plugin errors are passed to `on_error` with the record number, and replay continues if it returns `true`.
+pub fn replay(
+    plugin: &mut dyn GeyserPlugin,
+    mut reader: impl BufRead,
+    mut on_error: impl FnMut(usize, &dyn Error) -> bool,
+) -> Result<ReplayStats, Box<dyn Error + Send + Sync>> {
+    // A length prefix never starts with `{`, that would take a record of 2 GiB.
+    let framed = reader.fill_buf()?.first().map_or(false, |b| *b != b'{');
+    let mut stats = ReplayStats::default();
+    let mut record = Vec::new();
+    for i in 1.. {
+        let more = if framed {
+            read_framed(&mut reader, &mut record)?
+        } else {
+            record.clear();
+            reader.read_until(b'\n', &mut record)? > 0
+        };
+        if !more {
+            break;
+        }
+        if record.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        let callback: Callback = serde_json::from_slice(&record)
+            .map_err(|e| format!("Invalid callback in record {}: {}", i, e))?;
+        if !callback.wanted_by(plugin) {
+            stats.skipped += 1;
+            continue;
+        }
+        stats.invoked += 1;
+        if let Err(e) = callback.invoke(plugin) {
+            stats.failed += 1;
+            if !on_error(i, &e) {
+                break;
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// Reads the next length-prefixed record into `record`, returning `false` at the end.
+pub(crate) fn read_framed(reader: &mut impl Read, record: &mut Vec<u8>) -> std::io::Result<bool> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+        Err(e) => return Err(e),
+    }
+    record.resize(u32::from_be_bytes(len) as usize, 0);
+    reader.read_exact(record)?;
+    Ok(true)
+}
+
+mod base58 {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&bs58::encode(bytes).into_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        bs58::decode(s).into_vec().map_err(D::Error::custom)
+    }
+}
+
+mod wire_transaction {
+    use {
+        serde::{de::Error, ser, Deserialize, Deserializer, Serializer},
+        solana_sdk::transaction::VersionedTransaction,
+    };
+
+    pub fn serialize<S: Serializer>(
+        tx: &VersionedTransaction,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let bytes = bincode::serialize(tx).map_err(ser::Error::custom)?;
+        serializer.serialize_str(&base64::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<VersionedTransaction, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        let bytes = base64::decode(s).map_err(D::Error::custom)?;
+        bincode::deserialize(&bytes).map_err(D::Error::custom)
+    }
+}
+
+mod base64_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        base64::decode(s).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_geyser_plugin_interface::geyser_plugin_interface::ReplicaAccountInfoVersions,
+        solana_program::{message::Message, pubkey::Pubkey, system_instruction},
+        solana_sdk::{signature::Keypair, signer::Signer, transaction::Transaction},
+        std::fmt::{Debug, Formatter},
+    };
+
+    /// Records everything it is notified of.
+    #[derive(Default)]
+    struct Recorder {
+        callbacks: Vec<Callback>,
+        accounts_enabled: bool,
+    }
+
+    impl Debug for Recorder {
+        fn fmt(&self, _: &mut Formatter<'_>) -> std::fmt::Result {
+            Ok(())
+        }
+    }
+
+    impl GeyserPlugin for Recorder {
+        fn name(&self) -> &'static str {
+            "Recorder"
+        }
+
+        fn update_account(
+            &mut self,
+            account: ReplicaAccountInfoVersions,
+            slot: u64,
+            is_startup: bool,
+        ) -> PluginResult<()> {
+            self.callbacks.push(Callback::UpdateAccount {
+                account: (&AccountInfo::from(account)).into(),
+                slot,
+                is_startup,
+            });
+            Ok(())
+        }
+
+        fn update_slot_status(
+            &mut self,
+            slot: u64,
+            parent: Option<u64>,
+            status: PluginSlotStatus,
+        ) -> PluginResult<()> {
+            self.callbacks.push(Callback::UpdateSlotStatus {
+                slot,
+                parent,
+                status: (&status).into(),
+            });
+            Ok(())
+        }
+
+        fn notify_transaction(
+            &mut self,
+            transaction: ReplicaTransactionInfoVersions,
+            slot: u64,
+        ) -> PluginResult<()> {
+            let ReplicaTransactionInfoVersions::V0_0_1(transaction) = transaction;
+            self.callbacks.push(Callback::NotifyTransaction {
+                transaction: Box::new(transaction.into()),
+                slot,
+            });
+            Ok(())
+        }
+
+        fn account_data_notifications_enabled(&self) -> bool {
+            self.accounts_enabled
+        }
+
+        fn transaction_notifications_enabled(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_replay_round_trip() {
+        let payer = Keypair::new();
+        let ix = system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 42);
+        let tx = Transaction::new(
+            &[&payer],
+            Message::new(&[ix], Some(&payer.pubkey())),
+            Hash::default(),
+        );
+        let sanitized = SanitizedTransaction::try_from_legacy_transaction(tx).unwrap();
+        let meta = TransactionStatusMeta {
+            fee: 5000,
+            log_messages: Some(vec![
+                "Program 11111111111111111111111111111111 success".into()
+            ]),
+            ..TransactionStatusMeta::default()
+        };
+        let transaction = RecordedTransaction::from(&ReplicaTransactionInfo {
+            signature: &sanitized.signatures()[0],
+            is_vote: false,
+            transaction: &sanitized,
+            transaction_status_meta: &meta,
+        });
+
+        let account = RecordedAccount {
+            pubkey: vec![1; 32],
+            lamports: 10,
+            owner: vec![2; 32],
+            executable: false,
+            rent_epoch: 3,
+            data: vec![0, 1, 2, 255],
+            write_version: 7,
+        };
+        let callbacks = vec![
+            Callback::UpdateAccount {
+                account,
+                slot: 5,
+                is_startup: false,
+            },
+            Callback::NotifyTransaction {
+                transaction: Box::new(transaction),
+                slot: 5,
+            },
+            Callback::UpdateSlotStatus {
+                slot: 5,
+                parent: Some(4),
+                status: RecordedSlotStatus::Confirmed,
+            },
+        ];
+        let mut recording = Vec::new();
+        for callback in &callbacks {
+            callback.write_to(&mut recording).unwrap();
+        }
+
+        let mut plugin = Recorder {
+            accounts_enabled: true,
+            ..Recorder::default()
+        };
+        let stats = replay(&mut plugin, &recording[..], |_, _| true).unwrap();
+        assert_eq!(stats.invoked, 3);
+        assert_eq!(plugin.callbacks, callbacks);
+
+        let mut plugin = Recorder::default();
+        let stats = replay(&mut plugin, &recording[..], |_, _| true).unwrap();
+        assert_eq!(stats.skipped, 1);
+        assert_eq!(plugin.callbacks, callbacks[1..]);
+
+        let mut framed = Vec::new();
+        for callback in &callbacks {
+            callback.write_framed(&mut framed).unwrap();
+        }
+        let mut plugin = Recorder::default();
+        let stats = replay(&mut plugin, &framed[..], |_, _| true).unwrap();
+        assert_eq!(stats.invoked, 2);
+        assert_eq!(plugin.callbacks, callbacks[1..]);
+    }
+}
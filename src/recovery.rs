@@ -0,0 +1,160 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    crossbeam_channel::{bounded, select, Receiver, RecvTimeoutError, Sender},
+    log::{error, info},
+    std::{
+        sync::Arc,
+        thread::{self, JoinHandle},
+        time::{Duration, Instant},
+    },
+};
+
+/// Delay between producer recreations, doubled while fatal errors keep coming.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Exponential backoff, reset once the last attempt is long enough ago.
+struct Backoff {
+    delay: Duration,
+    last: Option<Instant>,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Self {
+            delay: MIN_BACKOFF,
+            last: None,
+        }
+    }
+
+    /// How long to wait before the next attempt at `now`.
+    fn next(&mut self, now: Instant) -> Duration {
+        let wait = match self.last {
+            // Quiet for a while, the previous attempt succeeded.
+            Some(last) if now.duration_since(last) > self.delay * 2 => {
+                self.delay = MIN_BACKOFF;
+                Duration::ZERO
+            }
+            Some(last) => {
+                let wait = (last + self.delay).saturating_duration_since(now);
+                self.delay = (self.delay * 2).min(MAX_BACKOFF);
+                wait
+            }
+            None => Duration::ZERO,
+        };
+        self.last = Some(now + wait);
+        wait
+    }
+}
+
+/// Recreates the Kafka producers once they report a fatal error,
+/// such as a fenced idempotent producer or expired credentials.
+pub struct ProducerRecovery {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ProducerRecovery {
+    /// Starts watching the fatal errors reported to `context`, if any.
+    pub fn spawn(
+        config: &Config,
+        context: DeliveryContext,
+        publisher: Arc<Publisher>,
+    ) -> Option<Self> {
+        let fatal_errors = context.fatal_errors()?.clone();
+        let config = config.clone();
+        let (stop, stopped) = bounded(0);
+        let thread = thread::Builder::new()
+            .name("kafka-producer-recovery".to_owned())
+            .spawn(move || Self::run(config, context, publisher, fatal_errors, stopped))
+            .expect("failed to spawn producer recovery thread");
+        Some(Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+
+    fn run(
+        config: Config,
+        context: DeliveryContext,
+        publisher: Arc<Publisher>,
+        fatal_errors: Receiver<String>,
+        stopped: Receiver<()>,
+    ) {
+        let mut backoff = Backoff::new();
+        loop {
+            let reason = select! {
+                recv(fatal_errors) -> reason => match reason {
+                    Ok(reason) => reason,
+                    Err(_) => return,
+                },
+                recv(stopped) -> _ => return,
+            };
+            error!("Kafka producer failed fatally, recreating it: {}", reason);
+            let mut stopping = false;
+            publisher.rebuild_producers(|| loop {
+                match stopped.recv_timeout(backoff.next(Instant::now())) {
+                    Err(RecvTimeoutError::Timeout) => {}
+                    _ => {
+                        stopping = true;
+                        return None;
+                    }
+                }
+                match config.producers(context.clone()) {
+                    Ok(producers) => return Some(producers),
+                    Err(e) => error!("Failed to recreate Kafka producers, retrying: {}", e),
+                }
+            });
+            if stopping {
+                return;
+            }
+            metrics().producer_recreations.inc();
+            info!("Recreated Kafka producers");
+            // Errors of the replaced producers, reported while they drained.
+            while fatal_errors.try_recv().is_ok() {}
+        }
+    }
+}
+
+impl Drop for ProducerRecovery {
+    fn drop(&mut self) {
+        self.stop = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Producer recovery thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let start = Instant::now();
+        let mut backoff = Backoff::new();
+        assert_eq!(backoff.next(start), Duration::ZERO);
+        assert_eq!(backoff.next(start), MIN_BACKOFF);
+        assert_eq!(backoff.next(start + MIN_BACKOFF), MIN_BACKOFF * 2);
+        // Long after the last attempt, the backoff starts over.
+        let later = start + MAX_BACKOFF * 10;
+        assert_eq!(backoff.next(later), Duration::ZERO);
+        assert_eq!(backoff.next(later), MIN_BACKOFF);
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    crossbeam_channel::{bounded, RecvTimeoutError, Sender},
+    log::{error, info},
+    std::{
+        fs,
+        path::PathBuf,
+        sync::Arc,
+        thread::{self, JoinHandle},
+        time::{Duration, SystemTime},
+    },
+};
+
+/// librdkafka options naming TLS files that may be rotated.
+const TLS_FILE_OPTIONS: [&str; 3] = [
+    "ssl.certificate.location",
+    "ssl.key.location",
+    "ssl.ca.location",
+];
+
+/// Detects changes of a set of files by their modification time and size.
+pub struct FileWatcher {
+    files: Vec<(PathBuf, Option<(SystemTime, u64)>)>,
+}
+
+impl FileWatcher {
+    pub fn new(paths: impl IntoIterator<Item = PathBuf>) -> Self {
+        Self {
+            files: paths
+                .into_iter()
+                .map(|path| {
+                    let version = Self::version(&path);
+                    (path, version)
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Whether any file changed since the last call to [`FileWatcher::acknowledge`].
+    pub fn changed(&self) -> bool {
+        self.files
+            .iter()
+            .any(|(path, version)| Self::version(path) != *version)
+    }
+
+    /// Takes the current state of all files as the new baseline.
+    pub fn acknowledge(&mut self) {
+        for (path, version) in self.files.iter_mut() {
+            *version = Self::version(path);
+        }
+    }
+
+    fn version(path: &PathBuf) -> Option<(SystemTime, u64)> {
+        let metadata = fs::metadata(path).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+}
+
+/// Recreates the Kafka producers when the TLS files they were configured with change,
+/// so short-lived client certificates can be rotated without restarting the validator.
+pub struct TlsReloader {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TlsReloader {
+    /// Starts watching, unless reloading is disabled or no TLS files are configured.
+    pub fn spawn(
+        config: &Config,
+        context: DeliveryContext,
+        publisher: Arc<Publisher>,
+    ) -> Option<Self> {
+        if config.tls_reload_interval_sec == 0 {
+            return None;
+        }
+        let options = config.producer_options();
+        let mut watcher = FileWatcher::new(
+            TLS_FILE_OPTIONS
+                .iter()
+                .filter_map(|k| options.get(*k))
+                .map(PathBuf::from),
+        );
+        if watcher.is_empty() {
+            return None;
+        }
+
+        let config = config.clone();
+        let interval = Duration::from_secs(config.tls_reload_interval_sec);
+        let (stop, stopped) = bounded(0);
+        let thread = thread::Builder::new()
+            .name("kafka-tls-reload".to_owned())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    if !watcher.changed() {
+                        continue;
+                    }
+                    info!("TLS files changed, recreating Kafka producers");
+                    match config.producers(context.clone()) {
+                        Ok(producers) => {
+                            publisher.replace_producers(producers);
+                            watcher.acknowledge();
+                        }
+                        // Files may be mid-rotation, e.g. a new certificate with the old key.
+                        Err(e) => error!("Failed to recreate Kafka producers, retrying: {}", e),
+                    }
+                }
+            })
+            .expect("failed to spawn TLS reload thread");
+
+        Some(Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for TlsReloader {
+    fn drop(&mut self) {
+        self.stop = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("TLS reload thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_watcher() {
+        let dir = std::env::temp_dir().join(format!("kafka-plugin-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cert = dir.join("client.pem");
+        fs::write(&cert, "old").unwrap();
+
+        let mut watcher = FileWatcher::new(vec![cert.clone(), dir.join("missing.pem")]);
+        assert!(!watcher.changed());
+
+        fs::write(&cert, "rotated").unwrap();
+        assert!(watcher.changed());
+        assert!(watcher.changed());
+        watcher.acknowledge();
+        assert!(!watcher.changed());
+
+        fs::remove_file(&cert).unwrap();
+        assert!(watcher.changed());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
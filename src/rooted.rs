@@ -0,0 +1,236 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    prost::bytes::Bytes,
+    std::collections::{BTreeMap, HashMap, HashSet},
+};
+
+/// Holds back account updates until their slot is rooted.
+///
+/// Rooted slots yield the net effect of their updates, the highest `write_version` of
+/// every account, along with the buffered ancestors rooted with them. Older slots off the
+/// rooted ancestry belonged to abandoned forks, their updates are dropped and noted in
+/// `discarded_slots` of the rooted updates of the same accounts.
+pub struct RootedDiff {
+    optimistic: BTreeMap<u64, HashMap<Bytes, UpdateAccountEvent>>,
+    /// Parent of every slot not rooted yet, as reported by its slot status.
+    parents: BTreeMap<u64, u64>,
+    /// Abandoned slots with updates of each account, not yet reported.
+    discarded: HashMap<Bytes, Vec<u64>>,
+    /// Of the updates in `optimistic`.
+    memory: MemoryUsage,
+}
+
+impl Default for RootedDiff {
+    fn default() -> Self {
+        Self {
+            optimistic: BTreeMap::new(),
+            parents: BTreeMap::new(),
+            discarded: HashMap::new(),
+            memory: MemoryUsage::new(MemoryComponent::SlotBuffers),
+        }
+    }
+}
+
+impl RootedDiff {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Buffers `ev`, returning `false` if it was coalesced with an update of the same account.
+    pub fn push(&mut self, ev: UpdateAccountEvent) -> bool {
+        let accounts = self.optimistic.entry(ev.slot).or_default();
+        match accounts.get_mut(&ev.pubkey) {
+            Some(existing) => {
+                metrics().coalesced_account_updates.inc();
+                if ev.write_version > existing.write_version {
+                    self.memory.sub(account_update_bytes(existing));
+                    self.memory.add(account_update_bytes(&ev));
+                    *existing = ev;
+                }
+                false
+            }
+            None => {
+                self.memory.add(account_update_bytes(&ev));
+                accounts.insert(ev.pubkey.clone(), ev);
+                true
+            }
+        }
+    }
+
+    /// Records the parent of `slot`, to tell its rooted ancestors from abandoned forks.
+    pub fn set_parent(&mut self, slot: u64, parent: u64) {
+        self.parents.insert(slot, parent);
+    }
+
+    /// The slots before `slot` on its ancestry, as far as their parents are known.
+    fn ancestors(&self, slot: u64) -> HashSet<u64> {
+        let oldest = match self.optimistic.keys().next() {
+            Some(oldest) => *oldest,
+            None => return HashSet::new(),
+        };
+        let mut ancestors = HashSet::new();
+        let mut current = slot;
+        while let Some(parent) = self.parents.get(&current) {
+            if *parent < oldest || *parent >= current {
+                break;
+            }
+            ancestors.insert(*parent);
+            current = *parent;
+        }
+        ancestors
+    }
+
+    /// Account updates [`Self::root`] of `slot` will discard, of the slots before it.
+    pub fn abandoned_updates(&self, slot: u64) -> usize {
+        let ancestors = self.ancestors(slot);
+        self.optimistic
+            .range(..slot)
+            .filter(|(slot, _)| !ancestors.contains(slot))
+            .map(|(_, accounts)| accounts.len())
+            .sum()
+    }
+
+    /// Takes the net effect of rooted `slot` and of its buffered ancestors, oldest first,
+    /// discarding the other unrooted slots before it.
+    ///
+    /// A root's ancestors are rooted with it, and roots are notified in ascending order,
+    /// so any other older slot still buffered was never rooted.
+    pub fn root(&mut self, slot: u64) -> Vec<SlotAccountsBatch> {
+        let ancestors = self.ancestors(slot);
+        let newer = self.optimistic.split_off(&slot);
+        let older = std::mem::replace(&mut self.optimistic, newer);
+        self.parents = self.parents.split_off(&(slot + 1));
+
+        let mut rooted = Vec::new();
+        for (older_slot, accounts) in older {
+            if ancestors.contains(&older_slot) {
+                rooted.push((older_slot, accounts));
+                continue;
+            }
+            metrics().optimistic_slots_discarded.inc();
+            metrics()
+                .optimistic_account_updates_discarded
+                .add(accounts.len() as u64);
+            for (pubkey, ev) in accounts {
+                self.memory.sub(account_update_bytes(&ev));
+                self.discarded.entry(pubkey).or_default().push(older_slot);
+            }
+        }
+        if let Some(accounts) = self.optimistic.remove(&slot) {
+            rooted.push((slot, accounts));
+        }
+
+        let batches = rooted
+            .into_iter()
+            .map(|(slot, accounts)| {
+                let mut accounts: Vec<UpdateAccountEvent> = accounts.into_values().collect();
+                accounts.sort_unstable_by_key(|ev| ev.write_version);
+                for ev in &mut accounts {
+                    self.memory.sub(account_update_bytes(ev));
+                    // Reported once, with the oldest rooted update of the account.
+                    if let Some(slots) = self.discarded.remove(&ev.pubkey) {
+                        ev.discarded_slots = slots;
+                    }
+                }
+                SlotAccountsBatch { slot, accounts }
+            })
+            .collect();
+        // Accounts updated on abandoned forks only never changed on the rooted one.
+        self.discarded.clear();
+        batches
+    }
+
+    /// Number of slots held back, not rooted yet.
+    pub fn pending_slots(&self) -> usize {
+        self.optimistic.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(slot: u64, pubkey: u8, write_version: u64) -> UpdateAccountEvent {
+        UpdateAccountEvent {
+            slot,
+            pubkey: vec![pubkey; 32].into(),
+            write_version,
+            ..UpdateAccountEvent::default()
+        }
+    }
+
+    #[test]
+    fn test_rooted_diff() {
+        let mut diff = RootedDiff::new();
+        diff.push(update(10, 1, 1));
+        diff.push(update(10, 1, 3));
+        diff.push(update(10, 1, 2));
+        // Slot 11 is on a fork abandoned in favor of 12.
+        diff.push(update(11, 1, 4));
+        diff.push(update(11, 2, 5));
+        diff.push(update(12, 1, 6));
+        diff.push(update(12, 3, 7));
+        diff.push(update(13, 1, 8));
+
+        let rooted = diff.root(10).remove(0);
+        assert_eq!(rooted.slot, 10);
+        assert_eq!(rooted.accounts.len(), 1);
+        assert_eq!(rooted.accounts[0].write_version, 3);
+        assert!(rooted.accounts[0].discarded_slots.is_empty());
+
+        let rooted = diff.root(12).remove(0);
+        let versions: Vec<(u64, Vec<u64>)> = rooted
+            .accounts
+            .iter()
+            .map(|ev| (ev.write_version, ev.discarded_slots.clone()))
+            .collect();
+        assert_eq!(versions, [(6, vec![11]), (7, vec![])]);
+
+        assert_eq!(diff.pending_slots(), 1);
+    }
+
+    #[test]
+    fn test_rooted_diff_ancestors() {
+        let mut diff = RootedDiff::new();
+        // 20 <- 21 <- 23 <- 24 is rooted at 24, 22 forked off 21.
+        for (slot, parent) in [(21, 20), (22, 21), (23, 21), (24, 23)] {
+            diff.set_parent(slot, parent);
+        }
+        diff.push(update(20, 1, 1));
+        diff.push(update(21, 1, 2));
+        diff.push(update(21, 2, 3));
+        diff.push(update(22, 2, 4));
+        diff.push(update(22, 3, 5));
+        diff.push(update(24, 1, 6));
+        diff.push(update(25, 1, 7));
+
+        assert_eq!(diff.abandoned_updates(24), 2);
+        let batches = diff.root(24);
+        let slots: Vec<u64> = batches.iter().map(|batch| batch.slot).collect();
+        assert_eq!(slots, [20, 21, 24]);
+        let versions: Vec<(u64, Vec<u64>)> = batches
+            .iter()
+            .flat_map(|batch| &batch.accounts)
+            .map(|ev| (ev.write_version, ev.discarded_slots.clone()))
+            .collect();
+        assert_eq!(
+            versions,
+            [(1, vec![]), (2, vec![]), (3, vec![22]), (6, vec![])]
+        );
+        assert_eq!(diff.pending_slots(), 1);
+    }
+}
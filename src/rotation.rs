@@ -0,0 +1,207 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    serde::Deserialize,
+    std::{
+        fs::{self, File},
+        io::{BufWriter, Write},
+        path::{Path, PathBuf},
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// When to start a new file and how many to keep.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct Rotation {
+    /// Start a new file once the current one reaches this size.
+    #[serde(default = "Rotation::default_max_file_bytes")]
+    pub max_file_bytes: u64,
+    /// Start a new file once the current one is this old, zero disables.
+    #[serde(default = "Rotation::default_max_file_age_sec")]
+    pub max_file_age_sec: u64,
+    /// Delete the oldest files beyond this many, zero keeps all.
+    #[serde(default)]
+    pub max_files: usize,
+}
+
+impl Default for Rotation {
+    fn default() -> Self {
+        Self {
+            max_file_bytes: Self::default_max_file_bytes(),
+            max_file_age_sec: Self::default_max_file_age_sec(),
+            max_files: 0,
+        }
+    }
+}
+
+impl Rotation {
+    fn default_max_file_bytes() -> u64 {
+        256 * 1024 * 1024
+    }
+
+    fn default_max_file_age_sec() -> u64 {
+        3600
+    }
+}
+
+/// Files in a directory written one record at a time, rotated between records.
+///
+/// File names carry the creation time, so they sort chronologically.
+pub struct RotatingFile {
+    directory: PathBuf,
+    prefix: &'static str,
+    suffix: &'static str,
+    rotation: Rotation,
+    writer: BufWriter<File>,
+    written: u64,
+    opened: Instant,
+}
+
+impl RotatingFile {
+    pub fn new(
+        directory: impl Into<PathBuf>,
+        prefix: &'static str,
+        suffix: &'static str,
+        rotation: Rotation,
+    ) -> std::io::Result<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+        let writer = Self::open(&directory, prefix, suffix)?;
+        Ok(Self {
+            directory,
+            prefix,
+            suffix,
+            rotation,
+            writer,
+            written: 0,
+            opened: Instant::now(),
+        })
+    }
+
+    /// Appends a record with `write`, which returns the number of bytes it wrote.
+    pub fn append(
+        &mut self,
+        write: impl FnOnce(&mut BufWriter<File>) -> std::io::Result<usize>,
+    ) -> std::io::Result<()> {
+        if self.needs_rotation() {
+            self.rotate()?;
+        }
+        self.written += write(&mut self.writer)? as u64;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn needs_rotation(&self) -> bool {
+        self.written >= self.rotation.max_file_bytes
+            || (self.rotation.max_file_age_sec > 0
+                && self.opened.elapsed() >= Duration::from_secs(self.rotation.max_file_age_sec))
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.writer.flush()?;
+        self.writer = Self::open(&self.directory, self.prefix, self.suffix)?;
+        self.written = 0;
+        self.opened = Instant::now();
+        self.prune()
+    }
+
+    fn open(directory: &Path, prefix: &str, suffix: &str) -> std::io::Result<BufWriter<File>> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut path = directory.to_path_buf();
+        let mut seq = 0;
+        loop {
+            path.push(format!(
+                "{}{:020}-{}{}",
+                prefix,
+                now.as_micros(),
+                seq,
+                suffix
+            ));
+            if !path.exists() {
+                break;
+            }
+            path.pop();
+            seq += 1;
+        }
+        Ok(BufWriter::new(File::create(path)?))
+    }
+
+    fn prune(&self) -> std::io::Result<()> {
+        if self.rotation.max_files == 0 {
+            return Ok(());
+        }
+        let mut files: Vec<PathBuf> = fs::read_dir(&self.directory)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .map_or(false, |name| {
+                        name.starts_with(self.prefix) && name.ends_with(self.suffix)
+                    })
+            })
+            .collect();
+        files.sort();
+        let excess = files.len().saturating_sub(self.rotation.max_files);
+        for path in &files[..excess] {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RotatingFile {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            log::error!("Failed to flush {}: {}", self.directory.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation() {
+        let dir =
+            std::env::temp_dir().join(format!("kafka-plugin-rotation-{}", std::process::id()));
+        let rotation = Rotation {
+            max_file_bytes: 1,
+            max_file_age_sec: 0,
+            max_files: 2,
+        };
+        let mut file = RotatingFile::new(&dir, "test-", ".bin", rotation).unwrap();
+        for i in 0..5u8 {
+            file.append(|w| w.write_all(&[i]).map(|()| 1)).unwrap();
+        }
+        drop(file);
+
+        let mut files: Vec<PathBuf> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 2);
+        assert_eq!(fs::read(&files[0]).unwrap(), [3]);
+        assert_eq!(fs::read(&files[1]).unwrap(), [4]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    regex::Regex,
+    serde::Deserialize,
+    std::{collections::HashMap, sync::Mutex},
+};
+
+/// Rule routing the account updates of programs whose labels or pubkey match `pattern` to `topic`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TopicRule {
+    /// `label:<pattern>`, matching any label of the program in the remote allowlist,
+    /// or `owner:<pattern>`, matching the base58 program id.
+    #[serde(rename = "match")]
+    pub pattern: String,
+    /// Whether the pattern is a regular expression rather than a glob of `*` and `?`.
+    #[serde(default)]
+    pub regex: bool,
+    pub topic: String,
+}
+
+enum Subject {
+    Label,
+    Owner,
+}
+
+enum Matcher {
+    Glob(String),
+    Regex(Regex),
+}
+
+struct CompiledRule {
+    subject: Subject,
+    matcher: Matcher,
+    topic: String,
+}
+
+impl CompiledRule {
+    fn new(rule: &TopicRule) -> Result<Self, String> {
+        let (subject, pattern) = if let Some(pattern) = rule.pattern.strip_prefix("label:") {
+            (Subject::Label, pattern)
+        } else if let Some(pattern) = rule.pattern.strip_prefix("owner:") {
+            (Subject::Owner, pattern)
+        } else {
+            return Err(format!(
+                "topic rule {} must match `label:` or `owner:`",
+                rule.pattern
+            ));
+        };
+        let matcher = if rule.regex {
+            let regex = Regex::new(pattern)
+                .map_err(|e| format!("invalid topic rule {}: {}", rule.pattern, e))?;
+            Matcher::Regex(regex)
+        } else {
+            Matcher::Glob(pattern.to_owned())
+        };
+        Ok(Self {
+            subject,
+            matcher,
+            topic: rule.topic.clone(),
+        })
+    }
+
+    fn matches(&self, owner: &str, labels: &[String]) -> bool {
+        let is_match = |text: &str| match &self.matcher {
+            Matcher::Glob(pattern) => glob_match(pattern.as_bytes(), text.as_bytes()),
+            Matcher::Regex(regex) => regex.is_match(text),
+        };
+        match self.subject {
+            Subject::Label => labels.iter().any(|label| is_match(label)),
+            Subject::Owner => is_match(owner),
+        }
+    }
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any run of characters and `?` any one.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was, and the text position it currently extends to.
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Checks that every rule compiles.
+pub fn check_topic_rules(rules: &[TopicRule]) -> Result<(), String> {
+    rules
+        .iter()
+        .try_for_each(|rule| CompiledRule::new(rule).map(|_| ()))
+}
+
+/// Picks the topic of account updates by owner program: the topic of its remote allowlist entry,
+/// else that of the first matching `topic_rules` entry.
+pub struct TopicRouter {
+    routes: Option<TopicRoutes>,
+    rules: Vec<CompiledRule>,
+    resolved: Mutex<Resolved>,
+}
+
+/// Rule outcomes by program, valid for one generation of the routes.
+#[derive(Default)]
+struct Resolved {
+    generation: usize,
+    topics: HashMap<[u8; 32], Option<String>>,
+}
+
+impl TopicRouter {
+    /// Router of the `routes` of a remote allowlist, if any, and `rules`, checked on load.
+    pub fn new(routes: Option<TopicRoutes>, rules: &[TopicRule]) -> Self {
+        let rules = rules
+            .iter()
+            .map(|rule| CompiledRule::new(rule).expect("topic rules are checked on load"))
+            .collect();
+        Self {
+            routes,
+            rules,
+            resolved: Mutex::default(),
+        }
+    }
+
+    /// The topic account updates of `owner` go to instead of `update_account_topic`, if any.
+    pub fn route(&self, owner: &[u8]) -> Option<String> {
+        if let Some(topic) = self.routes.as_ref().and_then(|routes| routes.get(owner)) {
+            return Some(topic);
+        }
+        let key = <[u8; 32]>::try_from(owner).ok()?;
+        if self.rules.is_empty() {
+            return None;
+        }
+        let generation = self.routes.as_ref().map_or(0, TopicRoutes::generation);
+        let mut resolved = self.resolved.lock().unwrap();
+        if resolved.generation != generation {
+            *resolved = Resolved {
+                generation,
+                topics: HashMap::new(),
+            };
+        }
+        resolved
+            .topics
+            .entry(key)
+            .or_insert_with(|| {
+                let labels = self
+                    .routes
+                    .as_ref()
+                    .map(|routes| routes.labels(owner))
+                    .unwrap_or_default();
+                let owner = bs58::encode(owner).into_string();
+                self.rules
+                    .iter()
+                    .find(|rule| rule.matches(&owner, &labels))
+                    .map(|rule| rule.topic.clone())
+            })
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topic_router() {
+        assert!(glob_match(b"defi-*", b"defi-amm"));
+        assert!(glob_match(b"*-amm", b"defi-amm"));
+        assert!(glob_match(b"d?fi*m", b"defi-amm"));
+        assert!(!glob_match(b"defi-*", b"nft-defi-amm"));
+
+        let rules: Vec<TopicRule> = serde_json::from_str(
+            r#"[
+                {"match": "label:defi-*", "topic": "solana.defi"},
+                {"match": "owner:^Vote", "regex": true, "topic": "solana.votes"},
+                {"match": "label:*", "topic": "solana.labeled"}
+            ]"#,
+        )
+        .unwrap();
+        check_topic_rules(&rules).unwrap();
+        let routes = TopicRoutes::default();
+        let amm = [1; 32];
+        let labeled = [2; 32];
+        let explicit = [3; 32];
+        let label = |labels: &[&str]| ProgramRoute {
+            topic: "".to_owned(),
+            labels: labels.iter().map(|label| label.to_string()).collect(),
+        };
+        routes.replace(
+            [
+                (amm, label(&["core", "defi-amm"])),
+                (labeled, label(&["nft"])),
+                (
+                    explicit,
+                    ProgramRoute {
+                        topic: "explicit".to_owned(),
+                        labels: vec!["defi-amm".to_owned()],
+                    },
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let router = TopicRouter::new(Some(routes.clone()), &rules);
+        let vote = solana_program::vote::program::id().to_bytes();
+
+        assert_eq!(router.route(&amm).as_deref(), Some("solana.defi"));
+        assert_eq!(router.route(&labeled).as_deref(), Some("solana.labeled"));
+        assert_eq!(router.route(&explicit).as_deref(), Some("explicit"));
+        assert_eq!(router.route(&vote).as_deref(), Some("solana.votes"));
+        assert_eq!(router.route(&[4; 32]), None);
+
+        // Refreshed labels take effect.
+        routes.replace([(amm, label(&["nft"]))].into_iter().collect());
+        assert_eq!(router.route(&amm).as_deref(), Some("solana.labeled"));
+
+        let invalid = TopicRule {
+            pattern: "program:*".to_owned(),
+            regex: false,
+            topic: "x".to_owned(),
+        };
+        assert!(check_topic_rules(&[invalid]).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Schemas of the published messages, as built into the plugin.
+
+use {crate::*, serde::Serialize};
+
+/// Protobuf package of the plugin's own messages.
+pub const PROTO_PACKAGE: &str = "blockdaemon.solana.accountsdb_plugin_kafka.types";
+
+/// The `.proto` source the messages were generated from.
+pub const EVENT_PROTO: &str = include_str!("../proto/event.proto");
+
+/// Serialized `google.protobuf.FileDescriptorSet` of [`EVENT_PROTO`] and the Yellowstone messages,
+/// for schema registries and dynamic decoders.
+pub const EVENT_FILE_DESCRIPTOR_SET: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/event_descriptor_set.bin"));
+
+/// Message types published to one topic.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TopicSchema {
+    pub topic: String,
+    /// Fully qualified protobuf message names.
+    pub messages: Vec<String>,
+}
+
+/// Which messages the plugin publishes to which topic under `config`.
+pub fn topic_schemas(config: &Config) -> Vec<TopicSchema> {
+    // Yellowstone replaces the messages of accounts, slot statuses and transactions.
+    let (account, slot_status, transaction) = match config.event_schema {
+        EventSchema::Native => ("UpdateAccountEvent", "SlotStatusEvent", "TransactionEvent"),
+        EventSchema::Yellowstone => (SUBSCRIBE_UPDATE, SUBSCRIBE_UPDATE, SUBSCRIBE_UPDATE),
+    };
+    let mut account_messages = vec![account];
+    if config.batch_account_updates {
+        account_messages.push("SlotAccountsBatch");
+    }
+    let topics = [
+        (&config.update_account_topic, account_messages),
+        (&config.startup_account_topic, vec![account]),
+        (&config.slot_status_topic, vec![slot_status]),
+        (&config.transaction_topic, vec![transaction]),
+        (&config.vote_transaction_topic, vec![transaction]),
+        (
+            &config.transaction_status_topic,
+            vec!["TransactionStatusEvent"],
+        ),
+        (&config.compressed_nft_topic, vec!["CompressedNftEvent"]),
+        (&config.program_upgrade_topic, vec!["ProgramUpgraded"]),
+        (&config.token_balance_topic, vec!["TokenBalanceChange"]),
+        (&config.owner_change_topic, vec!["OwnerChanged"]),
+        (&config.block_metadata_topic, vec!["BlockMetadataEvent"]),
+        (
+            &config.slot_accounts_complete_topic,
+            vec!["SlotAccountsComplete"],
+        ),
+        (&config.watermark_topic, vec!["Watermark"]),
+        (&config.stats_topic, vec!["StatsSnapshot"]),
+    ];
+    topics
+        .into_iter()
+        .filter(|(topic, _)| !topic.is_empty())
+        .map(|(topic, messages)| TopicSchema {
+            topic: topic.clone(),
+            messages: messages
+                .into_iter()
+                .map(|message| match message {
+                    SUBSCRIBE_UPDATE => message.to_owned(),
+                    _ => format!("{}.{}", PROTO_PACKAGE, message),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topic_schemas() {
+        let config = Config {
+            update_account_topic: "accounts".to_owned(),
+            slot_status_topic: "slots".to_owned(),
+            batch_account_updates: true,
+            ..Config::default()
+        };
+        let schemas = topic_schemas(&config);
+        assert_eq!(schemas.len(), 2);
+        assert_eq!(schemas[0].topic, "accounts");
+        assert_eq!(
+            schemas[0].messages,
+            [
+                "blockdaemon.solana.accountsdb_plugin_kafka.types.UpdateAccountEvent",
+                "blockdaemon.solana.accountsdb_plugin_kafka.types.SlotAccountsBatch",
+            ]
+        );
+
+        // Every message named must be declared in the exported proto.
+        for schema in &schemas {
+            for message in &schema.messages {
+                let name = message.rsplit('.').next().unwrap();
+                assert!(EVENT_PROTO.contains(&format!("message {} {{", name)));
+            }
+        }
+        let config = Config {
+            event_schema: EventSchema::Yellowstone,
+            ..config
+        };
+        assert_eq!(topic_schemas(&config)[1].messages, [SUBSCRIBE_UPDATE]);
+
+        // The descriptor set embeds the package name as a plain string.
+        assert!(EVENT_FILE_DESCRIPTOR_SET
+            .windows(PROTO_PACKAGE.len())
+            .any(|w| w == PROTO_PACKAGE.as_bytes()));
+    }
+}
@@ -0,0 +1,72 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {crate::*, std::collections::HashMap};
+
+/// Numbers the published updates of every account 1, 2, 3, ...
+///
+/// A consumer seeing a number other than the last plus one missed or reordered updates.
+/// Numbering restarts at 1 when the plugin is restarted.
+#[derive(Default)]
+pub struct AccountSequences {
+    last: HashMap<[u8; 32], u64>,
+}
+
+impl AccountSequences {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the sequence number of `ev` to the next one of its account.
+    pub fn assign(&mut self, ev: &mut UpdateAccountEvent) {
+        let mut key = [0u8; 32];
+        let len = ev.pubkey.len().min(key.len());
+        key[..len].copy_from_slice(&ev.pubkey[..len]);
+        let last = self.last.entry(key).or_default();
+        *last += 1;
+        ev.sequence = *last;
+    }
+
+    /// Number of accounts numbered so far.
+    pub fn len(&self) -> usize {
+        self.last.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.last.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_account_sequences() {
+        let mut sequences = AccountSequences::new();
+        let mut numbered = |pubkey: u8| {
+            let mut ev = UpdateAccountEvent {
+                pubkey: vec![pubkey; 32].into(),
+                ..UpdateAccountEvent::default()
+            };
+            sequences.assign(&mut ev);
+            ev.sequence
+        };
+        assert_eq!(numbered(1), 1);
+        assert_eq!(numbered(1), 2);
+        assert_eq!(numbered(2), 1);
+        assert_eq!(numbered(1), 3);
+        assert_eq!(sequences.len(), 2);
+    }
+}
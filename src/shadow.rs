@@ -0,0 +1,109 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    serde::Deserialize,
+    std::{
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+    },
+};
+
+/// Mirror of a sample of the records to a shadow topic.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ShadowConfig {
+    pub topic: String,
+    /// Share of the records mirrored, in percent.
+    pub sample_percent: f64,
+    #[serde(default)]
+    pub source: ShadowSource,
+}
+
+/// Which stream the shadow topic mirrors.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ShadowSource {
+    /// Records of every topic, as published.
+    Published,
+    /// Account updates as received, before any filter.
+    Unfiltered,
+}
+
+impl Default for ShadowSource {
+    fn default() -> Self {
+        Self::Published
+    }
+}
+
+impl ShadowConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if self.topic.is_empty() {
+            return Err("shadow requires a topic".to_owned());
+        }
+        if !(self.sample_percent > 0.0 && self.sample_percent <= 100.0) {
+            return Err(format!(
+                "shadow sample_percent {} is not within (0, 100]",
+                self.sample_percent
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Picks the records to mirror by their key, so all records of a sampled account, transaction or slot
+/// are mirrored alike.
+#[derive(Clone, Copy, Debug)]
+pub struct Sampler {
+    /// Sampled keys out of [`Self::RESOLUTION`].
+    threshold: u64,
+}
+
+impl Sampler {
+    const RESOLUTION: u64 = 10_000;
+
+    pub fn new(percent: f64) -> Self {
+        Self {
+            threshold: (percent / 100.0 * Self::RESOLUTION as f64).round() as u64,
+        }
+    }
+
+    pub fn sampled(&self, key: &(impl Hash + ?Sized)) -> bool {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish() % Self::RESOLUTION < self.threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampler() {
+        let all = Sampler::new(100.0);
+        assert!((0..1000u64).all(|key| all.sampled(&key)));
+
+        let tenth = Sampler::new(10.0);
+        let sampled = (0..10_000u64).filter(|key| tenth.sampled(key)).count();
+        assert!((800..1200).contains(&sampled), "{}", sampled);
+        // The same key is always sampled alike.
+        let key = [7u8; 32];
+        assert!((0..10).all(|_| tenth.sampled(&key[..]) == tenth.sampled(&key[..])));
+
+        let config: ShadowConfig =
+            serde_json::from_str(r#"{"topic": "shadow", "sample_percent": 0}"#).unwrap();
+        assert_eq!(config.source, ShadowSource::Published);
+        assert!(config.check().is_err());
+    }
+}
@@ -0,0 +1,131 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    serde::Deserialize,
+    solana_program::pubkey::Pubkey,
+    std::{collections::HashMap, str::FromStr},
+};
+
+/// Byte range of account data to publish, like the `dataSlice` of RPC.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct DataSlice {
+    /// Owner program whose accounts are sliced. Empty applies to accounts of all programs.
+    #[serde(default)]
+    pub owner: String,
+    pub offset: usize,
+    pub length: usize,
+}
+
+/// Cuts account data down to the configured slices of its owner program.
+#[derive(Clone, Debug, Default)]
+pub struct DataSlicer {
+    by_owner: HashMap<[u8; 32], Vec<(usize, usize)>>,
+    /// Slices of accounts whose owner has none of its own.
+    any_owner: Vec<(usize, usize)>,
+}
+
+impl DataSlicer {
+    pub fn new(slices: &[DataSlice]) -> Result<Self, String> {
+        let mut this = Self::default();
+        for slice in slices {
+            let range = (slice.offset, slice.length);
+            if slice.owner.is_empty() {
+                this.any_owner.push(range);
+                continue;
+            }
+            let owner = Pubkey::from_str(&slice.owner).map_err(|e| {
+                format!(
+                    "invalid owner {:?} in account_data_slices: {}",
+                    slice.owner, e
+                )
+            })?;
+            this.by_owner
+                .entry(owner.to_bytes())
+                .or_default()
+                .push(range);
+        }
+        Ok(this)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_owner.is_empty() && self.any_owner.is_empty()
+    }
+
+    /// The configured slices of `data` concatenated in order,
+    /// or `None` if accounts of `owner` are published whole.
+    ///
+    /// Slices reaching past the end of the data are cut short, like RPC does.
+    pub fn slice(&self, owner: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+        let ranges = <[u8; 32]>::try_from(owner)
+            .ok()
+            .and_then(|owner| self.by_owner.get(&owner))
+            .unwrap_or(&self.any_owner);
+        if ranges.is_empty() {
+            return None;
+        }
+        let mut sliced = Vec::with_capacity(ranges.iter().map(|(_, length)| length).sum());
+        for (offset, length) in ranges {
+            let start = (*offset).min(data.len());
+            let end = offset.saturating_add(*length).min(data.len());
+            sliced.extend_from_slice(&data[start..end]);
+        }
+        Some(sliced)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_slicer() {
+        let orderbook = Pubkey::new_unique();
+        let slicer = DataSlicer::new(&[
+            DataSlice {
+                owner: orderbook.to_string(),
+                offset: 8,
+                length: 16,
+            },
+            DataSlice {
+                owner: orderbook.to_string(),
+                offset: 2,
+                length: 2,
+            },
+            DataSlice {
+                owner: "".to_owned(),
+                offset: 0,
+                length: 1,
+            },
+        ])
+        .unwrap();
+        let data: Vec<u8> = (0..20).collect();
+
+        let sliced = slicer.slice(orderbook.as_ref(), &data).unwrap();
+        assert_eq!(
+            sliced,
+            vec![8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 2, 3]
+        );
+        assert_eq!(slicer.slice(&[1; 32], &data), Some(vec![0]));
+        assert_eq!(slicer.slice(&[1; 32], &[]), Some(vec![]));
+        assert_eq!(DataSlicer::new(&[]).unwrap().slice(&[1; 32], &data), None);
+
+        assert!(DataSlicer::new(&[DataSlice {
+            owner: "not a pubkey".to_owned(),
+            offset: 0,
+            length: 1,
+        }])
+        .is_err());
+    }
+}
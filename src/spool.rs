@@ -0,0 +1,267 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::{recording::read_framed, *},
+    crossbeam_channel::{bounded, RecvTimeoutError, Sender},
+    log::{error, info},
+    serde::{Deserialize, Serialize},
+    std::{
+        fs::{self, File},
+        io::{BufReader, Write},
+        path::{Path, PathBuf},
+        sync::Arc,
+        thread::{self, JoinHandle},
+        time::Duration,
+    },
+};
+
+const PREFIX: &str = "spool-";
+const SUFFIX: &str = ".rec";
+
+/// How often the spool is checked for records to replay.
+const REPLAY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where Kafka records are buffered while all brokers are down.
+#[derive(Clone, Debug, Deserialize)]
+pub struct SpoolConfig {
+    /// Directory the spool files are written to.
+    pub directory: String,
+    #[serde(flatten)]
+    pub rotation: Rotation,
+}
+
+/// A Kafka record as it would have been handed to the producer.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpooledRecord {
+    pub topic: String,
+    pub key: Option<Vec<u8>>,
+    pub partition: Option<i32>,
+    pub headers: Vec<(String, Option<Vec<u8>>)>,
+    pub payload: Vec<u8>,
+    /// Delivery opaque, untracked for records spooled by an earlier run.
+    pub opaque: usize,
+    /// Index of the producer shard picked for the record.
+    pub shard: usize,
+}
+
+/// A spool file to replay, oldest first.
+pub struct SpoolFile {
+    pub path: PathBuf,
+    /// Written by an earlier run, whose delivery opaques mean nothing anymore.
+    pub inherited: bool,
+}
+
+/// Rotating files buffering records, written in place of the producers until replayed.
+///
+/// Once active, records keep going to the spool until it is fully replayed, which preserves their order.
+pub struct Spool {
+    config: SpoolConfig,
+    file: Option<RotatingFile>,
+    active: bool,
+    inherited: Vec<PathBuf>,
+    /// Of the paths in `inherited`.
+    memory: MemoryUsage,
+}
+
+impl Spool {
+    /// Opens the spool, which starts out active if an earlier run left records behind.
+    pub fn new(config: SpoolConfig) -> std::io::Result<Self> {
+        fs::create_dir_all(&config.directory)?;
+        let inherited = Self::files(Path::new(&config.directory))?;
+        let mut memory = MemoryUsage::new(MemoryComponent::SpoolIndex);
+        memory.set(
+            inherited
+                .iter()
+                .map(|path| std::mem::size_of::<PathBuf>() + path.as_os_str().len())
+                .sum(),
+        );
+        Ok(Self {
+            config,
+            file: None,
+            active: !inherited.is_empty(),
+            inherited,
+            memory,
+        })
+    }
+
+    /// Whether records go to the spool instead of the producers.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn append(&mut self, record: &SpooledRecord) -> std::io::Result<()> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self.file.insert(RotatingFile::new(
+                &self.config.directory,
+                PREFIX,
+                SUFFIX,
+                self.config.rotation.clone(),
+            )?),
+        };
+        let record = bincode::serialize(record)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        file.append(|writer| {
+            writer.write_all(&(record.len() as u32).to_be_bytes())?;
+            writer.write_all(&record)?;
+            Ok(4 + record.len())
+        })?;
+        self.active = true;
+        Ok(())
+    }
+
+    /// Closes the file being written and returns all spool files to replay.
+    ///
+    /// Deactivates the spool if there are none left.
+    pub fn take_files(&mut self) -> std::io::Result<Vec<SpoolFile>> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+        }
+        let files: Vec<SpoolFile> = Self::files(Path::new(&self.config.directory))?
+            .into_iter()
+            .map(|path| SpoolFile {
+                inherited: self.inherited.contains(&path),
+                path,
+            })
+            .collect();
+        if files.is_empty() {
+            self.active = false;
+            self.inherited.clear();
+            self.memory.set(0);
+        }
+        Ok(files)
+    }
+
+    fn files(directory: &Path) -> std::io::Result<Vec<PathBuf>> {
+        let mut files: Vec<PathBuf> = fs::read_dir(directory)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .map_or(false, |name| {
+                        name.starts_with(PREFIX) && name.ends_with(SUFFIX)
+                    })
+            })
+            .collect();
+        files.sort();
+        Ok(files)
+    }
+}
+
+/// Reads the records of a spool file, in order.
+pub fn read_spool_file(
+    path: &Path,
+    mut f: impl FnMut(SpooledRecord),
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut record = Vec::new();
+    while read_framed(&mut reader, &mut record)? {
+        f(bincode::deserialize(&record)?);
+    }
+    Ok(())
+}
+
+/// Replays the spool through the producers whenever the brokers are reachable.
+pub struct SpoolReplayer {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl SpoolReplayer {
+    pub fn spawn(publisher: Arc<Publisher>) -> Self {
+        let (stop, stopped) = bounded(0);
+        let thread = thread::Builder::new()
+            .name("kafka-spool-replay".to_owned())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(REPLAY_INTERVAL) {
+                    let replayed = publisher.replay_spool();
+                    if replayed > 0 {
+                        info!("Replayed {} spooled records", replayed);
+                    }
+                }
+            })
+            .expect("failed to spawn spool replay thread");
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for SpoolReplayer {
+    fn drop(&mut self) {
+        self.stop = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("Spool replay thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spool() {
+        let dir = std::env::temp_dir().join(format!("kafka-plugin-spool-{}", std::process::id()));
+        let config = SpoolConfig {
+            directory: dir.to_str().unwrap().to_owned(),
+            rotation: Rotation {
+                max_file_bytes: 1,
+                max_file_age_sec: 0,
+                max_files: 0,
+            },
+        };
+        let record = |opaque| SpooledRecord {
+            topic: "topic".to_owned(),
+            key: Some(vec![1, 2]),
+            partition: None,
+            headers: vec![("fork-slots".to_owned(), Some(b"1,2".to_vec()))],
+            payload: vec![opaque as u8; 10],
+            opaque,
+            shard: 0,
+        };
+
+        let mut spool = Spool::new(config.clone()).unwrap();
+        assert!(!spool.is_active());
+        spool.append(&record(1)).unwrap();
+        spool.append(&record(2)).unwrap();
+        assert!(spool.is_active());
+        drop(spool);
+
+        // Left behind by an earlier run.
+        let mut spool = Spool::new(config).unwrap();
+        assert!(spool.is_active());
+        spool.append(&record(3)).unwrap();
+        let files = spool.take_files().unwrap();
+        assert_eq!(
+            files.iter().map(|file| file.inherited).collect::<Vec<_>>(),
+            [true, true, false]
+        );
+        let mut records = Vec::new();
+        for file in &files {
+            read_spool_file(&file.path, |record| records.push(record)).unwrap();
+            fs::remove_file(&file.path).unwrap();
+        }
+        assert_eq!(records, [record(1), record(2), record(3)]);
+        assert!(spool.is_active());
+        assert!(spool.take_files().unwrap().is_empty());
+        assert!(!spool.is_active());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
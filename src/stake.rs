@@ -0,0 +1,101 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    solana_program::stake::state::{Meta, StakeState},
+};
+
+/// Decodes a stake account, unless it is uninitialized.
+pub fn decode_stake(data: &[u8]) -> Option<decoded_account::Account> {
+    let (state, meta, stake) = match bincode::deserialize(data).ok()? {
+        StakeState::Uninitialized => return None,
+        StakeState::Initialized(meta) => ("initialized", Some(meta), None),
+        StakeState::Stake(meta, stake) => ("delegated", Some(meta), Some(stake)),
+        StakeState::RewardsPool => ("rewards_pool", None, None),
+    };
+    let Meta {
+        rent_exempt_reserve,
+        authorized,
+        lockup,
+    } = meta.unwrap_or_default();
+    Some(decoded_account::Account::StakeAccount(StakeAccount {
+        state: state.to_owned(),
+        rent_exempt_reserve,
+        staker: authorized.staker.to_bytes().into(),
+        withdrawer: authorized.withdrawer.to_bytes().into(),
+        lockup_unix_timestamp: lockup.unix_timestamp,
+        lockup_epoch: lockup.epoch,
+        lockup_custodian: lockup.custodian.to_bytes().into(),
+        delegation: stake.map(|stake| StakeDelegation {
+            voter: stake.delegation.voter_pubkey.to_bytes().into(),
+            stake: stake.delegation.stake,
+            activation_epoch: stake.delegation.activation_epoch,
+            deactivation_epoch: stake.delegation.deactivation_epoch,
+            warmup_cooldown_rate: stake.delegation.warmup_cooldown_rate,
+        }),
+        credits_observed: stake.map_or(0, |stake| stake.credits_observed),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_program::{
+            pubkey::Pubkey,
+            stake::state::{Authorized, Delegation, Lockup, Stake},
+        },
+    };
+
+    #[test]
+    fn test_decode_stake() {
+        let meta = Meta {
+            rent_exempt_reserve: 2_282_880,
+            authorized: Authorized {
+                staker: Pubkey::new_from_array([1; 32]),
+                withdrawer: Pubkey::new_from_array([2; 32]),
+            },
+            lockup: Lockup::default(),
+        };
+        let stake = Stake {
+            delegation: Delegation {
+                voter_pubkey: Pubkey::new_from_array([3; 32]),
+                stake: 5_000_000_000,
+                activation_epoch: 300,
+                ..Delegation::default()
+            },
+            credits_observed: 42,
+        };
+        let mut data = bincode::serialize(&StakeState::Stake(meta, stake)).unwrap();
+        data.resize(200, 0);
+
+        let account = match decode_stake(&data) {
+            Some(decoded_account::Account::StakeAccount(account)) => account,
+            other => panic!("unexpected decoding {:?}", other),
+        };
+        assert_eq!(account.state, "delegated");
+        assert_eq!(account.staker, vec![1; 32]);
+        assert_eq!(account.withdrawer, vec![2; 32]);
+        assert_eq!(account.credits_observed, 42);
+        let delegation = account.delegation.unwrap();
+        assert_eq!(delegation.voter, vec![3; 32]);
+        assert_eq!(delegation.activation_epoch, 300);
+        assert_eq!(delegation.deactivation_epoch, u64::MAX);
+
+        let data = bincode::serialize(&StakeState::Uninitialized).unwrap();
+        assert_eq!(decode_stake(&data), None);
+        assert_eq!(decode_stake(&[7; 4]), None);
+    }
+}
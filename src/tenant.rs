@@ -0,0 +1,340 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    serde::Deserialize,
+    simple_error::SimpleError,
+    solana_program::pubkey::Pubkey,
+    std::{collections::HashMap, sync::Arc},
+};
+
+/// A customer of a shared validator, publishing to its own topics with its own filters.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TenantConfig {
+    /// Name of the tenant, appended to the `client.id` of its producers.
+    pub name: String,
+    /// Headers added to every record of the tenant.
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    /// The `producer_identities` entry of the tenant's producers, the root's `producer_identity` if empty.
+    #[serde(default)]
+    pub producer_identity: String,
+    #[serde(default)]
+    pub update_account_topic: String,
+    #[serde(default)]
+    pub startup_account_topic: String,
+    #[serde(default)]
+    pub slot_status_topic: String,
+    #[serde(default)]
+    pub transaction_topic: String,
+    #[serde(default)]
+    pub vote_transaction_topic: String,
+    #[serde(default)]
+    pub transaction_status_topic: String,
+    #[serde(default)]
+    pub block_metadata_topic: String,
+    #[serde(default)]
+    pub program_ignores: Vec<String>,
+    #[serde(default)]
+    pub program_allowlist: Vec<String>,
+    #[serde(default)]
+    pub program_allowlist_url: String,
+    #[serde(default)]
+    pub program_allowlist_expiry_sec: u64,
+    #[serde(default)]
+    pub topic_rules: Vec<TopicRule>,
+    #[serde(default)]
+    pub unrouted_account_topic: String,
+    #[serde(default)]
+    pub drop_unrouted_accounts: bool,
+    #[serde(default)]
+    pub filter_transactions_by_program: bool,
+    #[serde(default)]
+    pub min_transaction_fee_lamports: u64,
+    #[serde(default)]
+    pub min_compute_units: u64,
+    /// Messages per second the tenant may publish, 0 for no limit.
+    #[serde(default)]
+    pub max_messages_per_sec: u64,
+    /// Encoded bytes per second the tenant may publish, 0 for no limit.
+    #[serde(default)]
+    pub max_bytes_per_sec: u64,
+}
+
+impl TenantConfig {
+    /// Config of the tenant's pipeline: the connection and delivery settings of `root`,
+    /// with the tenant's topics and filters and none of the root-only features.
+    pub fn config(&self, root: &Config) -> Config {
+        let identity = if self.producer_identity.is_empty() {
+            &root.producer_identity
+        } else {
+            &self.producer_identity
+        };
+        let identity = root.producer_identities.get(identity);
+        let mut kafka = root.kafka.clone();
+        kafka.extend(identity.cloned().unwrap_or_default());
+        // Only an identity of the tenant's own names its producers.
+        let named = !self.producer_identity.is_empty()
+            && identity.map_or(false, |options| options.contains_key("client.id"));
+        if !named {
+            let client_id = kafka
+                .get("client.id")
+                .map(String::as_str)
+                .unwrap_or("rdkafka");
+            let client_id = format!("{}-{}", client_id, self.name);
+            kafka.insert("client.id".to_owned(), client_id);
+        }
+        Config {
+            kafka,
+            sasl_oauthbearer: root.sasl_oauthbearer.clone(),
+            payload_encryption: root.payload_encryption.clone(),
+            payload_compression: root.payload_compression.clone(),
+            sink: root.sink.clone(),
+            shutdown_timeout_ms: root.shutdown_timeout_ms,
+            flush_interval_ms: root.flush_interval_ms,
+            idempotency_key: root.idempotency_key,
+            account_payload_format: root.account_payload_format,
+            event_schema: root.event_schema,
+            max_message_bytes: root.max_message_bytes,
+            max_in_flight_messages: root.max_in_flight_messages,
+            max_in_flight_bytes: root.max_in_flight_bytes,
+            queue_capacity: root.queue_capacity,
+            queue_overflow_policy: root.queue_overflow_policy,
+            producer_shards: root.producer_shards,
+            serialization_workers: root.serialization_workers,
+            // Failed producers are only recreated for the root pipeline.
+            recreate_failed_producers: false,
+            update_account_topic: self.update_account_topic.clone(),
+            startup_account_topic: self.startup_account_topic.clone(),
+            slot_status_topic: self.slot_status_topic.clone(),
+            transaction_topic: self.transaction_topic.clone(),
+            vote_transaction_topic: self.vote_transaction_topic.clone(),
+            transaction_status_topic: self.transaction_status_topic.clone(),
+            block_metadata_topic: self.block_metadata_topic.clone(),
+            program_ignores: self.program_ignores.clone(),
+            program_allowlist: self.program_allowlist.clone(),
+            program_allowlist_url: self.program_allowlist_url.clone(),
+            program_allowlist_tls_cert: root.program_allowlist_tls_cert.clone(),
+            program_allowlist_tls_key: root.program_allowlist_tls_key.clone(),
+            program_allowlist_tls_ca: root.program_allowlist_tls_ca.clone(),
+            program_allowlist_expiry_sec: self.program_allowlist_expiry_sec,
+            topic_rules: self.topic_rules.clone(),
+            unrouted_account_topic: self.unrouted_account_topic.clone(),
+            drop_unrouted_accounts: self.drop_unrouted_accounts,
+            filter_transactions_by_program: self.filter_transactions_by_program,
+            min_transaction_fee_lamports: self.min_transaction_fee_lamports,
+            min_compute_units: self.min_compute_units,
+            ..Config::default()
+        }
+    }
+
+    /// `labels` in a stable order, as they are added to the records.
+    pub fn sorted_labels(&self) -> Vec<(String, String)> {
+        let mut labels: Vec<(String, String)> = self
+            .labels
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        labels.sort();
+        labels
+    }
+}
+
+/// Pipeline of a tenant, filtering and publishing events independently of the root one.
+pub struct Tenant {
+    name: String,
+    filter: Filter,
+    dispatcher: Dispatcher,
+    filter_transactions_by_program: bool,
+    quota: Option<Quota>,
+    rate_limited: Arc<Counter>,
+}
+
+impl Tenant {
+    pub fn new(config: &TenantConfig, filter: Filter, dispatcher: Dispatcher) -> Self {
+        Self {
+            name: config.name.clone(),
+            filter,
+            dispatcher,
+            filter_transactions_by_program: config.filter_transactions_by_program,
+            quota: Quota::new(config.max_messages_per_sec, config.max_bytes_per_sec),
+            rate_limited: labeled_counter(
+                "tenant_messages_rate_limited",
+                &[("tenant", &config.name)],
+            ),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn dispatcher(&self) -> &Dispatcher {
+        &self.dispatcher
+    }
+
+    /// Whether an update of an account owned by `owner` is published, accounting for it if filtered.
+    pub fn wants_account(&self, is_startup: bool, owner: &[u8]) -> bool {
+        let wanted = if is_startup {
+            self.dispatcher.wants_startup_account()
+        } else {
+            self.dispatcher.wants_update_account()
+        };
+        if !wanted {
+            return false;
+        }
+        if !self.filter.wants_program(owner) {
+            let topic = self.dispatcher.publisher().account_topic(is_startup);
+            drops().dropped(topic, DropReason::Filtered, self.filter.program_filter());
+            return false;
+        }
+        // Trigger an update of the remote allowlist but don't wait for it to complete.
+        self.filter
+            .get_allowlist()
+            .update_from_http_if_needed_async();
+        true
+    }
+
+    /// Dispatches `event` unless it exceeds the tenant's quota, in which case it is dropped.
+    pub fn publish(&self, event: Event) -> Result<(), SimpleError> {
+        if let Some(quota) = &self.quota {
+            if let Err(limit) = quota.admit(event.encoded_len()) {
+                let topic = self.dispatcher.publisher().topic_of(&event);
+                drops().dropped(topic, DropReason::RateLimited, limit);
+                self.rate_limited.inc();
+                return Ok(());
+            }
+        }
+        self.dispatcher.dispatch(event)
+    }
+
+    /// Whether a transaction is published, accounting for it if filtered.
+    ///
+    /// `compute_units` and `programs` are only evaluated if the tenant filters by them.
+    pub fn wants_transaction(
+        &self,
+        is_vote: bool,
+        fee: u64,
+        compute_units: impl FnOnce() -> u64,
+        programs: impl FnOnce() -> Vec<Pubkey>,
+    ) -> bool {
+        let publisher = self.dispatcher.publisher();
+        let wants_kind = publisher.wants_transaction_kind(is_vote);
+        if !wants_kind && !publisher.wants_transaction_status() {
+            return false;
+        }
+        let compute_units = if self.filter.filters_compute_units() {
+            compute_units()
+        } else {
+            0
+        };
+        let mut rejected_by = self.filter.transaction_cost_filter(fee, compute_units);
+        if rejected_by.is_none()
+            && self.filter_transactions_by_program
+            && !self
+                .filter
+                .wants_any_program(programs().iter().map(|p| p.as_ref()))
+        {
+            rejected_by = Some(self.filter.program_filter());
+        }
+        let rejected_by = match rejected_by {
+            Some(rejected_by) => rejected_by,
+            None => return true,
+        };
+        if wants_kind {
+            let topic = publisher.transaction_topic_for(is_vote);
+            drops().dropped(topic, DropReason::Filtered, rejected_by);
+        }
+        if publisher.wants_transaction_status() {
+            let topic = publisher.transaction_status_topic();
+            drops().dropped(topic, DropReason::Filtered, rejected_by);
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenant_config() {
+        let root = Config {
+            kafka: [("client.id".to_owned(), "validator".to_owned())].into(),
+            update_account_topic: "accounts".to_owned(),
+            program_allowlist: vec!["11111111111111111111111111111111".to_owned()],
+            stats_topic: "stats".to_owned(),
+            producer_shards: 2,
+            ..Config::default()
+        };
+        let tenant: TenantConfig = serde_json::from_str(
+            r#"{
+                "name": "acme",
+                "labels": {"team": "acme", "env": "prod"},
+                "transaction_topic": "acme.transactions",
+                "min_transaction_fee_lamports": 5000
+            }"#,
+        )
+        .unwrap();
+        let config = tenant.config(&root);
+
+        assert_eq!(config.kafka["client.id"], "validator-acme");
+        assert_eq!(config.producer_shards, 2);
+        assert_eq!(config.update_account_topic, "");
+        assert_eq!(config.transaction_topic, "acme.transactions");
+        assert_eq!(config.min_transaction_fee_lamports, 5000);
+        assert!(config.program_allowlist.is_empty());
+        assert!(config.stats_topic.is_empty());
+
+        let root = Config {
+            producer_identities: [
+                (
+                    "shared".to_owned(),
+                    [("sasl.username".to_owned(), "shared".to_owned())].into(),
+                ),
+                (
+                    "acme".to_owned(),
+                    [
+                        ("sasl.username".to_owned(), "acme".to_owned()),
+                        ("client.id".to_owned(), "acme-producer".to_owned()),
+                    ]
+                    .into(),
+                ),
+            ]
+            .into(),
+            producer_identity: "shared".to_owned(),
+            ..root
+        };
+        let inherited = tenant.config(&root);
+        assert_eq!(inherited.kafka["sasl.username"], "shared");
+        assert_eq!(inherited.kafka["client.id"], "validator-acme");
+        let bound = TenantConfig {
+            producer_identity: "acme".to_owned(),
+            ..tenant.clone()
+        };
+        let bound = bound.config(&root);
+        assert_eq!(bound.kafka["sasl.username"], "acme");
+        assert_eq!(bound.kafka["client.id"], "acme-producer");
+        assert_eq!(bound.producer_options(), bound.kafka);
+
+        assert_eq!(
+            tenant.sorted_labels(),
+            [
+                ("env".to_owned(), "prod".to_owned()),
+                ("team".to_owned(), "acme".to_owned())
+            ]
+        );
+    }
+}
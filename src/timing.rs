@@ -0,0 +1,133 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    solana_geyser_plugin_interface::geyser_plugin_interface::SlotStatus as PluginSlotStatus,
+    std::{
+        collections::BTreeMap,
+        time::{Duration, Instant},
+    },
+};
+
+#[derive(Default)]
+struct SlotTimes {
+    processed: Option<Instant>,
+    confirmed: Option<Instant>,
+}
+
+/// Time elapsed until a slot reached its current status.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SlotElapsed {
+    pub since_processed: Option<Duration>,
+    pub since_confirmed: Option<Duration>,
+}
+
+/// Remembers when every slot was first processed and confirmed, until it is rooted.
+#[derive(Default)]
+pub struct SlotTimer {
+    slots: BTreeMap<u64, SlotTimes>,
+}
+
+impl SlotTimer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Records the status transition of `slot`
+    /// and returns the time elapsed since its earlier transitions.
+    pub fn record(&mut self, slot: u64, status: PluginSlotStatus) -> SlotElapsed {
+        self.record_at(slot, status, Instant::now())
+    }
+
+    fn record_at(&mut self, slot: u64, status: PluginSlotStatus, now: Instant) -> SlotElapsed {
+        let times = match status {
+            PluginSlotStatus::Rooted => {
+                // Older slots are rooted already or abandoned.
+                let mut newer = self.slots.split_off(&slot);
+                self.slots = newer.split_off(&(slot + 1));
+                newer.remove(&slot).unwrap_or_default()
+            }
+            _ => {
+                let times = self.slots.entry(slot).or_default();
+                match status {
+                    PluginSlotStatus::Processed => {
+                        times.processed.get_or_insert(now);
+                    }
+                    _ => {
+                        times.confirmed.get_or_insert(now);
+                    }
+                }
+                SlotTimes {
+                    processed: times.processed,
+                    confirmed: times.confirmed,
+                }
+            }
+        };
+
+        let elapsed = |since: Option<Instant>| since.map(|t| now.saturating_duration_since(t));
+        match status {
+            PluginSlotStatus::Processed => SlotElapsed::default(),
+            PluginSlotStatus::Confirmed => SlotElapsed {
+                since_processed: elapsed(times.processed),
+                since_confirmed: None,
+            },
+            PluginSlotStatus::Rooted => SlotElapsed {
+                since_processed: elapsed(times.processed),
+                since_confirmed: elapsed(times.confirmed),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slot_timer() {
+        let mut timer = SlotTimer::new();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert_eq!(
+            timer.record_at(10, PluginSlotStatus::Processed, at(0)),
+            SlotElapsed::default()
+        );
+        timer.record_at(11, PluginSlotStatus::Processed, at(400));
+        // Repeated notifications don't reset the clock.
+        timer.record_at(10, PluginSlotStatus::Processed, at(500));
+        assert_eq!(
+            timer.record_at(10, PluginSlotStatus::Confirmed, at(1_000)),
+            SlotElapsed {
+                since_processed: Some(Duration::from_millis(1_000)),
+                since_confirmed: None,
+            }
+        );
+        assert_eq!(
+            timer.record_at(10, PluginSlotStatus::Rooted, at(13_000)),
+            SlotElapsed {
+                since_processed: Some(Duration::from_millis(13_000)),
+                since_confirmed: Some(Duration::from_millis(12_000)),
+            }
+        );
+        assert_eq!(timer.slots.len(), 1);
+
+        // Slots never seen before rooting have no timings.
+        assert_eq!(
+            timer.record_at(12, PluginSlotStatus::Rooted, at(14_000)),
+            SlotElapsed::default()
+        );
+        assert!(timer.slots.is_empty());
+    }
+}
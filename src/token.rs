@@ -0,0 +1,375 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Decoding of SPL Token and Token-2022 mints and token accounts.
+//!
+//! Layouts are read directly rather than through `spl-token-2022`, whose version pinned
+//! by Solana 1.13 predates extensions such as the metadata pointer.
+
+use {crate::*, lazy_static::lazy_static, solana_program::pubkey::Pubkey, std::str::FromStr};
+
+lazy_static! {
+    pub(crate) static ref TOKEN_PROGRAM_ID: Pubkey =
+        Pubkey::from_str("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA").unwrap();
+    pub(crate) static ref TOKEN_2022_PROGRAM_ID: Pubkey =
+        Pubkey::from_str("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb").unwrap();
+}
+
+/// Length of a token account, and of the base of every Token-2022 account with extensions.
+const ACCOUNT_LEN: usize = 165;
+const MINT_LEN: usize = 82;
+/// Length of a multisig account, which Token-2022 never extends.
+const MULTISIG_LEN: usize = 355;
+/// Token-2022 `AccountType` following the base of accounts with extensions.
+const ACCOUNT_TYPE_MINT: u8 = 1;
+const ACCOUNT_TYPE_ACCOUNT: u8 = 2;
+
+/// Token-2022 `ExtensionType`s decoded into a [`TokenExtension`].
+const TRANSFER_FEE_CONFIG: u16 = 1;
+const TRANSFER_FEE_AMOUNT: u16 = 2;
+const CONFIDENTIAL_TRANSFER_MINT: u16 = 4;
+const CONFIDENTIAL_TRANSFER_ACCOUNT: u16 = 5;
+const INTEREST_BEARING_CONFIG: u16 = 10;
+const METADATA_POINTER: u16 = 18;
+
+/// Whether `owner` is the SPL Token program, `Some(false)`, or Token-2022, `Some(true)`.
+pub fn token_program(owner: &[u8]) -> Option<bool> {
+    if owner == TOKEN_PROGRAM_ID.as_ref() {
+        Some(false)
+    } else if owner == TOKEN_2022_PROGRAM_ID.as_ref() {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+/// Decodes a mint or token account of the SPL Token or, with `token_2022`, the Token-2022 program.
+///
+/// Multisig accounts and anything not initialized are not decoded.
+pub fn decode_token(token_2022: bool, data: &[u8]) -> Option<decoded_account::Account> {
+    let account_type = match data.len() {
+        ACCOUNT_LEN => ACCOUNT_TYPE_ACCOUNT,
+        MINT_LEN => ACCOUNT_TYPE_MINT,
+        // The byte after the base is part of a signer.
+        MULTISIG_LEN => return None,
+        len if token_2022 && len > ACCOUNT_LEN => data[ACCOUNT_LEN],
+        _ => return None,
+    };
+    let extensions = data
+        .get(ACCOUNT_LEN + 1..)
+        .filter(|_| token_2022)
+        .map(decode_extensions)
+        .unwrap_or_default();
+    let data = Fields(data);
+    match account_type {
+        ACCOUNT_TYPE_ACCOUNT => {
+            let state = match data.u8(108)? {
+                0 => return None,
+                1 => "initialized",
+                2 => "frozen",
+                _ => return None,
+            };
+            Some(decoded_account::Account::TokenAccount(TokenAccount {
+                token_2022,
+                mint: data.pubkey(0)?,
+                owner: data.pubkey(32)?,
+                amount: data.u64(64)?,
+                delegate: data.optional_pubkey(72)?,
+                state: state.to_owned(),
+                native_reserve: match data.u32(109)? {
+                    0 => None,
+                    _ => Some(data.u64(113)?),
+                },
+                delegated_amount: data.u64(121)?,
+                close_authority: data.optional_pubkey(129)?,
+                extensions,
+            }))
+        }
+        ACCOUNT_TYPE_MINT => {
+            let is_initialized = data.u8(45)? != 0;
+            if !is_initialized {
+                return None;
+            }
+            Some(decoded_account::Account::TokenMint(TokenMint {
+                token_2022,
+                mint_authority: data.optional_pubkey(0)?,
+                supply: data.u64(36)?,
+                decimals: data.u8(44)? as u32,
+                is_initialized,
+                freeze_authority: data.optional_pubkey(46)?,
+                extensions,
+            }))
+        }
+        _ => None,
+    }
+}
+
+/// Decodes the type-length-value extensions following the account type.
+fn decode_extensions(mut tlv: &[u8]) -> Vec<TokenExtension> {
+    use token_extension::Extension;
+    let mut extensions = Vec::new();
+    while tlv.len() >= 4 {
+        let extension_type = u16::from_le_bytes([tlv[0], tlv[1]]);
+        let len = u16::from_le_bytes([tlv[2], tlv[3]]) as usize;
+        // Uninitialized entries pad the end of the account.
+        if extension_type == 0 || tlv.len() < 4 + len {
+            break;
+        }
+        let value = Fields(&tlv[4..4 + len]);
+        let extension = match extension_type {
+            TRANSFER_FEE_CONFIG => value
+                .transfer_fee_config()
+                .map(Extension::TransferFeeConfig),
+            TRANSFER_FEE_AMOUNT => value.u64(0).map(Extension::WithheldTransferFees),
+            CONFIDENTIAL_TRANSFER_MINT => value
+                .confidential_transfer_mint()
+                .map(Extension::ConfidentialTransferMint),
+            CONFIDENTIAL_TRANSFER_ACCOUNT => value
+                .confidential_transfer_account()
+                .map(Extension::ConfidentialTransferAccount),
+            INTEREST_BEARING_CONFIG => value
+                .interest_bearing_config()
+                .map(Extension::InterestBearingConfig),
+            METADATA_POINTER => value.metadata_pointer().map(Extension::MetadataPointer),
+            _ => None,
+        };
+        extensions.push(TokenExtension {
+            extension: Some(extension.unwrap_or(Extension::Other(extension_type as u32))),
+        });
+        tlv = &tlv[4 + len..];
+    }
+    extensions
+}
+
+/// Little-endian fields at fixed offsets.
+#[derive(Clone, Copy)]
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn get<const N: usize>(self, offset: usize) -> Option<[u8; N]> {
+        self.0.get(offset..offset + N)?.try_into().ok()
+    }
+
+    fn u8(self, offset: usize) -> Option<u8> {
+        self.0.get(offset).copied()
+    }
+
+    fn u16(self, offset: usize) -> Option<u16> {
+        self.get(offset).map(u16::from_le_bytes)
+    }
+
+    fn u32(self, offset: usize) -> Option<u32> {
+        self.get(offset).map(u32::from_le_bytes)
+    }
+
+    fn u64(self, offset: usize) -> Option<u64> {
+        self.get(offset).map(u64::from_le_bytes)
+    }
+
+    fn i64(self, offset: usize) -> Option<i64> {
+        self.get(offset).map(i64::from_le_bytes)
+    }
+
+    fn pubkey(self, offset: usize) -> Option<Vec<u8>> {
+        self.get::<32>(offset).map(Vec::from)
+    }
+
+    /// `COption<Pubkey>`, a four byte tag followed by the key, empty if unset.
+    fn optional_pubkey(self, offset: usize) -> Option<Vec<u8>> {
+        match self.u32(offset)? {
+            0 => Some(Vec::new()),
+            _ => self.pubkey(offset + 4),
+        }
+    }
+
+    /// `OptionalNonZeroPubkey` of the extensions, all zeros if unset.
+    fn nonzero_pubkey(self, offset: usize) -> Option<Vec<u8>> {
+        let key = self.get::<32>(offset)?;
+        Some(if key == [0; 32] {
+            Vec::new()
+        } else {
+            key.into()
+        })
+    }
+
+    fn transfer_fee(self, offset: usize) -> Option<TransferFee> {
+        Some(TransferFee {
+            epoch: self.u64(offset)?,
+            maximum_fee: self.u64(offset + 8)?,
+            transfer_fee_basis_points: self.u16(offset + 16)? as u32,
+        })
+    }
+
+    fn transfer_fee_config(self) -> Option<TransferFeeConfig> {
+        Some(TransferFeeConfig {
+            transfer_fee_config_authority: self.nonzero_pubkey(0)?,
+            withdraw_withheld_authority: self.nonzero_pubkey(32)?,
+            withheld_amount: self.u64(64)?,
+            older_transfer_fee: Some(self.transfer_fee(72)?),
+            newer_transfer_fee: Some(self.transfer_fee(90)?),
+        })
+    }
+
+    fn interest_bearing_config(self) -> Option<InterestBearingConfig> {
+        Some(InterestBearingConfig {
+            rate_authority: self.nonzero_pubkey(0)?,
+            initialization_timestamp: self.i64(32)?,
+            pre_update_average_rate: self.u16(40)? as i16 as i32,
+            last_update_timestamp: self.i64(42)?,
+            current_rate: self.u16(50)? as i16 as i32,
+        })
+    }
+
+    fn confidential_transfer_mint(self) -> Option<ConfidentialTransferMint> {
+        Some(ConfidentialTransferMint {
+            authority: self.nonzero_pubkey(0)?,
+            auto_approve_new_accounts: self.u8(32)? != 0,
+            auditor_elgamal_pubkey: self.nonzero_pubkey(33)?,
+        })
+    }
+
+    /// The flags of the confidential transfer account layout of the deployed program,
+    /// after the ElGamal key, the encrypted pending and available balances and the decryptable balance.
+    fn confidential_transfer_account(self) -> Option<ConfidentialTransferAccount> {
+        const FLAGS: usize = 1 + 32 + 64 + 64 + 64 + 36;
+        if self.0.len() != FLAGS + 2 + 4 * 8 {
+            return None;
+        }
+        Some(ConfidentialTransferAccount {
+            approved: self.u8(0)? != 0,
+            allow_confidential_credits: self.u8(FLAGS)? != 0,
+            allow_non_confidential_credits: self.u8(FLAGS + 1)? != 0,
+            pending_balance_credit_counter: self.u64(FLAGS + 2)?,
+        })
+    }
+
+    fn metadata_pointer(self) -> Option<MetadataPointer> {
+        Some(MetadataPointer {
+            authority: self.nonzero_pubkey(0)?,
+            metadata_address: self.nonzero_pubkey(32)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token_account(extensions: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut data = vec![0; ACCOUNT_LEN];
+        data[..32].copy_from_slice(&[1; 32]);
+        data[32..64].copy_from_slice(&[2; 32]);
+        data[64..72].copy_from_slice(&500u64.to_le_bytes());
+        data[108] = 1;
+        if !extensions.is_empty() {
+            data.push(ACCOUNT_TYPE_ACCOUNT);
+            for (extension_type, value) in extensions {
+                data.extend_from_slice(&extension_type.to_le_bytes());
+                data.extend_from_slice(&(value.len() as u16).to_le_bytes());
+                data.extend_from_slice(value);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_decode_token_account() {
+        let account = match decode_token(false, &token_account(&[])) {
+            Some(decoded_account::Account::TokenAccount(account)) => account,
+            other => panic!("unexpected decoding {:?}", other),
+        };
+        assert_eq!(account.mint, vec![1; 32]);
+        assert_eq!(account.owner, vec![2; 32]);
+        assert_eq!(account.amount, 500);
+        assert_eq!(account.state, "initialized");
+        assert!(account.delegate.is_empty());
+        assert_eq!(account.native_reserve, None);
+        assert!(account.extensions.is_empty());
+
+        let mut pointer = vec![3; 32];
+        pointer.extend_from_slice(&[4; 32]);
+        let data = token_account(&[
+            (TRANSFER_FEE_AMOUNT, 42u64.to_le_bytes().to_vec()),
+            (7, Vec::new()),
+            (METADATA_POINTER, pointer),
+        ]);
+        assert_eq!(decode_token(false, &data), None);
+        let account = match decode_token(true, &data) {
+            Some(decoded_account::Account::TokenAccount(account)) => account,
+            other => panic!("unexpected decoding {:?}", other),
+        };
+        let extensions: Vec<token_extension::Extension> = account
+            .extensions
+            .into_iter()
+            .map(|extension| extension.extension.unwrap())
+            .collect();
+        assert_eq!(
+            extensions,
+            vec![
+                token_extension::Extension::WithheldTransferFees(42),
+                token_extension::Extension::Other(7),
+                token_extension::Extension::MetadataPointer(MetadataPointer {
+                    authority: vec![3; 32],
+                    metadata_address: vec![4; 32],
+                }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_token_mint() {
+        let mut data = vec![0; ACCOUNT_LEN];
+        data[44] = 6;
+        data[45] = 1;
+        data[36..44].copy_from_slice(&1_000_000u64.to_le_bytes());
+        data.push(ACCOUNT_TYPE_MINT);
+        let mut config = vec![5; 32];
+        config.extend_from_slice(&1_650_000_000i64.to_le_bytes());
+        config.extend_from_slice(&(-25i16).to_le_bytes());
+        config.extend_from_slice(&1_660_000_000i64.to_le_bytes());
+        config.extend_from_slice(&300i16.to_le_bytes());
+        data.extend_from_slice(&INTEREST_BEARING_CONFIG.to_le_bytes());
+        data.extend_from_slice(&(config.len() as u16).to_le_bytes());
+        data.extend_from_slice(&config);
+        // Padding of a reallocated account.
+        data.extend_from_slice(&[0; 8]);
+
+        let mint = match decode_token(true, &data) {
+            Some(decoded_account::Account::TokenMint(mint)) => mint,
+            other => panic!("unexpected decoding {:?}", other),
+        };
+        assert_eq!(mint.decimals, 6);
+        assert_eq!(mint.supply, 1_000_000);
+        assert!(mint.mint_authority.is_empty());
+        assert_eq!(mint.extensions.len(), 1);
+        let config = match mint.extensions[0].extension.clone() {
+            Some(token_extension::Extension::InterestBearingConfig(config)) => config,
+            other => panic!("unexpected extension {:?}", other),
+        };
+        assert_eq!(config.rate_authority, vec![5; 32]);
+        assert_eq!(config.pre_update_average_rate, -25);
+        assert_eq!(config.current_rate, 300);
+    }
+
+    #[test]
+    fn test_decode_token_multisig() {
+        // Signers whose bytes look like the account type of a mint or token account.
+        let mut data = vec![ACCOUNT_TYPE_MINT; MULTISIG_LEN];
+        data[..3].copy_from_slice(&[2, 3, 1]);
+        assert_eq!(decode_token(true, &data), None);
+        data[ACCOUNT_LEN] = ACCOUNT_TYPE_ACCOUNT;
+        assert_eq!(decode_token(true, &data), None);
+        assert_eq!(decode_token(false, &data), None);
+    }
+}
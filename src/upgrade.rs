@@ -0,0 +1,234 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {
+    crate::*,
+    sha2::{Digest, Sha256},
+    solana_program::{
+        bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+        pubkey::Pubkey,
+    },
+    std::collections::{HashMap, HashSet},
+};
+
+/// Detects upgrades of programs of the BPF upgradeable loader from updates of their programdata accounts.
+///
+/// A programdata account does not name its program, so programdata addresses are derived from the allowlisted
+/// programs and learned from the program accounts seen.
+#[derive(Default)]
+pub struct ProgramUpgrades {
+    /// Programs by the address of their programdata account.
+    programs: HashMap<[u8; 32], [u8; 32]>,
+    /// Allowlisted programs whose programdata address was derived already.
+    derived: HashSet<[u8; 32]>,
+}
+
+impl ProgramUpgrades {
+    /// A `ProgramUpgraded` event if `data` is the programdata of a program wanted by `filter`.
+    ///
+    /// Programs whose programdata is unknown are only reported, without program ID, if all programs are wanted.
+    pub fn detect(
+        &mut self,
+        slot: u64,
+        info: &AccountInfo,
+        filter: &Filter,
+    ) -> Option<ProgramUpgraded> {
+        if info.owner != bpf_loader_upgradeable::id().as_ref() {
+            return None;
+        }
+        let state = bincode::deserialize(info.data).ok()?;
+        let (deployment_slot, upgrade_authority) = match state {
+            UpgradeableLoaderState::Program {
+                programdata_address,
+            } => {
+                self.insert_program(&programdata_address, info);
+                return None;
+            }
+            UpgradeableLoaderState::ProgramData {
+                slot,
+                upgrade_authority_address,
+            } => (slot, upgrade_authority_address),
+            _ => return None,
+        };
+        let programdata: [u8; 32] = info.pubkey.try_into().ok()?;
+        let allowlist = filter.get_allowlist();
+        if !self.programs.contains_key(&programdata) && !allowlist.is_empty() {
+            self.derive(&allowlist.programs());
+        }
+        let program_id = match self.programs.get(&programdata) {
+            Some(program) if filter.wants_program(program) => program.to_vec(),
+            Some(_) => return None,
+            None if allowlist.is_empty() => Vec::new(),
+            None => return None,
+        };
+        // Without the zeroes the allocation is padded with, as `solana-verify` hashes programs.
+        let offset = UpgradeableLoaderState::programdata_data_offset().ok()?;
+        let elf = info.data.get(offset..)?;
+        let elf = &elf[..elf
+            .iter()
+            .rposition(|byte| *byte != 0)
+            .map_or(0, |end| end + 1)];
+        Some(ProgramUpgraded {
+            slot,
+            program_id,
+            programdata: programdata.to_vec(),
+            deployment_slot,
+            upgrade_authority: upgrade_authority
+                .map_or_else(Vec::new, |authority| authority.to_bytes().into()),
+            data_hash: Sha256::digest(elf).to_vec(),
+            data_len: elf.len() as u64,
+            write_version: info.write_version,
+        })
+    }
+
+    /// Learns the programdata address of `info` if it is a program account, without detecting
+    /// upgrades, as for the accounts notified on startup.
+    pub fn learn(&mut self, info: &AccountInfo) {
+        if info.owner != bpf_loader_upgradeable::id().as_ref() {
+            return;
+        }
+        if let Ok(UpgradeableLoaderState::Program {
+            programdata_address,
+        }) = bincode::deserialize(info.data)
+        {
+            self.insert_program(&programdata_address, info);
+        }
+    }
+
+    fn insert_program(&mut self, programdata_address: &Pubkey, info: &AccountInfo) {
+        if let Ok(program) = info.pubkey.try_into() {
+            self.programs
+                .insert(programdata_address.to_bytes(), program);
+        }
+    }
+
+    /// Derives the programdata addresses of `programs` not derived yet.
+    fn derive(&mut self, programs: &[[u8; 32]]) {
+        for program in programs {
+            if self.derived.insert(*program) {
+                let (programdata, _) =
+                    Pubkey::find_program_address(&[program], &bpf_loader_upgradeable::id());
+                self.programs.insert(programdata.to_bytes(), *program);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn programdata(slot: u64, elf: &[u8]) -> Vec<u8> {
+        let mut data = bincode::serialize(&UpgradeableLoaderState::ProgramData {
+            slot,
+            upgrade_authority_address: Some(Pubkey::new_from_array([3; 32])),
+        })
+        .unwrap();
+        data.extend_from_slice(elf);
+        data.extend_from_slice(&[0; 16]);
+        data
+    }
+
+    #[test]
+    fn test_program_upgrades() {
+        let program = Pubkey::new_unique();
+        let (programdata_address, _) =
+            Pubkey::find_program_address(&[program.as_ref()], &bpf_loader_upgradeable::id());
+        let loader = bpf_loader_upgradeable::id();
+        let data = programdata(7, b"\x7fELF");
+        let info = AccountInfo {
+            pubkey: programdata_address.as_ref(),
+            lamports: 1,
+            owner: loader.as_ref(),
+            executable: false,
+            rent_epoch: 0,
+            data: &data,
+            write_version: 4,
+        };
+        let filter = Filter::new(&Config {
+            program_allowlist: vec![program.to_string()],
+            ..Config::default()
+        });
+
+        let mut upgrades = ProgramUpgrades::default();
+        let event = upgrades.detect(9, &info, &filter).unwrap();
+        assert_eq!(event.program_id, program.to_bytes().to_vec());
+        assert_eq!(event.programdata, programdata_address.to_bytes().to_vec());
+        assert_eq!(event.deployment_slot, 7);
+        assert_eq!(event.upgrade_authority, vec![3; 32]);
+        assert_eq!(event.data_hash, Sha256::digest(b"\x7fELF").to_vec());
+        assert_eq!(event.data_len, 4);
+
+        // Programdata of other programs is not reported, neither are program accounts.
+        let other = Pubkey::new_unique();
+        let info = AccountInfo {
+            pubkey: other.as_ref(),
+            ..info
+        };
+        assert_eq!(upgrades.detect(9, &info, &filter), None);
+        let program_data = bincode::serialize(&UpgradeableLoaderState::Program {
+            programdata_address,
+        })
+        .unwrap();
+        let info = AccountInfo {
+            pubkey: program.as_ref(),
+            data: &program_data,
+            ..info
+        };
+        assert_eq!(upgrades.detect(9, &info, &filter), None);
+    }
+
+    #[test]
+    fn test_program_upgrades_learned() {
+        let program = Pubkey::new_unique();
+        let programdata_address = Pubkey::new_unique();
+        let loader = bpf_loader_upgradeable::id();
+        let program_data = bincode::serialize(&UpgradeableLoaderState::Program {
+            programdata_address,
+        })
+        .unwrap();
+        let program_info = AccountInfo {
+            pubkey: program.as_ref(),
+            lamports: 1,
+            owner: loader.as_ref(),
+            executable: true,
+            rent_epoch: 0,
+            data: &program_data,
+            write_version: 3,
+        };
+        let data = programdata(7, b"\x7fELF");
+        let info = AccountInfo {
+            pubkey: programdata_address.as_ref(),
+            executable: false,
+            data: &data,
+            write_version: 4,
+            ..program_info
+        };
+        let filter = Filter::new(&Config::default());
+
+        // The programdata address is learned from the program account seen first.
+        let mut upgrades = ProgramUpgrades::default();
+        assert_eq!(upgrades.detect(9, &program_info, &filter), None);
+        let event = upgrades.detect(10, &info, &filter).unwrap();
+        assert_eq!(event.program_id, program.to_bytes().to_vec());
+        assert_eq!(event.deployment_slot, 7);
+
+        // Or on startup, where programdata is not detected as an upgrade.
+        let mut upgrades = ProgramUpgrades::default();
+        upgrades.learn(&info);
+        upgrades.learn(&program_info);
+        let event = upgrades.detect(10, &info, &filter).unwrap();
+        assert_eq!(event.program_id, program.to_bytes().to_vec());
+    }
+}
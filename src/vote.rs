@@ -0,0 +1,87 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {crate::*, solana_vote_program::vote_state::VoteStateVersions};
+
+/// Decodes the state of a vote account, of any version.
+pub fn decode_vote(data: &[u8]) -> Option<decoded_account::Account> {
+    let versions: VoteStateVersions = bincode::deserialize(data).ok()?;
+    if versions.is_uninitialized() {
+        return None;
+    }
+    let state = versions.convert_to_current();
+    let (_, authorized_voter) = state.authorized_voters().last()?;
+    Some(decoded_account::Account::VoteAccount(VoteAccount {
+        node_pubkey: state.node_pubkey.to_bytes().into(),
+        authorized_voter: authorized_voter.to_bytes().into(),
+        authorized_withdrawer: state.authorized_withdrawer.to_bytes().into(),
+        commission: state.commission.into(),
+        last_vote_slot: state.last_voted_slot(),
+        root_slot: state.root_slot,
+        epoch_credits: state
+            .epoch_credits()
+            .iter()
+            .map(|&(epoch, credits, previous_credits)| EpochCredits {
+                epoch,
+                credits,
+                previous_credits,
+            })
+            .collect(),
+        last_timestamp_slot: state.last_timestamp.slot,
+        last_timestamp: state.last_timestamp.timestamp,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_program::{clock::Clock, pubkey::Pubkey},
+        solana_vote_program::vote_state::{VoteInit, VoteState},
+    };
+
+    #[test]
+    fn test_decode_vote() {
+        let mut state = VoteState::new(
+            &VoteInit {
+                node_pubkey: Pubkey::new_from_array([1; 32]),
+                authorized_voter: Pubkey::new_from_array([2; 32]),
+                authorized_withdrawer: Pubkey::new_from_array([3; 32]),
+                commission: 10,
+            },
+            &Clock::default(),
+        );
+        for slot in 1..=40 {
+            state.process_slot_vote_unchecked(slot);
+        }
+        state.increment_credits(7);
+        let mut data = vec![0; VoteState::size_of()];
+        VoteState::serialize(&VoteStateVersions::new_current(state), &mut data).unwrap();
+
+        let account = match decode_vote(&data) {
+            Some(decoded_account::Account::VoteAccount(account)) => account,
+            other => panic!("unexpected decoding {:?}", other),
+        };
+        assert_eq!(account.node_pubkey, vec![1; 32]);
+        assert_eq!(account.authorized_voter, vec![2; 32]);
+        assert_eq!(account.authorized_withdrawer, vec![3; 32]);
+        assert_eq!(account.commission, 10);
+        assert_eq!(account.last_vote_slot, Some(40));
+        assert_eq!(account.root_slot, Some(9));
+        let last = account.epoch_credits.last().unwrap();
+        assert_eq!((last.epoch, last.credits - last.previous_credits), (7, 1));
+
+        assert_eq!(decode_vote(&vec![0; VoteState::size_of()]), None);
+    }
+}
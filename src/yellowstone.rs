@@ -0,0 +1,310 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Messages structurally compatible with Yellowstone gRPC, and their conversion from the plugin's events.
+
+use crate::{
+    sanitized_message::MessagePayload, SanitizedTransaction, SlotStatus as EventSlotStatus,
+    SlotStatusEvent, TransactionEvent, UpdateAccountEvent,
+};
+
+#[allow(clippy::enum_variant_names, clippy::large_enum_variant)]
+pub mod geyser {
+    include!(concat!(env!("OUT_DIR"), "/geyser.rs"));
+}
+
+pub mod solana {
+    pub mod storage {
+        pub mod confirmed_block {
+            include!(concat!(
+                env!("OUT_DIR"),
+                "/solana.storage.confirmed_block.rs"
+            ));
+        }
+    }
+}
+
+use {
+    geyser::{subscribe_update::UpdateOneof, SubscribeUpdate},
+    solana::storage::confirmed_block as storage,
+};
+
+/// Protobuf message name of every record in the Yellowstone schema.
+pub const SUBSCRIBE_UPDATE: &str = "geyser.SubscribeUpdate";
+
+/// `ev` as the `SubscribeUpdateAccount` Yellowstone sends to subscriptions matching `filter`.
+pub fn account_update(ev: &UpdateAccountEvent, is_startup: bool, filter: &str) -> SubscribeUpdate {
+    SubscribeUpdate {
+        filters: vec![filter.to_owned()],
+        update_oneof: Some(UpdateOneof::Account(geyser::SubscribeUpdateAccount {
+            account: Some(geyser::SubscribeUpdateAccountInfo {
+                pubkey: ev.pubkey.clone(),
+                lamports: ev.lamports,
+                owner: ev.owner.clone(),
+                executable: ev.executable,
+                rent_epoch: ev.rent_epoch,
+                data: ev.data.clone(),
+                write_version: ev.write_version,
+                // Not reported by the Solana 1.13 interface.
+                txn_signature: None,
+            }),
+            slot: ev.slot,
+            is_startup,
+        })),
+    }
+}
+
+/// `ev` as the `SubscribeUpdateSlot` Yellowstone sends to subscriptions matching `filter`.
+pub fn slot_update(ev: &SlotStatusEvent, filter: &str) -> SubscribeUpdate {
+    let status = match EventSlotStatus::from_i32(ev.status).unwrap_or(EventSlotStatus::Processed) {
+        EventSlotStatus::Processed => geyser::SlotStatus::SlotProcessed,
+        EventSlotStatus::Confirmed => geyser::SlotStatus::SlotConfirmed,
+        EventSlotStatus::Rooted => geyser::SlotStatus::SlotFinalized,
+    };
+    SubscribeUpdate {
+        filters: vec![filter.to_owned()],
+        update_oneof: Some(UpdateOneof::Slot(geyser::SubscribeUpdateSlot {
+            slot: ev.slot,
+            // Slot status events report no parent as 0.
+            parent: Some(ev.parent).filter(|parent| *parent != 0),
+            status: status.into(),
+        })),
+    }
+}
+
+/// `ev` as the `SubscribeUpdateTransaction` Yellowstone sends to subscriptions matching `filter`.
+pub fn transaction_update(ev: &TransactionEvent, filter: &str) -> SubscribeUpdate {
+    let mut loaded = crate::LoadedAddresses::default();
+    let transaction = ev
+        .transaction
+        .as_ref()
+        .map(|transaction| convert_transaction(transaction, &mut loaded));
+    let meta = ev.transaction_status_meta.as_ref().map(|meta| {
+        let mut meta = convert_meta(meta);
+        meta.loaded_writable_addresses = loaded.writable;
+        meta.loaded_readonly_addresses = loaded.readonly;
+        meta.compute_units_consumed = Some(ev.compute_units_consumed);
+        meta
+    });
+    SubscribeUpdate {
+        filters: vec![filter.to_owned()],
+        update_oneof: Some(UpdateOneof::Transaction(
+            geyser::SubscribeUpdateTransaction {
+                transaction: Some(geyser::SubscribeUpdateTransactionInfo {
+                    signature: ev.signature.clone(),
+                    is_vote: ev.is_vote,
+                    transaction,
+                    meta,
+                    index: 0,
+                }),
+                slot: ev.slot,
+            },
+        )),
+    }
+}
+
+/// Converts `transaction`, taking the addresses it loaded from lookup tables out into `loaded`.
+fn convert_transaction(
+    transaction: &SanitizedTransaction,
+    loaded: &mut crate::LoadedAddresses,
+) -> storage::Transaction {
+    let message = transaction
+        .message
+        .as_ref()
+        .and_then(|message| message.message_payload.as_ref())
+        .map(|payload| match payload {
+            MessagePayload::Legacy(message) => storage::Message {
+                header: message.header.as_ref().map(convert_header),
+                account_keys: message.account_keys.clone(),
+                recent_blockhash: message.recent_block_hash.clone(),
+                instructions: message
+                    .instructions
+                    .iter()
+                    .map(convert_instruction)
+                    .collect(),
+                versioned: false,
+                address_table_lookups: Vec::new(),
+            },
+            MessagePayload::V0(v0) => {
+                if let Some(addresses) = &v0.loaded_adresses {
+                    *loaded = addresses.clone();
+                }
+                let message = v0.message.clone().unwrap_or_default();
+                storage::Message {
+                    header: message.header.as_ref().map(convert_header),
+                    account_keys: message.account_keys,
+                    recent_blockhash: message.recent_block_hash,
+                    instructions: message
+                        .instructions
+                        .iter()
+                        .map(convert_instruction)
+                        .collect(),
+                    versioned: true,
+                    address_table_lookups: message
+                        .address_table_lookup
+                        .into_iter()
+                        .map(|lookup| storage::MessageAddressTableLookup {
+                            account_key: lookup.account_key,
+                            writable_indexes: indexes(&lookup.writable_indexes),
+                            readonly_indexes: indexes(&lookup.readonly_indexes),
+                        })
+                        .collect(),
+                }
+            }
+        });
+    storage::Transaction {
+        signatures: transaction.signatures.clone(),
+        message,
+    }
+}
+
+fn convert_meta(meta: &crate::TransactionStatusMeta) -> storage::TransactionStatusMeta {
+    let token_balances = |balances: &[crate::TransactionTokenBalance]| {
+        balances
+            .iter()
+            .map(|balance| storage::TokenBalance {
+                account_index: balance.account_index,
+                mint: balance.mint.clone(),
+                ui_token_amount: balance.ui_token_account.as_ref().map(|amount| {
+                    storage::UiTokenAmount {
+                        ui_amount: amount.ui_amount.unwrap_or_default(),
+                        decimals: amount.decimals,
+                        amount: amount.amount.clone(),
+                        ui_amount_string: amount.ui_amount_string.clone(),
+                    }
+                }),
+                owner: balance.owner.clone(),
+            })
+            .collect()
+    };
+    storage::TransactionStatusMeta {
+        err: Some(storage::TransactionError {
+            err: meta.error.clone(),
+        })
+        .filter(|_| meta.is_status_err),
+        fee: meta.fee,
+        pre_balances: meta.pre_balances.clone(),
+        post_balances: meta.post_balances.clone(),
+        inner_instructions: meta
+            .inner_instructions
+            .iter()
+            .map(|inner| storage::InnerInstructions {
+                index: inner.index,
+                instructions: inner
+                    .instructions
+                    .iter()
+                    .map(|ix| storage::InnerInstruction {
+                        program_id_index: ix.program_id_index,
+                        accounts: indexes(&ix.accounts),
+                        data: ix.data.clone(),
+                    })
+                    .collect(),
+            })
+            .collect(),
+        inner_instructions_none: false,
+        log_messages: meta.log_messages.clone(),
+        log_messages_none: false,
+        pre_token_balances: token_balances(&meta.pre_token_balances),
+        post_token_balances: token_balances(&meta.post_token_balances),
+        rewards: meta.rewards.iter().map(convert_reward).collect(),
+        loaded_writable_addresses: Vec::new(),
+        loaded_readonly_addresses: Vec::new(),
+        compute_units_consumed: None,
+    }
+}
+
+fn convert_header(header: &crate::MessageHeader) -> storage::MessageHeader {
+    storage::MessageHeader {
+        num_required_signatures: header.num_required_signatures,
+        num_readonly_signed_accounts: header.num_readonly_signed_accounts,
+        num_readonly_unsigned_accounts: header.num_readonly_unsigned_accounts,
+    }
+}
+
+fn convert_instruction(ix: &crate::CompiledInstruction) -> storage::CompiledInstruction {
+    storage::CompiledInstruction {
+        program_id_index: ix.program_id_index,
+        accounts: indexes(&ix.accounts),
+        data: ix.data.clone(),
+    }
+}
+
+/// Rewards of typed events are numbered from 0, Yellowstone reserves 0 for rewards without type.
+fn convert_reward(reward: &crate::Reward) -> storage::Reward {
+    storage::Reward {
+        pubkey: reward.pubkey.clone(),
+        lamports: reward.lamports,
+        post_balance: reward.post_balance,
+        reward_type: reward.reward_type + 1,
+        commission: match reward.commission {
+            0 => "".to_owned(),
+            commission => commission.to_string(),
+        },
+    }
+}
+
+/// Account indexes are single bytes in the ledger's storage format.
+fn indexes(indexes: &[u32]) -> Vec<u8> {
+    indexes.iter().map(|index| *index as u8).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, prost::Message};
+
+    #[test]
+    fn test_account_update_round_trip() {
+        let ev = UpdateAccountEvent {
+            slot: 7,
+            pubkey: vec![1; 32].into(),
+            lamports: 5,
+            owner: vec![2; 32].into(),
+            data: vec![3, 4].into(),
+            write_version: 9,
+            ..UpdateAccountEvent::default()
+        };
+        let encoded = account_update(&ev, true, "accounts").encode_to_vec();
+        let decoded = SubscribeUpdate::decode(&encoded[..]).unwrap();
+        assert_eq!(decoded.filters, ["accounts"]);
+        match decoded.update_oneof {
+            Some(UpdateOneof::Account(update)) => {
+                assert_eq!(update.slot, 7);
+                assert!(update.is_startup);
+                let account = update.account.unwrap();
+                assert_eq!(&account.pubkey[..], &[1; 32]);
+                assert_eq!(&account.data[..], &[3, 4]);
+                assert_eq!(account.write_version, 9);
+                assert_eq!(account.txn_signature, None);
+            }
+            other => panic!("unexpected update {:?}", other),
+        }
+
+        let slot = slot_update(
+            &SlotStatusEvent {
+                slot: 8,
+                parent: 7,
+                status: EventSlotStatus::Rooted.into(),
+                ..SlotStatusEvent::default()
+            },
+            "slots",
+        );
+        match slot.update_oneof {
+            Some(UpdateOneof::Slot(slot)) => {
+                assert_eq!(slot.parent, Some(7));
+                assert_eq!(slot.status, geyser::SlotStatus::SlotFinalized as i32);
+            }
+            other => panic!("unexpected update {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,193 @@
+// Copyright 2022 Blockdaemon Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! End to end tests against a real broker, e.g. Redpanda:
+//!
+//! ```shell
+//! docker run -d -p 9092:9092 redpandadata/redpanda redpanda start --mode dev-container \
+//!     --kafka-addr 0.0.0.0:9092 --advertise-kafka-addr localhost:9092
+//! KAFKA_BOOTSTRAP_SERVERS=localhost:9092 cargo test --test kafka -- --ignored
+//! ```
+//!
+//! Topics are not created upfront, they rely on the broker's topic auto-creation.
+
+use {
+    prost::Message as _,
+    rdkafka::{
+        consumer::{BaseConsumer, Consumer},
+        ClientConfig, Message,
+    },
+    solana_accountsdb_plugin_kafka::*,
+    std::{
+        collections::HashMap,
+        sync::Arc,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+};
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+fn bootstrap_servers() -> String {
+    std::env::var("KAFKA_BOOTSTRAP_SERVERS").expect("KAFKA_BOOTSTRAP_SERVERS is not set")
+}
+
+/// A topic name not used by any earlier run.
+fn unique_topic(name: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    format!("it-{}-{}", name, nanos)
+}
+
+fn config() -> Config {
+    let kafka: HashMap<String, String> = [
+        ("bootstrap.servers", bootstrap_servers()),
+        ("request.required.acks", "all".to_owned()),
+        ("message.timeout.ms", "30000".to_owned()),
+        ("linger.ms", "50".to_owned()),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_owned(), v))
+    .collect();
+    Config {
+        kafka,
+        ..Config::default()
+    }
+}
+
+/// Reads all messages of `topic` from the start until `count` arrived.
+fn consume(topic: &str, count: usize) -> Vec<(Option<Vec<u8>>, Vec<u8>)> {
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", bootstrap_servers())
+        .set("group.id", unique_topic("group"))
+        .set("auto.offset.reset", "earliest")
+        .set("enable.auto.commit", "false")
+        .create()
+        .unwrap();
+    consumer.subscribe(&[topic]).unwrap();
+
+    let mut messages = Vec::new();
+    let deadline = Instant::now() + TIMEOUT;
+    while messages.len() < count {
+        assert!(
+            Instant::now() < deadline,
+            "received {} of {} messages from {}",
+            messages.len(),
+            count,
+            topic
+        );
+        // Subscribing to a topic that is not auto-created yet reports an error first.
+        if let Some(Ok(message)) = consumer.poll(Duration::from_millis(500)) {
+            messages.push((
+                message.key().map(<[u8]>::to_vec),
+                message.payload().unwrap_or_default().to_vec(),
+            ));
+        }
+    }
+    messages
+}
+
+fn account(slot: u64, i: u8) -> UpdateAccountEvent {
+    UpdateAccountEvent {
+        slot,
+        pubkey: vec![i; 32].into(),
+        owner: vec![0; 32].into(),
+        lamports: i as u64,
+        data: vec![i; 100].into(),
+        write_version: i as u64,
+        ..UpdateAccountEvent::default()
+    }
+}
+
+#[test]
+#[ignore = "needs a Kafka broker, set KAFKA_BOOTSTRAP_SERVERS"]
+fn test_produce_and_consume() {
+    let mut config = config();
+    config.update_account_topic = unique_topic("accounts");
+    let producers = config.producers(DeliveryContext::default()).unwrap();
+    let publisher = Publisher::new(producers, None, &config);
+
+    for i in 0..10 {
+        publisher.update_account(account(1, i)).unwrap();
+    }
+    drop(publisher);
+
+    let messages = consume(&config.update_account_topic, 10);
+    for (key, payload) in messages {
+        let ev = UpdateAccountEvent::decode(&payload[..]).unwrap();
+        assert_eq!(key.unwrap(), ev.pubkey.to_vec());
+        assert_eq!(ev, account(1, ev.pubkey[0]));
+    }
+}
+
+#[test]
+#[ignore = "needs a Kafka broker, set KAFKA_BOOTSTRAP_SERVERS"]
+fn test_delivery_reports() {
+    let mut config = config();
+    config.update_account_topic = unique_topic("accounts");
+    config.slot_accounts_complete_topic = unique_topic("complete");
+    let tracker = Arc::new(SlotTracker::new());
+    let producers = config
+        .producers(DeliveryContext::tracking(tracker.clone()))
+        .unwrap();
+    let dispatcher = Dispatcher::new(Publisher::new(producers, None, &config), &config);
+
+    for i in 0..20 {
+        dispatcher.update_account(account(7, i)).unwrap();
+    }
+    dispatcher.close_slot(7);
+
+    let messages = consume(&config.slot_accounts_complete_topic, 1);
+    let complete = SlotAccountsComplete::decode(&messages[0].1[..]).unwrap();
+    assert_eq!(
+        complete,
+        SlotAccountsComplete {
+            slot: 7,
+            messages_delivered: 20,
+            messages_failed: 0,
+        }
+    );
+}
+
+#[test]
+#[ignore = "needs a Kafka broker, set KAFKA_BOOTSTRAP_SERVERS"]
+fn test_shutdown_flushes_queued_events() {
+    let mut config = config();
+    config.slot_status_topic = unique_topic("slots");
+    config.serialization_workers = 2;
+    // Hold messages back in librdkafka so they are still queued when the dispatcher is dropped.
+    config
+        .kafka
+        .insert("linger.ms".to_owned(), "1000".to_owned());
+    let producers = config.producers(DeliveryContext::default()).unwrap();
+    let dispatcher = Dispatcher::new(Publisher::new(producers, None, &config), &config);
+
+    for slot in 0..500 {
+        let ev = SlotStatusEvent {
+            slot,
+            parent: slot.saturating_sub(1),
+            ..SlotStatusEvent::default()
+        };
+        dispatcher.update_slot_status(ev).unwrap();
+    }
+    drop(dispatcher);
+
+    let mut slots: Vec<u64> = consume(&config.slot_status_topic, 500)
+        .into_iter()
+        .map(|(_, payload)| SlotStatusEvent::decode(&payload[..]).unwrap().slot)
+        .collect();
+    slots.sort_unstable();
+    assert_eq!(slots, (0..500).collect::<Vec<_>>());
+}